@@ -13,7 +13,7 @@ fn utc_millis(ts: &str) -> i64 {
         .timestamp_millis()
 }
 
-fn append(count: usize, batch_size: usize, writer: Arc<SeriesWriter>) -> Result<usize, Error> {
+fn append(count: usize, batch_size: usize, sorted: bool, writer: Arc<SeriesWriter>) -> Result<usize, Error> {
     let mut appender = writer.appender()?;
     let mut entries = 0usize;
     for batch in (utc_millis("-262000-01-01 00:00")..)
@@ -28,7 +28,10 @@ fn append(count: usize, batch_size: usize, writer: Arc<SeriesWriter>) -> Result<
         .buffering::<Vec<Entry>>(batch_size)
     {
         entries += batch.len();
-        appender.append(&batch)?;
+        match sorted {
+            true => appender.append_sorted(&batch)?,
+            false => appender.append(&batch)?,
+        };
     }
 
     appender.done()?;
@@ -61,6 +64,7 @@ fn main() -> Result<(), Error> {
 
     let series_table = series_table::create(env::create(file_system::open(path)?))?;
     series_table.create("t")?;
+    series_table.create("t-sorted")?;
 
     let entries = matches
         .value_of("entries")
@@ -70,9 +74,19 @@ fn main() -> Result<(), Error> {
 
     let batch = matches.value_of("batch").unwrap().parse::<usize>().unwrap();
 
-    let start_ts = time::Instant::now();
-    let result = append(entries, batch, series_table.writer("t").unwrap())?;
-    log::debug!("Inserted {} in {}ms", result, start_ts.elapsed().as_millis());
+    // the entries are generated in order, so both modes append the same blocks
+    for (name, sorted) in [("t", false), ("t-sorted", true)] {
+        let start_ts = time::Instant::now();
+        let result = append(entries, batch, sorted, series_table.writer(name).unwrap())?;
+        let elapsed = start_ts.elapsed();
+        log::debug!(
+            "Inserted {} (sorted: {}) in {}ms ({:.0} entries/s)",
+            result,
+            sorted,
+            elapsed.as_millis(),
+            result as f64 / elapsed.as_secs_f64()
+        );
+    }
 
     Ok(())
 }
@@ -0,0 +1,152 @@
+use clap::clap_app;
+use milliseriesdb::storage::{env, error::Error, file_system, series_table, Compression, Entry, SeriesTable};
+use std::path::Path;
+use std::{fs, time};
+
+const PATTERNS: [&str; 4] = ["constant", "sawtooth", "random_walk", "sparse_spikes"];
+const CODECS: [Compression; 3] = [Compression::None, Compression::Deflate, Compression::Delta];
+const BATCH: usize = 10_000;
+
+/// xorshift64*, the benchmark should not depend on a random crate and should be reproducible.
+struct Random(u64);
+
+impl Random {
+    fn next_f64(&mut self) -> f64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        (self.0.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+fn generate(pattern: &str, count: usize) -> Vec<Entry> {
+    let mut random = Random(0x9e37_79b9_7f4a_7c15);
+    let mut walk = 20.0f64;
+    (0..count)
+        .map(|i| Entry {
+            ts: 1_600_000_000_000 + i as i64 * 1000,
+            value: match pattern {
+                "constant" => 42.0,
+                "sawtooth" => (i % 100) as f64,
+                "random_walk" => {
+                    walk += random.next_f64() - 0.5;
+                    (walk * 100.0).round() / 100.0
+                }
+                "sparse_spikes" => match random.next_f64() < 0.001 {
+                    true => random.next_f64() * 1000.0,
+                    false => 0.0,
+                },
+                _ => unreachable!(),
+            },
+        })
+        .collect()
+}
+
+struct Report {
+    pattern: &'static str,
+    compression: Compression,
+    write_rate: f64,
+    read_rate: f64,
+    bytes_per_entry: f64,
+}
+
+fn run(
+    series_table: &SeriesTable,
+    pattern: &'static str,
+    compression: Compression,
+    entries: &[Entry],
+) -> Result<Report, Error> {
+    let name = format!("{}-{:?}", pattern, compression).to_lowercase();
+    series_table.create(&name)?;
+
+    let start_ts = time::Instant::now();
+    let writer = series_table.writer(&name).unwrap();
+    let mut appender = writer.appender()?;
+    appender.compression(compression);
+    for batch in entries.chunks(BATCH) {
+        appender.append_sorted(batch)?;
+    }
+    appender.done()?;
+    let write_elapsed = start_ts.elapsed();
+
+    let reader = series_table.reader(&name).unwrap();
+    let start_ts = time::Instant::now();
+    let mut read = 0usize;
+    for entry in reader.iterator(i64::MIN)? {
+        entry?;
+        read += 1;
+    }
+    let read_elapsed = start_ts.elapsed();
+    assert_eq!(entries.len(), read);
+
+    Ok(Report {
+        pattern,
+        compression,
+        write_rate: entries.len() as f64 / write_elapsed.as_secs_f64(),
+        read_rate: entries.len() as f64 / read_elapsed.as_secs_f64(),
+        bytes_per_entry: reader.size()?.data_bytes as f64 / entries.len() as f64,
+    })
+}
+
+fn main() -> Result<(), Error> {
+    stderrlog::new().verbosity(2).init().unwrap();
+
+    let matches = clap_app!(milliseriesdb =>
+        (@arg path: -p <PATH> --path default_value("playground/compression_bench") "path to the (dropped) database")
+        (@arg entries: -e <ENTRIES> --entries default_value("1000000") "entries per series")
+        (@arg quick: -q --quick "append 50000 entries per series, e.g. on CI")
+    )
+    .get_matches();
+
+    let path = matches.value_of("path").unwrap();
+    if Path::new(path).exists() {
+        fs::remove_dir_all(path)?;
+    }
+
+    let entries = match matches.is_present("quick") {
+        true => 50_000,
+        false => matches.value_of("entries").unwrap().parse::<usize>().unwrap(),
+    };
+
+    let series_table = series_table::create(env::create(file_system::open(path)?))?;
+
+    let mut reports = Vec::new();
+    for pattern in PATTERNS {
+        let generated = generate(pattern, entries);
+        for compression in CODECS {
+            reports.push(run(&series_table, pattern, compression, &generated)?);
+        }
+    }
+
+    println!(
+        "{:<14} {:<8} {:>14} {:>14} {:>14}",
+        "pattern", "codec", "write/s", "read/s", "bytes/entry"
+    );
+    for report in reports.iter() {
+        println!(
+            "{:<14} {:<8} {:>14.0} {:>14.0} {:>14.2}",
+            report.pattern,
+            format!("{:?}", report.compression),
+            report.write_rate,
+            report.read_rate,
+            report.bytes_per_entry
+        );
+    }
+
+    let bytes_per_entry = |compression: &str| {
+        reports
+            .iter()
+            .find(|report| report.pattern == "random_walk" && format!("{:?}", report.compression) == compression)
+            .map(|report| report.bytes_per_entry)
+            .unwrap()
+    };
+    assert!(
+        bytes_per_entry("Delta") < bytes_per_entry("None"),
+        "Delta should be smaller than None on the random walk"
+    );
+
+    drop(series_table);
+    fs::remove_dir_all(path)?;
+
+    Ok(())
+}
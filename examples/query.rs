@@ -13,6 +13,9 @@ fn query(reader: Arc<SeriesReader>, group_by: &str, limit: &str) -> Result<usize
                 group_by: group_by.to_owned(),
                 aggregators: "mean".to_string(),
                 limit: limit.to_owned(),
+                fill: None,
+                align: None,
+                to: None,
             }
             .try_into()
             .unwrap(),
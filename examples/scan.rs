@@ -0,0 +1,58 @@
+use clap::clap_app;
+use milliseriesdb::storage::{env, error::Error, file_system, series_table, SeriesReader};
+use std::sync::Arc;
+use std::time;
+
+fn scan(reader: Arc<SeriesReader>, read_ahead: usize) -> Result<usize, Error> {
+    let mut entries = 0usize;
+    for entry in reader.iterator(i64::MIN)?.read_ahead(read_ahead) {
+        entry?;
+        entries += 1;
+    }
+    Ok(entries)
+}
+
+fn main() -> Result<(), Error> {
+    stderrlog::new().verbosity(4).init().unwrap();
+
+    let matches = clap_app!(milliseriesdb =>
+        (@arg path: -p <PATH> --path default_value("playground/examples") "path to database")
+        (@arg read_ahead: -r <READ_AHEAD> --read_ahead default_value("8") "blocks to read ahead")
+        (@arg samples: -s <SAMPLES> --samples default_value("10") "samples")
+    )
+    .get_matches();
+
+    let path = matches.value_of("path").unwrap();
+
+    let series_table = series_table::create(env::create(file_system::open(path)?))?;
+
+    let read_ahead = matches
+        .value_of("read_ahead")
+        .unwrap()
+        .parse::<usize>()
+        .unwrap();
+
+    let samples = matches
+        .value_of("samples")
+        .unwrap()
+        .parse::<usize>()
+        .unwrap();
+
+    for sample in 0..samples {
+        for blocks in [0, read_ahead] {
+            let start_ts = time::Instant::now();
+            let entries = scan(series_table.reader("t").unwrap(), blocks)?;
+            let elapsed = start_ts.elapsed();
+            log::debug!(
+                "[{}] Read ahead {}: {} entries in {}ms ({:.0} entries/s)",
+                sample + 1,
+                blocks,
+                entries,
+                elapsed.as_millis(),
+                entries as f64 / elapsed.as_secs_f64()
+            );
+        }
+    }
+
+    Ok(())
+}
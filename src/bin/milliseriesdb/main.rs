@@ -1,11 +1,36 @@
 use clap::clap_app;
-use milliseriesdb::storage::{file_system, env, series_table};
+use milliseriesdb::restapi;
+use milliseriesdb::storage::{file_system, env, series_table, Checksum, IndexSyncPolicy, SyncMode};
+use std::io;
 use std::sync::Arc;
+use std::time::Duration;
+use tokio::runtime::{Builder, Runtime};
 
 mod server;
 
-#[tokio::main]
-async fn main() {
+/// Builds the multi-threaded runtime, tokio defaults are used for the sizes that are not given.
+fn build_runtime(worker_threads: Option<usize>, blocking_threads: Option<usize>) -> io::Result<Runtime> {
+    let mut builder = Builder::new_multi_thread();
+    builder.enable_all();
+    if let Some(worker_threads) = worker_threads {
+        builder.worker_threads(worker_threads);
+    }
+    if let Some(blocking_threads) = blocking_threads {
+        builder.max_blocking_threads(blocking_threads);
+    }
+    builder.build()
+}
+
+/// Parses the batch size of the exports and the restores, the size must be positive.
+fn parse_batch_size(size: &str) -> Result<usize, String> {
+    match size.parse::<usize>() {
+        Ok(0) => Err("the batch size must be positive".to_owned()),
+        Ok(size) => Ok(size),
+        Err(error) => Err(error.to_string()),
+    }
+}
+
+fn main() {
     stderrlog::new()
         .module(module_path!())
         .verbosity(4)
@@ -14,26 +39,126 @@ async fn main() {
 
     let matches = clap_app!(milliseriesdb =>
         (@setting SubcommandRequiredElseHelp)
-        (@arg path: -p <PATH> --path "path to database")        
+        (@arg path: -p <PATH>... --path number_of_values(1) "path to database, repeat to spread the series across multiple data roots")
+        (@arg sync_mode: --("sync-mode") default_value("always") "when to fsync appended data: always, every:{commits} or interval:{millis}")
+        (@arg index_sync: --("index-sync") default_value("with_data") "when to fsync the index: with_data or every:{data fsyncs}")
+        (@arg commit_checksum: --("commit-checksum") default_value("crc32c") "checksum of the written commits: crc32c or crc16")
+        (@arg worker_threads: --("worker-threads") +takes_value "number of the threads serving the requests, the number of cores by default")
+        (@arg blocking_threads: --("blocking-threads") +takes_value "max number of the threads reading and writing the series, 512 by default")
+        (@arg max_open_series: --("max-open-series") +takes_value "open the series on the first access and keep at most given number of idle series open, all series are opened at startup by default")
+        (@arg max_series: --("max-series") +takes_value "max number of series, the creation of new series fails once reached, no limit by default")
         (@subcommand server =>
             (about: "start the server")
             (@arg addr: -a <ADDR> --addr default_value("127.0.0.1:8080") "listen address, like 0.0.0.0:8080")
+            (@arg graphite_addr: --("graphite-addr") +takes_value "graphite plaintext listen address, like 0.0.0.0:2003")
+            (@arg scrub_interval: --("scrub-interval") +takes_value "verify all series in background every given number of seconds")
+            (@arg max_limit: --("max-limit") +takes_value "max limit of the queries, 100000 by default")
+            (@arg export_batch_size: --("export-batch-size") +takes_value "entries per parquet row group of the exports, 65536 by default")
+            (@arg import_batch_size: --("import-batch-size") +takes_value "entries per appended batch of the restores, 1048576 by default")
+            (@arg restore_allowed_host: --("restore-allowed-host") +takes_value ... number_of_values(1) "host (or host:port) the series could be restored from by url, repeat to allow several hosts")
+        )
+        (@subcommand archive =>
+            (about: "gzip the data file of the series, the series can't be appended afterwards")
+            (@arg series: -s <SERIES> --series "series name")
         )
     )
     .get_matches();
 
-    let fs = file_system::open(matches.value_of("path").unwrap()).unwrap();
+    let fs = file_system::open_roots(&matches.values_of("path").unwrap().collect::<Vec<&str>>()).unwrap();
+
+    let sync_mode: SyncMode = matches.value_of("sync_mode").unwrap().parse().expect("invalid sync mode");
+
+    let index_sync: IndexSyncPolicy = matches.value_of("index_sync").unwrap().parse().expect("invalid index sync policy");
+
+    let checksum: Checksum = matches.value_of("commit_checksum").unwrap().parse().expect("invalid commit checksum");
 
-    let env = env::create(fs);
-    let series_table = series_table::create(env).unwrap();
+    let env = env::create(fs)
+        .with_sync_mode(sync_mode)
+        .with_index_sync(index_sync)
+        .with_checksum(checksum);
+    let series_table = match matches.value_of("max_open_series") {
+        Some(max_open) => series_table::create_lazy(env, max_open.parse().expect("invalid max open series")),
+        None => series_table::create(env),
+    }
+    .unwrap();
+    let series_table = match matches.value_of("max_series") {
+        Some(max_series) => series_table.with_max_series(max_series.parse().expect("invalid max series")),
+        None => series_table,
+    };
+
+    let runtime = build_runtime(
+        matches.value_of("worker_threads").map(|n| n.parse().expect("invalid number of worker threads")),
+        matches.value_of("blocking_threads").map(|n| n.parse().expect("invalid number of blocking threads")),
+    )
+    .unwrap();
 
     match matches.subcommand() {
-        ("server", Some(sub_match)) => server::start_server(
-            Arc::new(series_table),
-            sub_match.value_of("addr").unwrap().parse().unwrap(),
-        )
-        .await
-        .unwrap(),
+        ("server", Some(sub_match)) => runtime
+            .block_on(server::start_server(
+                Arc::new(series_table),
+                sub_match.value_of("addr").unwrap().parse().unwrap(),
+                sub_match.value_of("graphite_addr").map(|addr| addr.parse().unwrap()),
+                sub_match
+                    .value_of("scrub_interval")
+                    .map(|secs| Duration::from_secs(secs.parse().unwrap())),
+                restapi::Options {
+                    max_limit: sub_match
+                        .value_of("max_limit")
+                        .map(|limit| limit.parse().expect("invalid max limit"))
+                        .unwrap_or(restapi::query::DEFAULT_MAX_LIMIT),
+                    export_batch_size: sub_match
+                        .value_of("export_batch_size")
+                        .map(|size| parse_batch_size(size).expect("invalid export batch size"))
+                        .unwrap_or(restapi::export::DEFAULT_BATCH_SIZE),
+                    import_batch_size: sub_match
+                        .value_of("import_batch_size")
+                        .map(|size| parse_batch_size(size).expect("invalid import batch size"))
+                        .unwrap_or(restapi::restore::DEFAULT_BATCH_SIZE),
+                    restore_allowed_hosts: sub_match
+                        .values_of("restore_allowed_host")
+                        .map(|hosts| hosts.map(|host| host.to_owned()).collect())
+                        .unwrap_or_default(),
+                    scrub_stats: None,
+                },
+            ))
+            .unwrap(),
+        ("archive", Some(sub_match)) => {
+            let name = sub_match.value_of("series").unwrap();
+            if !series_table.archive(name).unwrap() {
+                log::warn!("series '{}' doesn't exist", name);
+            }
+        }
         _ => unreachable!(),
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_build_runtime() {
+        let runtime = build_runtime(Some(2), Some(4)).unwrap();
+        let sum = runtime.block_on(async {
+            let handles = (0..8)
+                .map(|i| tokio::task::spawn_blocking(move || i))
+                .collect::<Vec<_>>();
+            let mut sum = 0;
+            for handle in handles {
+                sum += handle.await.unwrap();
+            }
+            sum
+        });
+        assert_eq!(28, sum);
+
+        build_runtime(None, None).unwrap();
+    }
+
+    #[test]
+    fn test_parse_batch_size() {
+        assert_eq!(Ok(1024), parse_batch_size("1024"));
+        assert!(parse_batch_size("0").is_err());
+        assert!(parse_batch_size("-1").is_err());
+        assert!(parse_batch_size("").is_err());
+    }
+}
@@ -1,17 +1,30 @@
-use milliseriesdb::storage::SeriesTable;
-use milliseriesdb::restapi;
+use milliseriesdb::storage::{scrubber, SeriesTable};
+use milliseriesdb::{graphite, restapi};
 use std::io;
 use std::net::SocketAddr;
 use std::sync::Arc;
-use warp::Filter;
+use std::time::Duration;
 
-pub async fn start_server(series_table: Arc<SeriesTable>, addr: SocketAddr) -> io::Result<()> {
-    let server_api = restapi::create::filter(series_table.clone())
-        .or(restapi::append::filter(series_table.clone()))
-        .or(restapi::query::filter(series_table.clone()))
-        .or(restapi::export::filter(series_table.clone()))
-        .or(restapi::restore::filter(series_table.clone()));
+pub async fn start_server(
+    series_table: Arc<SeriesTable>,
+    addr: SocketAddr,
+    graphite_addr: Option<SocketAddr>,
+    scrub_interval: Option<Duration>,
+    mut options: restapi::Options,
+) -> io::Result<()> {
+    if let Some(scrub_interval) = scrub_interval {
+        options.scrub_stats = Some(scrubber::start(&series_table, scrub_interval));
+    }
 
-    warp::serve(server_api).run(addr).await;
+    if let Some(graphite_addr) = graphite_addr {
+        let series_table = series_table.clone();
+        tokio::spawn(async move {
+            if let Err(e) = graphite::serve(graphite_addr, series_table).await {
+                log::warn!("Graphite listener failed: {:?}", e);
+            }
+        });
+    }
+
+    warp::serve(restapi::filter_with_options(series_table, options)).run(addr).await;
     Ok(())
 }
@@ -31,6 +31,7 @@ pub trait BufferingBuilder<I, U>
 where
     I: Iterator<Item = U>,
 {
+    /// Collects the items into batches of `size` items, the last batch holds the rest.
     fn buffering<F>(self, size: usize) -> Buffering<I, U, F>
     where
         F: FromIterator<U>;
@@ -65,4 +66,19 @@ mod test {
                 .collect::<Vec<Vec<u32>>>()
         );
     }
+
+    #[test]
+    fn test_buffering_count() {
+        let batches = |count: u32, size: usize| {
+            (0..count)
+                .buffering::<Vec<u32>>(size)
+                .map(|batch| batch.len())
+                .collect::<Vec<usize>>()
+        };
+
+        assert_eq!(vec![10, 10, 10], batches(30, 10));
+        assert_eq!(vec![10, 10, 10, 1], batches(31, 10));
+        assert_eq!(vec![5], batches(5, 10));
+        assert_eq!(Vec::<usize>::new(), batches(0, 10));
+    }
 }
@@ -1,6 +1,23 @@
 use crate::storage::Entry;
 use bytes::buf::Buf;
 
+/// Default number of the digits after the decimal point of the exported values.
+pub const DEFAULT_PRECISION: usize = 2;
+
+/// Max number of the digits after the decimal point, enough to round trip any f64.
+pub const MAX_PRECISION: usize = 17;
+
+/// Formats the entries as `ts; value` lines, the values are written with
+/// `precision` digits after the decimal point (up to `MAX_PRECISION`).
+pub fn to_csv(entries: &[Entry], precision: usize) -> String {
+    let precision = precision.min(MAX_PRECISION);
+    entries
+        .iter()
+        .map(|entry| format!("{}; {:.*}\n", entry.ts, precision, entry.value))
+        .collect::<Vec<String>>()
+        .join("")
+}
+
 pub fn read_csv_line(line: &str) -> Option<Entry> {
     let mut split = line.split(';');
 
@@ -25,24 +42,47 @@ impl ChunkedReader {
         }
     }
 
-    pub fn read<B: Buf>(&mut self, chunk: B) -> Chunk<B> {
+    pub fn read<B: Buf>(&mut self, chunk: B) -> Chunk<'_, B, fn(&str) -> Option<Entry>> {
+        self.read_with(chunk, read_csv_line)
+    }
+
+    /// Splits the chunk into lines and parses each line with `parse`. The incomplete
+    /// line at the end of the chunk is kept until the next chunk is read.
+    pub fn read_with<B: Buf, T, F: Fn(&str) -> Option<T>>(&mut self, chunk: B, parse: F) -> Chunk<'_, B, F> {
         Chunk {
             chunk: chunk,
             buf: &mut self.buf,
+            parse,
         }
     }
+
+    /// Parses the remaining line that is not terminated by the new line.
+    pub fn finish_with<T, F: Fn(&str) -> Option<T>>(&mut self, parse: F) -> Option<Result<T, ()>> {
+        if self.buf.is_empty() {
+            return None;
+        }
+
+        let line = std::str::from_utf8(&self.buf).ok();
+        let entry = line.and_then(parse).ok_or(());
+
+        self.buf.clear();
+
+        Some(entry)
+    }
 }
 
-pub struct Chunk<'a, B: Buf> {
+pub struct Chunk<'a, B: Buf, F> {
     chunk: B,
-    buf: &'a mut Vec<u8>
+    buf: &'a mut Vec<u8>,
+    parse: F,
 }
 
-impl<'a, B> Iterator for Chunk<'a, B>
+impl<'a, B, T, F> Iterator for Chunk<'a, B, F>
 where
     B: Buf,
+    F: Fn(&str) -> Option<T>,
 {
-    type Item = Result<Entry, ()>;
+    type Item = Result<T, ()>;
     fn next(&mut self) -> Option<Self::Item> {
         while self.chunk.has_remaining() {
             let c = self.chunk.get_u8();
@@ -52,7 +92,7 @@ where
                 let line = std::str::from_utf8(&self.buf).ok();
 
                 let entry = Some(
-                    line.and_then(|line| read_csv_line(&line))
+                    line.and_then(|line| (self.parse)(line))
                         .map(Ok)
                         .unwrap_or_else(|| Err(())),
                 );
@@ -40,6 +40,11 @@ impl Failpoints {
 #[cfg(test)]
 #[macro_export]
 macro_rules! failpoint {
+    ($fp:expr, $name:expr, panic) => {
+        if $fp.is_on($name) {
+            panic!("fp");
+        }
+    };
     ($fp:expr, $name:expr, $ret:expr) => {
         if $fp.is_on($name) {
             return $ret;
@@ -50,6 +55,9 @@ macro_rules! failpoint {
 #[cfg(not(test))]
 #[macro_export]
 macro_rules! failpoint {
+    ($fp:expr, $name:expr, panic) => {
+        {};
+    };
     ($fp:expr, $name:expr, $ret:expr) => {
         {};
     }
@@ -79,4 +87,15 @@ mod test {
         fp.off("write::error");
         assert!(matches!(write(&fp), Ok(())));
     }
+
+    #[test]
+    fn test_panic() {
+        let fp = Failpoints::create();
+        let write = |fp: &Failpoints| failpoint!(fp, "write::panic", panic);
+
+        write(&fp);
+
+        fp.on("write::panic");
+        assert!(std::panic::catch_unwind(|| write(&fp)).is_err());
+    }
 }
\ No newline at end of file
@@ -0,0 +1,180 @@
+use crate::storage::error::Error;
+use crate::storage::{series_table, Entry, SeriesTable, TimestampUnit};
+use std::collections::BTreeMap;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::net::TcpListener;
+
+/// A metric parsed from the graphite plaintext protocol: `metric.path value timestamp`,
+/// where timestamp is in seconds.
+#[derive(Debug, PartialEq)]
+pub struct Metric {
+    pub path: String,
+    pub value: f64,
+    pub ts: i64,
+}
+
+pub fn read_line(line: &str) -> Option<Metric> {
+    let mut split = line.split_whitespace();
+
+    let metric = Metric {
+        path: split.next()?.to_owned(),
+        value: split.next()?.parse::<f64>().ok()?,
+        ts: split.next()?.parse::<i64>().ok()?,
+    };
+
+    match split.next() {
+        Some(_) => None,
+        None => Some(metric),
+    }
+}
+
+/// Appends the batches, the failure of one series is logged and doesn't affect the others.
+async fn flush(series_table: &SeriesTable, batches: &mut BTreeMap<String, Vec<Metric>>) -> usize {
+    let mut written = 0usize;
+    for (path, metrics) in std::mem::take(batches) {
+        if !series_table::is_valid_name(&path) {
+            log::warn!("invalid graphite metric path: {:?}", path);
+            continue;
+        }
+
+        let writer = match series_table.create_and_writer(&path) {
+            Ok(writer) => writer,
+            Err(error) => {
+                log::warn!("can not create graphite series {:?}: {:?}", path, error);
+                continue;
+            }
+        };
+        let unit = writer.config().timestamp_unit;
+
+        let entries = metrics
+            .into_iter()
+            .filter_map(|metric| {
+                TimestampUnit::Seconds
+                    .convert(metric.ts, unit)
+                    .map(|ts| Entry { ts, value: metric.value })
+            })
+            .collect::<Vec<Entry>>();
+
+        match writer.append_async(entries).await {
+            Ok(count) => written += count,
+            Err(error) => log::warn!("can not append graphite series {:?}: {:?}", path, error),
+        }
+    }
+    written
+}
+
+/// Reads the metrics from the connection and appends them to the corresponding series
+/// (created if missing). The metrics are appended as soon as there is no more buffered
+/// input. Malformed lines and the series that can not be appended are skipped. Returns
+/// the number of written entries.
+pub async fn handle<R: AsyncRead + Unpin>(read: R, series_table: Arc<SeriesTable>) -> Result<usize, Error> {
+    let mut reader = BufReader::new(read);
+    let mut batches: BTreeMap<String, Vec<Metric>> = BTreeMap::new();
+    let mut line = String::new();
+    let mut written = 0usize;
+
+    loop {
+        line.clear();
+        if reader.read_line(&mut line).await? == 0 {
+            break;
+        }
+
+        match read_line(&line) {
+            Some(metric) => batches.entry(metric.path.clone()).or_default().push(metric),
+            None => log::warn!("malformed graphite line: {:?}", line),
+        }
+
+        if reader.buffer().is_empty() {
+            written += flush(&series_table, &mut batches).await;
+        }
+    }
+
+    written += flush(&series_table, &mut batches).await;
+
+    Ok(written)
+}
+
+pub async fn serve(addr: SocketAddr, series_table: Arc<SeriesTable>) -> io::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+
+    log::debug!("Graphite listener started on {}", addr);
+
+    loop {
+        let (socket, peer) = listener.accept().await?;
+        let series_table = series_table.clone();
+
+        tokio::spawn(async move {
+            if let Err(e) = handle(socket, series_table).await {
+                log::warn!("Graphite connection {} failed: {:?}", peer, e);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::storage::series_table;
+    use crate::storage::{BinaryOp, VirtualDefinition};
+    use tokio::io::AsyncWriteExt;
+
+    #[test]
+    fn test_read_line() {
+        assert_eq!(
+            Some(Metric {
+                path: "room.co2".to_owned(),
+                value: 410.5,
+                ts: 1621890712,
+            }),
+            read_line("room.co2 410.5 1621890712\n")
+        );
+        assert_eq!(None, read_line("room.co2 410.5\n"));
+        assert_eq!(None, read_line("room.co2 abc 1621890712\n"));
+        assert_eq!(None, read_line("room.co2 1 2 3\n"));
+    }
+
+    #[tokio::test]
+    async fn test_handle() -> Result<(), Error> {
+        let series_table = series_table::test::create()?;
+        // the virtual series can not be appended, the other series of the batch are written
+        series_table.create("room.in")?;
+        series_table.create("room.out")?;
+        let definition = VirtualDefinition {
+            left: "room.in".to_owned(),
+            right: "room.out".to_owned(),
+            op: BinaryOp::Sub,
+        };
+        assert!(series_table.define_virtual("room.net", definition)?);
+
+        let (mut client, server) = tokio::io::duplex(64);
+
+        let handle = tokio::spawn(handle(server, series_table.series_table.clone()));
+
+        client.write_all(b"room.co2 410 1\nroom.net 1 1\nroom.temp 21.5 1\n").await?;
+        client.write_all(b"malformed\nroom.co2 420 2\n").await?;
+        drop(client);
+
+        assert_eq!(3, handle.await.unwrap()?);
+
+        let entries = |name: &str| {
+            series_table
+                .reader(name)
+                .unwrap()
+                .iterator(0)
+                .unwrap()
+                .collect::<Result<Vec<Entry>, Error>>()
+                .unwrap()
+        };
+
+        assert_eq!(
+            vec![Entry { ts: 1000, value: 410.0 }, Entry { ts: 2000, value: 420.0 }],
+            entries("room.co2")
+        );
+        assert_eq!(vec![Entry { ts: 1000, value: 21.5 }], entries("room.temp"));
+
+        Ok(())
+    }
+}
@@ -1,6 +1,11 @@
 pub mod storage;
 pub mod query;
 pub mod csv;
+pub mod lineproto;
+pub mod graphite;
 pub mod failpoints;
 pub mod buffering;
-pub mod restapi;
\ No newline at end of file
+#[cfg(feature = "server")]
+pub mod restapi;
+#[cfg(feature = "parquet")]
+pub mod parquet;
\ No newline at end of file
@@ -0,0 +1,100 @@
+/// A point parsed from the InfluxDB line protocol:
+/// `measurement[,tag=value...] value=1.23[,field=...] [timestamp]`.
+/// Tags are ignored, the measurement is used as the series name.
+#[derive(Debug, PartialEq)]
+pub struct Point {
+    pub measurement: String,
+    pub value: f64,
+    pub ts: Option<i64>,
+}
+
+fn parse_number(s: &str) -> Option<f64> {
+    s.strip_suffix('i')
+        .or_else(|| s.strip_suffix('u'))
+        .unwrap_or(s)
+        .parse::<f64>()
+        .ok()
+}
+
+fn parse_value(fields: &str) -> Option<f64> {
+    let fields = fields
+        .split(',')
+        .map(|field| {
+            let mut split = field.splitn(2, '=');
+            match (split.next(), split.next()) {
+                (Some(key), Some(value)) if !key.is_empty() => Some((key, value)),
+                _ => None,
+            }
+        })
+        .collect::<Option<Vec<(&str, &str)>>>()?;
+
+    match fields.iter().find(|(key, _)| *key == "value") {
+        Some((_, value)) => parse_number(value),
+        None if fields.len() == 1 => parse_number(fields[0].1),
+        None => None,
+    }
+}
+
+/// Parses the line, returns `Some(None)` for empty lines and comments and `None`
+/// if the line is malformed.
+pub fn read_line(line: &str) -> Option<Option<Point>> {
+    let line = line.trim();
+
+    if line.is_empty() || line.starts_with('#') {
+        return Some(None);
+    }
+
+    let mut split = line.split_whitespace();
+
+    let measurement = split.next()?.split(',').next()?;
+    if measurement.is_empty() {
+        return None;
+    }
+
+    let value = parse_value(split.next()?)?;
+
+    let ts = match split.next() {
+        Some(ts) => Some(ts.parse::<i64>().ok()?),
+        None => None,
+    };
+
+    if split.next().is_some() {
+        return None;
+    }
+
+    Some(Some(Point {
+        measurement: measurement.to_owned(),
+        value,
+        ts,
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn point(measurement: &str, value: f64, ts: Option<i64>) -> Option<Option<Point>> {
+        Some(Some(Point {
+            measurement: measurement.to_owned(),
+            value,
+            ts,
+        }))
+    }
+
+    #[test]
+    fn test_read_line() {
+        assert_eq!(point("co2", 1.23, Some(1699999999000)), read_line("co2 value=1.23 1699999999000\n"));
+        assert_eq!(point("co2", 4.0, None), read_line("co2,room=kitchen value=4i"));
+        assert_eq!(point("temp", 21.5, Some(-10)), read_line("temp celsius=21.5 -10"));
+        assert_eq!(point("temp", 21.5, None), read_line("temp celsius=20,value=21.5"));
+        assert_eq!(Some(None), read_line("\n"));
+        assert_eq!(Some(None), read_line("# comment"));
+
+        assert_eq!(None, read_line("co2"));
+        assert_eq!(None, read_line("co2 value=abc"));
+        assert_eq!(None, read_line("co2 value=1.0 abc"));
+        assert_eq!(None, read_line("co2 value=1.0 10 20"));
+        assert_eq!(None, read_line("co2 a=1,b=2"));
+        assert_eq!(None, read_line(",tag=1 value=1.0"));
+    }
+}
@@ -0,0 +1,113 @@
+use crate::storage::{error::Error, Entry};
+use parquet::data_type::{DoubleType, Int64Type};
+use parquet::errors::ParquetError;
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::SerializedFileWriter;
+use parquet::schema::parser::parse_message_type;
+use std::io::Write;
+use std::sync::Arc;
+
+const SCHEMA: &str = "
+message entry {
+    REQUIRED INT64 ts;
+    REQUIRED DOUBLE value;
+}
+";
+
+fn parquet_error(err: ParquetError) -> Error {
+    Error::Other(format!("parquet: {}", err))
+}
+
+/// Writes entries as a parquet file with columns `ts: int64` and `value: double`.
+/// Each call to `write_row_group` produces a separate row group, so the memory
+/// usage is bounded by the size of the batch.
+pub struct ParquetWriter<W: Write + Send> {
+    writer: SerializedFileWriter<W>,
+}
+
+impl<W: Write + Send> ParquetWriter<W> {
+    pub fn new(write: W) -> Result<ParquetWriter<W>, Error> {
+        let schema = Arc::new(parse_message_type(SCHEMA).map_err(parquet_error)?);
+        let props = Arc::new(WriterProperties::builder().build());
+
+        Ok(ParquetWriter {
+            writer: SerializedFileWriter::new(write, schema, props).map_err(parquet_error)?,
+        })
+    }
+
+    pub fn write_row_group(&mut self, entries: &[Entry]) -> Result<(), Error> {
+        let ts: Vec<i64> = entries.iter().map(|entry| entry.ts).collect();
+        let values: Vec<f64> = entries.iter().map(|entry| entry.value).collect();
+
+        let mut row_group = self.writer.next_row_group().map_err(parquet_error)?;
+
+        if let Some(mut column) = row_group.next_column().map_err(parquet_error)? {
+            column
+                .typed::<Int64Type>()
+                .write_batch(&ts, None, None)
+                .map_err(parquet_error)?;
+            column.close().map_err(parquet_error)?;
+        }
+
+        if let Some(mut column) = row_group.next_column().map_err(parquet_error)? {
+            column
+                .typed::<DoubleType>()
+                .write_batch(&values, None, None)
+                .map_err(parquet_error)?;
+            column.close().map_err(parquet_error)?;
+        }
+
+        row_group.close().map_err(parquet_error)?;
+
+        Ok(())
+    }
+
+    pub fn close(self) -> Result<(), Error> {
+        self.writer.close().map_err(parquet_error)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use bytes::Bytes;
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+    use parquet::record::RowAccessor;
+
+    pub fn read_entries(bytes: Bytes) -> Vec<Entry> {
+        let reader = SerializedFileReader::new(bytes).unwrap();
+        reader
+            .get_row_iter(None)
+            .unwrap()
+            .map(|row| {
+                let row = row.unwrap();
+                Entry {
+                    ts: row.get_long(0).unwrap(),
+                    value: row.get_double(1).unwrap(),
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_write() -> Result<(), Error> {
+        let entries = vec![
+            Entry { ts: -1, value: 1.5 },
+            Entry { ts: 2, value: 2.5 },
+            Entry { ts: 3, value: 3.5 },
+        ];
+
+        let mut buf = Vec::new();
+        {
+            let mut writer = ParquetWriter::new(&mut buf)?;
+            writer.write_row_group(&entries[..2])?;
+            writer.write_row_group(&entries[2..])?;
+            writer.close()?;
+        }
+
+        assert_eq!(entries, read_entries(Bytes::from(buf)));
+
+        Ok(())
+    }
+}
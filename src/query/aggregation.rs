@@ -1,10 +1,17 @@
 use super::group_by::Folder;
+use serde::{Deserialize as _, Deserializer, Serializer};
 use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Base of the histogram buckets unless given, e.g. `hist`.
+pub const DEFAULT_HISTOGRAM_BASE: f64 = 2.0;
 
 #[allow(dead_code)]
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, PartialEq)]
 pub enum Aggregator {
-    Mean, Min, Max
+    Mean, Min, Max, Last, Count,
+    /// Counts of the values in the exponential buckets `(base^(i-1), base^i]`.
+    Histogram { base: f64 },
 }
 
 impl Aggregator {
@@ -13,15 +20,33 @@ impl Aggregator {
             Aggregator::Mean => State::Mean { count: 0, sum: 0.0 },
             Aggregator::Min => State::Min { min: f64::MAX },
             Aggregator::Max => State::Max { max: f64::MIN },
+            Aggregator::Last => State::Last { last: f64::NAN },
+            Aggregator::Count => State::Count { count: 0 },
+            Aggregator::Histogram { base } => State::Histogram { base: *base, counts: BTreeMap::new() },
         }
     }
 }
 
+/// Returns the exponent of the upper bound of the bucket of the positive `value`,
+/// the smallest `i` with `value <= base^i`.
+fn bucket_exponent(value: f64, base: f64) -> i32 {
+    let exponent = (value.ln() / base.ln()).ceil() as i32;
+    // the logarithm of the exact powers could be rounded up
+    match base.powi(exponent - 1) >= value {
+        true => exponent - 1,
+        false => exponent,
+    }
+}
+
 #[allow(dead_code)]
 pub enum State {
     Mean { count: usize, sum: f64 },
     Min { min: f64 },
     Max { max: f64 },
+    Last { last: f64 },
+    Count { count: u64 },
+    /// Counts by the bucket exponent, `None` is the bucket of the values `<= 0`.
+    Histogram { base: f64, counts: BTreeMap<Option<i32>, u64> },
 }
 
 impl State {
@@ -37,6 +62,22 @@ impl State {
             State::Max { max } => {
                 *max = max.max(value);
             },
+            State::Last { last } => {
+                *last = value;
+            },
+            State::Count { count } => {
+                *count += 1;
+            },
+            State::Histogram { base, counts } => {
+                if value.is_nan() {
+                    return;
+                }
+                let bucket = match value > 0.0 {
+                    true => Some(bucket_exponent(value, *base)),
+                    false => None,
+                };
+                *counts.entry(bucket).or_insert(0) += 1;
+            },
         }
     }
     pub fn complete(&mut self) -> Aggregation {
@@ -56,15 +97,84 @@ impl State {
                 let result = Aggregation::Max(*max);
                 *max = f64::MIN;
                 result
+            },
+            State::Last { last } => {
+                let result = Aggregation::Last(*last);
+                *last = f64::NAN;
+                result
+            }
+            State::Count { count } => {
+                let result = Aggregation::Count(*count);
+                *count = 0;
+                result
+            }
+            State::Histogram { base, counts } => {
+                let buckets = counts
+                    .iter()
+                    .map(|(bucket, count)| match bucket {
+                        Some(exponent) => (base.powi(*exponent), *count),
+                        None => (0.0, *count),
+                    })
+                    .collect();
+                counts.clear();
+                Aggregation::Histogram(buckets)
             }
         }
     }
 }
 
+/// Non-finite values (e.g. the mean of the empty group) are not valid JSON
+/// numbers, so they are serialized as `null` (and deserialized back as NaN).
 #[allow(dead_code)]
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub enum Aggregation {
-    Mean(f64), Min(f64), Max(f64),
+    Mean(#[serde(serialize_with = "serialize_finite", deserialize_with = "deserialize_finite")] f64),
+    Min(#[serde(serialize_with = "serialize_finite", deserialize_with = "deserialize_finite")] f64),
+    Max(#[serde(serialize_with = "serialize_finite", deserialize_with = "deserialize_finite")] f64),
+    Last(#[serde(serialize_with = "serialize_finite", deserialize_with = "deserialize_finite")] f64),
+    /// Number of the entries in the group.
+    Count(u64),
+    /// `(upper bound, count)` of the non-empty buckets, sorted by the bound. The values
+    /// `<= 0` are counted in the bucket with the bound `0`, NaN values are skipped.
+    Histogram(Vec<(f64, u64)>),
+}
+
+impl Aggregation {
+    /// Returns the value of the aggregation, the number of the counted entries for the histogram.
+    pub fn value(&self) -> f64 {
+        match self {
+            Aggregation::Mean(value) => *value,
+            Aggregation::Min(value) => *value,
+            Aggregation::Max(value) => *value,
+            Aggregation::Last(value) => *value,
+            Aggregation::Count(count) => *count as f64,
+            Aggregation::Histogram(buckets) => buckets.iter().map(|(_, count)| *count).sum::<u64>() as f64,
+        }
+    }
+
+    /// Name of the aggregator, as in the query.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Aggregation::Mean(_) => "mean",
+            Aggregation::Min(_) => "min",
+            Aggregation::Max(_) => "max",
+            Aggregation::Last(_) => "last",
+            Aggregation::Count(_) => "count",
+            Aggregation::Histogram(_) => "hist",
+        }
+    }
+}
+
+fn serialize_finite<S: Serializer>(value: &f64, serializer: S) -> Result<S::Ok, S::Error> {
+    if value.is_finite() {
+        serializer.serialize_f64(*value)
+    } else {
+        serializer.serialize_none()
+    }
+}
+
+fn deserialize_finite<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+    Ok(Option::<f64>::deserialize(deserializer)?.unwrap_or(f64::NAN))
 }
 
 #[cfg(test)]
@@ -82,6 +192,21 @@ impl PartialEq<Aggregation> for Aggregation {
             Aggregation::Max(lhs) => match other {
                 Aggregation::Max(rhs) => (lhs - rhs).abs() <= 10e-6,
                 _ => false
+            },
+            Aggregation::Last(lhs) => match other {
+                Aggregation::Last(rhs) => (lhs - rhs).abs() <= 10e-6,
+                _ => false
+            },
+            Aggregation::Count(lhs) => match other {
+                Aggregation::Count(rhs) => lhs == rhs,
+                _ => false
+            },
+            Aggregation::Histogram(lhs) => match other {
+                Aggregation::Histogram(rhs) => {
+                    lhs.len() == rhs.len()
+                        && lhs.iter().zip(rhs.iter()).all(|(lhs, rhs)| (lhs.0 - rhs.0).abs() <= 10e-6 && lhs.1 == rhs.1)
+                }
+                _ => false
             }
         }
     }
@@ -112,4 +237,68 @@ impl Folder for AggregatorsFolder {
             .map(|state| state.complete())
             .collect()
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_serialize_non_finite() {
+        let values = vec![
+            Aggregation::Mean(f64::NAN),
+            Aggregation::Min(f64::INFINITY),
+            Aggregation::Max(f64::NEG_INFINITY),
+            Aggregation::Last(1.5),
+        ];
+
+        let json = serde_json::to_string(&values).unwrap();
+        assert_eq!("[{\"Mean\":null},{\"Min\":null},{\"Max\":null},{\"Last\":1.5}]", json);
+
+        match serde_json::from_str::<Vec<Aggregation>>(&json).unwrap()[..] {
+            [Aggregation::Mean(mean), _, _, Aggregation::Last(last)] => {
+                assert!(mean.is_nan());
+                assert_eq!(1.5, last);
+            }
+            _ => panic!("unexpected aggregations"),
+        }
+    }
+
+    #[test]
+    fn test_histogram() {
+        let mut folder = AggregatorsFolder::new(&[Aggregator::Histogram { base: 2.0 }]);
+        for value in [0.5, 1.0, 1.5, 2.0, 3.0, 4.0, 4.5, 8.0, 100.0, 0.0, -3.0, f64::NAN] {
+            folder.fold(value);
+        }
+
+        assert_eq!(
+            vec![Aggregation::Histogram(vec![
+                (0.0, 2),
+                (0.5, 1),
+                (1.0, 1),
+                (2.0, 2),
+                (4.0, 2),
+                (8.0, 2),
+                (128.0, 1),
+            ])],
+            folder.complete()
+        );
+
+        // the buckets are reset after the group is completed
+        folder.fold(10.0);
+        assert_eq!(vec![Aggregation::Histogram(vec![(16.0, 1)])], folder.complete());
+
+        let mut folder = AggregatorsFolder::new(&[Aggregator::Histogram { base: 10.0 }]);
+        for value in [1.0, 10.0, 11.0, 99.0, 100.0, 1000.0] {
+            folder.fold(value);
+        }
+        let histogram = folder.complete();
+        assert_eq!(vec![Aggregation::Histogram(vec![(1.0, 1), (10.0, 1), (100.0, 3), (1000.0, 1)])], histogram);
+        assert_eq!(6.0, histogram[0].value());
+
+        assert_eq!(
+            "[{\"Histogram\":[[1.0,1],[10.0,1],[100.0,3],[1000.0,1]]}]",
+            serde_json::to_string(&histogram).unwrap()
+        );
+    }
+}
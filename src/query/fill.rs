@@ -0,0 +1,108 @@
+use super::aggregation::Aggregation;
+use crate::storage::error::Error;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Fill {
+    /// Empty buckets are skipped.
+    #[default]
+    None,
+    /// Empty buckets repeat the values of the previous bucket, except the count, which is zero.
+    Previous,
+}
+
+/// The count and the histogram of the empty bucket are empty, the other values are carried forward.
+fn empty_group_value(value: &Aggregation) -> Aggregation {
+    match value {
+        Aggregation::Count(_) => Aggregation::Count(0),
+        Aggregation::Histogram(_) => Aggregation::Histogram(Vec::new()),
+        value => value.clone(),
+    }
+}
+
+/// Emits the rows for the empty buckets between the rows of the underlying
+/// iterator, carrying forward the values of the previous row.
+pub struct FillPrevious<I>
+where
+    I: Iterator<Item = Result<(i64, Vec<Aggregation>), Error>>,
+{
+    pub iterator: I,
+    pub granularity: i64,
+    pub previous: Option<(i64, Vec<Aggregation>)>,
+    pub next: Option<(i64, Vec<Aggregation>)>,
+}
+
+impl<I> FillPrevious<I>
+where
+    I: Iterator<Item = Result<(i64, Vec<Aggregation>), Error>>,
+{
+    pub fn new(iterator: I, granularity: i64) -> FillPrevious<I> {
+        FillPrevious {
+            iterator,
+            granularity,
+            previous: None,
+            next: None,
+        }
+    }
+}
+
+impl<I> Iterator for FillPrevious<I>
+where
+    I: Iterator<Item = Result<(i64, Vec<Aggregation>), Error>>,
+{
+    type Item = Result<(i64, Vec<Aggregation>), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let next = match self.next.take() {
+            Some(next) => next,
+            None => match self.iterator.next()? {
+                Ok(next) => next,
+                Err(err) => return Some(Err(err)),
+            },
+        };
+
+        let gap = self.previous.as_ref().and_then(|(ts, values)| {
+            ts.checked_add(self.granularity)
+                .filter(|ts| *ts < next.0)
+                .map(|ts| (ts, values.iter().map(empty_group_value).collect()))
+        });
+
+        let row = match gap {
+            Some(gap) => {
+                self.next = Some(next);
+                gap
+            }
+            None => next,
+        };
+
+        self.previous = Some(row.clone());
+
+        Some(Ok(row))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_fill_previous() {
+        let rows = vec![
+            Ok((0, vec![Aggregation::Last(1.0)])),
+            Ok((10, vec![Aggregation::Last(2.0)])),
+            Ok((40, vec![Aggregation::Last(3.0)])),
+        ];
+
+        assert_eq!(
+            vec![
+                (0, vec![Aggregation::Last(1.0)]),
+                (10, vec![Aggregation::Last(2.0)]),
+                (20, vec![Aggregation::Last(2.0)]),
+                (30, vec![Aggregation::Last(2.0)]),
+                (40, vec![Aggregation::Last(3.0)]),
+            ],
+            FillPrevious::new(rows.into_iter(), 10)
+                .collect::<Result<Vec<(i64, Vec<Aggregation>)>, Error>>()
+                .unwrap()
+        );
+    }
+}
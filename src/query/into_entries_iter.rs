@@ -1,9 +1,45 @@
-use crate::storage::{error::Error, Entry, SeriesReader, SeriesIterator};
+use crate::storage::{error::Error, Entry, ScanPlan, SeriesReader, SeriesIterator, TimestampUnit, VirtualIterator, VirtualSeries};
 use std::sync::Arc;
 
+/// Source of the entries of a query, e.g. a series or the in-memory entries:
+///
+/// ```
+/// use milliseriesdb::query::{QueryBuilder, Statement, StatementExpr};
+/// use milliseriesdb::storage::Entry;
+/// use std::convert::TryFrom;
+///
+/// let entries = vec![
+///     Entry { ts: 0, value: 1.0 },
+///     Entry { ts: 1000, value: 3.0 },
+///     Entry { ts: 60000, value: 5.0 },
+/// ];
+///
+/// let statement = Statement::try_from(StatementExpr {
+///     from: "0".to_owned(),
+///     group_by: "minute".to_owned(),
+///     aggregators: "mean".to_owned(),
+///     limit: "10".to_owned(),
+///     fill: None,
+///     align: None,
+///     to: None,
+/// })
+/// .unwrap();
+///
+/// let rows = entries.query(statement).rows().unwrap();
+///
+/// assert_eq!(vec![0, 60000], rows.iter().map(|row| row.ts).collect::<Vec<i64>>());
+/// assert_eq!(vec![2.0, 5.0], rows.iter().map(|row| row.values[0].value()).collect::<Vec<f64>>());
+/// ```
 pub trait IntoEntriesIter {
     type Iter: Iterator<Item = Result<Entry, Error>>;
     fn into_iter(&self, from: i64) -> Result<Self::Iter, Error>;
+    fn timestamp_unit(&self) -> TimestampUnit {
+        TimestampUnit::Millis
+    }
+    /// Returns the blocks read by the entries of `from..=to`, `None` if the source has no blocks.
+    fn plan(&self, _from: i64, _to: i64) -> Result<Option<ScanPlan>, Error> {
+        Ok(None)
+    }
 }
 
 impl IntoEntriesIter for Arc<SeriesReader> {
@@ -11,36 +47,54 @@ impl IntoEntriesIter for Arc<SeriesReader> {
     fn into_iter(&self, from: i64) -> Result<Self::Iter, Error> {
         self.iterator(from)
     }
+    fn timestamp_unit(&self) -> TimestampUnit {
+        self.config().timestamp_unit
+    }
+    fn plan(&self, from: i64, to: i64) -> Result<Option<ScanPlan>, Error> {
+        SeriesReader::plan(self, from, to).map(Some)
+    }
 }
 
-#[cfg(test)]
-use std::collections::VecDeque;
+impl IntoEntriesIter for Arc<VirtualSeries> {
+    type Iter = VirtualIterator;
+    fn into_iter(&self, from: i64) -> Result<Self::Iter, Error> {
+        self.iterator(from)
+    }
+    fn timestamp_unit(&self) -> TimestampUnit {
+        self.left().config().timestamp_unit
+    }
+}
 
-#[cfg(test)]
+/// Iterator over the copied in-memory entries.
 pub struct VecIterator {
-    deque: VecDeque<Entry>,
+    entries: std::vec::IntoIter<Entry>,
 }
 
-#[cfg(test)]
 impl Iterator for VecIterator {
     type Item = Result<Entry, Error>;
     fn next(&mut self) -> Option<Self::Item> {
-        self.deque.pop_front().map(Ok)
+        self.entries.next().map(Ok)
+    }
+}
+
+/// The entries are expected to be sorted by timestamp, as in a series.
+impl IntoEntriesIter for &[Entry] {
+    type Iter = VecIterator;
+    fn into_iter(&self, from: i64) -> Result<Self::Iter, Error> {
+        Ok(VecIterator {
+            entries: self
+                .iter()
+                .filter(|entry| entry.ts >= from)
+                .cloned()
+                .collect::<Vec<Entry>>()
+                .into_iter(),
+        })
     }
 }
 
-#[cfg(test)]
 impl IntoEntriesIter for Vec<Entry> {
     type Iter = VecIterator;
     fn into_iter(&self, from: i64) -> Result<Self::Iter, Error> {
-        let mut iter = VecIterator {
-            deque: VecDeque::new(),
-        };
-        for entry in self.iter() {
-            if entry.ts >= from {
-                iter.deque.push_back(entry.to_owned());
-            }
-        }
-        Ok(iter)
-    }
-}
\ No newline at end of file
+        IntoEntriesIter::into_iter(&self.as_slice(), from)
+    }
+}
@@ -1,4 +1,5 @@
 mod aggregation;
+mod fill;
 mod group_by;
 mod into_entries_iter;
 mod query;
@@ -7,14 +8,16 @@ mod statement_expr;
 mod round;
 
 pub use aggregation::Aggregation;
-pub use query::{QueryBuilder, Row};
+pub use fill::Fill;
+pub use into_entries_iter::{IntoEntriesIter, VecIterator};
+pub use query::{Plan, Query, QueryBuilder, Row};
 pub use statement::Statement;
 pub use statement_expr::StatementExpr;
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::storage::{error::Error, series_table, Entry};
+    use crate::storage::{error::Error, series_table, Entry, SeriesConfig, TimestampUnit};
     use chrono::{TimeZone, Utc};
     use std::convert::TryInto;
 
@@ -34,7 +37,9 @@ mod test {
     fn row(ts: &str, agg: Aggregation) -> Row {
         Row {
             ts: utc_millis(ts),
+            ts_nanos: None,
             values: vec![agg],
+            count: None,
         }
     }
 
@@ -65,6 +70,9 @@ mod test {
                     group_by: "hour".to_string(),
                     aggregators: "mean".to_string(),
                     limit: "1000".to_string(),
+                    fill: None,
+                    align: None,
+                    to: None,
                 }
                 .try_into()
                 .unwrap(),
@@ -84,4 +92,318 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn test_group_by_query_to() -> Result<(), Error> {
+        let table = series_table::test::create()?;
+        table.create("series-1")?;
+
+        let writer = table.writer("series-1").unwrap();
+        writer.append(&vec![
+            entry("1971-01-02 10:30", 1.0),
+            entry("1971-01-02 11:10", 2.0),
+            entry("1971-01-02 11:50", 4.0),
+            entry("1971-01-02 12:10", 5.0),
+        ])?;
+
+        let reader = table.reader("series-1").unwrap();
+
+        let rows = reader
+            .query(
+                StatementExpr {
+                    from: "now-1h".to_string(),
+                    group_by: "hour".to_string(),
+                    aggregators: "mean".to_string(),
+                    limit: "1000".to_string(),
+                    fill: None,
+                    align: None,
+                    to: Some("now".to_string()),
+                }
+                .parse_at(utc_millis("1971-01-02 11:50"))
+                .unwrap(),
+            )
+            .rows()?;
+
+        assert_eq!(vec![row("1971-01-02 11:00", Aggregation::Mean(3.0))], rows);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_group_by_query_align() -> Result<(), Error> {
+        let table = series_table::test::create()?;
+        table.create("series-1")?;
+
+        let writer = table.writer("series-1").unwrap();
+        writer.append(&vec![
+            entry("1971-01-02 00:30", 1.0),
+            entry("1971-01-02 01:10", 2.0),
+            entry("1971-01-02 23:50", 3.0),
+            entry("1971-01-03 00:59", 4.0),
+            entry("1971-01-03 01:00", 5.0),
+        ])?;
+
+        let reader = table.reader("series-1").unwrap();
+
+        let query = |align: Option<&str>| {
+            reader
+                .clone()
+                .query(
+                    StatementExpr {
+                        from: "1971-01-01".to_string(),
+                        group_by: "day".to_string(),
+                        aggregators: "mean".to_string(),
+                        limit: "1000".to_string(),
+                        fill: None,
+                        align: align.map(|align| align.to_string()),
+                        to: None,
+                    }
+                    .try_into()
+                    .unwrap(),
+                )
+                .rows()
+        };
+
+        assert_eq!(
+            vec![
+                row("1971-01-01 01:00", Aggregation::Mean(1.0)),
+                row("1971-01-02 01:00", Aggregation::Mean(3.0)),
+                row("1971-01-03 01:00", Aggregation::Mean(5.0)),
+            ],
+            query(Some("3600000"))?
+        );
+
+        assert_eq!(
+            vec![
+                row("1971-01-02 00:00", Aggregation::Mean(2.0)),
+                row("1971-01-03 00:00", Aggregation::Mean(4.5)),
+            ],
+            query(None)?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_group_by_query_nanos_unit() -> Result<(), Error> {
+        let table = series_table::test::create()?;
+        table.create_with_config(
+            "series-1",
+            SeriesConfig {
+                timestamp_unit: TimestampUnit::Nanos,
+                ..SeriesConfig::default()
+            },
+        )?;
+
+        // 1971-01-02 00:00 plus the nanos
+        let base = utc_millis("1971-01-02 00:00") * 1_000_000;
+        let nanos = |ts: i64, value: f64| Entry { ts: base + ts, value };
+
+        let writer = table.writer("series-1").unwrap();
+        writer.append(&vec![
+            nanos(1_000, 1.0),
+            nanos(1_500, 3.0),
+            nanos(2_100, 4.0),
+            nanos(2_999, 6.0),
+            nanos(4_000, 7.0),
+            nanos(1_000_000, 8.0),
+        ])?;
+
+        let reader = table.reader("series-1").unwrap();
+
+        let query = |group_by: &str| {
+            reader
+                .clone()
+                .query(
+                    StatementExpr {
+                        from: "1971-01-02".to_string(),
+                        group_by: group_by.to_string(),
+                        aggregators: "mean".to_string(),
+                        limit: "1000".to_string(),
+                        fill: None,
+                        align: None,
+                        to: None,
+                    }
+                    .try_into()
+                    .unwrap(),
+                )
+                .rows()
+        };
+
+        let row_at = |ts: i64, value: f64| Row {
+            ts: (base + ts) / 1_000_000,
+            ts_nanos: Some(base + ts).filter(|_| ts % 1_000_000 != 0),
+            values: vec![Aggregation::Mean(value)],
+            count: None,
+        };
+
+        assert_eq!(
+            vec![row_at(1_000, 2.0), row_at(2_000, 5.0), row_at(4_000, 7.0), row_at(1_000_000, 8.0)],
+            query("1us")?
+        );
+        assert_eq!(vec![row_at(0, 4.2), row_at(1_000_000, 8.0)], query("1ms")?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_group_by_query_seconds_unit() -> Result<(), Error> {
+        let table = series_table::test::create()?;
+        table.create_with_config(
+            "series-1",
+            SeriesConfig {
+                timestamp_unit: TimestampUnit::Seconds,
+                ..SeriesConfig::default()
+            },
+        )?;
+
+        let secs = |ts: &str| Entry {
+            ts: utc_millis(ts) / 1000,
+            ..entry(ts, 0.0)
+        };
+
+        let writer = table.writer("series-1").unwrap();
+        writer.append(&vec![
+            Entry { value: 3.0, ..secs("1961-01-02 11:00") },
+            Entry { value: 2.0, ..secs("1961-01-02 11:02") },
+            Entry { value: 4.0, ..secs("1961-01-02 11:04") },
+            Entry { value: 5.0, ..secs("1961-01-02 12:02") },
+            Entry { value: 7.0, ..secs("1961-01-02 12:04") },
+            Entry { value: 5.0, ..secs("1971-01-02 12:02") },
+            Entry { value: 9.0, ..secs("1971-01-02 13:04") },
+        ])?;
+
+        let reader = table.reader("series-1").unwrap();
+
+        let rows: Vec<Row> = reader
+            .query(
+                StatementExpr {
+                    from: "1961-01-02".to_string(),
+                    group_by: "hour".to_string(),
+                    aggregators: "mean".to_string(),
+                    limit: "1000".to_string(),
+                    fill: None,
+                    align: None,
+                    to: None,
+                }
+                .try_into()
+                .unwrap(),
+            )
+            .rows()?;
+
+        assert_eq!(
+            vec![
+                row("1961-01-02 11:00", Aggregation::Mean(3.0)),
+                row("1961-01-02 12:00", Aggregation::Mean(6.0)),
+                row("1971-01-02 12:00", Aggregation::Mean(5.0)),
+                row("1971-01-02 13:00", Aggregation::Mean(9.0)),
+            ],
+            rows
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_query_out_of_range() -> Result<(), Error> {
+        let table = series_table::test::create()?;
+        table.create_with_config(
+            "series-1",
+            SeriesConfig {
+                timestamp_unit: TimestampUnit::Nanos,
+                ..SeriesConfig::default()
+            },
+        )?;
+
+        let reader = table.reader("series-1").unwrap();
+
+        let result = reader
+            .query(
+                StatementExpr {
+                    from: "3000-01-01".to_string(),
+                    group_by: "hour".to_string(),
+                    aggregators: "mean".to_string(),
+                    limit: "1000".to_string(),
+                    fill: None,
+                    align: None,
+                    to: None,
+                }
+                .try_into()
+                .unwrap(),
+            )
+            .rows();
+
+        assert!(matches!(result, Err(Error::TimestampOutOfRange)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_fill_previous_last() -> Result<(), Error> {
+        let table = series_table::test::create()?;
+        table.create("series-1")?;
+
+        let writer = table.writer("series-1").unwrap();
+        writer.append(&vec![
+            entry("1961-01-02 11:00", 3.0),
+            entry("1961-01-02 11:40", 2.0),
+            entry("1961-01-02 14:10", 5.0),
+            entry("1961-01-02 14:20", 7.0),
+        ])?;
+
+        let reader = table.reader("series-1").unwrap();
+
+        let rows = reader
+            .clone()
+            .query(
+                StatementExpr {
+                    from: "1961-01-02".to_string(),
+                    group_by: "hour".to_string(),
+                    aggregators: "last".to_string(),
+                    limit: "1000".to_string(),
+                    fill: Some("previous".to_string()),
+                    align: None,
+                    to: None,
+                }
+                .try_into()
+                .unwrap(),
+            )
+            .rows()?;
+
+        assert_eq!(
+            vec![
+                row("1961-01-02 11:00", Aggregation::Last(2.0)),
+                row("1961-01-02 12:00", Aggregation::Last(2.0)),
+                row("1961-01-02 13:00", Aggregation::Last(2.0)),
+                row("1961-01-02 14:00", Aggregation::Last(7.0)),
+            ],
+            rows
+        );
+
+        let rows = reader
+            .query(
+                StatementExpr {
+                    from: "1961-01-02".to_string(),
+                    group_by: "hour".to_string(),
+                    aggregators: "last".to_string(),
+                    limit: "2".to_string(),
+                    fill: Some("previous".to_string()),
+                    align: None,
+                    to: None,
+                }
+                .try_into()
+                .unwrap(),
+            )
+            .rows()?;
+
+        assert_eq!(
+            vec![
+                row("1961-01-02 11:00", Aggregation::Last(2.0)),
+                row("1961-01-02 12:00", Aggregation::Last(2.0)),
+            ],
+            rows
+        );
+
+        Ok(())
+    }
 }
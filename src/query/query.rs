@@ -1,23 +1,31 @@
-use super::aggregation::{Aggregation, AggregatorsFolder};
+use super::aggregation::{Aggregation, Aggregator, AggregatorsFolder};
+use super::fill::{Fill, FillPrevious};
 use super::group_by::GroupBy;
 use super::into_entries_iter::IntoEntriesIter;
 use super::statement::Statement;
-use super::round::round_to;
-use crate::storage::{error::Error, Entry};
+use super::round::round_to_aligned;
+use crate::storage::{error::Error, Entry, ScanPlan, TimestampUnit};
 use serde_derive::{Deserialize, Serialize};
 use std::convert::From;
 use std::time::SystemTime;
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Row {
+    /// Start of the group in millis, rounded down.
     pub ts: i64,
+    /// Start of the group in nanos if it is not a whole number of millis (sub-millisecond `group_by`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ts_nanos: Option<i64>,
     pub values: Vec<Aggregation>,
+    /// Number of the entries in the group, if requested with `Query::with_count`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub count: Option<u64>,
 }
 
 #[cfg(test)]
 impl PartialEq<Row> for Row {
     fn eq(&self, other: &Row) -> bool {
-        self.ts == other.ts && self.values == other.values
+        self.ts == other.ts && self.ts_nanos == other.ts_nanos && self.values == other.values
     }
 }
 
@@ -25,11 +33,25 @@ impl From<(i64, Vec<Aggregation>)> for Row {
     fn from(row: (i64, Vec<Aggregation>)) -> Row {
         Row {
             ts: row.0,
+            ts_nanos: None,
             values: row.1,
+            count: None,
         }
     }
 }
 
+/// Plan of the query, see `Query::explain`.
+#[derive(Debug, Serialize)]
+pub struct Plan {
+    /// Range of the query in the timestamp unit of the series.
+    pub from: i64,
+    pub to: i64,
+    /// Blocks read, `None` if the source is not a series.
+    pub scan: Option<ScanPlan>,
+}
+
+type GroupByItem = Result<(i64, Vec<Aggregation>), Error>;
+
 pub trait QueryBuilder {
     fn query(self, statement: Statement) -> Query<Self>
     where
@@ -38,6 +60,7 @@ pub trait QueryBuilder {
         Query {
             into_iterator: self,
             statement,
+            with_count: false,
         }
     }
 }
@@ -50,31 +73,115 @@ where
 {
     into_iterator: I,
     statement: Statement,
+    with_count: bool,
 }
 
 impl<I> Query<I>
 where
     I: IntoEntriesIter,
 {
+    /// Fills `Row::count` with the number of the entries in each group,
+    /// the aggregators of the statement stay as they are.
+    pub fn with_count(self) -> Query<I> {
+        Query {
+            with_count: true,
+            ..self
+        }
+    }
+
+    /// Returns the blocks the query reads, without running it.
+    pub fn explain(&self) -> Result<Plan, Error> {
+        let unit = self.into_iterator.timestamp_unit();
+
+        let from = unit
+            .from_millis(self.statement.from)
+            .ok_or(Error::TimestampOutOfRange)?;
+        let to = match self.statement.to {
+            Some(to) => unit.from_millis(to).ok_or(Error::TimestampOutOfRange)?,
+            None => i64::MAX,
+        };
+
+        Ok(Plan {
+            from,
+            to,
+            scan: self.into_iterator.plan(from, to)?,
+        })
+    }
+
     pub fn rows(self) -> Result<Vec<Row>, Error> {
-        let folder = AggregatorsFolder::new(&self.statement.aggregators);
+        let mut rows = Vec::new();
+        self.for_each_row(|row| {
+            rows.push(row);
+            Ok(())
+        })?;
+        Ok(rows)
+    }
 
-        let granularity = self.statement.group_by as i64;
+    /// Runs the query and calls `f` with each row as soon as the row is computed,
+    /// so the rows are not kept in memory. Stops on the first error returned by `f`.
+    pub fn for_each_row<F>(self, mut f: F) -> Result<(), Error>
+    where
+        F: FnMut(Row) -> Result<(), Error>,
+    {
+        let mut aggregators = self.statement.aggregators;
+        if self.with_count {
+            aggregators.push(Aggregator::Count);
+        }
+        let folder = AggregatorsFolder::new(&aggregators);
+
+        let unit = self.into_iterator.timestamp_unit();
+
+        let from = unit
+            .from_millis(self.statement.from)
+            .ok_or(Error::TimestampOutOfRange)?;
+
+        // the sub-millisecond groups are narrower than a timestamp of the coarse units
+        let granularity = match self.statement.group_by_nanos {
+            Some(nanos) => TimestampUnit::Nanos.convert(nanos as i64, unit),
+            None => unit.from_millis(self.statement.group_by as i64),
+        }
+        .filter(|granularity| *granularity > 0)
+        .ok_or(Error::TimestampOutOfRange)?;
+
+        let align = unit
+            .from_millis(self.statement.align)
+            .ok_or(Error::TimestampOutOfRange)?;
+
+        let to = match self.statement.to {
+            Some(to) => unit.from_millis(to).ok_or(Error::TimestampOutOfRange)?,
+            None => i64::MAX,
+        };
 
         let group_by = &mut GroupBy {
-            iterator: self.into_iterator.into_iter(self.statement.from)?,
+            iterator: self
+                .into_iterator
+                .into_iter(from)?
+                .take_while(|entry| entry.as_ref().map_or(true, |entry| entry.ts <= to)),
             folder: folder,
             current: None,
             iterations: 0,
-            key: { |e: &Entry| round_to(e.ts, granularity) },
+            key: { |e: &Entry| round_to_aligned(e.ts, granularity, align) },
         };
 
         let start_ts = SystemTime::now();
 
-        let rows = group_by
-            .map(|e| e.map(|e| e.into()))
-            .take(self.statement.limit)
-            .collect::<Result<Vec<Row>, Error>>()?;
+        let rows: Box<dyn Iterator<Item = GroupByItem>> = match self.statement.fill {
+            Fill::None => Box::new(group_by.by_ref()),
+            Fill::Previous => Box::new(FillPrevious::new(group_by.by_ref(), granularity)),
+        };
+
+        for row in rows.take(self.statement.limit) {
+            let (ts, mut values) = row?;
+            let count = match self.with_count {
+                true => values.pop().map(|count| count.value() as u64),
+                false => None,
+            };
+            let ts_nanos = unit.convert(ts, TimestampUnit::Nanos).filter(|nanos| nanos % 1_000_000 != 0);
+            match unit.to_millis(ts) {
+                Some(ts) => f(Row { ts, ts_nanos, values, count })?,
+                None => return Err(Error::TimestampOutOfRange),
+            }
+        }
 
         log::debug!(
             "Scanned {} entries in {}ms",
@@ -82,7 +189,7 @@ where
             start_ts.elapsed().unwrap().as_millis()
         );
 
-        Ok(rows)
+        Ok(())
     }
 }
 
@@ -2,6 +2,15 @@ pub fn round_to(ts: i64, to: i64) -> i64 {
     ts.div_euclid(to).checked_mul(to).unwrap_or(ts)
 }
 
+/// Rounds down to `offset` plus a multiple of `to`.
+pub fn round_to_aligned(ts: i64, to: i64, offset: i64) -> i64 {
+    let offset = offset.rem_euclid(to);
+    match ts.checked_sub(offset) {
+        Some(shifted) => round_to(shifted, to) + offset,
+        None => ts,
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -24,6 +33,17 @@ mod test {
     #[test]
     fn test_overflow() {
         assert_eq!(i64::MIN, round_to(i64::MIN, 10));
+        assert_eq!(i64::MIN, round_to_aligned(i64::MIN, 10, 3));
+    }
+
+    #[test]
+    fn test_round_to_aligned() {
+        assert_eq!(3, round_to_aligned(12, 10, 3));
+        assert_eq!(13, round_to_aligned(13, 10, 3));
+        assert_eq!(-7, round_to_aligned(2, 10, 3));
+        assert_eq!(3, round_to_aligned(12, 10, 23));
+        assert_eq!(3, round_to_aligned(12, 10, -7));
+        assert_eq!(10, round_to_aligned(12, 10, 0));
     }
 
     #[test]
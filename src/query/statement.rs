@@ -1,9 +1,24 @@
 use super::aggregation::Aggregator;
+use super::fill::Fill;
+use serde_derive::{Deserialize, Serialize};
 
-#[derive(Debug, PartialEq, Eq)]
+/// The aggregators and the fill are serialized as in the query, e.g. `"mean"`, `"previous"`.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct Statement {
     pub aggregators: Vec<Aggregator>,
+    /// Alias of each aggregator, the aggregator name if the alias is not given.
+    pub aliases: Vec<String>,
+    /// Width of the groups in millis, rounded down if `group_by_nanos` is set.
     pub group_by: u64,
+    /// Width of the groups in nanos if it is not a whole number of millis, e.g. `100us`.
+    #[serde(default)]
+    pub group_by_nanos: Option<u64>,
     pub limit: usize,
     pub from: i64,
+    pub fill: Fill,
+    /// The groups start at `align` millis after the multiples of `group_by`.
+    pub align: i64,
+    /// The entries after `to` millis are not queried.
+    #[serde(default)]
+    pub to: Option<i64>,
 }
\ No newline at end of file
@@ -1,8 +1,11 @@
-use super::aggregation::Aggregator;
+use super::aggregation::{Aggregator, DEFAULT_HISTOGRAM_BASE};
+use super::fill::Fill;
 use super::statement::Statement;
-use chrono::{TimeZone, Utc};
+use chrono::{NaiveDateTime, Utc};
+use serde::{de, Deserializer, Serializer};
 use serde_derive::{Deserialize, Serialize};
 use std::convert::TryFrom;
+use std::fmt;
 use std::str::FromStr;
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -11,12 +14,20 @@ pub struct StatementExpr {
     pub group_by: String,
     pub aggregators: String,
     pub limit: String,
+    #[serde(default)]
+    pub fill: Option<String>,
+    /// Offset of the groups in millis, e.g. `21600000` for the days starting at 06:00.
+    #[serde(default)]
+    pub align: Option<String>,
+    /// The entries after `to` are not queried, same format as `from`.
+    #[serde(default)]
+    pub to: Option<String>,
 }
 
 fn parse_date_time(s: &str, format: &str, s_suffix: &str) -> Result<i64, ()> {
-    Utc.datetime_from_str((s.to_owned() + s_suffix).as_ref(), format)
+    NaiveDateTime::parse_from_str((s.to_owned() + s_suffix).as_ref(), format)
         .map_err(|_| ())
-        .map(|dt| dt.timestamp_millis())
+        .map(|dt| dt.and_utc().timestamp_millis())
 }
 
 fn parse_millis(s: &str) -> Result<i64, ()> {
@@ -36,26 +47,64 @@ impl FromStr for FromTimestamp {
     }
 }
 
+/// Parses `now` or `now-{N}{unit}` with the unit `d`, `h`, `m` or `s`, relative to `now` millis.
+fn parse_relative(s: &str, now: i64) -> Result<i64, ()> {
+    let offset = match s.strip_prefix("now") {
+        Some("") => return Ok(now),
+        Some(offset) => offset.strip_prefix('-').ok_or(())?,
+        None => return Err(()),
+    };
+    let (amount, unit) = [("d", 24 * 60 * 60 * 1000), ("h", 60 * 60 * 1000), ("m", 60 * 1000), ("s", 1000)]
+        .iter()
+        .find_map(|(suffix, unit)| offset.strip_suffix(suffix).map(|amount| (amount, *unit)))
+        .ok_or(())?;
+    let amount = amount.parse::<u32>().map_err(|_| ())?;
+    now.checked_sub(amount as i64 * unit).ok_or(())
+}
+
+fn parse_timestamp(s: &str, now: i64) -> Result<i64, ()> {
+    parse_relative(s, now).or_else(|_| s.parse().map(|FromTimestamp(ts)| ts))
+}
+
 #[test]
 fn test_timestamp_from_str() {
     assert_eq!(FromTimestamp(1234), "1234".parse().unwrap());
 
     println!(
         "{:?}",
-        Utc.datetime_from_str("2020-07-16 10:00", "%F %H:%M")
+        NaiveDateTime::parse_from_str("2020-07-16 10:00", "%F %H:%M")
     );
 }
 
-struct GroupByMillis(u64);
+const NANOS_PER_MILLI: u64 = 1000 * 1000;
+
+/// Width of the groups in nanos: `day`, `hour`, `minute` or `{N}{unit}` with the
+/// unit `ns`, `us`, `ms`, `s`, `m`, `h` or `d`, e.g. `100us`.
+struct GroupByNanos(u64);
 
-impl FromStr for GroupByMillis {
+impl FromStr for GroupByNanos {
     type Err = ();
 
-    fn from_str(s: &str) -> Result<GroupByMillis, Self::Err> {
-        match s {
-            "day" => Ok(GroupByMillis(24 * 60 * 60 * 1000)),
-            "hour" => Ok(GroupByMillis(60 * 60 * 1000)),
-            "minute" => Ok(GroupByMillis(60 * 1000)),
+    fn from_str(s: &str) -> Result<GroupByNanos, Self::Err> {
+        let (amount, unit) = match s {
+            "day" => ("1", 24 * 60 * 60 * 1000 * NANOS_PER_MILLI),
+            "hour" => ("1", 60 * 60 * 1000 * NANOS_PER_MILLI),
+            "minute" => ("1", 60 * 1000 * NANOS_PER_MILLI),
+            _ => [
+                ("ns", 1),
+                ("us", 1000),
+                ("ms", NANOS_PER_MILLI),
+                ("s", 1000 * NANOS_PER_MILLI),
+                ("m", 60 * 1000 * NANOS_PER_MILLI),
+                ("h", 60 * 60 * 1000 * NANOS_PER_MILLI),
+                ("d", 24 * 60 * 60 * 1000 * NANOS_PER_MILLI),
+            ]
+            .iter()
+            .find_map(|(suffix, unit)| s.strip_suffix(suffix).map(|amount| (amount, *unit)))
+            .ok_or(())?,
+        };
+        match amount.parse::<u64>() {
+            Ok(amount) if amount > 0 => amount.checked_mul(unit).map(GroupByNanos).ok_or(()),
             _ => Err(()),
         }
     }
@@ -69,32 +118,142 @@ impl FromStr for Aggregator {
             "mean" => Ok(Aggregator::Mean),
             "min" => Ok(Aggregator::Min),
             "max" => Ok(Aggregator::Max),
+            "last" => Ok(Aggregator::Last),
+            "count" => Ok(Aggregator::Count),
+            "hist" => Ok(Aggregator::Histogram { base: DEFAULT_HISTOGRAM_BASE }),
+            s => match s.strip_prefix("hist:").map(|base| base.parse::<f64>()) {
+                Some(Ok(base)) if base.is_finite() && base > 1.0 => Ok(Aggregator::Histogram { base }),
+                _ => Err(()),
+            },
+        }
+    }
+}
+
+impl fmt::Display for Aggregator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Aggregator::Mean => write!(f, "mean"),
+            Aggregator::Min => write!(f, "min"),
+            Aggregator::Max => write!(f, "max"),
+            Aggregator::Last => write!(f, "last"),
+            Aggregator::Count => write!(f, "count"),
+            Aggregator::Histogram { base } if *base == DEFAULT_HISTOGRAM_BASE => write!(f, "hist"),
+            Aggregator::Histogram { base } => write!(f, "hist:{}", base),
+        }
+    }
+}
+
+impl serde::Serialize for Aggregator {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Aggregator {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Aggregator, D::Error> {
+        let s = <String as serde::Deserialize>::deserialize(deserializer)?;
+        s.parse()
+            .map_err(|_| de::Error::custom(format!("unknown aggregator: {}", s)))
+    }
+}
+
+impl FromStr for Fill {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Fill, Self::Err> {
+        match s {
+            "none" => Ok(Fill::None),
+            "previous" => Ok(Fill::Previous),
             _ => Err(()),
         }
     }
 }
 
-impl TryFrom<StatementExpr> for Statement {
-    type Error = ();
-    fn try_from(source: StatementExpr) -> Result<Statement, Self::Error> {
-        let FromTimestamp(from) = source.from.parse()?;
-        let GroupByMillis(group_by) = source.group_by.parse()?;
-        let aggregators = source
+impl fmt::Display for Fill {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Fill::None => write!(f, "none"),
+            Fill::Previous => write!(f, "previous"),
+        }
+    }
+}
+
+impl serde::Serialize for Fill {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Fill {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Fill, D::Error> {
+        let s = <String as serde::Deserialize>::deserialize(deserializer)?;
+        s.parse()
+            .map_err(|_| de::Error::custom(format!("unknown fill: {}", s)))
+    }
+}
+
+/// Parses `alias:aggregator` or `aggregator`, in the latter case the
+/// aggregator name is used as an alias. The aggregator could have a
+/// parameter, e.g. `hist:10` or `latency:hist:10`.
+fn parse_aggregator(s: &str) -> Result<(String, Aggregator), ()> {
+    if let Ok(aggregator) = s.parse() {
+        return Ok((s.to_owned(), aggregator));
+    }
+    match s.split_once(':') {
+        Some(("", _)) => Err(()),
+        Some((alias, aggregator)) => Ok((alias.to_owned(), aggregator.parse()?)),
+        None => Ok((s.to_owned(), s.parse()?)),
+    }
+}
+
+impl StatementExpr {
+    /// Parses the statement, the relative timestamps (e.g. `now-7d`) are resolved against `now` millis.
+    #[allow(clippy::result_unit_err)]
+    pub fn parse_at(self, now: i64) -> Result<Statement, ()> {
+        let from = parse_timestamp(&self.from, now)?;
+        let to = match self.to {
+            Some(to) => Some(parse_timestamp(&to, now)?),
+            None => None,
+        };
+        let GroupByNanos(group_by_nanos) = self.group_by.parse()?;
+        let (aliases, aggregators) = self
             .aggregators
             .split(',')
-            .map(|s| s.parse())
-            .collect::<Result<Vec<Aggregator>, ()>>()?;
-        let limit = source.limit.parse::<usize>().map_err(|_| ())?;
+            .map(parse_aggregator)
+            .collect::<Result<Vec<(String, Aggregator)>, ()>>()?
+            .into_iter()
+            .unzip();
+        let limit = self.limit.parse::<usize>().map_err(|_| ())?;
+        let fill = match self.fill {
+            Some(fill) => fill.parse()?,
+            None => Fill::None,
+        };
+        let align = match self.align {
+            Some(align) => parse_millis(&align)?,
+            None => 0,
+        };
 
         Ok(Statement {
             from,
-            group_by,
+            group_by: group_by_nanos / NANOS_PER_MILLI,
+            group_by_nanos: Some(group_by_nanos).filter(|nanos| nanos % NANOS_PER_MILLI != 0),
             aggregators,
+            aliases,
             limit,
+            fill,
+            align,
+            to,
         })
     }
 }
 
+impl TryFrom<StatementExpr> for Statement {
+    type Error = ();
+    fn try_from(source: StatementExpr) -> Result<Statement, Self::Error> {
+        source.parse_at(Utc::now().timestamp_millis())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -105,21 +264,147 @@ mod tests {
             group_by: "hour".to_string(),
             aggregators: "mean,min,max,min".to_string(),
             limit: "1000".to_string(),
+            fill: None,
+            align: None,
+            to: None,
         };
 
         assert_eq!(
             Statement {
                 from: 10,
                 group_by: 60 * 60 * 1000,
+                group_by_nanos: None,
                 aggregators: vec![
                     Aggregator::Mean,
                     Aggregator::Min,
                     Aggregator::Max,
                     Aggregator::Min
                 ],
+                aliases: vec!["mean".to_owned(), "min".to_owned(), "max".to_owned(), "min".to_owned()],
                 limit: 1000,
+                fill: Fill::None,
+                align: 0,
+                to: None,
             },
             Statement::try_from(expr).unwrap()
         );
     }
+
+    #[test]
+    fn test_aliases() {
+        let expr = |aggregators: &str| StatementExpr {
+            from: "10".to_string(),
+            group_by: "hour".to_string(),
+            aggregators: aggregators.to_string(),
+            limit: "1000".to_string(),
+            fill: None,
+            align: None,
+            to: None,
+        };
+
+        let statement = Statement::try_from(expr("avg:mean,min,high:max")).unwrap();
+        assert_eq!(vec![Aggregator::Mean, Aggregator::Min, Aggregator::Max], statement.aggregators);
+        assert_eq!(vec!["avg".to_owned(), "min".to_owned(), "high".to_owned()], statement.aliases);
+
+        assert!(Statement::try_from(expr(":mean")).is_err());
+        assert!(Statement::try_from(expr("avg:median")).is_err());
+
+        let statement = Statement::try_from(expr("hist,hist:10,latency:hist:1.5")).unwrap();
+        assert_eq!(
+            vec![
+                Aggregator::Histogram { base: 2.0 },
+                Aggregator::Histogram { base: 10.0 },
+                Aggregator::Histogram { base: 1.5 },
+            ],
+            statement.aggregators
+        );
+        assert_eq!(vec!["hist".to_owned(), "hist:10".to_owned(), "latency".to_owned()], statement.aliases);
+
+        assert!(Statement::try_from(expr("hist:1")).is_err());
+        assert!(Statement::try_from(expr("hist:x")).is_err());
+    }
+
+    #[test]
+    fn test_group_by() {
+        let parse = |group_by: &str| {
+            Statement::try_from(StatementExpr {
+                from: "10".to_string(),
+                group_by: group_by.to_string(),
+                aggregators: "mean".to_string(),
+                limit: "1000".to_string(),
+                fill: None,
+                align: None,
+                to: None,
+            })
+            .map(|statement| (statement.group_by, statement.group_by_nanos))
+        };
+
+        assert_eq!(Ok((60 * 1000, None)), parse("minute"));
+        assert_eq!(Ok((15 * 60 * 1000, None)), parse("15m"));
+        assert_eq!(Ok((2 * 24 * 60 * 60 * 1000, None)), parse("2d"));
+        assert_eq!(Ok((100, None)), parse("100ms"));
+        assert_eq!(Ok((1, None)), parse("1000us"));
+        assert_eq!(Ok((0, Some(100_000))), parse("100us"));
+        assert_eq!(Ok((0, Some(500))), parse("500ns"));
+        assert_eq!(Ok((1, Some(1_500_000))), parse("1500us"));
+
+        for invalid in ["0s", "-1s", "1w", "s", "1.5s", "milli", "99999999999999d"] {
+            assert!(parse(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_relative_timestamps() {
+        let at = |s: &str| parse_date_time(s, "%F %H:%M", "").unwrap();
+        let now = at("2020-07-16 10:00");
+        let expr = |from: &str, to: Option<&str>| StatementExpr {
+            from: from.to_string(),
+            group_by: "hour".to_string(),
+            aggregators: "mean".to_string(),
+            limit: "1000".to_string(),
+            fill: None,
+            align: None,
+            to: to.map(|to| to.to_string()),
+        };
+
+        let statement = expr("now-7d", Some("now")).parse_at(now).unwrap();
+        assert_eq!((at("2020-07-09 10:00"), Some(now)), (statement.from, statement.to));
+
+        let statement = expr("now-24h", None).parse_at(now).unwrap();
+        assert_eq!((at("2020-07-15 10:00"), None), (statement.from, statement.to));
+
+        let statement = expr("now-30m", Some("2020-07-16")).parse_at(now).unwrap();
+        assert_eq!((at("2020-07-16 09:30"), Some(at("2020-07-16 00:00"))), (statement.from, statement.to));
+
+        assert_eq!(now, expr("now", None).parse_at(now).unwrap().from);
+        assert_eq!(10, expr("10", None).parse_at(now).unwrap().from);
+
+        for invalid in ["now-", "now-7", "now-7w", "now+7d", "now--7d", "now-d"] {
+            assert!(expr(invalid, None).parse_at(now).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_serde() {
+        let expr = StatementExpr {
+            from: "2020-07-16".to_string(),
+            group_by: "day".to_string(),
+            aggregators: "avg:mean,max,last".to_string(),
+            limit: "10".to_string(),
+            fill: Some("previous".to_string()),
+            align: Some("3600000".to_string()),
+            to: None,
+        };
+        let statement = Statement::try_from(expr).unwrap();
+
+        let json = serde_json::to_value(&statement).unwrap();
+        assert_eq!(serde_json::json!(["mean", "max", "last"]), json["aggregators"]);
+        assert_eq!(serde_json::json!("previous"), json["fill"]);
+
+        assert_eq!(statement, serde_json::from_value::<Statement>(json).unwrap());
+
+        let mut json = serde_json::to_value(&statement).unwrap();
+        json["aggregators"] = serde_json::json!(["median"]);
+        assert!(serde_json::from_value::<Statement>(json).is_err());
+    }
 }
@@ -0,0 +1,122 @@
+use crate::query::{QueryBuilder, StatementExpr};
+use crate::storage::SeriesTable;
+use serde::ser::{Serialize, SerializeMap, Serializer};
+use serde_derive::Deserialize;
+use std::sync::Arc;
+use warp::reject::Rejection;
+use warp::Filter;
+
+#[derive(Deserialize)]
+pub struct AggParams {
+    pub from: String,
+    /// `now` by default.
+    pub to: Option<String>,
+    pub aggregators: String,
+}
+
+/// Values keyed by the aggregator alias, `null` if there are no entries in
+/// the range or the value is not finite.
+pub struct JsonAgg(Vec<(String, Option<f64>)>);
+
+impl Serialize for JsonAgg {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (alias, value) in &self.0 {
+            map.serialize_entry(alias, &value.filter(|value| value.is_finite()))?;
+        }
+        map.end()
+    }
+}
+
+async fn agg(name: String, params: AggParams, series_table: Arc<SeriesTable>) -> Result<warp::reply::Json, Rejection> {
+    let reader = series_table
+        .reader(&name)
+        .ok_or_else(|| super::error::not_found(&name))?;
+
+    let statement_expr = StatementExpr {
+        from: params.from,
+        group_by: "1ms".to_owned(),
+        aggregators: params.aggregators,
+        limit: "1".to_owned(),
+        fill: None,
+        align: None,
+        to: Some(params.to.unwrap_or_else(|| "now".to_owned())),
+    };
+    let mut statement = super::query::parse_statement(statement_expr, 1, &series_table)?;
+    let (from, to) = (statement.from, statement.to.unwrap_or(i64::MAX));
+    if from > to {
+        return Err(super::error::bad_request("from should not be after to"));
+    }
+
+    // a single group starting at `from` covers the whole range, the width is a whole
+    // number of seconds, so it is not rounded down for the series in seconds
+    statement.group_by = ((to as i128 - from as i128) / 1000 + 1) as u64 * 1000;
+    statement.align = from;
+
+    let aliases = statement.aliases.clone();
+    let rows = reader.query(statement).rows_async().await.map_err(super::error::internal)?;
+
+    let values = match rows.into_iter().next() {
+        Some(row) => row.values.iter().map(|agg| Some(agg.value())).collect(),
+        None => vec![None; aliases.len()],
+    };
+
+    Ok(warp::reply::json(&JsonAgg(aliases.into_iter().zip(values).collect())))
+}
+
+/// `GET /series/{name}/agg`, aggregates all entries of `[from, to]` into a single value per aggregator.
+pub fn filter(series_table: Arc<SeriesTable>) -> warp::filters::BoxedFilter<(impl warp::Reply,)> {
+    warp::path!("series" / String / "agg")
+        .and(warp::get())
+        .and(warp::query::<AggParams>())
+        .and(super::with_series_table(series_table.clone()))
+        .and_then(self::agg)
+        .recover(super::error::handle)
+        .boxed()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::storage::error::Error;
+    use crate::storage::{series_table, Entry};
+    use serde_json::{json, Value};
+    use warp::http::StatusCode;
+
+    #[tokio::test]
+    async fn test_agg() -> Result<(), Error> {
+        let series_table = series_table::test::create()?;
+        series_table.create("t")?;
+        series_table.writer("t").unwrap().append(&vec![
+            Entry { ts: 1000, value: 1.0 },
+            Entry { ts: 59000, value: 3.0 },
+            Entry { ts: 61000, value: 5.0 },
+            Entry { ts: 200000, value: 100.0 },
+        ])?;
+
+        let filter = super::filter(series_table.series_table.clone());
+        let agg = |path: &'static str| warp::test::request().method("GET").path(path).reply(&filter);
+
+        // the range crosses the minute boundary, but there is a single group
+        let resp = agg("/series/t/agg?from=30000&to=90000&aggregators=mean,max:max,count").await;
+        assert_eq!(StatusCode::OK, resp.status());
+        assert_eq!(
+            json!({"mean": 4.0, "max": 5.0, "count": 2.0}),
+            serde_json::from_slice::<Value>(resp.body()).unwrap()
+        );
+
+        let resp = agg("/series/t/agg?from=100000&to=110000&aggregators=mean").await;
+        assert_eq!(StatusCode::OK, resp.status());
+        assert_eq!(json!({"mean": null}), serde_json::from_slice::<Value>(resp.body()).unwrap());
+
+        let resp = agg("/series/t/agg?from=0&aggregators=min").await;
+        assert_eq!(json!({"min": 1.0}), serde_json::from_slice::<Value>(resp.body()).unwrap());
+
+        let resp = agg("/series/t/agg?from=2000&to=1000&aggregators=mean").await;
+        assert_eq!(StatusCode::BAD_REQUEST, resp.status());
+
+        let resp = agg("/series/unknown/agg?from=0&aggregators=mean").await;
+        assert_eq!(StatusCode::NOT_FOUND, resp.status());
+
+        Ok(())
+    }
+}
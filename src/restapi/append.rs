@@ -1,33 +1,83 @@
-use crate::storage::{Entry, SeriesTable};
-use serde_derive::Deserialize;
+use crate::storage::{error::Error, Entry, SeriesTable};
+use serde_derive::{Deserialize, Serialize};
 use std::sync::Arc;
-use warp::http::StatusCode;
 use warp::reject::Rejection;
 use warp::Filter;
 
 #[derive(Deserialize)]
 pub struct JsonEntries {
     pub entries: Vec<Entry>,
+    pub batch_id: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct AppendParams {
+    /// Creates the series with the default config if it doesn't exist.
+    #[serde(default)]
+    pub create: bool,
+}
+
+#[derive(Serialize)]
+pub struct JsonAppendResult {
+    pub written: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub highest_ts: Option<i64>,
 }
 
 async fn append(
     name: String,
+    params: AppendParams,
     entries: JsonEntries,
     series_table: Arc<SeriesTable>,
-) -> Result<StatusCode, Rejection> {
-    let writer = series_table
-        .writer(&name)
-        .ok_or_else(|| super::error::not_found(&name))?;
-    writer
-        .append_async(entries.entries)
-        .await
-        .map(|_| StatusCode::OK)
-        .map_err(|err| super::error::internal(err))
+) -> Result<warp::reply::Json, Rejection> {
+    let writer = match params.create {
+        // the series created concurrently is kept as is
+        true => series_table.create_and_writer(&name).map_err(|e| match e {
+            Error::InvalidSeriesName => super::error::bad_request(format!("invalid series name '{}'", name)),
+            e => super::error::internal(e),
+        })?,
+        false => series_table
+            .writer(&name)
+            .ok_or_else(|| super::error::not_found(&name))?,
+    };
+
+    let empty = entries.entries.is_empty();
+
+    let written = match entries.batch_id {
+        Some(batch_id) => writer.append_idempotent_async(batch_id, entries.entries).await,
+        None => writer.append_async(entries.entries).await.map(Some),
+    }
+    .map_err(|err| match err {
+        Error::DuplicateTimestamp => super::error::bad_request("duplicate timestamp"),
+        err => super::error::internal(err),
+    })?;
+
+    let result = match written {
+        Some(written) if written > 0 => JsonAppendResult {
+            written,
+            reason: None,
+            highest_ts: None,
+        },
+        written => JsonAppendResult {
+            written: 0,
+            reason: Some(match written {
+                None => "duplicate_batch",
+                _ if empty => "empty",
+                _ => "all_stale",
+            }),
+            highest_ts: series_table.reader(&name).map(|reader| reader.commit().highest_ts),
+        },
+    };
+
+    Ok(warp::reply::json(&result))
 }
 
 pub fn filter(series_table: Arc<SeriesTable>) -> warp::filters::BoxedFilter<(impl warp::Reply,)> {
     warp::path!("series" / String)
         .and(warp::post())
+        .and(warp::query::<AppendParams>())
         .and(warp::body::json())
         .and(super::with_series_table(series_table.clone()))
         .and_then(self::append)
@@ -39,8 +89,8 @@ pub fn filter(series_table: Arc<SeriesTable>) -> warp::filters::BoxedFilter<(imp
 mod test {
     use super::*;
     use crate::failpoints::Failpoints;
-    use crate::storage::error::Error;
     use crate::storage::series_table;
+    use crate::storage::{Compression, DuplicatePolicy, SeriesConfig};
     use warp::http::StatusCode;
 
     #[tokio::test]
@@ -108,4 +158,218 @@ mod test {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_append_create() -> Result<(), Error> {
+        let series_table = series_table::test::create()?;
+        let filter = super::filter(series_table.series_table.clone());
+        let append = |path: &str| {
+            warp::test::request()
+                .method("POST")
+                .path(path)
+                .body("{\"entries\": [{\"ts\": 1, \"value\": 1.0}]}")
+                .reply(&filter)
+        };
+
+        assert_eq!(StatusCode::NOT_FOUND, append("/series/t?create=false").await.status());
+        assert!(series_table.reader("t").is_none());
+
+        assert_eq!(StatusCode::OK, append("/series/t?create=true").await.status());
+        assert_eq!(1, series_table.reader("t").unwrap().iterator(0)?.count());
+
+        // the existing series is appended
+        let resp = append("/series/t?create=true").await;
+        assert_eq!(StatusCode::OK, resp.status());
+        assert_eq!("{\"written\":1}", std::str::from_utf8(resp.body()).unwrap());
+
+        assert_eq!(StatusCode::BAD_REQUEST, append("/series/..?create=true").await.status());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_append_series_compression() -> Result<(), Error> {
+        let series_table = series_table::test::create()?;
+        series_table.create_with_compression("t", Compression::Deflate)?;
+        series_table.create("d")?;
+
+        for name in ["t", "d"] {
+            for batch in 0..2 {
+                let resp = warp::test::request()
+                    .method("POST")
+                    .path(&format!("/series/{}", name))
+                    .body(format!("{{\"entries\": [{{\"ts\": {}, \"value\": 1.0}}]}}", batch))
+                    .reply(&super::filter(series_table.series_table.clone()))
+                    .await;
+                assert_eq!(StatusCode::OK, resp.status());
+            }
+        }
+
+        let reader = series_table.reader("t").unwrap();
+        assert_eq!(Compression::Deflate, reader.config().compression);
+        assert_eq!(vec![Compression::Deflate; 2], reader.block_compressions()?);
+        assert_eq!(2, reader.iterator(0)?.count());
+
+        assert_eq!(vec![Compression::Delta; 2], series_table.reader("d").unwrap().block_compressions()?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_append_storage_errors() -> Result<(), Error> {
+        let fp = Arc::new(Failpoints::create());
+        let series_table = series_table::test::create_with_failpoints(fp.clone())?;
+        series_table.create("t")?;
+
+        let filter = super::filter(series_table.series_table.clone());
+        let append = || {
+            warp::test::request()
+                .method("POST")
+                .path("/series/t")
+                .body("{\"entries\": [{\"ts\": 1, \"value\": 1.0}]}")
+                .reply(&filter)
+        };
+
+        fp.on("series_writer::data_file_too_big");
+
+        let resp = append().await;
+        assert_eq!(StatusCode::INSUFFICIENT_STORAGE, resp.status());
+        assert!(std::str::from_utf8(resp.body()).unwrap().contains("\"error\":\"storage_full\""));
+
+        fp.off("series_writer::data_file_too_big");
+
+        let resp = append().await;
+        assert_eq!(StatusCode::OK, resp.status());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_append_duplicate_rejected() -> Result<(), Error> {
+        let series_table = series_table::test::create()?;
+        series_table.create_with_config(
+            "t",
+            SeriesConfig {
+                duplicates: DuplicatePolicy::Reject,
+                ..SeriesConfig::default()
+            },
+        )?;
+
+        let resp = warp::test::request()
+            .method("POST")
+            .path("/series/t")
+            .body("{\"entries\": [{\"ts\": 1, \"value\": 1.0}, {\"ts\": 1, \"value\": 2.0}]}")
+            .reply(&super::filter(series_table.series_table.clone()))
+            .await;
+
+        assert_eq!(StatusCode::BAD_REQUEST, resp.status());
+        assert_eq!(0, series_table.reader("t").unwrap().iterator(0)?.count());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_append_rate_limited() -> Result<(), Error> {
+        let series_table = series_table::test::create()?;
+        series_table.create_with_config(
+            "t",
+            SeriesConfig {
+                rate_limit: Some(100),
+                ..SeriesConfig::default()
+            },
+        )?;
+
+        let filter = super::filter(series_table.series_table.clone());
+        let append = |from: i64, count: i64| {
+            let entries = (from..from + count)
+                .map(|ts| format!("{{\"ts\": {}, \"value\": 1.0}}", ts))
+                .collect::<Vec<String>>()
+                .join(", ");
+            warp::test::request()
+                .method("POST")
+                .path("/series/t")
+                .body(format!("{{\"entries\": [{}]}}", entries))
+                .reply(&filter)
+        };
+
+        assert_eq!(StatusCode::OK, append(0, 60).await.status());
+        // exhausts the burst, the bucket goes into debt
+        assert_eq!(StatusCode::OK, append(60, 60).await.status());
+
+        let resp = append(120, 10).await;
+        assert_eq!(StatusCode::TOO_MANY_REQUESTS, resp.status());
+        assert!(std::str::from_utf8(resp.body()).unwrap().contains("\"error\":\"rate_limited\""));
+
+        assert_eq!(120, series_table.reader("t").unwrap().iterator(0)?.count());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_append_batch_id() -> Result<(), Error> {
+        let series_table = series_table::test::create()?;
+        series_table.create("t")?;
+
+        let json = |batch_id: &str, ts: i64| {
+            format!(
+                "{{\"batch_id\": \"{}\", \"entries\": [{{\"ts\": {}, \"value\": 1.0}}]}}",
+                batch_id, ts
+            )
+        };
+
+        for (batch_id, ts) in &[("b1", 1), ("b1", 1), ("b2", 1), ("b2", 1)] {
+            let resp = warp::test::request()
+                .method("POST")
+                .path("/series/t")
+                .body(json(batch_id, *ts))
+                .reply(&super::filter(series_table.series_table.clone()))
+                .await;
+
+            assert_eq!(StatusCode::OK, resp.status());
+        }
+
+        let entries = series_table
+            .reader("t")
+            .unwrap()
+            .iterator(0)?
+            .collect::<Result<Vec<Entry>, Error>>()?;
+
+        assert_eq!(vec![Entry { ts: 1, value: 1.0 }, Entry { ts: 1, value: 1.0 }], entries);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_append_all_stale() -> Result<(), Error> {
+        let series_table = series_table::test::create()?;
+        series_table.create("t")?;
+
+        let filter = super::filter(series_table.series_table.clone());
+        let append = |json: &'static str| {
+            warp::test::request()
+                .method("POST")
+                .path("/series/t")
+                .body(json)
+                .reply(&filter)
+        };
+
+        let resp = append("{\"entries\": [{\"ts\": 10, \"value\": 1.0}, {\"ts\": 11, \"value\": 1.0}]}").await;
+        assert_eq!(StatusCode::OK, resp.status());
+        assert_eq!("{\"written\":2}", std::str::from_utf8(resp.body()).unwrap());
+
+        let resp = append("{\"entries\": [{\"ts\": 5, \"value\": 1.0}, {\"ts\": 7, \"value\": 1.0}]}").await;
+        assert_eq!(StatusCode::OK, resp.status());
+        assert_eq!(
+            "{\"written\":0,\"reason\":\"all_stale\",\"highest_ts\":11}",
+            std::str::from_utf8(resp.body()).unwrap()
+        );
+
+        let resp = append("{\"entries\": []}").await;
+        assert_eq!(
+            "{\"written\":0,\"reason\":\"empty\",\"highest_ts\":11}",
+            std::str::from_utf8(resp.body()).unwrap()
+        );
+
+        Ok(())
+    }
 }
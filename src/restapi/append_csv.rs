@@ -0,0 +1,90 @@
+use super::append::JsonAppendResult;
+use crate::storage::SeriesTable;
+use bytes::buf::Buf;
+use futures::Stream;
+use std::sync::Arc;
+use warp::reject::Rejection;
+use warp::Filter;
+
+async fn append_csv<S, B>(
+    name: String,
+    series_table: Arc<SeriesTable>,
+    body: S,
+) -> Result<warp::reply::Json, Rejection>
+where
+    S: Stream<Item = Result<B, warp::Error>> + Send + 'static + Unpin,
+    B: Buf + Send,
+{
+    let writer = series_table
+        .writer(&name)
+        .ok_or_else(|| super::error::not_found(&name))?;
+
+    // the body is validated before the append, so the malformed body appends nothing
+    let entries = super::restore::read_entries(body).await?;
+    let written = writer.append_async(entries).await?;
+
+    Ok(warp::reply::json(&JsonAppendResult {
+        written,
+        reason: None,
+        highest_ts: None,
+    }))
+}
+
+pub fn filter(series_table: Arc<SeriesTable>) -> warp::filters::BoxedFilter<(impl warp::Reply,)> {
+    warp::path!("series" / String / "append-csv")
+        .and(warp::post())
+        .and(super::with_series_table(series_table.clone()))
+        .and(warp::body::stream())
+        .and_then(self::append_csv)
+        .recover(super::error::handle)
+        .boxed()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::storage::error::Error;
+    use crate::storage::{series_table, Entry};
+    use warp::http::StatusCode;
+
+    #[tokio::test]
+    async fn test_append_csv() -> Result<(), Error> {
+        let series_table = series_table::test::create()?;
+        series_table.create("t")?;
+        series_table.writer("t").unwrap().append(&vec![Entry { ts: 1, value: 1.0 }])?;
+
+        let filter = super::filter(series_table.series_table.clone());
+        let append = |path: &'static str, body: &'static str| {
+            warp::test::request()
+                .method("POST")
+                .path(path)
+                .header("content-type", "text/csv")
+                .body(body)
+                .reply(&filter)
+        };
+
+        let resp = append("/series/t/append-csv", "2; 12.3\n3; 13.4").await;
+        assert_eq!(StatusCode::OK, resp.status());
+        assert_eq!("{\"written\":2}", std::str::from_utf8(resp.body()).unwrap());
+
+        assert_eq!(
+            vec![
+                Entry { ts: 1, value: 1.0 },
+                Entry { ts: 2, value: 12.3 },
+                Entry { ts: 3, value: 13.4 },
+            ],
+            series_table.reader("t").unwrap().iterator(0)?.collect::<Result<Vec<Entry>, Error>>()?
+        );
+
+        assert_eq!(StatusCode::BAD_REQUEST, append("/series/t/append-csv", "4; x\n").await.status());
+
+        // the valid lines before the malformed one are not appended either
+        assert_eq!(
+            StatusCode::BAD_REQUEST,
+            append("/series/t/append-csv", "4; 14.5\n5; 15.6\n6; x\n7; 17.8").await.status()
+        );
+        assert_eq!(3, series_table.reader("t").unwrap().iterator(0)?.count());
+        assert_eq!(StatusCode::NOT_FOUND, append("/series/u/append-csv", "1; 1.0\n").await.status());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,74 @@
+use crate::storage::SeriesTable;
+use std::sync::Arc;
+use warp::reject::Rejection;
+use warp::Filter;
+
+async fn checkpoint(name: String, series_table: Arc<SeriesTable>) -> Result<warp::reply::Json, Rejection> {
+    let reader = series_table
+        .reader(&name)
+        .ok_or_else(|| super::error::not_found(&name))?;
+
+    Ok(warp::reply::json(&*reader.commit()))
+}
+
+pub fn filter(series_table: Arc<SeriesTable>) -> warp::filters::BoxedFilter<(impl warp::Reply,)> {
+    warp::path!("series" / String / "checkpoint")
+        .and(warp::get())
+        .and(super::with_series_table(series_table.clone()))
+        .and_then(self::checkpoint)
+        .recover(super::error::handle)
+        .boxed()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::storage::error::Error;
+    use crate::storage::{series_table, Commit, Entry};
+    use serde_json::Value;
+    use warp::http::StatusCode;
+
+    #[tokio::test]
+    async fn test_checkpoint() -> Result<(), Error> {
+        let series_table = series_table::test::create()?;
+
+        let resp = warp::test::request()
+            .method("GET")
+            .path("/series/t/checkpoint")
+            .reply(&super::filter(series_table.series_table.clone()))
+            .await;
+
+        assert_eq!(StatusCode::NOT_FOUND, resp.status());
+
+        series_table.create("t")?;
+
+        let checkpoint = || async {
+            let resp = warp::test::request()
+                .method("GET")
+                .path("/series/t/checkpoint")
+                .reply(&super::filter(series_table.series_table.clone()))
+                .await;
+
+            assert_eq!(StatusCode::OK, resp.status());
+
+            serde_json::from_slice::<Value>(resp.body()).unwrap()
+        };
+
+        let before = checkpoint().await;
+        assert_eq!(0, before["data_offset"]);
+
+        series_table.writer("t").unwrap().append(&vec![
+            Entry { ts: 1, value: 1.2 },
+            Entry { ts: 2, value: 3.1 },
+        ])?;
+
+        let after = checkpoint().await;
+        let commit: Commit = (*series_table.reader("t").unwrap().commit()).clone();
+
+        assert_eq!(commit.data_offset, after["data_offset"]);
+        assert_eq!(commit.index_offset, after["index_offset"]);
+        assert_eq!(2, after["highest_ts"]);
+        assert!(after["data_offset"].as_u64() > before["data_offset"].as_u64());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,77 @@
+use crate::storage::SeriesTable;
+use std::sync::Arc;
+use warp::reject::Rejection;
+use warp::Filter;
+
+async fn compact(name: String, series_table: Arc<SeriesTable>) -> Result<warp::reply::Json, Rejection> {
+    let report = {
+        let name = name.clone();
+        tokio::task::spawn_blocking(move || series_table.compact(&name))
+            .await
+            .unwrap()
+            .map_err(super::error::internal)?
+    };
+
+    match report {
+        Some(report) => Ok(warp::reply::json(&report)),
+        None => Err(super::error::not_found(&name)),
+    }
+}
+
+pub fn filter(series_table: Arc<SeriesTable>) -> warp::filters::BoxedFilter<(impl warp::Reply,)> {
+    warp::path!("series" / String / "compact")
+        .and(warp::post())
+        .and(super::with_series_table(series_table.clone()))
+        .and_then(self::compact)
+        .recover(super::error::handle)
+        .boxed()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::storage::error::Error;
+    use crate::storage::{series_table, Entry};
+    use warp::http::StatusCode;
+
+    #[tokio::test]
+    async fn test_compact() -> Result<(), Error> {
+        let series_table = series_table::test::create()?;
+        let filter = super::filter(series_table.series_table.clone());
+
+        let resp = warp::test::request()
+            .method("POST")
+            .path("/series/t/compact")
+            .reply(&filter)
+            .await;
+
+        assert_eq!(StatusCode::NOT_FOUND, resp.status());
+
+        series_table.create("t")?;
+        let entries = (0..1000).map(|ts| Entry { ts, value: 1.0 }).collect::<Vec<Entry>>();
+        for entry in &entries {
+            series_table.writer("t").unwrap().append(&vec![entry.clone()])?;
+        }
+
+        let resp = warp::test::request()
+            .method("POST")
+            .path("/series/t/compact")
+            .reply(&filter)
+            .await;
+
+        assert_eq!(StatusCode::OK, resp.status());
+
+        let report: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+        assert!(report["bytes_after"].as_u64().unwrap() < report["bytes_before"].as_u64().unwrap());
+
+        assert_eq!(
+            entries,
+            series_table
+                .reader("t")
+                .unwrap()
+                .iterator(i64::MIN)?
+                .collect::<Result<Vec<Entry>, Error>>()?
+        );
+
+        Ok(())
+    }
+}
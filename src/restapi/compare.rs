@@ -0,0 +1,157 @@
+use crate::query::{Aggregation, QueryBuilder, StatementExpr};
+use crate::storage::SeriesTable;
+use serde_derive::{Deserialize, Serialize};
+use std::sync::Arc;
+use warp::reject::Rejection;
+use warp::Filter;
+
+#[derive(Deserialize)]
+pub struct CompareParams {
+    /// Comma separated `from:to` pairs, same format as `from` of the query, e.g. `now-7d:now,now-14d:now-7d`.
+    pub windows: String,
+    pub group_by: String,
+    pub aggregators: String,
+    pub limit: Option<usize>,
+}
+
+#[derive(Serialize)]
+pub struct JsonWindowRow {
+    /// Number of the group since the start of the window.
+    pub index: i64,
+    pub values: Vec<Aggregation>,
+}
+
+#[derive(Serialize)]
+pub struct JsonWindow {
+    pub from: i64,
+    pub to: i64,
+    pub rows: Vec<JsonWindowRow>,
+}
+
+#[derive(Serialize)]
+pub struct JsonWindows {
+    pub windows: Vec<JsonWindow>,
+}
+
+async fn compare(
+    name: String,
+    params: CompareParams,
+    max_limit: usize,
+    series_table: Arc<SeriesTable>,
+) -> Result<warp::reply::Json, Rejection> {
+    let reader = series_table
+        .reader(&name)
+        .ok_or_else(|| super::error::not_found(&name))?;
+
+    // all windows are resolved against the same time
+    let now = series_table.clock().now_millis();
+
+    let mut windows = Vec::new();
+    for window in params.windows.split(',') {
+        let (from, to) = window
+            .split_once(':')
+            .ok_or_else(|| super::error::bad_request(format!("invalid window '{}', expected from:to", window)))?;
+
+        let statement_expr = StatementExpr {
+            from: from.to_owned(),
+            group_by: params.group_by.clone(),
+            aggregators: params.aggregators.clone(),
+            limit: params.limit.unwrap_or(max_limit).to_string(),
+            fill: None,
+            align: None,
+            to: Some(to.to_owned()),
+        };
+        let mut statement = super::query::parse_statement_at(statement_expr, max_limit, now)?;
+        if statement.group_by_nanos.is_some() {
+            return Err(super::error::bad_request("group_by should be a whole number of millis"));
+        }
+
+        // the groups start at the start of the window, so the windows overlay
+        let (from, to, group_by) = (statement.from, statement.to.unwrap_or(i64::MAX), statement.group_by as i64);
+        statement.align = from;
+
+        let rows = reader.clone().query(statement).rows_async().await.map_err(super::error::internal)?;
+
+        windows.push(JsonWindow {
+            from,
+            to,
+            rows: rows
+                .into_iter()
+                .map(|row| JsonWindowRow {
+                    index: (row.ts - from).div_euclid(group_by),
+                    values: row.values,
+                })
+                .collect(),
+        });
+    }
+
+    Ok(warp::reply::json(&JsonWindows { windows }))
+}
+
+pub fn filter(series_table: Arc<SeriesTable>) -> warp::filters::BoxedFilter<(impl warp::Reply,)> {
+    filter_with_max_limit(series_table, super::query::DEFAULT_MAX_LIMIT)
+}
+
+/// Same as `filter`, the `limit` of each window should not exceed `max_limit`.
+pub fn filter_with_max_limit(
+    series_table: Arc<SeriesTable>,
+    max_limit: usize,
+) -> warp::filters::BoxedFilter<(impl warp::Reply,)> {
+    warp::path!("series" / String / "compare")
+        .and(warp::get())
+        .and(warp::query::<CompareParams>())
+        .and(warp::any().map(move || max_limit))
+        .and(super::with_series_table(series_table.clone()))
+        .and_then(self::compare)
+        .recover(super::error::handle)
+        .boxed()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::storage::error::Error;
+    use crate::storage::{series_table, Entry};
+    use serde_json::{json, Value};
+    use warp::http::StatusCode;
+
+    #[tokio::test]
+    async fn test_compare() -> Result<(), Error> {
+        let series_table = series_table::test::create()?;
+        series_table.create("t")?;
+
+        // an entry every 30 minutes for two days, the value is the number of the entry
+        series_table.writer("t").unwrap().append(
+            &(0..96)
+                .map(|i| Entry { ts: i * 1_800_000, value: i as f64 })
+                .collect::<Vec<Entry>>(),
+        )?;
+
+        let filter = super::filter(series_table.series_table.clone());
+        let compare = |path: &'static str| warp::test::request().method("GET").path(path).reply(&filter);
+
+        // the second window starts at half past, its groups are aligned to its start
+        let resp = compare("/series/t/compare?windows=0:7199999,88200000:95399999&group_by=hour&aggregators=mean").await;
+        assert_eq!(StatusCode::OK, resp.status());
+        assert_eq!(
+            json!({"windows": [
+                {"from": 0, "to": 7199999, "rows": [
+                    {"index": 0, "values": [{"Mean": 0.5}]},
+                    {"index": 1, "values": [{"Mean": 2.5}]},
+                ]},
+                {"from": 88200000, "to": 95399999, "rows": [
+                    {"index": 0, "values": [{"Mean": 49.5}]},
+                    {"index": 1, "values": [{"Mean": 51.5}]},
+                ]},
+            ]}),
+            serde_json::from_slice::<Value>(resp.body()).unwrap()
+        );
+
+        let resp = compare("/series/t/compare?windows=0&group_by=hour&aggregators=mean").await;
+        assert_eq!(StatusCode::BAD_REQUEST, resp.status());
+
+        let resp = compare("/series/unknown/compare?windows=0:1&group_by=hour&aggregators=mean").await;
+        assert_eq!(StatusCode::NOT_FOUND, resp.status());
+
+        Ok(())
+    }
+}
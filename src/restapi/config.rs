@@ -0,0 +1,109 @@
+use crate::storage::{Compression, DuplicatePolicy, SeriesTable};
+use serde_derive::Deserialize;
+use std::sync::Arc;
+use warp::reject::Rejection;
+use warp::Filter;
+
+/// Settings of the appends that could be changed for the existing series, the timestamp
+/// unit and the index density describe the written data and are rejected as unknown fields.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigPatch {
+    pub compression: Option<Compression>,
+    pub duplicates: Option<DuplicatePolicy>,
+    /// Entries per second, `0` removes the limit.
+    pub rate_limit: Option<u32>,
+}
+
+async fn update(
+    name: String,
+    patch: ConfigPatch,
+    series_table: Arc<SeriesTable>,
+) -> Result<warp::reply::Json, Rejection> {
+    let updated = {
+        let name = name.clone();
+        tokio::task::spawn_blocking(move || {
+            series_table.update_config(&name, |config| {
+                if let Some(compression) = patch.compression {
+                    config.compression = compression;
+                }
+                if let Some(duplicates) = patch.duplicates {
+                    config.duplicates = duplicates;
+                }
+                if let Some(rate_limit) = patch.rate_limit {
+                    config.rate_limit = Some(rate_limit).filter(|rate| *rate > 0);
+                }
+            })
+        })
+        .await
+        .unwrap()
+        .map_err(super::error::internal)?
+    };
+
+    match updated {
+        Some(config) => Ok(warp::reply::json(&config)),
+        None => Err(super::error::not_found(&name)),
+    }
+}
+
+pub fn filter(series_table: Arc<SeriesTable>) -> warp::filters::BoxedFilter<(impl warp::Reply,)> {
+    warp::path!("series" / String / "config")
+        .and(warp::patch())
+        .and(warp::body::json::<ConfigPatch>())
+        .and(super::with_series_table(series_table.clone()))
+        .and_then(self::update)
+        .recover(super::error::handle)
+        .boxed()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::storage::error::Error;
+    use crate::storage::{series_table, Compression, Entry};
+    use serde_json::{json, Value};
+    use warp::http::StatusCode;
+
+    #[tokio::test]
+    async fn test_update_config() -> Result<(), Error> {
+        let series_table = series_table::test::create()?;
+        series_table.create("t")?;
+
+        let writer = series_table.writer("t").unwrap();
+        writer.append(&vec![Entry { ts: 1, value: 1.0 }])?;
+
+        let filter = super::filter(series_table.series_table.clone());
+        let update = |path: &'static str, body: &'static str| {
+            warp::test::request().method("PATCH").path(path).body(body).reply(&filter)
+        };
+
+        let resp = update("/series/t/config", "{\"compression\": \"deflate\", \"rate_limit\": 1000}").await;
+        assert_eq!(StatusCode::OK, resp.status());
+
+        let config = serde_json::from_slice::<Value>(resp.body()).unwrap();
+        assert_eq!(json!("deflate"), config["compression"]);
+        assert_eq!(json!(1000), config["rate_limit"]);
+        assert_eq!(json!("ms"), config["timestamp_unit"]);
+
+        // the writer opened before the update appends with the new compression
+        writer.append(&vec![Entry { ts: 2, value: 2.0 }])?;
+
+        let reader = series_table.reader("t").unwrap();
+        assert_eq!(vec![Compression::Delta, Compression::Deflate], reader.block_compressions()?);
+
+        let resp = update("/series/t/config", "{\"rate_limit\": 0}").await;
+        assert_eq!(StatusCode::OK, resp.status());
+        assert_eq!(None, reader.config().rate_limit);
+        assert_eq!(Compression::Deflate, reader.config().compression);
+
+        let resp = update("/series/t/config", "{\"compression\": \"lz4\"}").await;
+        assert_eq!(StatusCode::BAD_REQUEST, resp.status());
+
+        let resp = update("/series/t/config", "{\"timestamp_unit\": \"s\"}").await;
+        assert_eq!(StatusCode::BAD_REQUEST, resp.status());
+
+        let resp = update("/series/unknown/config", "{}").await;
+        assert_eq!(StatusCode::NOT_FOUND, resp.status());
+
+        Ok(())
+    }
+}
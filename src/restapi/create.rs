@@ -1,21 +1,116 @@
-use crate::storage::SeriesTable;
+use crate::storage::{error::Error, Compression, DuplicatePolicy, SeriesConfig, SeriesTable, TimestampUnit};
+use serde_derive::{Deserialize, Serialize};
 use std::sync::Arc;
 use warp::http::StatusCode;
 use warp::reject::Rejection;
 use warp::Filter;
 
-async fn create(name: String, series_table: Arc<SeriesTable>) -> Result<StatusCode, Rejection> {
+#[derive(Deserialize)]
+pub struct CreateParams {
+    pub timestamp_unit: Option<TimestampUnit>,
+    pub index_density: Option<u32>,
+    pub duplicates: Option<DuplicatePolicy>,
+    pub rate_limit: Option<u32>,
+    pub compression: Option<Compression>,
+    pub preallocate_mb: Option<u32>,
+}
+
+#[derive(Deserialize)]
+pub struct JsonNames {
+    pub names: Vec<String>,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CreateStatus {
+    Created,
+    Exists,
+    /// The series exists with a different config.
+    Conflict,
+    Invalid,
+}
+
+#[derive(Serialize)]
+pub struct JsonCreateResult {
+    pub name: String,
+    pub status: CreateStatus,
+}
+
+#[derive(Serialize)]
+pub struct JsonCreateResults {
+    pub results: Vec<JsonCreateResult>,
+}
+
+fn config(params: CreateParams) -> Result<SeriesConfig, Rejection> {
+    if params.index_density == Some(0) {
+        return Err(super::error::bad_request("index_density should be positive"));
+    }
+    if params.rate_limit == Some(0) {
+        return Err(super::error::bad_request("rate_limit should be positive"));
+    }
+    Ok(SeriesConfig {
+        timestamp_unit: params.timestamp_unit.unwrap_or_default(),
+        index_density: params.index_density.unwrap_or(1),
+        duplicates: params.duplicates.unwrap_or_default(),
+        rate_limit: params.rate_limit,
+        compression: params.compression.unwrap_or_default(),
+        preallocate_mb: params.preallocate_mb.filter(|mb| *mb > 0),
+    })
+}
+
+async fn create(
+    name: String,
+    params: CreateParams,
+    series_table: Arc<SeriesTable>,
+) -> Result<StatusCode, Rejection> {
+    let config = config(params)?;
     series_table
-        .create(&name)
+        .create_with_config(&name, config)
         .map(|_| StatusCode::CREATED)
-        .map_err(|e| super::error::internal(e))
+        .map_err(|e| match e {
+            Error::InvalidSeriesName => super::error::bad_request(format!("invalid series name '{}'", name)),
+            e => super::error::internal(e),
+        })
+}
+
+/// Creates the series with the same config, the invalid names are skipped. Fails
+/// on the first storage error, the series created before are kept.
+async fn create_many(
+    params: CreateParams,
+    names: JsonNames,
+    series_table: Arc<SeriesTable>,
+) -> Result<warp::reply::Json, Rejection> {
+    let config = config(params)?;
+    let mut results = Vec::with_capacity(names.names.len());
+    for name in names.names {
+        let status = match series_table.create_with_config(&name, config.clone()) {
+            Ok(true) => CreateStatus::Created,
+            Ok(false) => CreateStatus::Exists,
+            Err(Error::ConfigMismatch) => CreateStatus::Conflict,
+            Err(Error::InvalidSeriesName) => CreateStatus::Invalid,
+            Err(e) => return Err(super::error::internal(e)),
+        };
+        results.push(JsonCreateResult { name, status });
+    }
+    Ok(warp::reply::json(&JsonCreateResults { results }))
 }
 
 pub fn filter(series_table: Arc<SeriesTable>) -> warp::filters::BoxedFilter<(impl warp::Reply,)> {
-    warp::path!("series" / String)
+    let create = warp::path!("series" / String)
         .and(warp::put())
+        .and(warp::query::<CreateParams>())
+        .and(super::with_series_table(series_table.clone()))
+        .and_then(self::create);
+
+    let create_many = warp::path!("series")
+        .and(warp::post())
+        .and(warp::query::<CreateParams>())
+        .and(warp::body::json())
         .and(super::with_series_table(series_table.clone()))
-        .and_then(self::create)
+        .and_then(self::create_many);
+
+    create
+        .or(create_many)
         .recover(super::error::handle)
         .boxed()
 }
@@ -24,7 +119,6 @@ pub fn filter(series_table: Arc<SeriesTable>) -> warp::filters::BoxedFilter<(imp
 mod test {
     use super::*;
     use crate::failpoints::Failpoints;
-    use crate::storage::error::Error;
     use crate::storage::series_table;
 
     #[tokio::test]
@@ -52,6 +146,119 @@ mod test {
 
         fp.off("series_table::create");
 
+        let resp = warp::test::request()
+            .method("PUT")
+            .path("/series/secs?timestamp_unit=s")
+            .reply(&super::filter(series_table.series_table.clone()))
+            .await;
+
+        assert_eq!(StatusCode::CREATED, resp.status());
+        assert_eq!(
+            TimestampUnit::Seconds,
+            series_table.reader("secs").unwrap().config().timestamp_unit
+        );
+
+        let resp = warp::test::request()
+            .method("PUT")
+            .path("/series/weeks?timestamp_unit=w")
+            .reply(&super::filter(series_table.series_table.clone()))
+            .await;
+
+        assert_eq!(StatusCode::BAD_REQUEST, resp.status());
+
+        let resp = warp::test::request()
+            .method("PUT")
+            .path("/series/sparse?index_density=8")
+            .reply(&super::filter(series_table.series_table.clone()))
+            .await;
+
+        assert_eq!(StatusCode::CREATED, resp.status());
+        assert_eq!(8, series_table.reader("sparse").unwrap().config().index_density);
+
+        let resp = warp::test::request()
+            .method("PUT")
+            .path("/series/dense?index_density=0")
+            .reply(&super::filter(series_table.series_table.clone()))
+            .await;
+
+        assert_eq!(StatusCode::BAD_REQUEST, resp.status());
+
+        let resp = warp::test::request()
+            .method("PUT")
+            .path("/series/unique?duplicates=reject")
+            .reply(&super::filter(series_table.series_table.clone()))
+            .await;
+
+        assert_eq!(StatusCode::CREATED, resp.status());
+        assert_eq!(
+            DuplicatePolicy::Reject,
+            series_table.reader("unique").unwrap().config().duplicates
+        );
+
+        let resp = warp::test::request()
+            .method("PUT")
+            .path("/series/a%20b")
+            .reply(&super::filter(series_table.series_table.clone()))
+            .await;
+
+        assert_eq!(StatusCode::BAD_REQUEST, resp.status());
+
+        // the existing series is created with the same config only
+        let paths = [
+            ("/series/secs?timestamp_unit=s", StatusCode::CREATED),
+            ("/series/secs", StatusCode::CONFLICT),
+        ];
+        for (path, status) in paths {
+            let resp = warp::test::request()
+                .method("PUT")
+                .path(path)
+                .reply(&super::filter(series_table.series_table.clone()))
+                .await;
+
+            assert_eq!(status, resp.status());
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_create_many() -> Result<(), Error> {
+        let series_table = series_table::test::create()?;
+        series_table.create("b")?;
+
+        let resp = warp::test::request()
+            .method("POST")
+            .path("/series?duplicates=reject")
+            .body("{\"names\": [\"a\", \"b\", \"a b\", \"c\", \"a\"]}")
+            .reply(&super::filter(series_table.series_table.clone()))
+            .await;
+
+        assert_eq!(StatusCode::OK, resp.status());
+        assert_eq!(
+            concat!(
+                "{\"results\":[",
+                "{\"name\":\"a\",\"status\":\"created\"},",
+                "{\"name\":\"b\",\"status\":\"conflict\"},",
+                "{\"name\":\"a b\",\"status\":\"invalid\"},",
+                "{\"name\":\"c\",\"status\":\"created\"},",
+                "{\"name\":\"a\",\"status\":\"exists\"}",
+                "]}"
+            ),
+            std::str::from_utf8(resp.body()).unwrap()
+        );
+        assert_eq!(DuplicatePolicy::Reject, series_table.reader("c").unwrap().config().duplicates);
+        assert_eq!(DuplicatePolicy::Allow, series_table.reader("b").unwrap().config().duplicates);
+
+        let resp = warp::test::request()
+            .method("POST")
+            .path("/series?index_density=0")
+            .body("{\"names\": [\"d\"]}")
+            .reply(&super::filter(series_table.series_table.clone()))
+            .await;
+
+        assert_eq!(StatusCode::BAD_REQUEST, resp.status());
+        assert!(series_table.reader("d").is_none());
+
         Ok(())
     }
 }
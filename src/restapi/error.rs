@@ -1,6 +1,5 @@
 use crate::storage::error::Error;
 use serde_derive::Serialize;
-use std::convert::Infallible;
 use warp::http::StatusCode;
 use warp::reject::{Reject, Rejection};
 
@@ -54,22 +53,69 @@ pub fn conflict<S: AsRef<str>>(series: S) -> Rejection {
     })
 }
 
+/// Maps the storage error to the response status and the stable error kind,
+/// that could be matched by the clients.
+fn classify(error: &Error) -> (StatusCode, &'static str) {
+    match error {
+        Error::TooManyEntries
+        | Error::TimestampOutOfRange
+        | Error::InvalidSeriesName
+        | Error::DuplicateTimestamp
+        | Error::ArgTooSmall => (StatusCode::BAD_REQUEST, "invalid_input"),
+        Error::DataFileTooBig | Error::IndexFull => {
+            (StatusCode::INSUFFICIENT_STORAGE, "storage_full")
+        }
+        Error::ReadOnly => (StatusCode::FORBIDDEN, "read_only"),
+        Error::Archived => (StatusCode::FORBIDDEN, "archived"),
+        Error::CompactionInProgress
+        | Error::SeriesReplaced
+        | Error::VirtualSeriesExists
+        | Error::ConfigMismatch => (StatusCode::CONFLICT, "conflict"),
+        Error::RateLimited => (StatusCode::TOO_MANY_REQUESTS, "rate_limited"),
+        Error::TooManySeries => (StatusCode::INSUFFICIENT_STORAGE, "too_many_series"),
+        Error::Crc16Mismatch
+        | Error::ChecksumMismatch
+        | Error::UnknownCompression
+        | Error::CorruptBlock
+        | Error::VarIntError
+        | Error::Slice(_)
+        | Error::InvalidOffset
+        | Error::IndexIsNotConsistent
+        | Error::OffsetOutsideTheRange
+        | Error::OffsetIsNotAligned
+        | Error::UnorderedEntries => (StatusCode::INTERNAL_SERVER_ERROR, "corrupted"),
+        Error::Io(_) | Error::Other(_) => (StatusCode::INTERNAL_SERVER_ERROR, "internal"),
+    }
+}
+
+pub fn status_for(error: &Error) -> StatusCode {
+    classify(error).0
+}
+
+fn kind_for(error: &Error) -> &'static str {
+    classify(error).1
+}
+
 #[derive(Serialize)]
 struct ErrorMessage {
     code: u16,
     message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<&'static str>,
 }
 
-pub async fn handle(err: Rejection) -> Result<impl warp::Reply, Infallible> {
+pub async fn handle(err: Rejection) -> Result<impl warp::Reply, Rejection> {
     let code;
     let message;
+    let mut error = None;
 
     if let Some(not_found) = err.find::<NotFound>() {
         code = StatusCode::NOT_FOUND;
         message = format!("series '{}' not found", not_found.series);
     } else if let Some(internal) = err.find::<InternalError>() {
-        code = StatusCode::INTERNAL_SERVER_ERROR;
-        message = format!("internal error: {}", internal.error);
+        code = status_for(&internal.error);
+        message = format!("storage error: {}", internal.error);
+        error = Some(kind_for(&internal.error));
     } else if let Some(bad_request) = err.find::<BadRequest>() {
         code = StatusCode::BAD_REQUEST;
         message = format!("{}", bad_request.reason);
@@ -79,15 +125,35 @@ pub async fn handle(err: Rejection) -> Result<impl warp::Reply, Infallible> {
     } else if let Some(_) = err.find::<warp::filters::body::BodyDeserializeError>() {
         message = "invalid json body".to_owned();
         code = StatusCode::BAD_REQUEST;
+    } else if err.find::<warp::reject::InvalidQuery>().is_some() {
+        message = "invalid query string".to_owned();
+        code = StatusCode::BAD_REQUEST;
     } else {
-        code = StatusCode::INTERNAL_SERVER_ERROR;
-        message = "unhandled rejection".to_string();
+        return Err(err);
     }
 
     let json = warp::reply::json(&ErrorMessage {
         code: code.as_u16(),
         message: message.into(),
+        error,
     });
 
     Ok(warp::reply::with_status(json, code))
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io;
+
+    #[test]
+    fn test_status_for() {
+        assert_eq!(StatusCode::BAD_REQUEST, status_for(&Error::TooManyEntries));
+        assert_eq!(StatusCode::INSUFFICIENT_STORAGE, status_for(&Error::DataFileTooBig));
+        assert_eq!(StatusCode::INSUFFICIENT_STORAGE, status_for(&Error::IndexFull));
+        assert_eq!(StatusCode::FORBIDDEN, status_for(&Error::Archived));
+        assert_eq!(StatusCode::INSUFFICIENT_STORAGE, status_for(&Error::TooManySeries));
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, status_for(&Error::Crc16Mismatch));
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, status_for(&Error::Io(io::Error::other("io"))));
+    }
+}
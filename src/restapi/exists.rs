@@ -0,0 +1,55 @@
+use crate::storage::SeriesTable;
+use std::sync::Arc;
+use warp::http::StatusCode;
+use warp::reject::Rejection;
+use warp::Filter;
+
+async fn exists(name: String, series_table: Arc<SeriesTable>) -> Result<StatusCode, Rejection> {
+    match series_table.reader(&name) {
+        Some(_) => Ok(StatusCode::OK),
+        None => Ok(StatusCode::NOT_FOUND),
+    }
+}
+
+pub fn filter(series_table: Arc<SeriesTable>) -> warp::filters::BoxedFilter<(impl warp::Reply,)> {
+    warp::path!("series" / String)
+        .and(warp::head())
+        .and(super::with_series_table(series_table.clone()))
+        .and_then(self::exists)
+        .recover(super::error::handle)
+        .boxed()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::storage::error::Error;
+    use crate::storage::series_table;
+    use warp::http::StatusCode;
+
+    #[tokio::test]
+    async fn test_exists() -> Result<(), Error> {
+        let series_table = series_table::test::create()?;
+
+        let resp = warp::test::request()
+            .method("HEAD")
+            .path("/series/t")
+            .reply(&super::filter(series_table.series_table.clone()))
+            .await;
+
+        assert_eq!(StatusCode::NOT_FOUND, resp.status());
+        assert!(resp.body().is_empty());
+
+        series_table.create("t")?;
+
+        let resp = warp::test::request()
+            .method("HEAD")
+            .path("/series/t")
+            .reply(&super::filter(series_table.series_table.clone()))
+            .await;
+
+        assert_eq!(StatusCode::OK, resp.status());
+        assert!(resp.body().is_empty());
+
+        Ok(())
+    }
+}
@@ -1,74 +1,200 @@
 use crate::buffering::BufferingBuilder;
+use crate::csv;
+use crate::parquet::ParquetWriter;
 use crate::storage::{error::Error, Entry, SeriesReader, SeriesTable};
 use hyper::body::{Body, Bytes, Sender};
-use std::io;
+use serde_derive::Deserialize;
+use std::io::{self, Write};
+use std::mem;
+use std::pin::Pin;
 use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::io::AsyncWrite;
+use tokio::sync::mpsc;
 use warp::http::Response;
 use warp::reject::Rejection;
 use warp::Filter;
 
-async fn export_entries(reader: Arc<SeriesReader>, sender: &mut Sender) -> io::Result<()> {
-    let (tx, mut rx) = tokio::sync::mpsc::channel::<Vec<Entry>>(1);
+/// Entries per parquet row group unless configured with `filter_with_batch_size`.
+pub const DEFAULT_BATCH_SIZE: usize = 64 * 1024;
 
-    tokio::task::spawn_blocking(move || {
-        for batch in reader
-            .iterator(0)?
-            .buffering::<Result<Vec<Entry>, Error>>(1024)
-        {
-            tx.blocking_send(batch?).map_err(|e| {
-                io::Error::new(
-                    io::ErrorKind::Other,
-                    format!("can not send the data from the reading thread {:?}", e),
-                )
+const PARQUET_CHUNK_SIZE: usize = 64 * 1024;
+
+#[derive(Deserialize)]
+pub struct ExportParams {
+    pub format: Option<String>,
+    pub precision: Option<usize>,
+    /// Exports only every `sample`-th entry, e.g. for a quick preview of a huge series.
+    pub sample: Option<usize>,
+}
+
+enum Format {
+    Csv,
+    Parquet,
+}
+
+/// Writes the chunks to the response body.
+struct SenderWriter<'a>(&'a mut Sender);
+
+impl AsyncWrite for SenderWriter<'_> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, data: &[u8]) -> Poll<io::Result<usize>> {
+        match self.0.poll_ready(cx) {
+            Poll::Ready(Ok(())) => {}
+            Poll::Ready(Err(e)) => return Poll::Ready(Err(io::Error::other(format!("can not send the data chunk {:?}", e)))),
+            Poll::Pending => return Poll::Pending,
+        }
+        match self.0.try_send_data(Bytes::copy_from_slice(data)) {
+            Ok(()) => Poll::Ready(Ok(data.len())),
+            Err(_) => Poll::Ready(Err(io::Error::other("the response body is closed"))),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+async fn export_entries(reader: Arc<SeriesReader>, sample: usize, precision: usize, sender: &mut Sender) -> io::Result<()> {
+    Ok(reader
+        .write_csv_sampled_async(i64::MIN, i64::MAX, sample, precision, &mut SenderWriter(sender))
+        .await?)
+}
+
+struct ChannelWriter {
+    tx: mpsc::Sender<io::Result<Bytes>>,
+    buf: Vec<u8>,
+}
+
+impl Write for ChannelWriter {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(data);
+        if self.buf.len() >= PARQUET_CHUNK_SIZE {
+            self.flush()?;
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.buf.is_empty() {
+            let chunk = Bytes::from(mem::take(&mut self.buf));
+            self.tx.blocking_send(Ok(chunk)).map_err(|e| {
+                io::Error::other(format!("can not send the data from the writing thread {:?}", e))
             })?;
         }
+        Ok(())
+    }
+}
+
+fn write_parquet(
+    reader: Arc<SeriesReader>,
+    sample: usize,
+    batch_size: usize,
+    writer: ChannelWriter,
+) -> Result<(), Error> {
+    let mut writer = ParquetWriter::new(writer)?;
+    for batch in reader
+        .sample(i64::MIN, sample)?
+        .buffering::<Result<Vec<Entry>, Error>>(batch_size)
+    {
+        writer.write_row_group(&batch?)?;
+    }
+    writer.close()
+}
 
-        Ok::<(), io::Error>(())
+async fn export_parquet(
+    reader: Arc<SeriesReader>,
+    sample: usize,
+    batch_size: usize,
+    sender: &mut Sender,
+) -> io::Result<()> {
+    let (tx, mut rx) = mpsc::channel::<io::Result<Bytes>>(1);
+
+    tokio::task::spawn_blocking(move || {
+        let writer = ChannelWriter {
+            tx: tx.clone(),
+            buf: Vec::with_capacity(PARQUET_CHUNK_SIZE),
+        };
+        if let Err(error) = write_parquet(reader, sample, batch_size, writer) {
+            let _ = tx.blocking_send(Err(error.into()));
+        }
     });
 
-    while let Some(entries) = rx.recv().await {
-        let format = entries
-            .iter()
-            .map(|entry| format!("{}; {:.2}\n", entry.ts, entry.value))
-            .collect::<Vec<String>>()
-            .join("");
-
-        sender.send_data(Bytes::from(format)).await.map_err(|e| {
-            io::Error::new(
-                io::ErrorKind::Other,
-                format!("can not send the data chunk {:?}", e),
-            )
-        })?
+    while let Some(chunk) = rx.recv().await {
+        sender
+            .send_data(chunk?)
+            .await
+            .map_err(|e| io::Error::other(format!("can not send the data chunk {:?}", e)))?
     }
 
     Ok(())
 }
 
-async fn export(name: String, series_table: Arc<SeriesTable>) -> Result<Response<Body>, Rejection> {
+async fn export(
+    name: String,
+    params: ExportParams,
+    batch_size: usize,
+    series_table: Arc<SeriesTable>,
+) -> Result<Response<Body>, Rejection> {
     let reader = series_table
         .reader(&name)
         .ok_or_else(|| super::error::not_found(&name))?;
 
+    let format = match params.format.as_deref() {
+        None | Some("csv") => Format::Csv,
+        Some("parquet") => Format::Parquet,
+        Some(format) => return Err(super::error::bad_request(format!("unknown format: {}", format))),
+    };
+
+    let precision = params.precision.unwrap_or(csv::DEFAULT_PRECISION);
+
+    let sample = match params.sample {
+        Some(0) => return Err(super::error::bad_request("sample should be positive")),
+        sample => sample.unwrap_or(1),
+    };
+
     let (mut sender, body) = Body::channel();
 
+    let content_type = match format {
+        Format::Csv => "text/csv",
+        Format::Parquet => "application/vnd.apache.parquet",
+    };
+
     tokio::spawn(async move {
-        export_entries(reader, &mut sender)
-            .await
-            .unwrap_or_else(|e| {
-                sender.abort();
-                log::warn!("Can not export the entries: {:?}", e);
-                ()
-            })
+        let result = match format {
+            Format::Csv => export_entries(reader, sample, precision, &mut sender).await,
+            Format::Parquet => export_parquet(reader, sample, batch_size, &mut sender).await,
+        };
+        result.unwrap_or_else(|e| {
+            sender.abort();
+            log::warn!("Can not export the entries: {:?}", e);
+        })
     });
 
     Response::builder()
+        .header("Content-Type", content_type)
         .body(body)
         .map_err(|_| super::error::internal(Error::Other("can not build the request".to_owned())))
 }
 
 pub fn filter(series_table: Arc<SeriesTable>) -> warp::filters::BoxedFilter<(impl warp::Reply,)> {
+    filter_with_batch_size(series_table, DEFAULT_BATCH_SIZE)
+}
+
+/// Same as `filter`, the parquet export writes `batch_size` entries per row group.
+/// Panics if `batch_size` is zero.
+pub fn filter_with_batch_size(
+    series_table: Arc<SeriesTable>,
+    batch_size: usize,
+) -> warp::filters::BoxedFilter<(impl warp::Reply,)> {
+    assert!(batch_size > 0, "the export batch size must be positive");
     warp::path!("series" / String / "export")
         .and(warp::get())
+        .and(warp::query::<ExportParams>())
+        .and(warp::any().map(move || batch_size))
         .and(super::with_series_table(series_table.clone()))
         .and_then(self::export)
         .recover(super::error::handle)
@@ -114,4 +240,95 @@ mod test {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_export_precision() -> Result<(), Error> {
+        let series_table = series_table::test::create()?;
+        series_table.create("t")?;
+
+        series_table.writer("t").unwrap().append(&vec![
+            Entry { ts: 1, value: 1.23456789 },
+            Entry { ts: 2, value: 3.0 },
+        ])?;
+
+        let filter = super::filter(series_table.series_table.clone());
+        let export = |path: &'static str| warp::test::request().method("GET").path(path).reply(&filter);
+
+        let resp = export("/series/t/export?precision=6").await;
+        assert_eq!(StatusCode::OK, resp.status());
+        assert_eq!("1; 1.234568\n2; 3.000000\n", std::str::from_utf8(resp.body()).unwrap());
+
+        let resp = export("/series/t/export?precision=0").await;
+        assert_eq!("1; 1\n2; 3\n", std::str::from_utf8(resp.body()).unwrap());
+
+        let resp = export("/series/t/export?precision=100").await;
+        assert_eq!(
+            "1; 1.23456788999999989\n2; 3.00000000000000000\n",
+            std::str::from_utf8(resp.body()).unwrap()
+        );
+
+        let resp = export("/series/t/export?precision=-1").await;
+        assert_eq!(StatusCode::BAD_REQUEST, resp.status());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_export_parquet() -> Result<(), Error> {
+        let series_table = series_table::test::create()?;
+        series_table.create("t")?;
+
+        let entries = (0..100_000)
+            .map(|i| Entry { ts: i, value: i as f64 / 10.0 })
+            .collect::<Vec<Entry>>();
+
+        series_table.writer("t").unwrap().append(&entries)?;
+
+        let resp = warp::test::request()
+            .method("GET")
+            .path("/series/t/export?format=parquet")
+            .reply(&super::filter(series_table.series_table.clone()))
+            .await;
+
+        assert_eq!(StatusCode::OK, resp.status());
+        assert_eq!(entries, crate::parquet::test::read_entries(resp.body().clone()));
+
+        let resp = warp::test::request()
+            .method("GET")
+            .path("/series/t/export?format=xml")
+            .reply(&super::filter(series_table.series_table.clone()))
+            .await;
+
+        assert_eq!(StatusCode::BAD_REQUEST, resp.status());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_export_sample() -> Result<(), Error> {
+        let series_table = series_table::test::create()?;
+        series_table.create("t")?;
+
+        let entries = (0..10).map(|i| Entry { ts: i, value: i as f64 }).collect::<Vec<Entry>>();
+        series_table.writer("t").unwrap().append(&entries)?;
+
+        let filter = super::filter(series_table.series_table.clone());
+        let export = |path: &'static str| warp::test::request().method("GET").path(path).reply(&filter);
+
+        let resp = export("/series/t/export?sample=4&precision=0").await;
+        assert_eq!(StatusCode::OK, resp.status());
+        assert_eq!("0; 0\n4; 4\n8; 8\n", std::str::from_utf8(resp.body()).unwrap());
+
+        let resp = export("/series/t/export?sample=3&format=parquet").await;
+        assert_eq!(StatusCode::OK, resp.status());
+        assert_eq!(
+            entries.iter().step_by(3).cloned().collect::<Vec<Entry>>(),
+            crate::parquet::test::read_entries(resp.body().clone())
+        );
+
+        let resp = export("/series/t/export?sample=0").await;
+        assert_eq!(StatusCode::BAD_REQUEST, resp.status());
+
+        Ok(())
+    }
 }
@@ -0,0 +1,218 @@
+use crate::query::{QueryBuilder, StatementExpr};
+use crate::storage::SeriesTable;
+use chrono::DateTime;
+use serde_derive::{Deserialize, Serialize};
+use std::sync::Arc;
+use warp::http::StatusCode;
+use warp::reject::Rejection;
+use warp::Filter;
+
+#[derive(Deserialize)]
+pub struct SearchRequest {
+    #[serde(default)]
+    pub target: String,
+}
+
+#[derive(Deserialize)]
+pub struct Range {
+    pub from: String,
+    pub to: String,
+}
+
+#[derive(Deserialize)]
+pub struct Target {
+    pub target: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QueryRequest {
+    pub range: Range,
+    pub interval_ms: Option<u64>,
+    pub max_data_points: Option<usize>,
+    pub targets: Vec<Target>,
+}
+
+/// Time series of the target, the datapoints are `[value, ts millis]`,
+/// non-finite values are serialized as `null`.
+#[derive(Serialize)]
+pub struct TimeSeries {
+    pub target: String,
+    pub datapoints: Vec<(Option<f64>, i64)>,
+}
+
+fn parse_time(time: &str) -> Result<i64, Rejection> {
+    DateTime::parse_from_rfc3339(time)
+        .map(|time| time.timestamp_millis())
+        .map_err(|_| super::error::bad_request(format!("invalid time '{}'", time)))
+}
+
+async fn datasource() -> Result<StatusCode, Rejection> {
+    Ok(StatusCode::OK)
+}
+
+async fn search(request: SearchRequest, series_table: Arc<SeriesTable>) -> Result<warp::reply::Json, Rejection> {
+    let names = series_table
+        .names()
+        .into_iter()
+        .filter(|name| name.contains(&request.target))
+        .collect::<Vec<String>>();
+    Ok(warp::reply::json(&names))
+}
+
+async fn query(
+    request: QueryRequest,
+    max_limit: usize,
+    series_table: Arc<SeriesTable>,
+) -> Result<warp::reply::Json, Rejection> {
+    let from = parse_time(&request.range.from)?;
+    let to = parse_time(&request.range.to)?;
+    let interval = request.interval_ms.unwrap_or(1000).max(1);
+    let limit = request.max_data_points.unwrap_or(max_limit).min(max_limit);
+
+    let mut series = Vec::new();
+    for target in request.targets {
+        let reader = series_table
+            .reader(&target.target)
+            .ok_or_else(|| super::error::not_found(&target.target))?;
+        let statement_expr = StatementExpr {
+            from: from.to_string(),
+            group_by: format!("{}ms", interval),
+            aggregators: "mean".to_owned(),
+            limit: limit.to_string(),
+            fill: None,
+            align: None,
+            to: Some(to.to_string()),
+        };
+        let statement = super::query::parse_statement(statement_expr, max_limit, &series_table)?;
+        let rows = reader.query(statement).rows_async().await.map_err(super::error::internal)?;
+        series.push(TimeSeries {
+            target: target.target,
+            datapoints: rows
+                .into_iter()
+                .map(|row| (Some(row.values[0].value()).filter(|value| value.is_finite()), row.ts))
+                .collect(),
+        });
+    }
+    Ok(warp::reply::json(&series))
+}
+
+pub fn filter(series_table: Arc<SeriesTable>) -> warp::filters::BoxedFilter<(impl warp::Reply,)> {
+    filter_with_max_limit(series_table, super::query::DEFAULT_MAX_LIMIT)
+}
+
+/// Endpoints of the Grafana SimpleJSON datasource under `/grafana`, the targets
+/// are the series names, the datapoints are the means of the `intervalMs` groups.
+pub fn filter_with_max_limit(
+    series_table: Arc<SeriesTable>,
+    max_limit: usize,
+) -> warp::filters::BoxedFilter<(impl warp::Reply,)> {
+    let datasource = warp::path!("grafana").and(warp::get()).and_then(self::datasource);
+
+    let search = warp::path!("grafana" / "search")
+        .and(warp::post())
+        .and(warp::body::json::<SearchRequest>())
+        .and(super::with_series_table(series_table.clone()))
+        .and_then(self::search);
+
+    let query = warp::path!("grafana" / "query")
+        .and(warp::post())
+        .and(warp::body::json::<QueryRequest>())
+        .and(warp::any().map(move || max_limit))
+        .and(super::with_series_table(series_table.clone()))
+        .and_then(self::query);
+
+    datasource
+        .or(search)
+        .or(query)
+        .recover(super::error::handle)
+        .boxed()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::storage::error::Error;
+    use crate::storage::{series_table, Entry};
+    use serde_json::{json, Value};
+    use warp::http::StatusCode;
+
+    #[tokio::test]
+    async fn test_search() -> Result<(), Error> {
+        let series_table = series_table::test::create()?;
+        series_table.create("cpu.load")?;
+        series_table.create("cpu.temp")?;
+        series_table.create("mem.used")?;
+
+        let search = |body: &'static str| {
+            let series_table = series_table.series_table.clone();
+            async move {
+                let resp = warp::test::request()
+                    .method("POST")
+                    .path("/grafana/search")
+                    .body(body)
+                    .reply(&super::filter(series_table))
+                    .await;
+
+                assert_eq!(StatusCode::OK, resp.status());
+
+                serde_json::from_slice::<Value>(resp.body()).unwrap()
+            }
+        };
+
+        assert_eq!(json!(["cpu.load", "cpu.temp", "mem.used"]), search("{}").await);
+        assert_eq!(json!(["cpu.load", "cpu.temp"]), search("{\"target\": \"cpu\"}").await);
+
+        let resp = warp::test::request()
+            .method("GET")
+            .path("/grafana")
+            .reply(&super::filter(series_table.series_table.clone()))
+            .await;
+
+        assert_eq!(StatusCode::OK, resp.status());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_query() -> Result<(), Error> {
+        let series_table = series_table::test::create()?;
+        series_table.create("t")?;
+        series_table.writer("t").unwrap().append(&vec![
+            Entry { ts: 0, value: 1.0 },
+            Entry { ts: 30000, value: 3.0 },
+            Entry { ts: 60000, value: 5.0 },
+            Entry { ts: 90000, value: 7.0 },
+            Entry { ts: 120000, value: 9.0 },
+        ])?;
+
+        let query = |targets: &'static str| {
+            let series_table = series_table.series_table.clone();
+            async move {
+                warp::test::request()
+                    .method("POST")
+                    .path("/grafana/query")
+                    .body(format!(
+                        "{{\"range\": {{\"from\": \"1970-01-01T00:00:00.000Z\", \"to\": \"1970-01-01T00:01:30.000Z\"}}, \
+                         \"intervalMs\": 60000, \"maxDataPoints\": 100, \"targets\": {}}}",
+                        targets
+                    ))
+                    .reply(&super::filter(series_table))
+                    .await
+            }
+        };
+
+        let resp = query("[{\"target\": \"t\", \"refId\": \"A\", \"type\": \"timeserie\"}]").await;
+
+        assert_eq!(StatusCode::OK, resp.status());
+        assert_eq!(
+            json!([{"target": "t", "datapoints": [[2.0, 0], [6.0, 60000]]}]),
+            serde_json::from_slice::<Value>(resp.body()).unwrap()
+        );
+
+        let resp = query("[{\"target\": \"unknown\"}]").await;
+
+        assert_eq!(StatusCode::NOT_FOUND, resp.status());
+
+        Ok(())
+    }
+}
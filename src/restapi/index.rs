@@ -0,0 +1,91 @@
+use crate::storage::SeriesTable;
+use serde_derive::Serialize;
+use std::sync::Arc;
+use warp::reject::Rejection;
+use warp::Filter;
+
+#[derive(Serialize)]
+pub struct JsonIndexEntry {
+    /// Highest timestamp of the group of blocks.
+    pub highest_ts: i64,
+    /// Offset of the first block of the group in the data file.
+    pub block_offset: u32,
+}
+
+#[derive(Serialize)]
+pub struct JsonIndex {
+    pub entries: Vec<JsonIndexEntry>,
+}
+
+async fn index(name: String, series_table: Arc<SeriesTable>) -> Result<warp::reply::Json, Rejection> {
+    let reader = series_table
+        .reader(&name)
+        .ok_or_else(|| super::error::not_found(&name))?;
+
+    let entries = tokio::task::spawn_blocking(move || reader.index_entries())
+        .await
+        .unwrap()
+        .map_err(super::error::internal)?;
+
+    Ok(warp::reply::json(&JsonIndex {
+        entries: entries
+            .into_iter()
+            .map(|(highest_ts, block_offset)| JsonIndexEntry { highest_ts, block_offset })
+            .collect(),
+    }))
+}
+
+pub fn filter(series_table: Arc<SeriesTable>) -> warp::filters::BoxedFilter<(impl warp::Reply,)> {
+    warp::path!("series" / String / "index")
+        .and(warp::get())
+        .and(super::with_series_table(series_table.clone()))
+        .and_then(self::index)
+        .recover(super::error::handle)
+        .boxed()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::storage::error::Error;
+    use crate::storage::{series_table, Entry};
+    use serde_json::{json, Value};
+    use warp::http::StatusCode;
+
+    #[tokio::test]
+    async fn test_index() -> Result<(), Error> {
+        let series_table = series_table::test::create()?;
+
+        let resp = warp::test::request()
+            .method("GET")
+            .path("/series/t/index")
+            .reply(&super::filter(series_table.series_table.clone()))
+            .await;
+
+        assert_eq!(StatusCode::NOT_FOUND, resp.status());
+
+        series_table.create("t")?;
+        series_table.writer("t").unwrap().append(&vec![
+            Entry { ts: 1, value: 1.2 },
+            Entry { ts: 2, value: 3.1 },
+        ])?;
+        let offset = series_table.reader("t").unwrap().commit().data_offset;
+        series_table.writer("t").unwrap().append(&vec![Entry { ts: 3, value: 1.2 }])?;
+
+        let resp = warp::test::request()
+            .method("GET")
+            .path("/series/t/index")
+            .reply(&super::filter(series_table.series_table.clone()))
+            .await;
+
+        assert_eq!(StatusCode::OK, resp.status());
+        assert_eq!(
+            json!({"entries": [
+                {"highest_ts": 2, "block_offset": 0},
+                {"highest_ts": 3, "block_offset": offset},
+            ]}),
+            serde_json::from_slice::<Value>(resp.body()).unwrap()
+        );
+
+        Ok(())
+    }
+}
@@ -1,17 +1,373 @@
+use crate::storage::scrubber::ScrubStats;
 use crate::storage::SeriesTable;
+use bytes::Bytes;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use futures::{Stream, StreamExt};
+use hyper::Body;
 use std::convert::Infallible;
+use std::io::{self, Write};
 use std::sync::Arc;
-use warp::Filter;
+use warp::http::header::{CONTENT_ENCODING, CONTENT_LENGTH, VARY};
+use warp::http::{HeaderValue, Response};
+use warp::reject::Rejection;
+use warp::{Filter, Reply};
 
 pub mod create;
 pub mod append;
+pub mod append_csv;
 pub mod query;
+pub mod compare;
+pub mod agg;
 pub mod export;
 pub mod restore;
+pub mod checkpoint;
+pub mod write;
+pub mod exists;
+pub mod stats;
+pub mod truncate;
+pub mod size;
+pub mod index;
+pub mod compact;
+pub mod config;
+pub mod grafana;
+pub mod prom;
 mod error;
 
+/// Server side settings of the REST API.
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// Max `limit` of the queries.
+    pub max_limit: usize,
+    /// Entries per parquet row group of the exports.
+    pub export_batch_size: usize,
+    /// Entries per appended batch of the restores.
+    pub import_batch_size: usize,
+    /// Hosts the series could be restored from by `url`, none by default.
+    pub restore_allowed_hosts: Vec<String>,
+    /// Counters of the background scrubber reported by `/stats`, if it's started.
+    pub scrub_stats: Option<Arc<ScrubStats>>,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            max_limit: query::DEFAULT_MAX_LIMIT,
+            export_batch_size: export::DEFAULT_BATCH_SIZE,
+            import_batch_size: restore::DEFAULT_BATCH_SIZE,
+            restore_allowed_hosts: Vec::new(),
+            scrub_stats: None,
+        }
+    }
+}
+
+pub fn filter(series_table: Arc<SeriesTable>) -> warp::filters::BoxedFilter<(impl warp::Reply,)> {
+    filter_with_options(series_table, Options::default())
+}
+
+pub fn filter_with_options(
+    series_table: Arc<SeriesTable>,
+    options: Options,
+) -> warp::filters::BoxedFilter<(impl warp::Reply,)> {
+    create::filter(series_table.clone())
+        .or(append::filter(series_table.clone()))
+        .or(append_csv::filter(series_table.clone()))
+        .or(gzip(query::filter_with_max_limit(series_table.clone(), options.max_limit)))
+        .or(gzip(compare::filter_with_max_limit(series_table.clone(), options.max_limit)))
+        .or(agg::filter(series_table.clone()))
+        .or(gzip(export::filter_with_batch_size(series_table.clone(), options.export_batch_size)))
+        .or(restore::filter_with_allowed_hosts(
+            series_table.clone(),
+            options.import_batch_size,
+            options.restore_allowed_hosts,
+        ))
+        .or(checkpoint::filter(series_table.clone()))
+        .or(write::filter(series_table.clone()))
+        .or(exists::filter(series_table.clone()))
+        .or(stats::filter_with_scrub_stats(series_table.clone(), options.scrub_stats))
+        .or(truncate::filter(series_table.clone()))
+        .or(size::filter(series_table.clone()))
+        .or(index::filter(series_table.clone()))
+        .or(compact::filter(series_table.clone()))
+        .or(config::filter(series_table.clone()))
+        .or(prom::filter(series_table.clone()))
+        .or(grafana::filter_with_max_limit(series_table.clone(), options.max_limit))
+        .boxed()
+}
+
+fn accepts_gzip(accept_encoding: Option<String>) -> bool {
+    accept_encoding
+        .unwrap_or_default()
+        .split(',')
+        .any(|encoding| {
+            let mut params = encoding.split(';').map(|param| param.trim());
+            params.next() == Some("gzip")
+                && params.all(|param| param.strip_prefix("q=").and_then(|q| q.parse::<f32>().ok()) != Some(0.0))
+        })
+}
+
+fn gzip_stream(body: Body) -> impl Stream<Item = io::Result<Bytes>> {
+    let encoder = GzEncoder::new(Vec::new(), Compression::Default);
+    futures::stream::unfold(Some((body, encoder)), |state| async move {
+        let (mut body, mut encoder) = state?;
+        match body.next().await {
+            Some(Ok(chunk)) => {
+                let compressed = encoder
+                    .write_all(&chunk)
+                    .and_then(|_| encoder.flush())
+                    .map(|_| Bytes::from(std::mem::take(encoder.get_mut())));
+                Some((compressed, Some((body, encoder))))
+            }
+            Some(Err(err)) => Some((Err(io::Error::other(err)), None)),
+            None => Some((encoder.finish().map(Bytes::from), None)),
+        }
+    })
+}
+
+/// Compresses the body of the successful responses with gzip if the client accepts it.
+/// The body is compressed chunk by chunk, so the streamed responses stay streamed.
+pub fn gzip<F, R>(filter: F) -> warp::filters::BoxedFilter<(Response<Body>,)>
+where
+    F: Filter<Extract = (R,), Error = Rejection> + Clone + Send + Sync + 'static,
+    R: Reply,
+{
+    warp::header::optional::<String>("accept-encoding")
+        .and(filter)
+        .map(|accept_encoding: Option<String>, reply: R| {
+            let response = reply.into_response();
+            if !accepts_gzip(accept_encoding)
+                || !response.status().is_success()
+                || response.headers().contains_key(CONTENT_ENCODING)
+            {
+                return response;
+            }
+
+            let (mut parts, body) = response.into_parts();
+            parts.headers.remove(CONTENT_LENGTH);
+            parts.headers.insert(CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+            parts.headers.append(VARY, HeaderValue::from_static("accept-encoding"));
+
+            Response::from_parts(parts, Body::wrap_stream(gzip_stream(body)))
+        })
+        .boxed()
+}
+
 pub fn with_series_table(
     series_table: Arc<SeriesTable>,
 ) -> impl Filter<Extract = (Arc<SeriesTable>,), Error = Infallible> + Clone {
     warp::any().map(move || series_table.clone())
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod test {
+    use crate::storage::error::Error;
+    use crate::storage::series_table;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+    use warp::http::StatusCode;
+
+    #[test]
+    fn test_accepts_gzip() {
+        assert!(super::accepts_gzip(Some("gzip".to_owned())));
+        assert!(super::accepts_gzip(Some("deflate, gzip;q=0.8".to_owned())));
+        assert!(!super::accepts_gzip(Some("gzip;q=0".to_owned())));
+        assert!(!super::accepts_gzip(Some("br".to_owned())));
+        assert!(!super::accepts_gzip(None));
+    }
+
+    #[tokio::test]
+    async fn test_gzip() -> Result<(), Error> {
+        let series_table = series_table::test::create()?;
+        series_table.create("t")?;
+
+        let entries = (0..10000)
+            .map(|i| format!("{{\"ts\": {}, \"value\": {}}}", i * 1000, i))
+            .collect::<Vec<String>>()
+            .join(",");
+
+        let resp = warp::test::request()
+            .method("POST")
+            .path("/series/t")
+            .body(format!("{{\"entries\": [{}]}}", entries))
+            .reply(&super::filter(series_table.series_table.clone()))
+            .await;
+
+        assert_eq!(StatusCode::OK, resp.status());
+
+        for path in ["/series/t/export", "/series/t?from=0&group_by=minute&aggregators=mean&limit=1000"] {
+            let plain = warp::test::request()
+                .method("GET")
+                .path(path)
+                .reply(&super::filter(series_table.series_table.clone()))
+                .await;
+
+            assert_eq!(StatusCode::OK, plain.status());
+            assert!(plain.headers().get("content-encoding").is_none());
+
+            let compressed = warp::test::request()
+                .method("GET")
+                .path(path)
+                .header("accept-encoding", "gzip, deflate")
+                .reply(&super::filter(series_table.series_table.clone()))
+                .await;
+
+            assert_eq!(StatusCode::OK, compressed.status());
+            assert_eq!("gzip", compressed.headers().get("content-encoding").unwrap());
+            assert!(compressed.body().len() < plain.body().len());
+
+            let mut decompressed = Vec::new();
+            GzDecoder::new(&compressed.body()[..])?.read_to_end(&mut decompressed)?;
+
+            assert_eq!(plain.body(), &decompressed[..]);
+        }
+
+        let resp = warp::test::request()
+            .method("GET")
+            .path("/series/unknown/export")
+            .header("accept-encoding", "gzip")
+            .reply(&super::filter(series_table.series_table.clone()))
+            .await;
+
+        assert_eq!(StatusCode::NOT_FOUND, resp.status());
+        assert!(resp.headers().get("content-encoding").is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_routing() -> Result<(), Error> {
+        let series_table = series_table::test::create()?;
+
+        let resp = warp::test::request()
+            .method("PUT")
+            .path("/series/t")
+            .reply(&super::filter(series_table.series_table.clone()))
+            .await;
+
+        assert_eq!(StatusCode::CREATED, resp.status());
+
+        let resp = warp::test::request()
+            .method("GET")
+            .path("/series/t?from=0&group_by=hour&aggregators=mean&limit=10")
+            .reply(&super::filter(series_table.series_table.clone()))
+            .await;
+
+        assert_eq!(StatusCode::OK, resp.status());
+
+        let resp = warp::test::request()
+            .method("GET")
+            .path("/series/t/export")
+            .reply(&super::filter(series_table.series_table.clone()))
+            .await;
+
+        assert_eq!(StatusCode::OK, resp.status());
+
+        let resp = warp::test::request()
+            .method("HEAD")
+            .path("/series/t")
+            .reply(&super::filter(series_table.series_table.clone()))
+            .await;
+
+        assert_eq!(StatusCode::OK, resp.status());
+
+        let resp = warp::test::request()
+            .method("GET")
+            .path("/unknown")
+            .reply(&super::filter(series_table.series_table.clone()))
+            .await;
+
+        assert_eq!(StatusCode::NOT_FOUND, resp.status());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_pre_epoch_timestamps() -> Result<(), Error> {
+        let series_table = series_table::test::create()?;
+        series_table.create("t")?;
+
+        let resp = warp::test::request()
+            .method("POST")
+            .path("/series/t")
+            .body("{\"entries\": [{\"ts\": -61000, \"value\": 1.0}, {\"ts\": -1000, \"value\": 2.0}]}")
+            .reply(&super::filter(series_table.series_table.clone()))
+            .await;
+
+        assert_eq!(StatusCode::OK, resp.status());
+
+        let resp = warp::test::request()
+            .method("GET")
+            .path("/series/t?from=-60000&group_by=minute&aggregators=mean&limit=10")
+            .reply(&super::filter(series_table.series_table.clone()))
+            .await;
+
+        assert_eq!(StatusCode::OK, resp.status());
+        assert_eq!(
+            "{\"rows\":[{\"timestamp\":\"1969-12-31T23:59:00+00:00\",\"values\":[{\"Mean\":2.0}]}]}",
+            std::str::from_utf8(resp.body()).unwrap()
+        );
+
+        let resp = warp::test::request()
+            .method("GET")
+            .path("/series/t/export")
+            .reply(&super::filter(series_table.series_table.clone()))
+            .await;
+
+        assert_eq!("-61000; 1.00\n-1000; 2.00\n", std::str::from_utf8(resp.body()).unwrap());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_batch_size_options() -> Result<(), Error> {
+        let series_table = series_table::test::create()?;
+        let filter = super::filter_with_options(
+            series_table.series_table.clone(),
+            super::Options {
+                export_batch_size: 3,
+                import_batch_size: 2,
+                ..Default::default()
+            },
+        );
+
+        let csv = (0..10).map(|i| format!("{}; {}.00\n", i, i)).collect::<String>();
+
+        let resp = warp::test::request()
+            .method("POST")
+            .path("/series/t/restore")
+            .body(csv.clone())
+            .reply(&filter)
+            .await;
+
+        assert_eq!(StatusCode::OK, resp.status());
+
+        let resp = warp::test::request()
+            .method("GET")
+            .path("/series/t/export")
+            .reply(&filter)
+            .await;
+
+        assert_eq!(StatusCode::OK, resp.status());
+        assert_eq!(csv, std::str::from_utf8(resp.body()).unwrap());
+
+        let resp = warp::test::request()
+            .method("GET")
+            .path("/series/t/export?format=parquet")
+            .reply(&filter)
+            .await;
+
+        assert_eq!(StatusCode::OK, resp.status());
+        assert_eq!(10, crate::parquet::test::read_entries(resp.body().clone()).len());
+
+        // the zero batch sizes are rejected
+        for options in [
+            super::Options { export_batch_size: 0, ..Default::default() },
+            super::Options { import_batch_size: 0, ..Default::default() },
+        ] {
+            let series_table = series_table.series_table.clone();
+            let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| super::filter_with_options(series_table, options)));
+            assert!(result.is_err());
+        }
+
+        Ok(())
+    }
+}
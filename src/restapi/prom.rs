@@ -0,0 +1,139 @@
+use crate::storage::{error::Error, Entry, SeriesTable};
+use std::sync::Arc;
+use warp::http::Response;
+use warp::reject::Rejection;
+use warp::Filter;
+
+/// Prometheus metric names are `[a-zA-Z_:][a-zA-Z0-9_:]*`, the other characters
+/// of the series name are replaced with `_`.
+fn metric_name(name: &str) -> String {
+    let mut metric = name
+        .chars()
+        .map(|c| match c.is_ascii_alphanumeric() || c == '_' || c == ':' {
+            true => c,
+            false => '_',
+        })
+        .collect::<String>();
+    if metric.starts_with(|c: char| c.is_ascii_digit()) {
+        metric.insert(0, '_');
+    }
+    metric
+}
+
+fn format_value(value: f64) -> String {
+    match value {
+        value if value.is_nan() => "NaN".to_owned(),
+        value if value == f64::INFINITY => "+Inf".to_owned(),
+        value if value == f64::NEG_INFINITY => "-Inf".to_owned(),
+        value => value.to_string(),
+    }
+}
+
+/// Formats the latest entry as a gauge sample, the timestamp is in millis.
+fn scrape(name: &str, latest: Option<(Entry, i64)>) -> String {
+    let metric = metric_name(name);
+    let mut body = format!("# TYPE {} gauge\n", metric);
+    if let Some((entry, ts)) = latest {
+        body.push_str(&format!("{} {} {}\n", metric, format_value(entry.value), ts));
+    }
+    body
+}
+
+async fn prom(name: String, series_table: Arc<SeriesTable>) -> Result<Response<String>, Rejection> {
+    let reader = series_table
+        .reader(&name)
+        .ok_or_else(|| super::error::not_found(&name))?;
+
+    let unit = reader.config().timestamp_unit;
+    let latest = tokio::task::spawn_blocking(move || reader.latest())
+        .await
+        .unwrap()
+        .map_err(super::error::internal)?;
+
+    let latest = match latest {
+        Some(entry) => match unit.to_millis(entry.ts) {
+            Some(ts) => Some((entry, ts)),
+            None => return Err(super::error::internal(Error::TimestampOutOfRange)),
+        },
+        None => None,
+    };
+
+    Response::builder()
+        .header("Content-Type", "text/plain; version=0.0.4")
+        .body(scrape(&name, latest))
+        .map_err(|_| super::error::internal(Error::Other("can not build the response".to_owned())))
+}
+
+pub fn filter(series_table: Arc<SeriesTable>) -> warp::filters::BoxedFilter<(impl warp::Reply,)> {
+    warp::path!("series" / String / "prom")
+        .and(warp::get())
+        .and(super::with_series_table(series_table.clone()))
+        .and_then(self::prom)
+        .recover(super::error::handle)
+        .boxed()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::storage::error::Error;
+    use crate::storage::{series_table, Entry, SeriesConfig, TimestampUnit};
+    use warp::http::StatusCode;
+
+    #[test]
+    fn test_metric_name() {
+        assert_eq!("room_co2", super::metric_name("room.co2"));
+        assert_eq!("cpu_load_1m", super::metric_name("cpu-load_1m"));
+        assert_eq!("_1st", super::metric_name("1st"));
+    }
+
+    #[tokio::test]
+    async fn test_prom() -> Result<(), Error> {
+        let series_table = series_table::test::create()?;
+
+        let scrape = |name: &'static str| {
+            let series_table = series_table.series_table.clone();
+            async move {
+                warp::test::request()
+                    .method("GET")
+                    .path(&format!("/series/{}/prom", name))
+                    .reply(&super::filter(series_table))
+                    .await
+            }
+        };
+
+        assert_eq!(StatusCode::NOT_FOUND, scrape("room.co2").await.status());
+
+        series_table.create("room.co2")?;
+
+        let resp = scrape("room.co2").await;
+        assert_eq!(StatusCode::OK, resp.status());
+        assert_eq!("# TYPE room_co2 gauge\n", std::str::from_utf8(resp.body()).unwrap());
+
+        series_table.writer("room.co2").unwrap().append(&vec![
+            Entry { ts: 1000, value: 410.0 },
+            Entry { ts: 2000, value: 412.5 },
+        ])?;
+
+        let resp = scrape("room.co2").await;
+        assert_eq!(StatusCode::OK, resp.status());
+        assert_eq!("text/plain; version=0.0.4", resp.headers().get("content-type").unwrap());
+        assert_eq!(
+            "# TYPE room_co2 gauge\nroom_co2 412.5 2000\n",
+            std::str::from_utf8(resp.body()).unwrap()
+        );
+
+        series_table.create_with_config(
+            "temp",
+            SeriesConfig {
+                timestamp_unit: TimestampUnit::Seconds,
+                ..SeriesConfig::default()
+            },
+        )?;
+        series_table.writer("temp").unwrap().append(&vec![Entry { ts: 3, value: f64::NAN }])?;
+
+        let resp = scrape("temp").await;
+        assert_eq!("# TYPE temp gauge\ntemp NaN 3000\n", std::str::from_utf8(resp.body()).unwrap());
+
+        Ok(())
+    }
+}
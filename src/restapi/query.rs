@@ -1,10 +1,15 @@
-use crate::query::{Aggregation, QueryBuilder, Row, Statement, StatementExpr};
-use crate::storage::{Entry, SeriesTable};
+use crate::query::{Aggregation, Fill, IntoEntriesIter, Query, QueryBuilder, Row, Statement, StatementExpr};
+use crate::storage::{error::Error, Entry, SeriesTable};
+use bytes::Bytes;
 use chrono::{TimeZone, Utc};
+use hyper::Body;
+use serde::ser::{Serialize, SerializeMap, Serializer};
 use serde_derive::{Deserialize, Serialize};
-use std::convert::TryInto;
+use std::collections::HashSet;
 use std::sync::Arc;
+use tokio::sync::mpsc;
 use warp::reject::Rejection;
+use warp::reply::{Reply, Response};
 use warp::Filter;
 
 #[derive(Deserialize)]
@@ -15,18 +20,17 @@ pub struct JsonEntries {
 #[derive(Serialize)]
 pub struct JsonRows {
     pub rows: Vec<JsonRow>,
+    /// Set if the series has no entries at all, to tell it from the empty query range.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub empty: Option<bool>,
 }
 
 impl JsonRows {
-    fn from_rows(rows: Vec<Row>) -> JsonRows {
+    /// Builds the rows with the values keyed by `aliases`, if given.
+    fn from_rows(rows: Vec<Row>, aliases: Option<&[String]>, flat: bool) -> JsonRows {
         JsonRows {
-            rows: rows
-                .into_iter()
-                .map(|row| JsonRow {
-                    timestamp: Utc.timestamp_millis(row.ts as i64).to_rfc3339(),
-                    values: row.values,
-                })
-                .collect(),
+            rows: rows.into_iter().map(|row| JsonRow::from_row(row, aliases, flat)).collect(),
+            empty: None,
         }
     }
 }
@@ -34,34 +38,291 @@ impl JsonRows {
 #[derive(Serialize)]
 pub struct JsonRow {
     pub timestamp: String,
-    pub values: Vec<Aggregation>,
+    pub values: JsonValues,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub count: Option<u64>,
 }
 
-async fn query(
+impl JsonRow {
+    fn from_row(row: Row, aliases: Option<&[String]>, flat: bool) -> JsonRow {
+        JsonRow {
+            timestamp: match row.ts_nanos {
+                Some(nanos) => Utc.timestamp_nanos(nanos).to_rfc3339(),
+                None => Utc.timestamp_millis(row.ts as i64).to_rfc3339(),
+            },
+            values: match aliases {
+                Some(aliases) => JsonValues::Named(NamedValues(
+                    aliases.iter().cloned().zip(row.values.iter().map(|agg| agg.value())).collect(),
+                )),
+                None if flat => JsonValues::Flat(row.values.into_iter().map(FlatAggregation).collect()),
+                None => JsonValues::Positional(row.values),
+            },
+            count: row.count,
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum JsonValues {
+    Positional(Vec<Aggregation>),
+    Named(NamedValues),
+    Flat(Vec<FlatAggregation>),
+}
+
+/// Aggregation as `{"type": "mean", "value": 3.0}` rather than `{"Mean": 3.0}`,
+/// non-finite values are serialized as `null`.
+pub struct FlatAggregation(Aggregation);
+
+impl Serialize for FlatAggregation {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(2))?;
+        map.serialize_entry("type", self.0.name())?;
+        match self.0 {
+            Aggregation::Count(count) => map.serialize_entry("value", &count)?,
+            Aggregation::Histogram(ref buckets) => map.serialize_entry("value", buckets)?,
+            ref aggregation => {
+                map.serialize_entry("value", &Some(aggregation.value()).filter(|value| value.is_finite()))?
+            }
+        }
+        map.end()
+    }
+}
+
+/// Values keyed by the aggregator alias, in the order of the aggregators.
+/// Non-finite values are serialized as `null`.
+pub struct NamedValues(Vec<(String, f64)>);
+
+impl Serialize for NamedValues {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (alias, value) in &self.0 {
+            map.serialize_entry(alias, &Some(*value).filter(|value| value.is_finite()))?;
+        }
+        map.end()
+    }
+}
+
+#[derive(Deserialize)]
+pub struct RowsParams {
+    #[serde(default)]
+    pub named: bool,
+    /// Streams the rows as NDJSON, one `JsonRow` per line.
+    #[serde(default)]
+    pub stream: bool,
+    /// Adds the number of the entries in the group to each row.
+    #[serde(default)]
+    pub with_count: bool,
+    /// `2` serializes the values as `FlatAggregation`, `1` (default) as `Aggregation`.
+    #[serde(default)]
+    pub format_version: Option<u32>,
+    /// Returns the `Plan` of the query instead of the rows.
+    #[serde(default)]
+    pub plan: bool,
+}
+
+#[derive(Serialize)]
+pub struct JsonStatement {
+    pub from: i64,
+    pub group_by: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub group_by_nanos: Option<u64>,
+    pub aggregators: Vec<String>,
+    pub limit: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fill: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub align: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub to: Option<i64>,
+}
+
+impl From<Statement> for JsonStatement {
+    fn from(statement: Statement) -> JsonStatement {
+        JsonStatement {
+            from: statement.from,
+            group_by: statement.group_by,
+            group_by_nanos: statement.group_by_nanos,
+            aggregators: statement
+                .aggregators
+                .iter()
+                .zip(statement.aliases.iter())
+                .map(|(agg, alias)| match agg.to_string() {
+                    name if &name == alias => name,
+                    name => format!("{}:{}", alias, name),
+                })
+                .collect(),
+            limit: statement.limit,
+            fill: match statement.fill {
+                Fill::None => None,
+                fill => Some(fill.to_string()),
+            },
+            align: Some(statement.align).filter(|align| *align != 0),
+            to: statement.to,
+        }
+    }
+}
+
+/// Max `limit` of the queries by default.
+pub const DEFAULT_MAX_LIMIT: usize = 100_000;
+
+/// Parses the statement, the relative timestamps are resolved by the clock of the series table.
+pub(super) fn parse_statement(
+    statement_expr: StatementExpr,
+    max_limit: usize,
+    series_table: &SeriesTable,
+) -> Result<Statement, Rejection> {
+    parse_statement_at(statement_expr, max_limit, series_table.clock().now_millis())
+}
+
+/// Same as `parse_statement`, the relative timestamps are resolved against `now` millis.
+pub(super) fn parse_statement_at(statement_expr: StatementExpr, max_limit: usize, now: i64) -> Result<Statement, Rejection> {
+    let statement = statement_expr
+        .parse_at(now)
+        .map_err(|err| super::error::bad_request(format!("can not parse expression: {:?}", err)))?;
+    if statement.limit > max_limit {
+        return Err(super::error::bad_request(format!("limit should not exceed {}", max_limit)));
+    }
+    Ok(statement)
+}
+
+async fn validate(
     name: String,
     statement_expr: StatementExpr,
+    max_limit: usize,
     series_table: Arc<SeriesTable>,
 ) -> Result<warp::reply::Json, Rejection> {
+    series_table
+        .reader(&name)
+        .ok_or_else(|| super::error::not_found(&name))?;
+    let statement = parse_statement(statement_expr, max_limit, &series_table)?;
+    Ok(warp::reply::json(&JsonStatement::from(statement)))
+}
+
+async fn query(
+    name: String,
+    statement_expr: StatementExpr,
+    params: RowsParams,
+    max_limit: usize,
+    series_table: Arc<SeriesTable>,
+) -> Result<Response, Rejection> {
     let reader = series_table
         .reader(&name)
         .ok_or_else(|| super::error::not_found(&name))?;
-    let statement: Statement = statement_expr
-        .try_into()
-        .map_err(|err| super::error::bad_request(format!("can not parse expression: {:?}", err)))?;
-    reader
-        .query(statement)
+    let statement = parse_statement(statement_expr, max_limit, &series_table)?;
+    let aliases = match params.named {
+        true => Some(statement.aliases.clone()),
+        false => None,
+    };
+    let flat = match params.format_version {
+        None | Some(1) => false,
+        Some(2) => true,
+        Some(version) => return Err(super::error::bad_request(format!("unknown format_version: {}", version))),
+    };
+    if let Some(aliases) = &aliases {
+        if aliases.iter().collect::<HashSet<&String>>().len() < aliases.len() {
+            return Err(super::error::bad_request("aggregator aliases should be unique"));
+        }
+    }
+    let series = reader.clone();
+    let query = match params.with_count {
+        true => reader.query(statement).with_count(),
+        false => reader.query(statement),
+    };
+    if params.plan {
+        return tokio::task::spawn_blocking(move || query.explain())
+            .await
+            .unwrap()
+            .map(|plan| warp::reply::json(&plan).into_response())
+            .map_err(super::error::internal);
+    }
+    if params.stream {
+        return stream_rows(query, aliases, flat);
+    }
+    query
         .rows_async()
         .await
-        .map(|rows| warp::reply::json(&JsonRows::from_rows(rows)))
-        .map_err(|e| super::error::internal(e))
+        .map(|rows| {
+            let mut rows = JsonRows::from_rows(rows, aliases.as_deref(), flat);
+            if rows.rows.is_empty() && series.commit().data_offset == 0 {
+                rows.empty = Some(true);
+            }
+            warp::reply::json(&rows).into_response()
+        })
+        .map_err(super::error::internal)
+}
+
+/// Streams the rows as they are computed, the response is aborted if the query fails.
+fn stream_rows<I>(query: Query<I>, aliases: Option<Vec<String>>, flat: bool) -> Result<Response, Rejection>
+where
+    I: IntoEntriesIter + Send + 'static,
+{
+    let (tx, mut rx) = mpsc::channel::<Result<Bytes, Error>>(1);
+
+    tokio::task::spawn_blocking(move || {
+        let result = query.for_each_row(|row| {
+            let mut line = serde_json::to_vec(&JsonRow::from_row(row, aliases.as_deref(), flat))
+                .map_err(|e| Error::Other(format!("can not serialize the row: {:?}", e)))?;
+            line.push(b'\n');
+            // the receiver is dropped if the client is gone
+            tx.blocking_send(Ok(Bytes::from(line)))
+                .map_err(|_| Error::Other("the response body is closed".to_owned()))
+        });
+        if let Err(error) = result {
+            let _ = tx.blocking_send(Err(error));
+        }
+    });
+
+    let (mut sender, body) = Body::channel();
+
+    tokio::spawn(async move {
+        while let Some(line) = rx.recv().await {
+            let sent = match line {
+                Ok(line) => sender.send_data(line).await.map_err(|e| Error::Other(format!("{:?}", e))),
+                Err(error) => Err(error),
+            };
+            if let Err(error) = sent {
+                log::warn!("Can not stream the rows: {:?}", error);
+                sender.abort();
+                return;
+            }
+        }
+    });
+
+    warp::http::Response::builder()
+        .header("Content-Type", "application/x-ndjson")
+        .body(body)
+        .map_err(|_| super::error::internal(Error::Other("can not build the response".to_owned())))
 }
 
 pub fn filter(series_table: Arc<SeriesTable>) -> warp::filters::BoxedFilter<(impl warp::Reply,)> {
-    warp::path!("series" / String)
+    filter_with_max_limit(series_table, DEFAULT_MAX_LIMIT)
+}
+
+/// Same as `filter`, the queries with the `limit` greater than `max_limit` are rejected with `400`.
+pub fn filter_with_max_limit(
+    series_table: Arc<SeriesTable>,
+    max_limit: usize,
+) -> warp::filters::BoxedFilter<(impl warp::Reply,)> {
+    let with_max_limit = warp::any().map(move || max_limit);
+
+    let query = warp::path!("series" / String)
         .and(warp::get())
         .and(warp::query::<StatementExpr>())
+        .and(warp::query::<RowsParams>())
+        .and(with_max_limit)
         .and(super::with_series_table(series_table.clone()))
-        .and_then(self::query)
+        .and_then(self::query);
+
+    let validate = warp::path!("series" / String / "query" / "validate")
+        .and(warp::get())
+        .and(warp::query::<StatementExpr>())
+        .and(with_max_limit)
+        .and(super::with_series_table(series_table.clone()))
+        .and_then(self::validate);
+
+    query
+        .or(validate)
         .recover(super::error::handle)
         .boxed()
 }
@@ -70,7 +331,6 @@ pub fn filter(series_table: Arc<SeriesTable>) -> warp::filters::BoxedFilter<(imp
 mod test {
     use super::*;
     use crate::failpoints::Failpoints;
-    use crate::storage::error::Error;
     use crate::storage::series_table;
     use warp::http::StatusCode;
 
@@ -107,4 +367,310 @@ mod test {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_query_empty_series() -> Result<(), Error> {
+        let series_table = series_table::test::create()?;
+        let filter = super::filter(series_table.series_table.clone());
+        let query = |name: &str, from: i64| {
+            warp::test::request()
+                .method("GET")
+                .path(&format!("/series/{}?from={}&group_by=minute&aggregators=mean&limit=10", name, from))
+                .reply(&filter)
+        };
+
+        let resp = query("t", 0).await;
+        assert_eq!(StatusCode::NOT_FOUND, resp.status());
+
+        series_table.create("t")?;
+
+        let resp = query("t", 0).await;
+        assert_eq!(StatusCode::OK, resp.status());
+        assert_eq!("{\"rows\":[],\"empty\":true}", std::str::from_utf8(resp.body()).unwrap());
+
+        series_table.writer("t").unwrap().append(&vec![Entry { ts: 1, value: 1.0 }])?;
+
+        // the series has entries, but not in the queried range
+        let resp = query("t", 60000).await;
+        assert_eq!(StatusCode::OK, resp.status());
+        assert_eq!("{\"rows\":[]}", std::str::from_utf8(resp.body()).unwrap());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_validate() -> Result<(), Error> {
+        let series_table = series_table::test::create()?;
+        series_table.create("t")?;
+
+        let resp = warp::test::request()
+            .method("GET")
+            .path("/series/t/query/validate?from=10&group_by=minute&aggregators=mean,max&limit=100")
+            .reply(&super::filter(series_table.series_table.clone()))
+            .await;
+
+        assert_eq!(StatusCode::OK, resp.status());
+        assert_eq!(
+            "{\"from\":10,\"group_by\":60000,\"aggregators\":[\"mean\",\"max\"],\"limit\":100}",
+            std::str::from_utf8(resp.body()).unwrap()
+        );
+
+        let resp = warp::test::request()
+            .method("GET")
+            .path("/series/t/query/validate?from=10&group_by=minute&aggregators=last&limit=100&fill=previous")
+            .reply(&super::filter(series_table.series_table.clone()))
+            .await;
+
+        assert_eq!(StatusCode::OK, resp.status());
+        assert_eq!(
+            "{\"from\":10,\"group_by\":60000,\"aggregators\":[\"last\"],\"limit\":100,\"fill\":\"previous\"}",
+            std::str::from_utf8(resp.body()).unwrap()
+        );
+
+        let resp = warp::test::request()
+            .method("GET")
+            .path("/series/t/query/validate?from=10&group_by=minute&aggregators=last&limit=100&fill=linear")
+            .reply(&super::filter(series_table.series_table.clone()))
+            .await;
+
+        assert_eq!(StatusCode::BAD_REQUEST, resp.status());
+
+        let resp = warp::test::request()
+            .method("GET")
+            .path("/series/t/query/validate?from=10&group_by=week&aggregators=mean&limit=100")
+            .reply(&super::filter(series_table.series_table.clone()))
+            .await;
+
+        assert_eq!(StatusCode::BAD_REQUEST, resp.status());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_query_max_limit() -> Result<(), Error> {
+        let series_table = series_table::test::create()?;
+        series_table.create("t")?;
+
+        let filter = super::filter_with_max_limit(series_table.series_table.clone(), 100);
+        let query = |path: &'static str| warp::test::request().method("GET").path(path).reply(&filter);
+
+        assert_eq!(StatusCode::OK, query("/series/t?from=0&group_by=minute&aggregators=mean&limit=100").await.status());
+
+        let resp = query("/series/t?from=0&group_by=minute&aggregators=mean&limit=101").await;
+        assert_eq!(StatusCode::BAD_REQUEST, resp.status());
+        assert!(std::str::from_utf8(resp.body()).unwrap().contains("limit should not exceed 100"));
+
+        let resp = query("/series/t/query/validate?from=0&group_by=minute&aggregators=mean&limit=18446744073709551615").await;
+        assert_eq!(StatusCode::BAD_REQUEST, resp.status());
+
+        let filter = super::filter(series_table.series_table.clone());
+        let resp = warp::test::request()
+            .method("GET")
+            .path("/series/t?from=0&group_by=minute&aggregators=mean&limit=18446744073709551615")
+            .reply(&filter)
+            .await;
+        assert_eq!(StatusCode::BAD_REQUEST, resp.status());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_query_with_count() -> Result<(), Error> {
+        let series_table = series_table::test::create()?;
+        series_table.create("t")?;
+        series_table.writer("t").unwrap().append(&vec![
+            Entry { ts: 0, value: 1.0 },
+            Entry { ts: 1000, value: 3.0 },
+            Entry { ts: 60000, value: 5.0 },
+            Entry { ts: 180000, value: 7.0 },
+        ])?;
+
+        let filter = super::filter(series_table.series_table.clone());
+        let query = |path: &'static str| warp::test::request().method("GET").path(path).reply(&filter);
+
+        let resp = query("/series/t?from=0&group_by=minute&aggregators=mean&limit=2&with_count=true").await;
+        assert_eq!(StatusCode::OK, resp.status());
+        assert_eq!(
+            concat!(
+                "{\"rows\":[",
+                "{\"timestamp\":\"1970-01-01T00:00:00+00:00\",\"values\":[{\"Mean\":2.0}],\"count\":2},",
+                "{\"timestamp\":\"1970-01-01T00:01:00+00:00\",\"values\":[{\"Mean\":5.0}],\"count\":1}",
+                "]}"
+            ),
+            std::str::from_utf8(resp.body()).unwrap()
+        );
+
+        // the filled group has no entries
+        let resp = query("/series/t?from=60000&group_by=minute&aggregators=avg:mean&limit=3&fill=previous&named=true&with_count=true").await;
+        assert_eq!(StatusCode::OK, resp.status());
+        assert_eq!(
+            concat!(
+                "{\"rows\":[",
+                "{\"timestamp\":\"1970-01-01T00:01:00+00:00\",\"values\":{\"avg\":5.0},\"count\":1},",
+                "{\"timestamp\":\"1970-01-01T00:02:00+00:00\",\"values\":{\"avg\":5.0},\"count\":0},",
+                "{\"timestamp\":\"1970-01-01T00:03:00+00:00\",\"values\":{\"avg\":7.0},\"count\":1}",
+                "]}"
+            ),
+            std::str::from_utf8(resp.body()).unwrap()
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_query_named() -> Result<(), Error> {
+        let series_table = series_table::test::create()?;
+        series_table.create("t")?;
+        series_table.writer("t").unwrap().append(&vec![
+            Entry { ts: 0, value: 1.0 },
+            Entry { ts: 1000, value: 3.0 },
+        ])?;
+
+        let filter = super::filter(series_table.series_table.clone());
+        let query = |path: &'static str| warp::test::request().method("GET").path(path).reply(&filter);
+
+        let resp = query("/series/t?from=0&group_by=minute&aggregators=avg:mean,min,high:max&limit=10&named=true").await;
+        assert_eq!(StatusCode::OK, resp.status());
+        assert_eq!(
+            "{\"rows\":[{\"timestamp\":\"1970-01-01T00:00:00+00:00\",\"values\":{\"avg\":2.0,\"min\":1.0,\"high\":3.0}}]}",
+            std::str::from_utf8(resp.body()).unwrap()
+        );
+
+        let resp = query("/series/t?from=0&group_by=minute&aggregators=avg:mean,min&limit=10").await;
+        assert_eq!(StatusCode::OK, resp.status());
+        assert_eq!(
+            "{\"rows\":[{\"timestamp\":\"1970-01-01T00:00:00+00:00\",\"values\":[{\"Mean\":2.0},{\"Min\":1.0}]}]}",
+            std::str::from_utf8(resp.body()).unwrap()
+        );
+
+        let resp = query("/series/t?from=0&group_by=minute&aggregators=min,min&limit=10&named=true").await;
+        assert_eq!(StatusCode::BAD_REQUEST, resp.status());
+
+        let resp = query("/series/t/query/validate?from=0&group_by=minute&aggregators=avg:mean,min&limit=10").await;
+        assert_eq!(
+            "{\"from\":0,\"group_by\":60000,\"aggregators\":[\"avg:mean\",\"min\"],\"limit\":10}",
+            std::str::from_utf8(resp.body()).unwrap()
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_query_format_version() -> Result<(), Error> {
+        let series_table = series_table::test::create()?;
+        series_table.create("t")?;
+        series_table.writer("t").unwrap().append(&vec![
+            Entry { ts: 0, value: 1.0 },
+            Entry { ts: 1000, value: 3.0 },
+        ])?;
+
+        let filter = super::filter(series_table.series_table.clone());
+        let query = |path: &'static str| warp::test::request().method("GET").path(path).reply(&filter);
+
+        let resp = query("/series/t?from=0&group_by=minute&aggregators=mean,min,max,last,count&limit=10&format_version=2").await;
+        assert_eq!(StatusCode::OK, resp.status());
+        assert_eq!(
+            "{\"rows\":[{\"timestamp\":\"1970-01-01T00:00:00+00:00\",\"values\":[\
+             {\"type\":\"mean\",\"value\":2.0},\
+             {\"type\":\"min\",\"value\":1.0},\
+             {\"type\":\"max\",\"value\":3.0},\
+             {\"type\":\"last\",\"value\":3.0},\
+             {\"type\":\"count\",\"value\":2}]}]}",
+            std::str::from_utf8(resp.body()).unwrap()
+        );
+
+        let resp = query("/series/t?from=0&group_by=minute&aggregators=mean&limit=10&format_version=1").await;
+        assert_eq!(StatusCode::OK, resp.status());
+        assert_eq!(
+            "{\"rows\":[{\"timestamp\":\"1970-01-01T00:00:00+00:00\",\"values\":[{\"Mean\":2.0}]}]}",
+            std::str::from_utf8(resp.body()).unwrap()
+        );
+
+        let resp = query("/series/t?from=0&group_by=minute&aggregators=mean&limit=10&format_version=2&stream=true").await;
+        assert_eq!(StatusCode::OK, resp.status());
+        assert_eq!(
+            "{\"timestamp\":\"1970-01-01T00:00:00+00:00\",\"values\":[{\"type\":\"mean\",\"value\":2.0}]}\n",
+            std::str::from_utf8(resp.body()).unwrap()
+        );
+
+        let resp = query("/series/t?from=0&group_by=minute&aggregators=mean&limit=10&format_version=3").await;
+        assert_eq!(StatusCode::BAD_REQUEST, resp.status());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_flat_aggregation_non_finite() {
+        assert_eq!(
+            "{\"type\":\"mean\",\"value\":null}",
+            serde_json::to_string(&FlatAggregation(Aggregation::Mean(f64::NAN))).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_query_stream() -> Result<(), Error> {
+        let series_table = series_table::test::create()?;
+        series_table.create("t")?;
+        series_table.writer("t").unwrap().append(
+            &(0..10_000)
+                .map(|i| Entry { ts: i * 1000, value: i as f64 })
+                .collect::<Vec<Entry>>(),
+        )?;
+
+        let filter = super::filter(series_table.series_table.clone());
+        let query = |path: &'static str| warp::test::request().method("GET").path(path).reply(&filter);
+
+        let path = "/series/t?from=0&group_by=minute&aggregators=mean,max&limit=100";
+        let resp = query(path).await;
+        assert_eq!(StatusCode::OK, resp.status());
+        let buffered: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+
+        let resp = query("/series/t?from=0&group_by=minute&aggregators=mean,max&limit=100&stream=true").await;
+        assert_eq!(StatusCode::OK, resp.status());
+        assert_eq!("application/x-ndjson", resp.headers()["Content-Type"]);
+        let streamed = std::str::from_utf8(resp.body())
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect::<Vec<serde_json::Value>>();
+
+        assert_eq!(100, streamed.len());
+        assert_eq!(buffered["rows"], serde_json::Value::Array(streamed));
+
+        let resp = query("/series/t?from=0&group_by=minute&aggregators=avg:mean&limit=1&named=true&stream=true").await;
+        assert_eq!(
+            "{\"timestamp\":\"1970-01-01T00:00:00+00:00\",\"values\":{\"avg\":29.5}}\n",
+            std::str::from_utf8(resp.body()).unwrap()
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_query_plan() -> Result<(), Error> {
+        let series_table = series_table::test::create()?;
+        series_table.create("t")?;
+        for i in 1..=3 {
+            series_table.writer("t").unwrap().append(&vec![Entry { ts: i * 60000, value: 1.0 }])?;
+        }
+        let data_offset = series_table.reader("t").unwrap().commit().data_offset;
+
+        let resp = warp::test::request()
+            .method("GET")
+            .path("/series/t?from=0&group_by=minute&aggregators=mean&limit=10&plan=true")
+            .reply(&super::filter(series_table.series_table.clone()))
+            .await;
+
+        assert_eq!(StatusCode::OK, resp.status());
+        assert_eq!(
+            serde_json::json!({
+                "from": 0,
+                "to": i64::MAX,
+                "scan": {"start_offset": 0, "end_offset": data_offset, "blocks": 3, "skipped": false},
+            }),
+            serde_json::from_slice::<serde_json::Value>(resp.body()).unwrap()
+        );
+
+        Ok(())
+    }
 }
@@ -4,12 +4,25 @@ use crate::storage::error::Error;
 use crate::storage::{Entry, SeriesTable, SeriesWriter};
 use bytes::buf::Buf;
 use futures::{Stream, StreamExt};
+use hyper::{Client, Uri};
+use serde_derive::Deserialize;
+use std::fmt::Display;
 use std::sync::Arc;
+use std::time::Duration;
 use warp::reject::Rejection;
 use warp::{http::StatusCode, Filter};
 
-enum ImportError {
+/// Entries appended per batch unless configured with `filter_with_batch_size`.
+pub const DEFAULT_BATCH_SIZE: usize = 1024 * 1024;
+
+/// Time to wait for the response of the restore `url` and for each chunk of its body,
+/// unless configured with `filter_with_fetch_timeout`.
+pub const FETCH_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub enum ImportError {
     Parse(String),
+    /// The body could not be read, e.g. the client disconnected.
+    Body(String),
     Internal(Error),
 }
 
@@ -29,45 +42,191 @@ impl From<ImportError> for Rejection {
     fn from(err: ImportError) -> Rejection {
         match err {
             ImportError::Parse(reason) => super::error::bad_request(reason),
+            ImportError::Body(reason) => super::error::bad_request(reason),
             ImportError::Internal(reason) => super::error::internal(reason),
         }
     }
 }
 
-async fn import_entries<S, B>(body: S, writer: Arc<SeriesWriter>) -> Result<(), ImportError>
+#[derive(Deserialize)]
+pub struct RestoreParams {
+    /// `http` URL of the csv to restore from instead of the request body.
+    pub url: Option<String>,
+}
+
+/// Progress of the import, reported after each appended batch.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ImportProgress {
+    /// Bytes of the body read so far.
+    pub bytes: u64,
+    /// Entries parsed so far.
+    pub entries: usize,
+    /// Entries written so far, the stale entries are skipped.
+    pub written: usize,
+}
+
+/// Appends the csv entries of the body to the writer, returns the number of entries written.
+pub async fn import_entries<S, B, E>(body: S, writer: Arc<SeriesWriter>) -> Result<usize, ImportError>
 where
-    S: Stream<Item = Result<B, warp::Error>> + Send + 'static + Unpin,
+    S: Stream<Item = Result<B, E>> + Send + 'static + Unpin,
+    B: Buf + Send,
+    E: Display,
+{
+    import_entries_with_progress(body, writer, DEFAULT_BATCH_SIZE, |_| {}).await
+}
+
+/// Same as `import_entries`, appends the entries in batches of `batch_size`,
+/// `progress` is called after each appended batch.
+pub async fn import_entries_with_progress<S, B, E, F>(
+    body: S,
+    writer: Arc<SeriesWriter>,
+    batch_size: usize,
+    mut progress: F,
+) -> Result<usize, ImportError>
+where
+    S: Stream<Item = Result<B, E>> + Send + 'static + Unpin,
     B: Buf + Send,
+    E: Display,
+    F: FnMut(&ImportProgress),
 {
     let mut csv = csv::ChunkedReader::new();
     let mut body = body.boxed();
     let mut entries_count = 0usize;
-    while let Some(Ok(mut chunk)) = body.next().await {
+    let mut written = 0usize;
+    let mut bytes = 0u64;
+    while let Some(chunk) = body.next().await {
+        let mut chunk = chunk.map_err(|e| ImportError::Body(format!("can not read the body: {}", e)))?;
+        bytes += chunk.remaining() as u64;
         for batch in csv
             .read(&mut chunk)
-            .buffering::<Result<Vec<Entry>, ()>>(1024 * 1024)
+            .buffering::<Result<Vec<Entry>, ()>>(batch_size)
         {
             let batch = batch.map_err(|_| ImportError::Parse("invalid csv".to_owned()))?;
 
             entries_count += batch.len();
 
-            writer.append_with_batch_size_async(10, batch).await?;
+            written += writer.append_with_batch_size_async(10, batch).await?;
 
             log::debug!("Imported {} entries", entries_count);
+
+            progress(&ImportProgress {
+                bytes,
+                entries: entries_count,
+                written,
+            });
         }
     }
+    if let Some(entry) = csv.finish_with(csv::read_csv_line) {
+        let entry = entry.map_err(|_| ImportError::Parse("invalid csv".to_owned()))?;
+
+        entries_count += 1;
+
+        written += writer.append_with_batch_size_async(10, vec![entry]).await?;
+
+        progress(&ImportProgress {
+            bytes,
+            entries: entries_count,
+            written,
+        });
+    }
     log::debug!("Import completed, imported {} entries", entries_count);
-    Ok(())
+    Ok(written)
+}
+
+/// Reads the csv entries of the whole body, so nothing is appended if any line is malformed.
+pub async fn read_entries<S, B, E>(body: S) -> Result<Vec<Entry>, ImportError>
+where
+    S: Stream<Item = Result<B, E>> + Send + 'static + Unpin,
+    B: Buf + Send,
+    E: Display,
+{
+    let mut csv = csv::ChunkedReader::new();
+    let mut body = body.boxed();
+    let mut entries = Vec::new();
+    while let Some(chunk) = body.next().await {
+        let mut chunk = chunk.map_err(|e| ImportError::Body(format!("can not read the body: {}", e)))?;
+        for entry in csv.read(&mut chunk) {
+            entries.push(entry.map_err(|_| ImportError::Parse("invalid csv".to_owned()))?);
+        }
+    }
+    if let Some(entry) = csv.finish_with(csv::read_csv_line) {
+        entries.push(entry.map_err(|_| ImportError::Parse("invalid csv".to_owned()))?);
+    }
+    Ok(entries)
+}
+
+/// Parses the restore URL, only `http` URLs of the allowed hosts (`host` or `host:port`)
+/// are accepted, so the server can not be used to reach arbitrary hosts. The server has
+/// no TLS client, so `https` URLs are rejected as well.
+fn parse_url(url: &str, allowed_hosts: &[String]) -> Result<Uri, Rejection> {
+    let uri = url
+        .parse::<Uri>()
+        .map_err(|_| super::error::bad_request(format!("invalid url '{}'", url)))?;
+    if uri.scheme_str() != Some("http") {
+        return Err(super::error::bad_request("only http urls are supported, https is not"));
+    }
+    let allowed = match (uri.host(), uri.authority()) {
+        (Some(host), Some(authority)) => allowed_hosts
+            .iter()
+            .any(|allowed| allowed == host || allowed == authority.as_str()),
+        _ => false,
+    };
+    if !allowed {
+        return Err(super::error::bad_request(format!("host of '{}' is not allowed", url)));
+    }
+    Ok(uri)
 }
 
 async fn restore<S, B>(
     name: String,
+    params: RestoreParams,
+    batch_size: usize,
+    allowed_hosts: Arc<Vec<String>>,
+    fetch_timeout: Duration,
     series_table: Arc<SeriesTable>,
     body: S,
 ) -> Result<StatusCode, Rejection>
 where
     S: Stream<Item = Result<B, warp::Error>> + Send + 'static + Unpin,
     B: Buf + Send,
+{
+    let url = match params.url {
+        Some(url) => url,
+        None => return restore_from(name, batch_size, series_table, body).await,
+    };
+
+    let uri = parse_url(&url, &allowed_hosts)?;
+    let resp = tokio::time::timeout(fetch_timeout, Client::new().get(uri))
+        .await
+        .map_err(|_| super::error::bad_request(format!("can not fetch '{}': timed out", url)))?
+        .map_err(|e| super::error::bad_request(format!("can not fetch '{}': {}", url, e)))?;
+    if !resp.status().is_success() {
+        return Err(super::error::bad_request(format!("can not fetch '{}': {}", url, resp.status())));
+    }
+
+    // the restore fails if the next chunk of the body is not received in time
+    let body = futures::stream::unfold(resp.into_body(), move |mut body| async move {
+        match tokio::time::timeout(fetch_timeout, body.next()).await {
+            Ok(Some(chunk)) => Some((chunk.map_err(|e| e.to_string()), body)),
+            Ok(None) => None,
+            Err(_) => Some((Err("timed out".to_owned()), body)),
+        }
+    });
+
+    restore_from(name, batch_size, series_table, Box::pin(body)).await
+}
+
+/// Imports the csv body into the temporary series and renames it to `name`.
+async fn restore_from<S, B, E>(
+    name: String,
+    batch_size: usize,
+    series_table: Arc<SeriesTable>,
+    body: S,
+) -> Result<StatusCode, Rejection>
+where
+    S: Stream<Item = Result<B, E>> + Send + 'static + Unpin,
+    B: Buf + Send,
+    E: Display,
 {
     let series_name = series_table.create_temp()?;
 
@@ -78,7 +237,7 @@ where
         ))
     })?;
 
-    import_entries(body, writer).await?;
+    import_entries_with_progress(body, writer, batch_size, |_| {}).await?;
 
     if !series_table.rename(&series_name, &name)? {
         #[rustfmt::skip]
@@ -90,8 +249,44 @@ where
 }
 
 pub fn filter(series_table: Arc<SeriesTable>) -> warp::filters::BoxedFilter<(impl warp::Reply,)> {
+    filter_with_batch_size(series_table, DEFAULT_BATCH_SIZE)
+}
+
+/// Same as `filter`, the restored entries are appended in batches of `batch_size`.
+/// Panics if `batch_size` is zero.
+pub fn filter_with_batch_size(
+    series_table: Arc<SeriesTable>,
+    batch_size: usize,
+) -> warp::filters::BoxedFilter<(impl warp::Reply,)> {
+    filter_with_allowed_hosts(series_table, batch_size, Vec::new())
+}
+
+/// Same as `filter_with_batch_size`, the series could be restored from the `url` of
+/// the `allowed_hosts`. The restores from URLs are rejected if there are none.
+pub fn filter_with_allowed_hosts(
+    series_table: Arc<SeriesTable>,
+    batch_size: usize,
+    allowed_hosts: Vec<String>,
+) -> warp::filters::BoxedFilter<(impl warp::Reply,)> {
+    filter_with_fetch_timeout(series_table, batch_size, allowed_hosts, FETCH_TIMEOUT)
+}
+
+/// Same as `filter_with_allowed_hosts`, the restores from URLs fail if the response or
+/// the next chunk of its body is not received within `fetch_timeout`.
+pub fn filter_with_fetch_timeout(
+    series_table: Arc<SeriesTable>,
+    batch_size: usize,
+    allowed_hosts: Vec<String>,
+    fetch_timeout: Duration,
+) -> warp::filters::BoxedFilter<(impl warp::Reply,)> {
+    assert!(batch_size > 0, "the import batch size must be positive");
+    let allowed_hosts = Arc::new(allowed_hosts);
     warp::path!("series" / String / "restore")
         .and(warp::post())
+        .and(warp::query::<RestoreParams>())
+        .and(warp::any().map(move || batch_size))
+        .and(warp::any().map(move || allowed_hosts.clone()))
+        .and(warp::any().map(move || fetch_timeout))
         .and(super::with_series_table(series_table.clone()))
         .and(warp::body::stream())
         .and_then(self::restore)
@@ -147,4 +342,151 @@ mod test {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_import_progress() -> Result<(), Error> {
+        let series_table = series_table::test::create()?;
+        series_table.create("t")?;
+
+        let chunks = ["1; 1.0\n2; 2", ".0\n3; 3.0\n", "4; 4.0\n5; 5.0"];
+        let body = futures::stream::iter(
+            chunks
+                .iter()
+                .map(|chunk| Ok::<_, warp::Error>(bytes::Bytes::from(*chunk)))
+                .collect::<Vec<_>>(),
+        );
+
+        let mut reported = Vec::new();
+        let writer = series_table.writer("t").unwrap();
+        let written = import_entries_with_progress(body, writer, DEFAULT_BATCH_SIZE, |progress| reported.push(*progress))
+        .await
+        .map_err(|_| Error::Other("import failed".to_owned()))?;
+
+        assert_eq!(5, written);
+        assert_eq!(vec![1, 3, 4, 5], reported.iter().map(|progress| progress.entries).collect::<Vec<usize>>());
+        assert!(reported.windows(2).all(|pair| pair[0].bytes <= pair[1].bytes && pair[0].written < pair[1].written));
+        assert_eq!(
+            ImportProgress {
+                bytes: chunks.concat().len() as u64,
+                entries: 5,
+                written: 5,
+            },
+            *reported.last().unwrap()
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_restore_storage_errors() -> Result<(), Error> {
+        let fp = Arc::new(Failpoints::create());
+        let series_table = series_table::test::create_with_failpoints(fp.clone())?;
+
+        fp.on("series_writer::data_file_too_big");
+
+        let resp = warp::test::request()
+            .method("POST")
+            .path("/series/t/restore")
+            .body("1; 12.3\n")
+            .reply(&super::filter(series_table.series_table.clone()))
+            .await;
+
+        assert_eq!(StatusCode::INSUFFICIENT_STORAGE, resp.status());
+
+        fp.off("series_writer::data_file_too_big");
+        fp.on("series_writer::data_writer::write_block");
+
+        let resp = warp::test::request()
+            .method("POST")
+            .path("/series/t/restore")
+            .body("1; 12.3\n")
+            .reply(&super::filter(series_table.series_table.clone()))
+            .await;
+
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, resp.status());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_restore_from_url() -> Result<(), Error> {
+        let series_table = series_table::test::create()?;
+
+        let csv = warp::path!("t.csv").map(|| "1; 12.3\n3; 13.4\n");
+        let (addr, server) = warp::serve(csv).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        let filter = super::filter_with_allowed_hosts(
+            series_table.series_table.clone(),
+            DEFAULT_BATCH_SIZE,
+            vec!["127.0.0.1".to_owned()],
+        );
+        let restore = |url: String| {
+            warp::test::request()
+                .method("POST")
+                .path(&format!("/series/t/restore?url={}", url))
+                .reply(&filter)
+        };
+
+        let resp = restore(format!("http://{}/t.csv", addr)).await;
+        assert_eq!(StatusCode::OK, resp.status());
+        assert_eq!(
+            vec![Entry { ts: 1, value: 12.3 }, Entry { ts: 3, value: 13.4 }],
+            series_table.reader("t").unwrap().iterator(0)?.collect::<Result<Vec<Entry>, Error>>()?
+        );
+
+        let resp = restore(format!("http://{}/missing.csv", addr)).await;
+        assert_eq!(StatusCode::BAD_REQUEST, resp.status());
+
+        let resp = restore(format!("http://localhost:{}/t.csv", addr.port())).await;
+        assert_eq!(StatusCode::BAD_REQUEST, resp.status());
+
+        let resp = restore(format!("https://{}/t.csv", addr)).await;
+        assert_eq!(StatusCode::BAD_REQUEST, resp.status());
+
+        // the restores from urls are disabled by default
+        let resp = warp::test::request()
+            .method("POST")
+            .path(&format!("/series/u/restore?url=http://{}/t.csv", addr))
+            .reply(&super::filter(series_table.series_table.clone()))
+            .await;
+        assert_eq!(StatusCode::BAD_REQUEST, resp.status());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_restore_from_url_timeout() -> Result<(), Error> {
+        let series_table = series_table::test::create()?;
+
+        let slow = warp::path!("slow.csv").then(|| async {
+            tokio::time::sleep(Duration::from_secs(10)).await;
+            "1; 12.3\n"
+        });
+        let stalled = warp::path!("stalled.csv").map(|| {
+            let chunks = futures::stream::iter(vec![Ok::<_, std::io::Error>("1; 12.3\n")]).chain(futures::stream::pending());
+            warp::reply::Response::new(hyper::Body::wrap_stream(chunks))
+        });
+        let (addr, server) = warp::serve(slow.or(stalled)).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        let filter = super::filter_with_fetch_timeout(
+            series_table.series_table.clone(),
+            DEFAULT_BATCH_SIZE,
+            vec!["127.0.0.1".to_owned()],
+            Duration::from_millis(200),
+        );
+
+        for path in &["slow.csv", "stalled.csv"] {
+            let resp = warp::test::request()
+                .method("POST")
+                .path(&format!("/series/t/restore?url=http://{}/{}", addr, path))
+                .reply(&filter)
+                .await;
+            assert_eq!(StatusCode::BAD_REQUEST, resp.status());
+            assert!(series_table.reader("t").is_none());
+        }
+
+        Ok(())
+    }
 }
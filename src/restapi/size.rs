@@ -0,0 +1,72 @@
+use crate::storage::SeriesTable;
+use std::sync::Arc;
+use warp::reject::Rejection;
+use warp::Filter;
+
+async fn size(name: String, series_table: Arc<SeriesTable>) -> Result<warp::reply::Json, Rejection> {
+    let reader = series_table
+        .reader(&name)
+        .ok_or_else(|| super::error::not_found(&name))?;
+
+    let size = tokio::task::spawn_blocking(move || reader.size())
+        .await
+        .unwrap()
+        .map_err(super::error::internal)?;
+
+    Ok(warp::reply::json(&size))
+}
+
+pub fn filter(series_table: Arc<SeriesTable>) -> warp::filters::BoxedFilter<(impl warp::Reply,)> {
+    warp::path!("series" / String / "size")
+        .and(warp::get())
+        .and(super::with_series_table(series_table.clone()))
+        .and_then(self::size)
+        .recover(super::error::handle)
+        .boxed()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::storage::error::Error;
+    use crate::storage::{series_table, Entry};
+    use serde_json::Value;
+    use warp::http::StatusCode;
+
+    #[tokio::test]
+    async fn test_size() -> Result<(), Error> {
+        let series_table = series_table::test::create()?;
+
+        let resp = warp::test::request()
+            .method("GET")
+            .path("/series/t/size")
+            .reply(&super::filter(series_table.series_table.clone()))
+            .await;
+
+        assert_eq!(StatusCode::NOT_FOUND, resp.status());
+
+        series_table.create("t")?;
+        series_table.writer("t").unwrap().append(&vec![
+            Entry { ts: 1, value: 1.2 },
+            Entry { ts: 2, value: 3.1 },
+        ])?;
+        series_table.writer("t").unwrap().append(&vec![Entry { ts: 3, value: 1.2 }])?;
+
+        let resp = warp::test::request()
+            .method("GET")
+            .path("/series/t/size")
+            .reply(&super::filter(series_table.series_table.clone()))
+            .await;
+
+        assert_eq!(StatusCode::OK, resp.status());
+
+        let size = serde_json::from_slice::<Value>(resp.body()).unwrap();
+        let commit = series_table.reader("t").unwrap().commit();
+
+        assert_eq!(commit.data_offset as u64, size["data_bytes"].as_u64().unwrap());
+        assert!(size["index_bytes"].as_u64().unwrap() > 0);
+        assert!(size["log_bytes"].as_u64().unwrap() > 0);
+        assert_eq!(3, size["entries_estimate"]);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,126 @@
+use crate::storage::scrubber::ScrubStats;
+use crate::storage::SeriesTable;
+use serde_derive::Serialize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use warp::reject::Rejection;
+use warp::Filter;
+
+#[derive(Serialize)]
+struct Stats {
+    series_count: usize,
+    data_bytes: u64,
+    index_bytes: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    scrub_passes: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    scrub_corrupted: Option<u64>,
+}
+
+async fn stats(
+    series_table: Arc<SeriesTable>,
+    scrub_stats: Option<Arc<ScrubStats>>,
+) -> Result<warp::reply::Json, Rejection> {
+    let mut stats = Stats {
+        series_count: 0,
+        data_bytes: 0,
+        index_bytes: 0,
+        scrub_passes: scrub_stats.as_ref().map(|scrub| scrub.passes.load(Ordering::Relaxed)),
+        scrub_corrupted: scrub_stats.as_ref().map(|scrub| scrub.corrupted.load(Ordering::Relaxed)),
+    };
+
+    for (_, reader) in series_table.iter_readers() {
+        let commit = reader.commit();
+        stats.series_count += 1;
+        stats.data_bytes += commit.data_offset as u64;
+        stats.index_bytes += commit.index_offset as u64;
+    }
+
+    Ok(warp::reply::json(&stats))
+}
+
+pub fn filter(series_table: Arc<SeriesTable>) -> warp::filters::BoxedFilter<(impl warp::Reply,)> {
+    filter_with_scrub_stats(series_table, None)
+}
+
+/// Reports the counters of the background scrubber as well.
+pub fn filter_with_scrub_stats(
+    series_table: Arc<SeriesTable>,
+    scrub_stats: Option<Arc<ScrubStats>>,
+) -> warp::filters::BoxedFilter<(impl warp::Reply,)> {
+    warp::path!("stats")
+        .and(warp::get())
+        .and(super::with_series_table(series_table.clone()))
+        .and(warp::any().map(move || scrub_stats.clone()))
+        .and_then(self::stats)
+        .recover(super::error::handle)
+        .boxed()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::storage::error::Error;
+    use crate::storage::scrubber::ScrubStats;
+    use crate::storage::{series_table, Entry};
+    use serde_json::Value;
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+    use warp::http::StatusCode;
+
+    #[tokio::test]
+    async fn test_stats() -> Result<(), Error> {
+        let series_table = series_table::test::create()?;
+
+        let stats = || async {
+            let resp = warp::test::request()
+                .method("GET")
+                .path("/stats")
+                .reply(&super::filter(series_table.series_table.clone()))
+                .await;
+
+            assert_eq!(StatusCode::OK, resp.status());
+
+            serde_json::from_slice::<Value>(resp.body()).unwrap()
+        };
+
+        let empty = stats().await;
+        assert_eq!(0, empty["series_count"]);
+        assert_eq!(0, empty["data_bytes"]);
+
+        series_table.create("t1")?;
+        series_table.create("t2")?;
+        series_table.writer("t1").unwrap().append(&vec![Entry { ts: 1, value: 1.2 }])?;
+        series_table.writer("t2").unwrap().append(&vec![Entry { ts: 1, value: 1.2 }])?;
+
+        let commit = series_table.reader("t1").unwrap().commit();
+
+        let stats = stats().await;
+        assert_eq!(2, stats["series_count"]);
+        assert_eq!(2 * commit.data_offset as u64, stats["data_bytes"]);
+        assert_eq!(2 * commit.index_offset as u64, stats["index_bytes"]);
+        assert!(stats.get("scrub_passes").is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_scrub_stats() -> Result<(), Error> {
+        let series_table = series_table::test::create()?;
+        let scrub_stats = Arc::new(ScrubStats::default());
+        scrub_stats.passes.store(3, Ordering::Relaxed);
+        scrub_stats.corrupted.store(1, Ordering::Relaxed);
+
+        let resp = warp::test::request()
+            .method("GET")
+            .path("/stats")
+            .reply(&super::filter_with_scrub_stats(series_table.series_table.clone(), Some(scrub_stats)))
+            .await;
+
+        assert_eq!(StatusCode::OK, resp.status());
+        let stats = serde_json::from_slice::<Value>(resp.body()).unwrap();
+        assert_eq!(3, stats["scrub_passes"]);
+        assert_eq!(1, stats["scrub_corrupted"]);
+
+        Ok(())
+    }
+}
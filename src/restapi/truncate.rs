@@ -0,0 +1,63 @@
+use crate::storage::SeriesTable;
+use std::sync::Arc;
+use warp::http::StatusCode;
+use warp::reject::Rejection;
+use warp::Filter;
+
+async fn truncate(name: String, series_table: Arc<SeriesTable>) -> Result<StatusCode, Rejection> {
+    let truncated = {
+        let name = name.clone();
+        tokio::task::spawn_blocking(move || series_table.truncate(&name))
+            .await
+            .unwrap()
+            .map_err(super::error::internal)?
+    };
+
+    match truncated {
+        true => Ok(StatusCode::NO_CONTENT),
+        false => Err(super::error::not_found(&name)),
+    }
+}
+
+pub fn filter(series_table: Arc<SeriesTable>) -> warp::filters::BoxedFilter<(impl warp::Reply,)> {
+    warp::path!("series" / String / "truncate")
+        .and(warp::post())
+        .and(super::with_series_table(series_table.clone()))
+        .and_then(self::truncate)
+        .recover(super::error::handle)
+        .boxed()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::storage::error::Error;
+    use crate::storage::{series_table, Entry};
+    use warp::http::StatusCode;
+
+    #[tokio::test]
+    async fn test_truncate() -> Result<(), Error> {
+        let series_table = series_table::test::create()?;
+
+        let resp = warp::test::request()
+            .method("POST")
+            .path("/series/t/truncate")
+            .reply(&super::filter(series_table.series_table.clone()))
+            .await;
+
+        assert_eq!(StatusCode::NOT_FOUND, resp.status());
+
+        series_table.create("t")?;
+        series_table.writer("t").unwrap().append(&vec![Entry { ts: 1, value: 1.0 }])?;
+
+        let resp = warp::test::request()
+            .method("POST")
+            .path("/series/t/truncate")
+            .reply(&super::filter(series_table.series_table.clone()))
+            .await;
+
+        assert_eq!(StatusCode::NO_CONTENT, resp.status());
+        assert_eq!(0, series_table.reader("t").unwrap().iterator(i64::MIN)?.count());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,183 @@
+use crate::csv;
+use crate::lineproto::{self, Point};
+use crate::storage::error::Error;
+use crate::storage::{Entry, SeriesTable, TimestampUnit};
+use bytes::buf::Buf;
+use futures::{Stream, StreamExt};
+use serde_derive::Deserialize;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+use warp::reject::Rejection;
+use warp::{http::StatusCode, Filter};
+
+#[derive(Deserialize)]
+pub struct WriteParams {
+    pub precision: Option<TimestampUnit>,
+}
+
+fn malformed(line: usize) -> Rejection {
+    super::error::bad_request(format!("malformed line {}", line))
+}
+
+async fn append_points(
+    series_table: &SeriesTable,
+    precision: TimestampUnit,
+    points: Vec<Point>,
+) -> Result<(), Rejection> {
+    let now = series_table.clock().now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos() as i64;
+
+    let mut batches: BTreeMap<String, Vec<(i64, f64)>> = BTreeMap::new();
+    for point in points {
+        let ts = match point.ts {
+            Some(ts) => ts,
+            None => TimestampUnit::Nanos.convert(now, precision).unwrap_or(now),
+        };
+        batches
+            .entry(point.measurement)
+            .or_default()
+            .push((ts, point.value));
+    }
+
+    // all batches are validated before any of them is appended
+    let mut appends = Vec::new();
+    for (name, points) in batches {
+        let writer = series_table.create_and_writer(&name).map_err(|e| match e {
+            Error::InvalidSeriesName => super::error::bad_request(format!("invalid series name '{}'", name)),
+            e => super::error::internal(e),
+        })?;
+        let unit = writer.config().timestamp_unit;
+
+        let entries = points
+            .into_iter()
+            .map(|(ts, value)| match precision.convert(ts, unit) {
+                Some(ts) => Ok(Entry { ts, value }),
+                None => Err(super::error::bad_request(format!("timestamp {} is out of range", ts))),
+            })
+            .collect::<Result<Vec<Entry>, Rejection>>()?;
+
+        appends.push((writer, entries));
+    }
+
+    for (writer, entries) in appends {
+        writer.append_async(entries).await?;
+    }
+
+    Ok(())
+}
+
+async fn write<S, B>(
+    params: WriteParams,
+    series_table: Arc<SeriesTable>,
+    body: S,
+) -> Result<StatusCode, Rejection>
+where
+    S: Stream<Item = Result<B, warp::Error>> + Send + 'static + Unpin,
+    B: Buf + Send,
+{
+    let precision = params.precision.unwrap_or(TimestampUnit::Nanos);
+
+    // the whole body is parsed first, so the malformed line doesn't leave a partial write
+    let mut reader = csv::ChunkedReader::new();
+    let mut body = body.boxed();
+    let mut line = 0usize;
+    let mut points = Vec::new();
+
+    while let Some(chunk) = body.next().await {
+        let mut chunk = chunk.map_err(|e| super::error::bad_request(format!("can not read the body: {}", e)))?;
+
+        for point in reader.read_with(&mut chunk, lineproto::read_line) {
+            line += 1;
+            points.extend(point.map_err(|_| malformed(line))?);
+        }
+    }
+
+    if let Some(point) = reader.finish_with(lineproto::read_line) {
+        line += 1;
+        points.extend(point.map_err(|_| malformed(line))?);
+    }
+
+    append_points(&series_table, precision, points).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub fn filter(series_table: Arc<SeriesTable>) -> warp::filters::BoxedFilter<(impl warp::Reply,)> {
+    warp::path!("write")
+        .and(warp::post())
+        .and(warp::query::<WriteParams>())
+        .and(super::with_series_table(series_table.clone()))
+        .and(warp::body::stream())
+        .and_then(self::write)
+        .recover(super::error::handle)
+        .boxed()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::storage::series_table;
+
+    #[tokio::test]
+    async fn test_write() -> Result<(), Error> {
+        let series_table = series_table::test::create()?;
+
+        let resp = warp::test::request()
+            .method("POST")
+            .path("/write")
+            .body("co2,room=kitchen value=410 1000000000\n\ntemp value=21.5 1000000000\nco2 value=420i 2000000000")
+            .reply(&super::filter(series_table.series_table.clone()))
+            .await;
+
+        assert_eq!(StatusCode::NO_CONTENT, resp.status());
+
+        let entries = |name: &str| {
+            series_table
+                .reader(name)
+                .unwrap()
+                .iterator(0)
+                .unwrap()
+                .collect::<Result<Vec<Entry>, Error>>()
+                .unwrap()
+        };
+
+        assert_eq!(
+            vec![Entry { ts: 1000, value: 410.0 }, Entry { ts: 2000, value: 420.0 }],
+            entries("co2")
+        );
+        assert_eq!(vec![Entry { ts: 1000, value: 21.5 }], entries("temp"));
+
+        let resp = warp::test::request()
+            .method("POST")
+            .path("/write?precision=s")
+            .body("co2 value=430 3\n")
+            .reply(&super::filter(series_table.series_table.clone()))
+            .await;
+
+        assert_eq!(StatusCode::NO_CONTENT, resp.status());
+        assert_eq!(Entry { ts: 3000, value: 430.0 }, entries("co2")[2]);
+
+        let resp = warp::test::request()
+            .method("POST")
+            .path("/write")
+            .body("co2 value=1 4000000000\n# comment\nco2 value=abc 5000000000\n")
+            .reply(&super::filter(series_table.series_table.clone()))
+            .await;
+
+        assert_eq!(StatusCode::BAD_REQUEST, resp.status());
+        assert!(std::str::from_utf8(resp.body()).unwrap().contains("malformed line 3"));
+        assert_eq!(3, entries("co2").len());
+
+        let resp = warp::test::request()
+            .method("POST")
+            .path("/write")
+            .body("co2 value=1 4000000000\n../co2 value=1 4000000000\n")
+            .reply(&super::filter(series_table.series_table.clone()))
+            .await;
+
+        assert_eq!(StatusCode::BAD_REQUEST, resp.status());
+        assert_eq!(3, entries("co2").len());
+
+        Ok(())
+    }
+}
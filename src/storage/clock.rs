@@ -0,0 +1,61 @@
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Source of the current time, so the time dependent behavior (rate limits,
+/// relative query timestamps, etc) can be tested with `TestClock`.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> SystemTime;
+
+    /// Millis since epoch, negative before epoch.
+    fn now_millis(&self) -> i64 {
+        match self.now().duration_since(UNIX_EPOCH) {
+            Ok(since) => since.as_millis() as i64,
+            Err(error) => -(error.duration().as_millis() as i64),
+        }
+    }
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// Clock which stays at the same time until advanced.
+pub struct TestClock {
+    now: Mutex<SystemTime>,
+}
+
+impl TestClock {
+    pub fn new(now: SystemTime) -> TestClock {
+        TestClock { now: Mutex::new(now) }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        *self.now.lock().unwrap() += duration;
+    }
+}
+
+impl Clock for TestClock {
+    fn now(&self) -> SystemTime {
+        *self.now.lock().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_clock() {
+        let clock = TestClock::new(UNIX_EPOCH + Duration::from_millis(1500));
+        assert_eq!(1500, clock.now_millis());
+
+        clock.advance(Duration::from_secs(60));
+        assert_eq!(61500, clock.now_millis());
+
+        assert_eq!(-1000, TestClock::new(UNIX_EPOCH - Duration::from_secs(1)).now_millis());
+    }
+}
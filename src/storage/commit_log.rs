@@ -4,22 +4,72 @@ use super::super::failpoints::Failpoints;
 use super::error::Error;
 use super::file_system::{FileKind, OpenMode, SeriesDir};
 use super::io_utils::{ReadBytes, WriteBytes};
-use crc::crc16;
+use crc::{crc16, crc32};
+use serde_derive::Serialize;
 use std::collections::VecDeque;
 use std::fs::File;
 use std::io::prelude::*;
 use std::io::{self, BufWriter};
+use std::str::FromStr;
 use std::sync::{Arc, RwLock};
 
-const COMMIT_SIZE: usize = 4 + 4 + 8 + 2;
+const COMMIT_SIZE: usize = 4 + 4 + 8;
+
+/// Log files starting with the magic contain versioned commits, each prefixed
+/// with a byte selecting the checksum. Older log files contain commits with
+/// CRC16 and no version byte.
+const LOG_MAGIC: [u8; 4] = *b"MSLG";
 
 #[cfg(not(test))]
 const MAX_LOG_SIZE: usize = 2 * 1024 * 1024;
 
 #[cfg(test)]
-const MAX_LOG_SIZE: usize = 80;
+const MAX_LOG_SIZE: usize = 100;
+
+/// The checksum of the written commits, the commits with either checksum are read.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum Checksum {
+    Crc16,
+    #[default]
+    Crc32c,
+}
+
+impl Checksum {
+    fn version(&self) -> u8 {
+        match self {
+            Checksum::Crc16 => 1,
+            Checksum::Crc32c => 2,
+        }
+    }
+    fn from_version(version: u8) -> Option<Checksum> {
+        match version {
+            1 => Some(Checksum::Crc16),
+            2 => Some(Checksum::Crc32c),
+            _ => None,
+        }
+    }
+    fn size(&self) -> usize {
+        match self {
+            Checksum::Crc16 => 2,
+            Checksum::Crc32c => 4,
+        }
+    }
+}
+
+impl FromStr for Checksum {
+    type Err = ();
 
-#[derive(Debug, PartialEq, Clone)]
+    /// Parses `crc16` or `crc32c`.
+    fn from_str(s: &str) -> Result<Checksum, Self::Err> {
+        match s {
+            "crc16" => Ok(Checksum::Crc16),
+            "crc32c" => Ok(Checksum::Crc32c),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize)]
 pub struct Commit {
     pub data_offset: u32,
     pub index_offset: u32,
@@ -27,36 +77,63 @@ pub struct Commit {
 }
 
 impl Commit {
-    fn checksum(&self) -> u16 {
-        let table = &crc16::USB_TABLE;
-        let mut checksum = 0u16;
-
-        checksum = crc16::update(checksum, table, &self.data_offset.to_be_bytes());
-        checksum = crc16::update(checksum, table, &self.index_offset.to_be_bytes());
-        checksum = crc16::update(checksum, table, &self.highest_ts.to_be_bytes());
-
-        checksum
+    fn to_bytes(&self) -> [u8; COMMIT_SIZE] {
+        let mut bytes = [0u8; COMMIT_SIZE];
+        bytes[0..4].copy_from_slice(&self.data_offset.to_be_bytes());
+        bytes[4..8].copy_from_slice(&self.index_offset.to_be_bytes());
+        bytes[8..16].copy_from_slice(&self.highest_ts.to_be_bytes());
+        bytes
     }
-    fn read<R: Read>(read: &mut R) -> Result<Commit, Error> {
-        let commit = Commit {
+    fn crc16(&self) -> u16 {
+        crc16::update(0u16, &crc16::USB_TABLE, &self.to_bytes())
+    }
+    fn crc32c(&self, version: u8) -> u32 {
+        let checksum = crc32::update(0u32, &crc32::CASTAGNOLI_TABLE, &[version]);
+        crc32::update(checksum, &crc32::CASTAGNOLI_TABLE, &self.to_bytes())
+    }
+    fn read_fields<R: Read>(read: &mut R) -> Result<Commit, Error> {
+        Ok(Commit {
             data_offset: read.read_u32()?,
             index_offset: read.read_u32()?,
             highest_ts: read.read_i64()?,
-        };
-
-        let checksum = read.read_u16()?;
+        })
+    }
+    /// Reads the commit written by the older versions: no version byte and CRC16.
+    fn read_legacy<R: Read>(read: &mut R) -> Result<Commit, Error> {
+        let commit = Commit::read_fields(read)?;
 
-        if checksum != commit.checksum() {
+        if read.read_u16()? != commit.crc16() {
             return Err(Error::Crc16Mismatch);
         }
 
         Ok(commit)
     }
+    fn read<R: Read>(read: &mut R) -> Result<Commit, Error> {
+        let mut version = [0u8; 1];
+        read.read_exact(&mut version)?;
+
+        let checksum = Checksum::from_version(version[0]).ok_or(Error::ChecksumMismatch)?;
+
+        let commit = Commit::read_fields(read)?;
+
+        let valid = match checksum {
+            Checksum::Crc16 => read.read_u16()? == commit.crc16(),
+            Checksum::Crc32c => read.read_u32()? == commit.crc32c(version[0]),
+        };
+
+        if !valid {
+            return Err(Error::ChecksumMismatch);
+        }
+
+        Ok(commit)
+    }
     fn write<W: Write>(
         &self,
         write: &mut W,
+        checksum: Checksum,
         #[cfg(test)] fp: Arc<Failpoints>,
     ) -> Result<(), Error> {
+        write.write_all(&[checksum.version()])?;
         write.write_u32(&self.data_offset)?;
         write.write_u32(&self.index_offset)?;
 
@@ -67,7 +144,10 @@ impl Commit {
         );
         
         write.write_i64(&self.highest_ts)?;
-        write.write_u16(&self.checksum())?;
+        match checksum {
+            Checksum::Crc16 => write.write_u16(&self.crc16())?,
+            Checksum::Crc32c => write.write_u32(&self.crc32c(checksum.version()))?,
+        };
         Ok(())
     }
 }
@@ -86,23 +166,59 @@ mod test_commit {
 
         let mut buf = Vec::new();
 
-        commit.write(&mut buf, Arc::new(Failpoints::create()))?;
+        commit.write_legacy(&mut buf)?;
 
-        assert_eq!(commit, Commit::read(&mut &buf[..])?);
+        assert_eq!(commit, Commit::read_legacy(&mut &buf[..])?);
 
-        buf[COMMIT_SIZE - 2] = 23;
-        buf[COMMIT_SIZE - 1] = 21;
+        buf[COMMIT_SIZE] = 23;
+        buf[COMMIT_SIZE + 1] = 21;
 
-        assert!(match Commit::read(&mut &buf[..]) {
-            Err(Error::Crc16Mismatch) => true,
-            _ => false,
-        });
+        assert!(matches!(Commit::read_legacy(&mut &buf[..]), Err(Error::Crc16Mismatch)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_write_versioned() -> Result<(), Error> {
+        let commit = Commit {
+            data_offset: 123,
+            index_offset: 321,
+            highest_ts: -110,
+        };
+
+        for checksum in [Checksum::Crc16, Checksum::Crc32c] {
+            let mut buf = Vec::new();
+
+            commit.write(&mut buf, checksum, Arc::new(Failpoints::create()))?;
+
+            assert_eq!(1 + COMMIT_SIZE + checksum.size(), buf.len());
+            assert_eq!(commit, Commit::read(&mut &buf[..])?);
+
+            let mut corrupted = buf.clone();
+            corrupted[5] ^= 1;
+
+            assert!(matches!(Commit::read(&mut &corrupted[..]), Err(Error::ChecksumMismatch)));
+
+            let mut corrupted = buf.clone();
+            corrupted[0] = 100;
+
+            assert!(matches!(Commit::read(&mut &corrupted[..]), Err(Error::ChecksumMismatch)));
+        }
 
         Ok(())
     }
 }
 
-const FIRST: Commit = Commit {
+#[cfg(test)]
+impl Commit {
+    fn write_legacy<W: Write>(&self, write: &mut W) -> Result<(), Error> {
+        write.write_all(&self.to_bytes())?;
+        write.write_u16(&self.crc16())?;
+        Ok(())
+    }
+}
+
+pub(super) const FIRST: Commit = Commit {
     data_offset: 0,
     index_offset: 0,
     highest_ts: i64::MIN,
@@ -116,39 +232,17 @@ struct Interior {
     current_size: usize,
     failure: bool,
     writer: BufWriter<File>,
+    checksum: Checksum,
     #[cfg(test)]
     #[allow(dead_code)]
     fp: Arc<Failpoints>,
 }
 
 impl Interior {
-    fn open(dir: Arc<SeriesDir>, #[cfg(test)] fp: Arc<Failpoints>) -> Result<Interior, Error> {
+    fn open(dir: Arc<SeriesDir>, checksum: Checksum, #[cfg(test)] fp: Arc<Failpoints>) -> Result<Interior, Error> {
         let mut seqs: VecDeque<u64> = dir.read_log_sequences()?.into();
 
-        let mut current: Option<Commit> = None;
-        for seq in seqs.iter() {
-            let mut file = dir.open(FileKind::Log(*seq), OpenMode::Write)?;
-            loop {
-                match Commit::read(&mut file) {
-                    Err(Error::Crc16Mismatch) => {
-                        log::warn!("crc16 mismatch in log {:?}", &file);
-                        break;
-                    }
-                    Err(Error::Io(error)) => match error.kind() {
-                        io::ErrorKind::UnexpectedEof => break,
-                        _ => return Err(Error::Io(error)),
-                    },
-                    Err(error) => return Err(error),
-                    Ok(entry) => current = Some(entry),
-                }
-            }
-
-            if let Some(_) = current {
-                break;
-            }
-        }
-
-        let current = current.unwrap_or(FIRST);
+        let current = Interior::read_current(&dir, seqs.iter())?.unwrap_or(FIRST);
 
         let current_seq = seqs.front().map(|seq| seq + 1).unwrap_or(0);
 
@@ -161,7 +255,8 @@ impl Interior {
             current_size: 0,
             seqs: seqs,
             failure: false,
-            writer: BufWriter::new(dir.open(FileKind::Log(current_seq), OpenMode::Write)?),
+            writer: Interior::create_log(&dir, current_seq)?,
+            checksum,
             #[cfg(test)]
             fp: fp,
         };
@@ -170,6 +265,65 @@ impl Interior {
 
         Ok(commit_log)
     }
+    /// Returns the last valid commit from the most recent log file containing commits.
+    /// The log files removed in the meantime (by the writer process) are skipped.
+    fn read_current<'a, S: Iterator<Item = &'a u64>>(dir: &SeriesDir, seqs: S) -> Result<Option<Commit>, Error> {
+        for seq in seqs {
+            let file = match dir.open(FileKind::Log(*seq), OpenMode::Read) {
+                Ok(file) => file,
+                Err(Error::Io(error)) if error.kind() == io::ErrorKind::NotFound => continue,
+                Err(error) => return Err(error),
+            };
+
+            if let Some(current) = Interior::read_log(file)? {
+                return Ok(Some(current));
+            }
+        }
+        Ok(None)
+    }
+    /// Returns the last valid commit in the log file.
+    fn read_log(mut file: File) -> Result<Option<Commit>, Error> {
+        let mut magic = [0u8; 4];
+        let versioned = match file.read_exact(&mut magic) {
+            Ok(()) => magic == LOG_MAGIC,
+            Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => false,
+            Err(error) => return Err(error.into()),
+        };
+
+        if !versioned {
+            file.seek(io::SeekFrom::Start(0))?;
+        }
+
+        let mut current = None;
+        loop {
+            let commit = match versioned {
+                true => Commit::read(&mut file),
+                false => Commit::read_legacy(&mut file),
+            };
+            match commit {
+                Err(Error::Crc16Mismatch) | Err(Error::ChecksumMismatch) => {
+                    log::warn!("checksum mismatch in log {:?}", &file);
+                    break;
+                }
+                Err(Error::Io(error)) => match error.kind() {
+                    io::ErrorKind::UnexpectedEof => break,
+                    _ => return Err(Error::Io(error)),
+                },
+                Err(error) => return Err(error),
+                Ok(entry) => current = Some(entry),
+            }
+        }
+
+        Ok(current)
+    }
+    fn create_log(dir: &SeriesDir, seq: u64) -> Result<BufWriter<File>, Error> {
+        let mut writer = BufWriter::new(dir.open(FileKind::Log(seq), OpenMode::Write)?);
+        writer.write_all(&LOG_MAGIC)?;
+
+        dir.sync_dir()?;
+
+        Ok(writer)
+    }
 }
 
 impl Interior {
@@ -187,7 +341,7 @@ impl Interior {
 
         self.writer.flush()?;
 
-        self.writer = BufWriter::new(self.dir.open(FileKind::Log(next_seq), OpenMode::Write)?);
+        self.writer = Interior::create_log(&self.dir, next_seq)?;
 
         self.current_seq = next_seq;
         self.current_size = 0;
@@ -220,6 +374,7 @@ impl Interior {
 
         match commit.write(
             &mut self.writer,
+            self.checksum,
             #[cfg(test)]
             self.fp.clone(),
         ) {
@@ -241,7 +396,7 @@ impl Interior {
         };
 
         self.current = Arc::new(commit);
-        self.current_size += COMMIT_SIZE;
+        self.current_size += 1 + COMMIT_SIZE + self.checksum.size();
 
         Ok(())
     }
@@ -271,7 +426,7 @@ mod test {
         let dir = fs.series("series1")?;
 
         {
-            let mut log = Interior::open(dir.clone(), fp.clone())?;
+            let mut log = Interior::open(dir.clone(), Checksum::default(), fp.clone())?;
 
             assert_eq!(Arc::new(FIRST), log.current());
 
@@ -287,7 +442,7 @@ mod test {
         }
 
         {
-            let mut log = Interior::open(dir.clone(), fp.clone())?;
+            let mut log = Interior::open(dir.clone(), Checksum::default(), fp.clone())?;
             assert_eq!(Arc::new(commit(4)), log.current());
             log.commit(commit(5))?;
             log.commit(commit(6))?;
@@ -297,18 +452,55 @@ mod test {
 
         {
             let mut file = dir.open(FileKind::Log(1), OpenMode::Write)?;
-            file.seek(SeekFrom::Start(COMMIT_SIZE as u64 + 1))?;
+            file.seek(SeekFrom::Start((LOG_MAGIC.len() + 1 + COMMIT_SIZE + Checksum::default().size() + 1) as u64))?;
             file.write(&[1, 2, 3])?;
         }
 
         {
-            let log = Interior::open(dir.clone(), fp.clone())?;
+            let log = Interior::open(dir.clone(), Checksum::default(), fp.clone())?;
             assert_eq!(Arc::new(commit(4)), log.current());
         }
 
         Ok(())
     }
 
+    #[test]
+    fn test_checksum() -> Result<(), Error> {
+        let fs = file_system::test::open()?;
+        let fp = Arc::new(Failpoints::create());
+        let dir = fs.series("series1")?;
+
+        {
+            let mut log = Interior::open(dir.clone(), Checksum::Crc16, fp.clone())?;
+            log.commit(commit(1))?;
+            log.commit(commit(2))?;
+        }
+
+        {
+            let mut file = dir.open(FileKind::Log(0), OpenMode::Read)?;
+            file.seek(SeekFrom::Start(LOG_MAGIC.len() as u64))?;
+            assert_eq!(Checksum::Crc16.version(), file.read_u8()?);
+        }
+
+        // the commits written with the other checksum are still read
+        {
+            let mut log = Interior::open(dir.clone(), Checksum::Crc32c, fp.clone())?;
+            assert_eq!(Arc::new(commit(2)), log.current());
+            log.commit(commit(3))?;
+        }
+
+        {
+            let log = Interior::open(dir.clone(), Checksum::Crc16, fp.clone())?;
+            assert_eq!(Arc::new(commit(3)), log.current());
+        }
+
+        assert_eq!(Ok(Checksum::Crc16), "crc16".parse());
+        assert_eq!(Ok(Checksum::Crc32c), "crc32c".parse());
+        assert_eq!(Err(()), "xxhash".parse::<Checksum>());
+
+        Ok(())
+    }
+
     #[test]
     fn test_rotate() -> Result<(), Error> {
         let fs = file_system::test::open()?;
@@ -316,7 +508,7 @@ mod test {
         let dir = fs.series("series1")?;
 
         {
-            let mut log = Interior::open(dir.clone(), fp.clone())?;
+            let mut log = Interior::open(dir.clone(), Checksum::default(), fp.clone())?;
 
             for i in 0..19 {
                 log.commit(commit(i))?;
@@ -326,7 +518,7 @@ mod test {
         }
 
         {
-            let log = Interior::open(dir.clone(), fp.clone())?;
+            let log = Interior::open(dir.clone(), Checksum::default(), fp.clone())?;
 
             assert_eq!(Arc::new(commit(18)), log.current());
         }
@@ -341,7 +533,7 @@ mod test {
         let dir = fs.series("series1")?;
 
         {
-            let mut log = Interior::open(dir.clone(), fp.clone())?;
+            let mut log = Interior::open(dir.clone(), Checksum::default(), fp.clone())?;
 
             log.commit(commit(0))?;
             log.commit(commit(1))?;
@@ -354,35 +546,145 @@ mod test {
         }
 
         {
-            let log = Interior::open(dir.clone(), fp.clone())?;
+            let log = Interior::open(dir.clone(), Checksum::default(), fp.clone())?;
 
             assert_eq!(Arc::new(commit(2)), log.current());
         }
 
         Ok(())
     }
+
+    #[test]
+    fn test_legacy_log() -> Result<(), Error> {
+        let fs = file_system::test::open()?;
+        let fp = Arc::new(Failpoints::create());
+        let dir = fs.series("series1")?;
+
+        {
+            let mut file = dir.open(FileKind::Log(0), OpenMode::Write)?;
+            commit(1).write_legacy(&mut file)?;
+            commit(2).write_legacy(&mut file)?;
+        }
+
+        {
+            let mut log = Interior::open(dir.clone(), Checksum::default(), fp.clone())?;
+            assert_eq!(Arc::new(commit(2)), log.current());
+            log.commit(commit(3))?;
+        }
+
+        {
+            let log = Interior::open(dir.clone(), Checksum::default(), fp.clone())?;
+            assert_eq!(Arc::new(commit(3)), log.current());
+        }
+
+        assert_eq!(vec![2u64, 1u64, 0u64], dir.read_log_sequences()?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_negative_highest_ts() -> Result<(), Error> {
+        let fs = file_system::test::open()?;
+        let fp = Arc::new(Failpoints::create());
+        let dir = fs.series("series1")?;
+
+        let negative = Commit {
+            data_offset: 10,
+            index_offset: 12,
+            highest_ts: -1000,
+        };
+
+        {
+            let mut log = Interior::open(dir.clone(), Checksum::default(), fp.clone())?;
+            log.commit(negative.clone())?;
+        }
+
+        {
+            let log = Interior::open(dir.clone(), Checksum::default(), fp.clone())?;
+            assert_eq!(Arc::new(negative), log.current());
+        }
+
+        Ok(())
+    }
+}
+
+/// The commit log of the series written by another process. The current
+/// commit is re-read from the log files on `refresh`.
+struct ReadOnly {
+    dir: Arc<SeriesDir>,
+    current: Arc<Commit>,
+}
+
+impl ReadOnly {
+    fn open(dir: Arc<SeriesDir>) -> Result<ReadOnly, Error> {
+        let mut read_only = ReadOnly {
+            dir,
+            current: Arc::new(FIRST),
+        };
+        read_only.refresh()?;
+        Ok(read_only)
+    }
+    fn refresh(&mut self) -> Result<(), Error> {
+        let seqs = self.dir.read_log_sequences()?;
+        if let Some(current) = Interior::read_current(&self.dir, seqs.iter())? {
+            if *self.current != current {
+                self.current = Arc::new(current);
+            }
+        }
+        Ok(())
+    }
+}
+
+enum Log {
+    ReadWrite(Interior),
+    ReadOnly(ReadOnly),
 }
 
 pub struct CommitLog {
-    inter: Arc<RwLock<Interior>>,
+    inter: Arc<RwLock<Log>>,
 }
 
 impl CommitLog {
-    pub fn open(dir: Arc<SeriesDir>, #[cfg(test)] fp: Arc<Failpoints>) -> Result<CommitLog, Error> {
+    /// Opens the log, the new commits are written with the `checksum`.
+    pub fn open(dir: Arc<SeriesDir>, checksum: Checksum, #[cfg(test)] fp: Arc<Failpoints>) -> Result<CommitLog, Error> {
         Ok(CommitLog {
-            inter: Arc::new(RwLock::new(Interior::open(
+            inter: Arc::new(RwLock::new(Log::ReadWrite(Interior::open(
                 dir,
+                checksum,
                 #[cfg(test)]
                 fp,
-            )?)),
+            )?))),
+        })
+    }
+    /// Opens the log without creating a new log file, the commits are rejected.
+    pub fn open_read_only(dir: Arc<SeriesDir>) -> Result<CommitLog, Error> {
+        Ok(CommitLog {
+            inter: Arc::new(RwLock::new(Log::ReadOnly(ReadOnly::open(dir)?))),
         })
     }
     pub fn commit(&self, commit: Commit) -> Result<(), Error> {
         let mut inter = self.inter.write().unwrap();
-        inter.commit(commit)
+        match &mut *inter {
+            Log::ReadWrite(inter) => inter.commit(commit),
+            Log::ReadOnly(_) => Err(Error::ReadOnly),
+        }
     }
     pub fn current(&self) -> Arc<Commit> {
         let inter = self.inter.read().unwrap();
-        inter.current()
+        match &*inter {
+            Log::ReadWrite(inter) => inter.current(),
+            Log::ReadOnly(read_only) => read_only.current.clone(),
+        }
+    }
+    /// Re-reads the current commit from the disk if the log is opened read-only.
+    pub fn refresh(&self) -> Result<Arc<Commit>, Error> {
+        let mut inter = self.inter.write().unwrap();
+        match &mut *inter {
+            Log::ReadWrite(inter) => Ok(inter.current()),
+            Log::ReadOnly(read_only) => {
+                read_only.refresh()?;
+                Ok(read_only.current.clone())
+            }
+        }
     }
 }
@@ -5,13 +5,16 @@ use flate2::read::DeflateDecoder;
 use flate2::write::DeflateEncoder;
 use flate2::Compression as DeflateCompression;
 use integer_encoding::{VarInt, VarIntWriter};
+use serde_derive::{Deserialize, Serialize};
 use std::convert::TryInto;
 use std::io::{Cursor, Write};
 
-#[derive(Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum Compression {
     None,
     Deflate,
+    #[default]
     Delta,
 }
 
@@ -47,42 +50,44 @@ fn write_deflate<W: Write>(block: &[&Entry], to: &mut W) -> Result<(), Error> {
     Ok(())
 }
 
-fn read_raw(from: &[u8], size: usize) -> Result<Vec<Entry>, Error> {
+fn read_raw<F: FnMut(Entry)>(from: &[u8], size: usize, mut push: F) -> Result<(), Error> {
     let mut cursor = Cursor::new(from);
-    let mut entries = Vec::new();
     for _ in 0..size {
-        entries.push(Entry {
+        push(Entry {
             ts: cursor.read_i64()?,
             value: cursor.read_f64()?,
         });
     }
-    Ok(entries)
+    Ok(())
 }
 
-fn read_deflate(from: &[u8], size: usize) -> Result<Vec<Entry>, Error> {
+fn read_deflate<F: FnMut(Entry)>(from: &[u8], size: usize, mut push: F) -> Result<(), Error> {
     let mut decoder = DeflateDecoder::new(from);
-    let mut entries = Vec::new();
     for _ in 0..size {
-        entries.push(Entry {
+        push(Entry {
             ts: decoder.read_i64()?,
             value: decoder.read_f64()?,
         });
     }
-    Ok(entries)
+    Ok(())
 }
 
-fn read_delta(from: &[u8], size: usize) -> Result<Vec<Entry>, Error> {
-    let mut entries = Vec::with_capacity(size);
+/// The payload could be truncated or corrupted, so the reads are bounds checked.
+fn read_delta<F: FnMut(Entry)>(from: &[u8], size: usize, mut push: F) -> Result<(), Error> {
+    if size == 0 {
+        return Ok(());
+    }
 
+    let first = from.get(..16).ok_or(Error::CorruptBlock)?;
     let mut offset = 0usize;
 
-    let mut last_ts = i64::from_be_bytes(from[..8].try_into()?);
+    let mut last_ts = i64::from_be_bytes(first[..8].try_into()?);
     offset += 8;
 
-    let mut last_val = f64::from_be_bytes(from[offset..offset + 8].try_into()?);
+    let mut last_val = f64::from_be_bytes(first[offset..offset + 8].try_into()?);
     offset += 8;
 
-    entries.push(Entry {
+    push(Entry {
         ts: last_ts,
         value: last_val,
     });
@@ -95,16 +100,16 @@ fn read_delta(from: &[u8], size: usize) -> Result<Vec<Entry>, Error> {
             u64::decode_var(&from[offset..]).ok_or(Error::VarIntError)?;
         offset += shift;
 
-        last_ts += cur_ts;
+        last_ts = last_ts.checked_add(cur_ts).ok_or(Error::CorruptBlock)?;
         last_val = f64::from_bits(last_val.to_bits() ^ cur_val_mask);
 
-        entries.push(Entry {
+        push(Entry {
             ts: last_ts,
             value: last_val,
         });
     }
 
-    Ok(entries)
+    Ok(())
 }
 
 impl Compression {
@@ -133,12 +138,33 @@ impl Compression {
         }
     }
 
-    pub fn read(&self, from: &[u8], size: usize) -> Result<Vec<Entry>, Error> {
+    fn decode<F: FnMut(Entry)>(&self, from: &[u8], size: usize, push: F) -> Result<(), Error> {
         match self {
-            Compression::None => read_raw(&from, size),
-            Compression::Deflate => read_deflate(&from, size),
-            Compression::Delta => read_delta(&from, size),
+            Compression::None => read_raw(&from, size, push),
+            Compression::Deflate => read_deflate(&from, size, push),
+            Compression::Delta => read_delta(&from, size, push),
+        }
+    }
+
+    pub fn read(&self, from: &[u8], size: usize) -> Result<Vec<Entry>, Error> {
+        let mut entries = Vec::with_capacity(size);
+        self.decode(from, size, |entry| entries.push(entry))?;
+        Ok(entries)
+    }
+
+    /// Same as `read`, but decodes the timestamps and values into the given buffers,
+    /// which should hold at least `size` entries.
+    pub fn read_to_buf(&self, from: &[u8], size: usize, ts: &mut [i64], values: &mut [f64]) -> Result<(), Error> {
+        if ts.len() < size || values.len() < size {
+            return Err(Error::ArgTooSmall);
         }
+
+        let mut i = 0;
+        self.decode(from, size, |entry| {
+            ts[i] = entry.ts;
+            values[i] = entry.value;
+            i += 1;
+        })
     }
 }
 
@@ -183,6 +209,21 @@ mod test {
         .unwrap();
     }
 
+    #[test]
+    fn test_truncated_delta() {
+        let entries = (0..100).map(|ts| Entry { ts: ts * 1000, value: ts as f64 }).collect::<Vec<Entry>>();
+        let mut payload = Vec::new();
+        Compression::Delta
+            .write(&entries.iter().collect::<Vec<&Entry>>(), &mut payload)
+            .unwrap();
+
+        for len in 0..payload.len() {
+            assert!(Compression::Delta.read(&payload[..len], entries.len()).is_err());
+        }
+        assert!(matches!(Compression::Delta.read(&payload[..15], 1), Err(Error::CorruptBlock)));
+        assert_eq!(entries, Compression::Delta.read(&payload, entries.len()).unwrap());
+    }
+
     #[test]
     fn test_deflate() {
         check(
@@ -0,0 +1,212 @@
+use super::compression::Compression;
+use super::error::Error;
+use super::file_system::{FileKind, SeriesDir};
+use serde_derive::{Deserialize, Serialize};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+pub enum TimestampUnit {
+    #[serde(rename = "s")]
+    Seconds,
+    #[default]
+    #[serde(rename = "ms")]
+    Millis,
+    #[serde(rename = "us")]
+    Micros,
+    #[serde(rename = "ns")]
+    Nanos,
+}
+
+impl FromStr for TimestampUnit {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<TimestampUnit, Self::Err> {
+        match s {
+            "s" => Ok(TimestampUnit::Seconds),
+            "ms" => Ok(TimestampUnit::Millis),
+            "us" => Ok(TimestampUnit::Micros),
+            "ns" => Ok(TimestampUnit::Nanos),
+            _ => Err(()),
+        }
+    }
+}
+
+impl TimestampUnit {
+    fn nanos(&self) -> i64 {
+        match self {
+            TimestampUnit::Seconds => 1000 * 1000 * 1000,
+            TimestampUnit::Millis => 1000 * 1000,
+            TimestampUnit::Micros => 1000,
+            TimestampUnit::Nanos => 1,
+        }
+    }
+
+    /// Converts the timestamp in this unit to the unit `to`, rounding down.
+    /// Returns `None` if the value can not be represented.
+    pub fn convert(&self, ts: i64, to: TimestampUnit) -> Option<i64> {
+        if self.nanos() >= to.nanos() {
+            ts.checked_mul(self.nanos() / to.nanos())
+        } else {
+            Some(ts.div_euclid(to.nanos() / self.nanos()))
+        }
+    }
+
+    /// Converts millis to this unit, rounding up, so the result can be used as
+    /// an inclusive lower bound. Returns `None` if the value can not be represented.
+    pub fn from_millis(&self, millis: i64) -> Option<i64> {
+        match self {
+            TimestampUnit::Seconds => {
+                let secs = millis.div_euclid(1000);
+                if millis.rem_euclid(1000) == 0 {
+                    Some(secs)
+                } else {
+                    secs.checked_add(1)
+                }
+            }
+            TimestampUnit::Millis => Some(millis),
+            TimestampUnit::Micros => millis.checked_mul(1000),
+            TimestampUnit::Nanos => millis.checked_mul(1000 * 1000),
+        }
+    }
+
+    /// Converts the timestamp in this unit to millis, rounding down.
+    /// Returns `None` if the value can not be represented.
+    pub fn to_millis(&self, ts: i64) -> Option<i64> {
+        match self {
+            TimestampUnit::Seconds => ts.checked_mul(1000),
+            TimestampUnit::Millis => Some(ts),
+            TimestampUnit::Micros => Some(ts.div_euclid(1000)),
+            TimestampUnit::Nanos => Some(ts.div_euclid(1000 * 1000)),
+        }
+    }
+}
+
+/// Defines how the entries with equal timestamps are appended, both within
+/// the batch and at the highest timestamp of the series.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DuplicatePolicy {
+    /// All entries are kept, in the order they are appended.
+    #[default]
+    Allow,
+    /// Only the last entry with the timestamp within the batch is kept. The entries equal to
+    /// the highest timestamp of the series are skipped though, the already written entry
+    /// is kept as the series is append only.
+    KeepLast,
+    /// The batch is rejected with `Error::DuplicateTimestamp`.
+    Reject,
+}
+
+fn default_index_density() -> u32 {
+    1
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct SeriesConfig {
+    #[serde(default)]
+    pub timestamp_unit: TimestampUnit,
+    /// Number of blocks covered by an index entry. With the density greater than 1,
+    /// the index is smaller, but the reads scan up to `index_density` blocks ahead.
+    #[serde(default = "default_index_density")]
+    pub index_density: u32,
+    #[serde(default)]
+    pub duplicates: DuplicatePolicy,
+    /// Maximum number of entries appended per second, with the burst of one second.
+    /// The appends over the limit fail with `Error::RateLimited`.
+    #[serde(default)]
+    pub rate_limit: Option<u32>,
+    /// Compression of the appended blocks, unless set by `Appender::compression`.
+    #[serde(default)]
+    pub compression: Compression,
+    /// MiB of the data file allocated ahead of the appends, to avoid the fragmentation
+    /// of the large series. The file is extended by each block if not set.
+    #[serde(default)]
+    pub preallocate_mb: Option<u32>,
+}
+
+impl Default for SeriesConfig {
+    fn default() -> SeriesConfig {
+        SeriesConfig {
+            timestamp_unit: TimestampUnit::default(),
+            index_density: default_index_density(),
+            duplicates: DuplicatePolicy::default(),
+            rate_limit: None,
+            compression: Compression::default(),
+            preallocate_mb: None,
+        }
+    }
+}
+
+impl SeriesConfig {
+    pub fn read(dir: &SeriesDir) -> Result<SeriesConfig, Error> {
+        let content = match dir.read_to_string(FileKind::Config)? {
+            Some(content) => content,
+            None => return Ok(SeriesConfig::default()),
+        };
+
+        serde_json::from_str(&content)
+            .map_err(|e| Error::Other(format!("can not parse series config: {}", e)))
+    }
+    pub fn write(&self, dir: &SeriesDir) -> Result<(), Error> {
+        let content = serde_json::to_vec(self)
+            .map_err(|e| Error::Other(format!("can not serialize series config: {}", e)))?;
+
+        dir.write_atomically(FileKind::Config, &content)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::file_system;
+    use super::*;
+
+    #[test]
+    fn test_read_write() -> Result<(), Error> {
+        let fs = file_system::test::open()?;
+        let dir = fs.series("series1")?;
+
+        assert_eq!(SeriesConfig::default(), SeriesConfig::read(&dir)?);
+
+        let config = SeriesConfig {
+            timestamp_unit: TimestampUnit::Seconds,
+            index_density: 4,
+            duplicates: DuplicatePolicy::KeepLast,
+            rate_limit: Some(1000),
+            compression: Compression::Deflate,
+            preallocate_mb: Some(64),
+        };
+        config.write(&dir)?;
+
+        assert_eq!(config, SeriesConfig::read(&dir)?);
+
+        dir.write_atomically(FileKind::Config, br#"{"timestamp_unit":"us"}"#)?;
+
+        assert_eq!(
+            SeriesConfig {
+                timestamp_unit: TimestampUnit::Micros,
+                index_density: 1,
+                duplicates: DuplicatePolicy::Allow,
+                rate_limit: None,
+                compression: Compression::Delta,
+                preallocate_mb: None,
+            },
+            SeriesConfig::read(&dir)?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_convert() {
+        assert_eq!(Some(2), TimestampUnit::Seconds.from_millis(1001));
+        assert_eq!(Some(1), TimestampUnit::Seconds.from_millis(1000));
+        assert_eq!(Some(-1), TimestampUnit::Seconds.from_millis(-1001));
+        assert_eq!(Some(1000), TimestampUnit::Seconds.to_millis(1));
+        assert_eq!(Some(-2), TimestampUnit::Nanos.to_millis(-1_000_001));
+        assert_eq!(None, TimestampUnit::Nanos.from_millis(i64::MAX / 1000));
+        assert_eq!(Some(1_500), TimestampUnit::Nanos.convert(1_500_000_999, TimestampUnit::Millis));
+        assert_eq!(Some(-2), TimestampUnit::Micros.convert(-1_500_000, TimestampUnit::Seconds));
+        assert_eq!(Some(3_000_000), TimestampUnit::Seconds.convert(3, TimestampUnit::Micros));
+        assert_eq!(None, TimestampUnit::Seconds.convert(i64::MAX, TimestampUnit::Nanos));
+    }
+}
@@ -1,15 +1,22 @@
 use crc::crc16;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression as GzCompression;
 use std::convert::TryInto;
 use std::fs::File;
 use std::io::prelude::*;
-use std::io::{Cursor, SeekFrom};
+use std::io::{self, Cursor, SeekFrom};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
 
 use super::compression::Compression;
 use super::entry::Entry;
 use super::error::Error;
+use super::file_system::{FileKind, OpenMode, SeriesDir};
 use super::io_utils::WriteBytes;
 
-const BLOCK_HEADER_SIZE: u64 = 2 + 1 + 4 + 2;
+/// Entries count, compression marker, payload size and the header checksum.
+pub const BLOCK_HEADER_SIZE: u64 = 2 + 1 + 4 + 2;
 
 #[cfg(not(test))]
 const MAX_DATA_FILE_SIZE: u32 = u32::MAX;
@@ -70,19 +77,80 @@ impl BlockHeader {
     }
 }
 
+/// Validates the header of the block given as is, e.g. encoded by another writer.
+fn read_raw_header(header: &[u8], payload: &[u8]) -> Result<BlockHeader, Error> {
+    if header.len() != BLOCK_HEADER_SIZE as usize {
+        return Err(Error::CorruptBlock);
+    }
+    let header = BlockHeader::read(header)?;
+    if header.payload_size as usize != payload.len() {
+        return Err(Error::CorruptBlock);
+    }
+    Ok(header)
+}
+
+/// Decodes the entries of the block given as is, see `DataWriter::write_raw_block`.
+pub fn decode_raw_block(header: &[u8], payload: &[u8]) -> Result<Vec<Entry>, Error> {
+    let header = read_raw_header(header, payload)?;
+    header.compression.read(payload, header.entries_count as usize)
+}
+
+/// Allocates the disk space of the file up to `len` bytes, the allocated bytes are zeros.
+#[cfg(target_os = "linux")]
+fn allocate(file: &File, offset: u64, len: u64) -> Result<(), Error> {
+    use std::os::unix::io::AsRawFd;
+
+    match unsafe { libc::posix_fallocate(file.as_raw_fd(), offset as libc::off_t, (len - offset) as libc::off_t) } {
+        0 => Ok(()),
+        errno => Err(io::Error::from_raw_os_error(errno).into()),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn allocate(file: &File, _offset: u64, len: u64) -> Result<(), Error> {
+    file.set_len(len)?;
+    Ok(())
+}
+
 pub struct DataWriter {
     file: File,
     buffer: Cursor<Vec<u8>>,
+    /// Bytes allocated ahead of the written blocks once the file is full, `0` if disabled.
+    preallocate: u64,
+    /// Length of the file, including the preallocated bytes.
+    allocated: u64,
 }
 
 impl DataWriter {
     pub fn create(file: File) -> Result<DataWriter, Error> {
         Ok(DataWriter {
+            allocated: file.metadata()?.len(),
             file,
             buffer: Cursor::new(Vec::with_capacity(MAX_BLOCK_SIZE as usize)),
+            preallocate: 0,
         })
     }
 
+    /// Extends the file by `bytes` at once when the blocks reach its end, rather than
+    /// by each block. The blocks after the committed offset are never read, so the
+    /// preallocated zeros are not visible to the readers.
+    pub fn with_preallocate(self, bytes: u64) -> DataWriter {
+        DataWriter {
+            preallocate: bytes,
+            ..self
+        }
+    }
+
+    fn reserve(&mut self, end: u64) -> Result<(), Error> {
+        if self.preallocate == 0 || end <= self.allocated {
+            return Ok(());
+        }
+        let len = (end + self.preallocate).min(MAX_DATA_FILE_SIZE as u64).max(end);
+        allocate(&self.file, self.allocated, len)?;
+        self.allocated = len;
+        Ok(())
+    }
+
     pub fn write_block<'a, I>(
         &mut self,
         offset: u32,
@@ -116,6 +184,8 @@ impl DataWriter {
             payload_size: payload_size as u32,
         };
 
+        self.reserve(next_offset)?;
+
         self.file.seek(SeekFrom::Start(offset as u64))?;
 
         block_header.write(&mut self.file)?;
@@ -126,27 +196,221 @@ impl DataWriter {
 
         Ok(next_offset as u32)
     }
+    /// Writes the already encoded block verbatim, the header checksum and the payload
+    /// size are validated. Returns the offset of the next block.
+    pub fn write_raw_block(&mut self, offset: u32, header: &[u8], payload: &[u8]) -> Result<u32, Error> {
+        read_raw_header(header, payload)?;
+
+        let next_offset = offset as u64 + BLOCK_HEADER_SIZE + payload.len() as u64;
+
+        if next_offset > MAX_DATA_FILE_SIZE as u64 {
+            return Err(Error::DataFileTooBig);
+        }
+
+        self.reserve(next_offset)?;
+
+        self.file.seek(SeekFrom::Start(offset as u64))?;
+        self.file.write_all(header)?;
+        self.file.write_all(payload)?;
+
+        Ok(next_offset as u32)
+    }
     pub fn sync(&mut self) -> Result<(), Error> {
         self.file.sync_data()?;
         Ok(())
     }
+    /// Removes all blocks from the data file.
+    pub fn truncate(&mut self) -> Result<(), Error> {
+        self.file.set_len(0)?;
+        self.allocated = 0;
+        self.sync()
+    }
+}
+
+/// The data file to read the blocks from: either the raw file, or the gzip-compressed
+/// file of the archived series, which is decompressed while read.
+pub enum DataFile {
+    Raw(File),
+    Gzip { decoder: Box<GzDecoder<File>>, position: u64 },
+}
+
+impl DataFile {
+    fn gzip(file: File) -> Result<DataFile, Error> {
+        Ok(DataFile::Gzip {
+            decoder: Box::new(GzDecoder::new(file)?),
+            position: 0,
+        })
+    }
+}
+
+impl From<File> for DataFile {
+    fn from(file: File) -> DataFile {
+        DataFile::Raw(file)
+    }
+}
+
+impl Read for DataFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            DataFile::Raw(file) => file.read(buf),
+            DataFile::Gzip { decoder, position } => {
+                let read = decoder.read(buf)?;
+                *position += read as u64;
+                Ok(read)
+            }
+        }
+    }
+}
+
+impl Seek for DataFile {
+    /// The compressed file is decompressed from the beginning to seek backward, and
+    /// skipped to seek forward, so only `SeekFrom::Start` is supported for it.
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let (decoder, position) = match self {
+            DataFile::Raw(file) => return file.seek(pos),
+            DataFile::Gzip { decoder, position } => (decoder, position),
+        };
+
+        let target = match pos {
+            SeekFrom::Start(target) => target,
+            _ => return Err(io::Error::other("only seek from start is supported")),
+        };
+
+        if target < *position {
+            let mut file = decoder.get_mut().try_clone()?;
+            file.seek(SeekFrom::Start(0))?;
+            **decoder = GzDecoder::new(file)?;
+            *position = 0;
+        }
+
+        let skipped = io::copy(&mut Read::by_ref(&mut **decoder).take(target - *position), &mut io::sink())?;
+        *position += skipped;
+
+        Ok(*position)
+    }
+}
+
+/// Opens the data file of the series for reading, the archived file is preferred.
+pub fn open_data(dir: &SeriesDir) -> Result<DataFile, Error> {
+    if !dir.exists(FileKind::ArchivedData) {
+        let file = dir.open(FileKind::Data, OpenMode::Read);
+        // the archived file is created before the raw one is removed, so the raw
+        // file is complete unless the series was archived in the meantime
+        if !dir.exists(FileKind::ArchivedData) {
+            return Ok(DataFile::Raw(file?));
+        }
+    }
+
+    DataFile::gzip(dir.open(FileKind::ArchivedData, OpenMode::Read)?)
+}
+
+/// Writes the blocks before `end_offset` to the gzip-compressed data file and
+/// removes the raw one. The series should not be appended afterwards.
+pub fn archive(dir: &SeriesDir, end_offset: u32) -> Result<(), Error> {
+    let mut data = dir.open(FileKind::Data, OpenMode::Read)?;
+
+    dir.write_atomically_with(FileKind::ArchivedData, |file| {
+        let mut encoder = GzEncoder::new(file, GzCompression::Best);
+        io::copy(&mut Read::by_ref(&mut data).take(end_offset as u64), &mut encoder)?;
+        encoder.finish()?;
+        Ok(())
+    })?;
+
+    dir.remove(FileKind::Data)?;
+    dir.sync_dir()
+}
+
+/// Counts the entries of the blocks before `end_offset`, only the block headers are read.
+pub fn count_entries<F: Read + Seek>(file: F, end_offset: u32) -> Result<u64, Error> {
+    count_entries_between(file, 0, end_offset)
+}
+
+/// Iterates over the `(offset, header)` of the blocks from `start_offset` to `end_offset`,
+/// only the block headers are read. Stops after the first error.
+struct BlockHeaders<F> {
+    file: F,
+    offset: u64,
+    end_offset: u64,
+}
+
+impl<F: Read + Seek> BlockHeaders<F> {
+    fn create(file: F, start_offset: u32, end_offset: u32) -> BlockHeaders<F> {
+        BlockHeaders {
+            file,
+            offset: start_offset as u64,
+            end_offset: end_offset as u64,
+        }
+    }
+
+    fn read_header(&mut self) -> Result<BlockHeader, Error> {
+        let mut header = [0u8; BLOCK_HEADER_SIZE as usize];
+        self.file.seek(SeekFrom::Start(self.offset))?;
+        self.file.read_exact(&mut header)?;
+        BlockHeader::read(&header)
+    }
+}
+
+impl<F: Read + Seek> Iterator for BlockHeaders<F> {
+    type Item = Result<(u64, BlockHeader), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.offset >= self.end_offset {
+            return None;
+        }
+
+        let offset = self.offset;
+        match self.read_header() {
+            Ok(block_header) => {
+                self.offset += BLOCK_HEADER_SIZE + block_header.payload_size as u64;
+                Some(Ok((offset, block_header)))
+            }
+            Err(error) => {
+                self.offset = self.end_offset;
+                Some(Err(error))
+            }
+        }
+    }
+}
+
+/// Counts the entries of the blocks from `start_offset` to `end_offset`, see `count_entries`.
+pub fn count_entries_between<F: Read + Seek>(file: F, start_offset: u32, end_offset: u32) -> Result<u64, Error> {
+    BlockHeaders::create(file, start_offset, end_offset)
+        .map(|block| block.map(|(_, header)| header.entries_count as u64))
+        .sum()
+}
+
+/// Counts the blocks from `start_offset` to `end_offset`, only the block headers are read.
+pub fn count_blocks_between<F: Read + Seek>(file: F, start_offset: u32, end_offset: u32) -> Result<u64, Error> {
+    BlockHeaders::create(file, start_offset, end_offset)
+        .map(|block| block.map(|_| 1))
+        .sum()
+}
+
+/// Returns the compression of each block before `end_offset`, only the block headers are read.
+pub fn block_compressions<F: Read + Seek>(file: F, end_offset: u32) -> Result<Vec<Compression>, Error> {
+    BlockHeaders::create(file, 0, end_offset)
+        .map(|block| block.map(|(_, header)| header.compression))
+        .collect()
 }
 
 pub struct DataReader {
-    file: File,
+    file: DataFile,
     buf: Vec<u8>,
     buf_pos: usize,
     buf_len: usize,
+    /// Offset of the first buffered byte, the file is positioned after the last one.
+    buf_offset: u64,
     offset: u64,
 }
 
 impl DataReader {
-    pub fn create(file: File, start_offset: u32) -> Result<DataReader, Error> {
+    pub fn create<F: Into<DataFile>>(file: F, start_offset: u32) -> Result<DataReader, Error> {
         let mut reader = DataReader {
-            file: file,
+            file: file.into(),
             buf: vec![0u8; 5 * 1024 * 1024],
             buf_pos: 0,
             buf_len: 0,
+            buf_offset: start_offset as u64,
             offset: start_offset as u64,
         };
 
@@ -155,11 +419,15 @@ impl DataReader {
         Ok(reader)
     }
 
+    /// Keeps the buffered bytes of the current block and reads the following ones, so the
+    /// file is read sequentially (the compressed file can't be seeked backward cheaply).
     fn refill(&mut self) -> Result<(), Error> {
-        self.file.seek(SeekFrom::Start(self.offset))?;
+        let block_pos = (self.offset - self.buf_offset) as usize;
+        self.buf.copy_within(block_pos..self.buf_len, 0);
 
         self.buf_pos = 0;
-        self.buf_len = 0;
+        self.buf_len -= block_pos;
+        self.buf_offset = self.offset;
 
         while self.buf_len < self.buf.len() {
             let read = self.file.read(&mut self.buf[self.buf_len..])?;
@@ -175,10 +443,35 @@ impl DataReader {
     }
 
     pub fn read_block(&mut self) -> Result<(Vec<Entry>, u32), Error> {
+        let (header, payload) = self.next_block()?;
+        let entries = header.compression.read(payload, header.entries_count as usize)?;
+
+        Ok((entries, self.offset as u32))
+    }
+
+    /// Same as `read_block`, but decodes the block into the given buffers instead of
+    /// allocating the entries. Returns the number of entries decoded and the offset of the
+    /// next block. The buffers should hold `MAX_ENTRIES_PER_BLOCK` entries to fit any
+    /// block, otherwise the larger blocks fail with `ArgTooSmall`.
+    pub fn read_block_to_buf(&mut self, ts: &mut [i64], values: &mut [f64]) -> Result<(usize, u32), Error> {
+        let (header, payload) = self.next_block()?;
+        let count = header.entries_count as usize;
+        header.compression.read_to_buf(payload, count, ts, values)?;
+
+        Ok((count, self.offset as u32))
+    }
+
+    /// Reads the header of the next block and returns it with the (still encoded) payload.
+    fn next_block(&mut self) -> Result<(BlockHeader, &[u8]), Error> {
         if self.buf_len - self.buf_pos < BLOCK_HEADER_SIZE as usize {
             self.refill()?;
         }
 
+        // the data file could be truncated in the meantime
+        if self.buf_len - self.buf_pos < BLOCK_HEADER_SIZE as usize {
+            return Err(Error::OffsetOutsideTheRange);
+        }
+
         let header = BlockHeader::read(&self.buf[self.buf_pos..])?;
 
         self.buf_pos += BLOCK_HEADER_SIZE as usize;
@@ -191,18 +484,57 @@ impl DataReader {
             self.buf_pos += BLOCK_HEADER_SIZE as usize;
         }
 
-        let compression = header.compression;
+        if self.buf_len - self.buf_pos < payload_size {
+            return Err(Error::OffsetOutsideTheRange);
+        }
 
-        let entries = compression.read(
-            &self.buf[self.buf_pos..self.buf_pos + payload_size],
-            header.entries_count as usize,
-        )?;
+        let payload_pos = self.buf_pos;
 
         self.buf_pos += payload_size;
 
         self.offset += header.payload_size as u64 + BLOCK_HEADER_SIZE;
 
-        Ok((entries, self.offset as u32))
+        Ok((header, &self.buf[payload_pos..payload_pos + payload_size]))
+    }
+}
+
+/// Reads the blocks on the helper thread, so the next blocks are read (and
+/// decoded) while the current one is processed.
+pub struct ReadAhead {
+    receiver: Receiver<Result<(Vec<Entry>, u32), Error>>,
+}
+
+impl ReadAhead {
+    /// Reads the blocks until `end_offset`, up to `blocks` blocks are read ahead.
+    pub fn spawn(mut reader: DataReader, end_offset: u32, blocks: usize) -> ReadAhead {
+        let (sender, receiver) = mpsc::sync_channel(blocks.max(1));
+
+        thread::spawn(move || {
+            let mut offset = reader.offset;
+            while offset < end_offset as u64 {
+                let block = reader.read_block();
+                let failed = match &block {
+                    Ok((_, next)) => {
+                        offset = *next as u64;
+                        false
+                    }
+                    Err(_) => true,
+                };
+                // the receiver is dropped if the iteration is stopped
+                if sender.send(block).is_err() || failed {
+                    return;
+                }
+            }
+        });
+
+        ReadAhead { receiver }
+    }
+
+    pub fn read_block(&mut self) -> Result<(Vec<Entry>, u32), Error> {
+        match self.receiver.recv() {
+            Ok(block) => block,
+            Err(_) => Err(Error::Other("read ahead is stopped".to_owned())),
+        }
     }
 }
 
@@ -212,6 +544,44 @@ mod test {
     use super::super::file_system::{FileKind, OpenMode};
     use super::*;
 
+    #[test]
+    fn test_archive() -> Result<(), Error> {
+        let env = env::test::create()?;
+        let series_dir = env.fs().series("series1")?;
+
+        let entries = (0..3000).map(|ts| Entry { ts, value: ts as f64 }).collect::<Vec<Entry>>();
+
+        let mut writer = DataWriter::create(series_dir.open(FileKind::Data, OpenMode::Write)?)?;
+        let first = writer.write_block(0, &entries[0..1000], Compression::Delta)?;
+        let second = writer.write_block(first, &entries[1000..2000], Compression::Deflate)?;
+        // the block after the end offset is not committed, so it is not archived
+        writer.write_block(second, &entries[2000..3000], Compression::None)?;
+
+        archive(&series_dir, second)?;
+        assert!(!series_dir.exists(FileKind::Data));
+        assert!(series_dir.exists(FileKind::ArchivedData));
+
+        let mut reader = DataReader::create(open_data(&series_dir)?, 0)?;
+        assert_eq!((entries[0..1000].to_owned(), first), reader.read_block()?);
+        assert_eq!((entries[1000..2000].to_owned(), second), reader.read_block()?);
+        assert!(matches!(reader.read_block(), Err(Error::OffsetOutsideTheRange)));
+
+        let mut reader = DataReader::create(open_data(&series_dir)?, first)?;
+        assert_eq!((entries[1000..2000].to_owned(), second), reader.read_block()?);
+
+        assert_eq!(2000, count_entries(open_data(&series_dir)?, second)?);
+
+        let mut file = open_data(&series_dir)?;
+        let mut header = [0u8; BLOCK_HEADER_SIZE as usize];
+        file.seek(SeekFrom::Start(first as u64))?;
+        file.seek(SeekFrom::Start(0))?;
+        file.read_exact(&mut header)?;
+        assert_eq!(1000, BlockHeader::read(&header)?.entries_count);
+        assert!(file.seek(SeekFrom::Current(1)).is_err());
+
+        Ok(())
+    }
+
     #[test]
     fn test_read_write() -> Result<(), Error> {
         let env = env::test::create()?;
@@ -247,6 +617,91 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn test_read_block_to_buf() -> Result<(), Error> {
+        let env = env::test::create()?;
+        let series_dir = env.fs().series("series1")?;
+
+        let entries = (0..300).map(|ts| Entry { ts, value: ts as f64 / 2.0 }).collect::<Vec<Entry>>();
+
+        let mut writer = DataWriter::create(series_dir.open(FileKind::Data, OpenMode::Write)?)?;
+        let mut offset = 0;
+        for (i, compression) in [Compression::None, Compression::Deflate, Compression::Delta].iter().enumerate() {
+            offset = writer.write_block(offset, &entries[i * 100..(i + 1) * 100], *compression)?;
+        }
+
+        let mut reader = DataReader::create(open_data(&series_dir)?, 0)?;
+        let mut buf_reader = DataReader::create(open_data(&series_dir)?, 0)?;
+        let mut ts = [0i64; 128];
+        let mut values = [0f64; 128];
+
+        for _ in 0..3 {
+            let (block, next) = reader.read_block()?;
+            let (count, buf_next) = buf_reader.read_block_to_buf(&mut ts, &mut values)?;
+
+            assert_eq!(next, buf_next);
+            assert_eq!(block.len(), count);
+            assert_eq!(block.iter().map(|entry| entry.ts).collect::<Vec<i64>>(), ts[..count]);
+            assert_eq!(block.iter().map(|entry| entry.value).collect::<Vec<f64>>(), values[..count]);
+        }
+
+        let mut buf_reader = DataReader::create(open_data(&series_dir)?, 0)?;
+        assert!(matches!(
+            buf_reader.read_block_to_buf(&mut ts[..99], &mut values),
+            Err(Error::ArgTooSmall)
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_count_entries() -> Result<(), Error> {
+        let env = env::test::create()?;
+        let series_dir = env.fs().series("series1")?;
+
+        let mut offset = 0u32;
+        {
+            let file = series_dir.open(FileKind::Data, OpenMode::Write)?;
+            let mut writer = DataWriter::create(file)?;
+
+            offset = writer.write_block(offset, &entries(3), Compression::Delta)?;
+            offset = writer.write_block(offset, &entries(1000), Compression::Deflate)?;
+        }
+
+        assert_eq!(1003, count_entries(series_dir.open(FileKind::Data, OpenMode::Read)?, offset)?);
+        assert_eq!(0, count_entries(series_dir.open(FileKind::Data, OpenMode::Read)?, 0)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_ahead() -> Result<(), Error> {
+        let env = env::test::create()?;
+        let series_dir = env.fs().series("series1")?;
+
+        let mut offset = 0u32;
+        {
+            let file = series_dir.open(FileKind::Data, OpenMode::Write)?;
+            let mut writer = DataWriter::create(file)?;
+
+            for ts in 0..100 {
+                offset = writer.write_block(offset, &[Entry { ts, value: 1.0 }], Compression::Delta)?;
+            }
+        }
+
+        let file = series_dir.open(FileKind::Data, OpenMode::Read)?;
+        let mut reader = ReadAhead::spawn(DataReader::create(file, 0)?, offset, 4);
+
+        for ts in 0..100 {
+            let (result, _) = reader.read_block()?;
+            assert_eq!(vec![Entry { ts, value: 1.0 }], result);
+        }
+
+        assert!(reader.read_block().is_err());
+
+        Ok(())
+    }
+
     fn entries(count: usize) -> Vec<Entry> {
         (0..count)
             .into_iter()
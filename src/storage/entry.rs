@@ -1,4 +1,6 @@
 use serde_derive::{Deserialize, Serialize};
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
 
 #[derive(Debug, Clone)]
 #[derive(Deserialize, Serialize)]
@@ -13,7 +15,128 @@ impl PartialEq for Entry {
     }
 }
 
+impl Entry {
+    /// Orders by timestamp, then by value (see `f64::total_cmp`), e.g. for `sort_by`.
+    /// `Entry` is not `Ord`, since the equality of the values is approximate, which
+    /// is not transitive.
+    pub fn total_cmp(&self, other: &Self) -> Ordering {
+        self.ts.cmp(&other.ts).then_with(|| self.value.total_cmp(&other.value))
+    }
+}
+
+/// Head of the merged source, ordered by the entry, then by the source.
+struct Head(Entry, usize);
+
+impl PartialEq for Head {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Head {}
+
+impl Ord for Head {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0).then_with(|| self.1.cmp(&other.1))
+    }
+}
+
+impl PartialOrd for Head {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Iterator over the entries of several sorted sources, see `merge_sorted`.
+pub struct MergeSorted<I>
+where
+    I: Iterator<Item = Entry>,
+{
+    sources: Vec<I>,
+    heads: BinaryHeap<Reverse<Head>>,
+}
+
+impl<I> Iterator for MergeSorted<I>
+where
+    I: Iterator<Item = Entry>,
+{
+    type Item = Entry;
+
+    fn next(&mut self) -> Option<Entry> {
+        let Reverse(Head(entry, source)) = self.heads.pop()?;
+        if let Some(next) = self.sources[source].next() {
+            self.heads.push(Reverse(Head(next, source)));
+        }
+        Some(entry)
+    }
+}
+
+/// Merges the sources sorted by timestamp into a single sorted iterator. The equal
+/// entries are returned in the order of the sources.
+pub fn merge_sorted<S, T>(sources: S) -> MergeSorted<T::IntoIter>
+where
+    S: IntoIterator<Item = T>,
+    T: IntoIterator<Item = Entry>,
+{
+    let mut sources = sources.into_iter().map(|source| source.into_iter()).collect::<Vec<_>>();
+    let heads = sources
+        .iter_mut()
+        .enumerate()
+        .filter_map(|(i, source)| source.next().map(|entry| Reverse(Head(entry, i))))
+        .collect();
+    MergeSorted { sources, heads }
+}
+
 #[test]
 fn test_eq() {
     assert_eq!(Entry { ts: 1, value: 1.0 }, Entry { ts: 1, value: 1.0 });
 }
+
+#[test]
+fn test_total_cmp() {
+    let entry = |ts: i64, value: f64| Entry { ts, value };
+
+    assert_eq!(Ordering::Less, entry(1, 2.0).total_cmp(&entry(2, 1.0)));
+    assert_eq!(Ordering::Less, entry(1, 1.0).total_cmp(&entry(1, 2.0)));
+    assert_eq!(Ordering::Less, entry(1, 1.0).total_cmp(&entry(1, 1.0 + 1e-9)));
+    assert_eq!(Ordering::Equal, entry(1, 1.0).total_cmp(&entry(1, 1.0)));
+
+    let mut entries = vec![entry(3, 1.0), entry(1, 2.0), entry(2, 0.0), entry(1, 1.0)];
+    entries.sort_by(Entry::total_cmp);
+    assert_eq!(vec![entry(1, 1.0), entry(1, 2.0), entry(2, 0.0), entry(3, 1.0)], entries);
+}
+
+#[test]
+fn test_sort_near_equal_values() {
+    // the neighbours are equal as in `PartialEq`, the values far apart are not
+    let mut entries = (0..1000)
+        .map(|i| Entry { ts: 1, value: ((i * 7919) % 1000) as f64 * 3e-7 })
+        .collect::<Vec<Entry>>();
+    entries.sort_by(Entry::total_cmp);
+
+    assert!(entries.windows(2).all(|pair| pair[0].value < pair[1].value));
+}
+
+#[test]
+fn test_merge_sorted() {
+    let entry = |ts: i64, value: f64| Entry { ts, value };
+
+    let a = vec![entry(1, 1.0), entry(3, 3.0), entry(5, 5.0), entry(5, 5.5)];
+    let b = vec![entry(2, 2.0), entry(3, 3.0), entry(6, 6.0)];
+
+    assert_eq!(
+        vec![
+            entry(1, 1.0),
+            entry(2, 2.0),
+            entry(3, 3.0),
+            entry(3, 3.0),
+            entry(5, 5.0),
+            entry(5, 5.5),
+            entry(6, 6.0),
+        ],
+        merge_sorted(vec![a.clone(), b]).collect::<Vec<Entry>>()
+    );
+
+    assert_eq!(a, merge_sorted(vec![a.clone(), vec![]]).collect::<Vec<Entry>>());
+    assert_eq!(0, merge_sorted(Vec::<Vec<Entry>>::new()).count());
+}
@@ -1,35 +1,84 @@
 #[cfg(test)]
 use super::super::failpoints::Failpoints;
-use super::commit_log::CommitLog;
+use super::clock::{Clock, SystemClock};
+use super::commit_log::{Checksum, Commit, CommitLog};
+use super::config::SeriesConfig;
+use super::data::{self, DataReader};
 use super::error::Error;
 use super::file_system::{FileKind, FileSystem, OpenMode, SeriesDir};
-use super::index::Index;
+use super::index::{self, Index};
+use super::sync_mode::{IndexSyncPolicy, SyncMode};
 use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock};
 
 pub struct SeriesEnv {
     dir: Arc<SeriesDir>,
     commit_log: CommitLog,
     index: Index,
+    config: RwLock<Arc<SeriesConfig>>,
+    /// Held while the index entries of an append are written together with its commit.
+    publishing: Mutex<()>,
+    read_only: bool,
+    clock: Arc<dyn Clock>,
     #[cfg(test)]
     fp: Arc<Failpoints>,
 }
 
 impl SeriesEnv {
-    fn create(dir: Arc<SeriesDir>, #[cfg(test)] fp: Arc<Failpoints>) -> Result<SeriesEnv, Error> {
+    fn create(
+        dir: Arc<SeriesDir>,
+        read_only: bool,
+        checksum: Checksum,
+        max_index_size: u32,
+        clock: Arc<dyn Clock>,
+        #[cfg(test)] fp: Arc<Failpoints>,
+    ) -> Result<SeriesEnv, Error> {
+        if read_only {
+            return Ok(SeriesEnv {
+                commit_log: CommitLog::open_read_only(dir.clone())?,
+                index: Index::open_read_only(dir.open(FileKind::Index, OpenMode::Read)?)?,
+                config: RwLock::new(Arc::new(SeriesConfig::read(&dir)?)),
+                publishing: Mutex::new(()),
+                dir,
+                read_only,
+                clock,
+                #[cfg(test)]
+                fp,
+            });
+        }
+
         let log = CommitLog::open(
             dir.clone(),
+            checksum,
             #[cfg(test)]
             fp.clone(),
         )?;
         let index_offset = log.current().index_offset;
+        let config = SeriesConfig::read(&dir)?;
+        let index = Index::open(
+            dir.clone().open(FileKind::Index, OpenMode::Write)?,
+            index_offset,
+            max_index_size,
+        )?;
+
+        recover_data(&dir, &index, &log, &config)?;
+
+        if let Some(synced) = read_index_synced(&dir)? {
+            if synced < index_offset {
+                recover_index(&dir, &index, &log, &config, synced)?;
+            }
+        }
+
+        recover_last_index_entry(&dir, &index, &log)?;
+
         Ok(SeriesEnv {
             dir: dir.clone(),
             commit_log: log,
-            index: Index::open(
-                dir.clone().open(FileKind::Index, OpenMode::Write)?,
-                index_offset,
-            )?,
+            index,
+            config: RwLock::new(Arc::new(config)),
+            publishing: Mutex::new(()),
+            read_only,
+            clock,
             #[cfg(test)]
             fp: fp,
         })
@@ -40,6 +89,9 @@ impl SeriesEnv {
     pub fn commit_log(&self) -> &CommitLog {
         &self.commit_log
     }
+    pub fn clock(&self) -> Arc<dyn Clock> {
+        self.clock.clone()
+    }
     #[cfg(test)]
     pub fn fp(&self) -> Arc<Failpoints> {
         self.fp.clone()
@@ -47,11 +99,210 @@ impl SeriesEnv {
     pub fn index(&self) -> &Index {
         &self.index
     }
+    pub fn config(&self) -> Arc<SeriesConfig> {
+        self.config.read().unwrap().clone()
+    }
+    /// Writes the config of the series, the readers and writers use it from the next call.
+    pub fn set_config(&self, config: SeriesConfig) -> Result<(), Error> {
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+
+        let mut current = self.config.write().unwrap();
+        config.write(&self.dir)?;
+        *current = Arc::new(config);
+        Ok(())
+    }
+    pub fn read_only(&self) -> bool {
+        self.read_only
+    }
+    /// Blocks the appends from publishing the index entries and the commit, so the
+    /// last index entry and the current commit are read consistently.
+    pub fn publishing(&self) -> MutexGuard<'_, ()> {
+        self.publishing.lock().unwrap()
+    }
+    /// Reloads the state committed by the writer process, if the series is opened read-only.
+    pub fn refresh(&self) -> Result<(), Error> {
+        let commit = self.commit_log.refresh()?;
+        self.index.refresh(commit.index_offset)
+    }
+}
+
+/// Reads the offset up to which the index is fsynced, `None` if the
+/// index is fsynced together with the data.
+fn read_index_synced(dir: &SeriesDir) -> Result<Option<u32>, Error> {
+    match dir.read_to_string(FileKind::IndexSynced)? {
+        Some(content) => content
+            .trim()
+            .parse::<u32>()
+            .map(Some)
+            .map_err(|e| Error::Other(format!("can not parse synced index offset: {}", e))),
+        None => Ok(None),
+    }
+}
+
+/// Returns `true` if the committed blocks from the last trusted index entry end exactly at
+/// the committed offset. The index entries after the fsynced offset are not trusted.
+fn last_blocks_complete(dir: &SeriesDir, index: &Index, commit: &Commit) -> Result<bool, Error> {
+    let trusted = match read_index_synced(dir)? {
+        Some(synced) => synced.min(commit.index_offset),
+        None => commit.index_offset,
+    };
+    let mut offset = match trusted {
+        0 => 0,
+        _ => index.offset_at(trusted - index::ENTRY_SIZE)?,
+    };
+
+    let mut reader = DataReader::create(data::open_data(dir)?, offset)?;
+    while offset < commit.data_offset {
+        match reader.read_block() {
+            Ok((_, next_offset)) => offset = next_offset,
+            Err(_) => return Ok(false),
+        }
+    }
+    Ok(offset == commit.data_offset)
+}
+
+/// Truncates the commit to the last complete block if the committed blocks are lost, e.g.
+/// if the file system lost the data file on crash: the data file is shorter than the
+/// committed offset, or the preallocated data file holds zeros instead of the last blocks.
+/// The blocks are scanned from the start, the index is rebuilt for the blocks kept.
+fn recover_data(dir: &SeriesDir, index: &Index, commit_log: &CommitLog, config: &SeriesConfig) -> Result<(), Error> {
+    let commit = commit_log.current();
+    if commit.data_offset == 0 || dir.exists(FileKind::ArchivedData) {
+        return Ok(());
+    }
+
+    let data_len = dir.file_sizes()?.data;
+    if data_len >= commit.data_offset as u64 && last_blocks_complete(dir, index, &commit)? {
+        return Ok(());
+    }
+
+    log::warn!(
+        "the data file of {:?} is {} bytes, but the {} committed bytes are not complete, truncating to the last complete block",
+        dir.path(),
+        data_len,
+        commit.data_offset
+    );
+
+    let data_len = data_len.min(commit.data_offset as u64);
+    let mut reader = DataReader::create(data::open_data(dir)?, 0)?;
+    let mut data_offset = 0u32;
+    let mut highest_ts = i64::MIN;
+
+    while (data_offset as u64) < data_len {
+        match reader.read_block() {
+            Ok((entries, next_offset)) if next_offset as u64 <= data_len => {
+                highest_ts = entries.iter().map(|entry| entry.ts).fold(highest_ts, i64::max);
+                data_offset = next_offset;
+            }
+            _ => break,
+        }
+    }
+
+    commit_log.commit(Commit {
+        data_offset,
+        index_offset: 0,
+        highest_ts,
+    })?;
+
+    recover_index(dir, index, commit_log, config, 0)
+}
+
+/// Resets the highest timestamp of the last index entry to the committed one. The entry
+/// holds the timestamp of an uncommitted block if the process crashed after the append
+/// extended the group of the entry, but before the commit.
+fn recover_last_index_entry(dir: &SeriesDir, index: &Index, commit_log: &CommitLog) -> Result<(), Error> {
+    let commit = commit_log.current();
+    if commit.index_offset == 0 {
+        return Ok(());
+    }
+
+    let last_offset = commit.index_offset - index::ENTRY_SIZE;
+    let ts = index.ts_at(last_offset)?;
+    if ts == commit.highest_ts {
+        return Ok(());
+    }
+
+    log::warn!(
+        "the last index entry of {:?} holds ts {}, but {} is committed, resetting",
+        dir.path(),
+        ts,
+        commit.highest_ts
+    );
+
+    index.set(last_offset, commit.highest_ts, index.offset_at(last_offset)?)?;
+    index.sync()
+}
+
+/// Rebuilds the index entries after the fsynced offset from the committed data blocks.
+/// The last fsynced entry is rebuilt as well, since its group could be extended later.
+fn recover_index(
+    dir: &SeriesDir,
+    index: &Index,
+    commit_log: &CommitLog,
+    config: &SeriesConfig,
+    synced: u32,
+) -> Result<(), Error> {
+    let commit = commit_log.current();
+    let density = config.index_density.max(1);
+
+    let mut entry_offset = synced.saturating_sub(index::ENTRY_SIZE);
+    let mut data_offset = match synced {
+        0 => 0,
+        _ => index.offset_at(entry_offset)?,
+    };
+
+    log::warn!(
+        "rebuilding the index of {:?} from offset {} up to {}",
+        dir.path(),
+        entry_offset,
+        commit.index_offset
+    );
+
+    let mut reader = DataReader::create(data::open_data(dir)?, data_offset)?;
+    let mut group_offset = data_offset;
+    let mut group_blocks = 0;
+    let mut index_offset = entry_offset;
+
+    while data_offset < commit.data_offset {
+        if group_blocks == density {
+            entry_offset += index::ENTRY_SIZE;
+            group_offset = data_offset;
+            group_blocks = 0;
+        }
+
+        let (entries, next_offset) = reader.read_block()?;
+        if let Some(last) = entries.last() {
+            index_offset = index.set(entry_offset, last.ts, group_offset)?;
+        }
+
+        group_blocks += 1;
+        data_offset = next_offset;
+    }
+
+    index.sync()?;
+
+    // the blocks appended after restart start a new group, so the rebuilt index could be shorter
+    if index_offset != commit.index_offset {
+        commit_log.commit(Commit {
+            index_offset,
+            ..commit.as_ref().clone()
+        })?;
+    }
+
+    Ok(())
 }
 
 pub struct Env {
     fs: FileSystem,
     series: Arc<Mutex<HashMap<String, Arc<SeriesEnv>>>>,
+    read_only: bool,
+    sync_mode: SyncMode,
+    index_sync: IndexSyncPolicy,
+    checksum: Checksum,
+    max_index_size: u32,
+    clock: Arc<dyn Clock>,
     #[cfg(test)]
     pub fp: Arc<Failpoints>,
 }
@@ -60,6 +311,42 @@ impl Env {
     pub fn fs(&self) -> &FileSystem {
         &self.fs
     }
+    pub fn read_only(&self) -> bool {
+        self.read_only
+    }
+    pub fn sync_mode(&self) -> SyncMode {
+        self.sync_mode
+    }
+    /// Sets the sync mode of the series writers opened by the series table.
+    pub fn with_sync_mode(self, sync_mode: SyncMode) -> Env {
+        Env { sync_mode, ..self }
+    }
+    pub fn index_sync(&self) -> IndexSyncPolicy {
+        self.index_sync
+    }
+    /// Sets the index sync policy of the series writers opened by the series table.
+    pub fn with_index_sync(self, index_sync: IndexSyncPolicy) -> Env {
+        Env { index_sync, ..self }
+    }
+    pub fn checksum(&self) -> Checksum {
+        self.checksum
+    }
+    /// Sets the checksum of the commits written by the series opened afterwards, CRC32C by default.
+    pub fn with_checksum(self, checksum: Checksum) -> Env {
+        Env { checksum, ..self }
+    }
+    pub fn clock(&self) -> Arc<dyn Clock> {
+        self.clock.clone()
+    }
+    /// Sets the clock of the series opened afterwards, `SystemClock` by default.
+    pub fn with_clock(self, clock: Arc<dyn Clock>) -> Env {
+        Env { clock, ..self }
+    }
+    /// Reduces the max index size of the series opened afterwards, so the tests fill the index up.
+    #[cfg(test)]
+    pub fn with_max_index_size(self, max_index_size: u32) -> Env {
+        Env { max_index_size, ..self }
+    }
     pub fn series<S: AsRef<str>>(&self, name: S) -> Result<Arc<SeriesEnv>, Error> {
         let mut series = self.series.lock().unwrap();
         match series.get(name.as_ref()) {
@@ -67,6 +354,10 @@ impl Env {
             _ => {
                 let env = Arc::new(SeriesEnv::create(
                     self.fs.series(name.as_ref())?,
+                    self.read_only,
+                    self.checksum,
+                    self.max_index_size,
+                    self.clock.clone(),
                     #[cfg(test)]
                     self.fp.clone(),
                 )?);
@@ -76,6 +367,43 @@ impl Env {
             }
         }
     }
+    /// Renames the series, the series env opened before (and the readers and
+    /// writers using it) keeps working under the new name.
+    pub fn rename_series<S: AsRef<str>>(&self, src: S, dst: S) -> Result<(), Error> {
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+
+        let mut series = self.series.lock().unwrap();
+        match series.remove(src.as_ref()) {
+            Some(env) => match self.fs.rename_series_dir(&env.dir(), dst.as_ref()) {
+                Ok(()) => {
+                    series.insert(dst.as_ref().to_owned(), env);
+                    Ok(())
+                }
+                Err(error) => {
+                    series.insert(src.as_ref().to_owned(), env);
+                    Err(error)
+                }
+            },
+            None => self.fs.rename_series(src, dst),
+        }
+    }
+    /// Forgets the opened series env, the next `series` call opens the series again.
+    /// The readers and writers using the env keep working.
+    pub fn close_series<S: AsRef<str>>(&self, name: S) {
+        self.series.lock().unwrap().remove(name.as_ref());
+    }
+    /// Removes the series files. The files opened before (e.g. by the iterators) stay readable.
+    pub fn remove_series<S: AsRef<str>>(&self, name: S) -> Result<(), Error> {
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+
+        let mut series = self.series.lock().unwrap();
+        series.remove(name.as_ref());
+        self.fs.remove_series(name)
+    }
 }
 
 pub fn create(
@@ -86,6 +414,34 @@ pub fn create(
     Env {
         fs: fs,
         series: Arc::new(Mutex::new(HashMap::new())),
+        read_only: false,
+        sync_mode: SyncMode::default(),
+        index_sync: IndexSyncPolicy::default(),
+        checksum: Checksum::default(),
+        max_index_size: index::MAX_INDEX_SIZE,
+        clock: Arc::new(SystemClock),
+        #[cfg(test)]
+        fp,
+    }
+}
+
+/// Creates the environment over the directory written by another process.
+/// The series are opened without creating or modifying any files, the state
+/// committed by the writer is picked up by `SeriesEnv::refresh`.
+pub fn create_read_only(
+    fs: FileSystem,
+    #[cfg(test)]
+    fp: Arc<Failpoints>,
+) -> Env {
+    Env {
+        fs: fs.with_read_only(),
+        series: Arc::new(Mutex::new(HashMap::new())),
+        read_only: true,
+        sync_mode: SyncMode::default(),
+        index_sync: IndexSyncPolicy::default(),
+        checksum: Checksum::default(),
+        max_index_size: index::MAX_INDEX_SIZE,
+        clock: Arc::new(SystemClock),
         #[cfg(test)]
         fp,
     }
@@ -98,7 +454,6 @@ pub mod test {
     use std::fs;
     use std::ops::Deref;
     use std::path::PathBuf;
-    use std::time::{SystemTime, UNIX_EPOCH};
 
     pub struct TempEnv {
         pub env: Env,
@@ -119,13 +474,7 @@ pub mod test {
     }
 
     pub fn create_with_failpoints(fp: Arc<Failpoints>) -> Result<TempEnv, Error> {
-        let path = PathBuf::from(format!(
-            "temp-dir-{:?}",
-            SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap()
-                .as_nanos()
-        ));
+        let path = file_system::test::temp_path();
 
         Ok(TempEnv {
             env: super::create(file_system::open(&path)?, fp),
@@ -133,7 +482,38 @@ pub mod test {
         })
     }
 
+    pub fn create_with_max_index_size(max_index_size: u32) -> Result<TempEnv, Error> {
+        let path = file_system::test::temp_path();
+
+        Ok(TempEnv {
+            env: super::create(file_system::open(&path)?, Arc::new(Failpoints::create()))
+                .with_max_index_size(max_index_size),
+            path: path.clone(),
+        })
+    }
+
+    pub fn create_with_clock(clock: Arc<dyn Clock>) -> Result<TempEnv, Error> {
+        let path = file_system::test::temp_path();
+
+        Ok(TempEnv {
+            env: super::create(file_system::open(&path)?, Arc::new(Failpoints::create())).with_clock(clock),
+            path: path.clone(),
+        })
+    }
+
     pub fn create() -> Result<TempEnv, Error> {
         create_with_failpoints(Arc::new(Failpoints::create()))
     }
+
+    impl TempEnv {
+        /// Opens the environment over the same directory, as it would be after restart.
+        pub fn reopen(&self) -> Result<Env, Error> {
+            Ok(super::create(file_system::open(&self.path)?, self.env.fp.clone()).with_clock(self.env.clock()))
+        }
+
+        /// Opens the environment over the same directory, as another read-only process would.
+        pub fn open_read_only(&self) -> Result<Env, Error> {
+            Ok(super::create_read_only(file_system::open(&self.path)?, self.env.fp.clone()).with_clock(self.env.clock()))
+        }
+    }
 }
@@ -3,7 +3,9 @@ use std::{error, array, io, fmt};
 #[derive(Debug)]
 pub enum Error {
     Crc16Mismatch,
+    ChecksumMismatch,
     UnknownCompression,
+    CorruptBlock,
     Io(io::Error),
     Slice(array::TryFromSliceError),
     VarIntError,
@@ -11,10 +13,22 @@ pub enum Error {
     TooManyEntries,
     DataFileTooBig,
     InvalidOffset,
-    IndexFileTooBig,
+    IndexFull,
     IndexIsNotConsistent,
     OffsetOutsideTheRange,
     OffsetIsNotAligned,
+    TimestampOutOfRange,
+    InvalidSeriesName,
+    ReadOnly,
+    Archived,
+    DuplicateTimestamp,
+    UnorderedEntries,
+    CompactionInProgress,
+    SeriesReplaced,
+    RateLimited,
+    TooManySeries,
+    VirtualSeriesExists,
+    ConfigMismatch,
     Other(String),
 }
 
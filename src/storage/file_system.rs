@@ -1,12 +1,21 @@
 use super::error::Error;
 use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
 
 pub enum FileKind {
     Data,
+    /// The gzip-compressed data file of the archived series.
+    ArchivedData,
     Index,
+    /// Offset up to which the index is fsynced, written if the index is fsynced less often than the data.
+    IndexSynced,
     Log(u64),
+    Config,
+    Batch,
+    /// Name of the series replaced by the compaction, written to the temporary series.
+    Compacted,
 }
 
 pub enum OpenMode {
@@ -14,33 +23,94 @@ pub enum OpenMode {
     Write,
 }
 
+/// Sizes of the series files in bytes, the missing files are counted as empty.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FileSizes {
+    pub data: u64,
+    pub index: u64,
+    /// Total size of the commit log files.
+    pub log: u64,
+}
+
+/// The base path is updated when the series is renamed, so the files are
+/// opened from the new location by the readers and writers created before.
 pub struct SeriesDir {
-    base_path: PathBuf,
+    base_path: RwLock<PathBuf>,
+}
+
+fn file_path(base_path: &Path, kind: FileKind) -> PathBuf {
+    base_path.join(match kind {
+        FileKind::Data => "series.dat".to_owned(),
+        FileKind::ArchivedData => "series.dat.gz".to_owned(),
+        FileKind::Index => "series.idx".to_owned(),
+        FileKind::IndexSynced => "series.idx.synced".to_owned(),
+        FileKind::Log(s) => format!("series.log.{}", s),
+        FileKind::Config => "series.cfg".to_owned(),
+        FileKind::Batch => "series.batch".to_owned(),
+        FileKind::Compacted => "series.compacted".to_owned(),
+    })
 }
 
 impl SeriesDir {
-    fn file_path(&self, kind: FileKind) -> PathBuf {
-        self.base_path.join(match kind {
-            FileKind::Data => "series.dat".to_owned(),
-            FileKind::Index => "series.idx".to_owned(),
-            FileKind::Log(s) => format!("series.log.{}", s),
-        })
-    }
     pub fn open(&self, kind: FileKind, mode: OpenMode) -> Result<File, Error> {
-        let path = self.file_path(kind);
+        let base_path = self.base_path.read().unwrap();
         let mut options = OpenOptions::new();
         let options = match mode {
             OpenMode::Read => options.read(true),
             OpenMode::Write => options.read(true).write(true).create(true),
         };
-        Ok(options.open(&path)?)
+        Ok(options.open(file_path(&base_path, kind))?)
+    }
+    pub fn read_to_string(&self, kind: FileKind) -> Result<Option<String>, Error> {
+        let base_path = self.base_path.read().unwrap();
+        match fs::read_to_string(file_path(&base_path, kind)) {
+            Ok(content) => Ok(Some(content)),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(error.into()),
+        }
+    }
+    pub fn read(&self, kind: FileKind) -> Result<Option<Vec<u8>>, Error> {
+        let base_path = self.base_path.read().unwrap();
+        match fs::read(file_path(&base_path, kind)) {
+            Ok(content) => Ok(Some(content)),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(error.into()),
+        }
+    }
+    pub fn write_atomically(&self, kind: FileKind, bytes: &[u8]) -> Result<(), Error> {
+        self.write_atomically_with(kind, |file| Ok(file.write_all(bytes)?))
+    }
+    /// Same as `write_atomically`, the content is written by `write` to the temporary file.
+    pub fn write_atomically_with<F>(&self, kind: FileKind, write: F) -> Result<(), Error>
+    where
+        F: FnOnce(&mut File) -> Result<(), Error>,
+    {
+        let base_path = self.base_path.read().unwrap();
+        let path = file_path(&base_path, kind);
+
+        let mut temp_path = path.clone().into_os_string();
+        temp_path.push(".tmp");
+
+        {
+            let mut file = File::create(&temp_path)?;
+            write(&mut file)?;
+            file.sync_data()?;
+        }
+
+        fs::rename(&temp_path, &path)?;
+
+        sync_dir(&base_path)
+    }
+    /// Fsyncs the directory, so the newly created (or renamed) files survive a crash.
+    pub fn sync_dir(&self) -> Result<(), Error> {
+        sync_dir(&self.base_path.read().unwrap())
     }
     fn parse_log_filename(&self, s: &str) -> Option<u64> {
         s.strip_prefix("series.log.")
             .and_then(|suffix| suffix.parse::<u64>().ok())
     }
     pub fn read_log_sequences(&self) -> Result<Vec<u64>, Error> {
-        let mut sequences = fs::read_dir(&self.base_path)?
+        let mut sequences = fs::read_dir(&*self.base_path.read().unwrap())?
             .filter_map(|entry| entry.ok())
             .filter_map(|entry| entry.file_name().into_string().ok())
             .filter_map(|entry| self.parse_log_filename(&entry))
@@ -49,53 +119,286 @@ impl SeriesDir {
         sequences.reverse();
         Ok(sequences)
     }
+    pub fn file_sizes(&self) -> Result<FileSizes, Error> {
+        let sequences = self.read_log_sequences()?;
+
+        let base_path = self.base_path.read().unwrap();
+        let size = |kind: FileKind| match fs::metadata(file_path(&base_path, kind)) {
+            Ok(metadata) => Ok(metadata.len()),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(0),
+            Err(error) => Err(error),
+        };
+
+        let mut log = 0u64;
+        for seq in sequences {
+            log += size(FileKind::Log(seq))?;
+        }
+
+        Ok(FileSizes {
+            data: size(FileKind::Data)? + size(FileKind::ArchivedData)?,
+            index: size(FileKind::Index)?,
+            log,
+        })
+    }
+    pub fn path(&self) -> PathBuf {
+        self.base_path.read().unwrap().clone()
+    }
+    pub fn exists(&self, kind: FileKind) -> bool {
+        file_path(&self.base_path.read().unwrap(), kind).is_file()
+    }
+    /// Removes the file, does nothing if it doesn't exist.
+    pub fn remove(&self, kind: FileKind) -> Result<(), Error> {
+        let base_path = self.base_path.read().unwrap();
+        match fs::remove_file(file_path(&base_path, kind)) {
+            Err(error) if error.kind() != io::ErrorKind::NotFound => Err(error.into()),
+            _ => Ok(()),
+        }
+    }
     pub fn remove_log(&self, seq: u64) -> Result<(), Error> {
-        Ok(fs::remove_file(self.file_path(FileKind::Log(seq)))?)
+        let base_path = self.base_path.read().unwrap();
+        Ok(fs::remove_file(file_path(&base_path, FileKind::Log(seq)))?)
+    }
+    /// Moves the directory to `path`, the files opened before stay valid.
+    fn rename(&self, path: PathBuf) -> Result<(), Error> {
+        let mut base_path = self.base_path.write().unwrap();
+        fs::rename(&*base_path, &path)?;
+        *base_path = path;
+        Ok(())
     }
 }
 
+#[cfg(unix)]
+fn sync_dir(path: &Path) -> Result<(), Error> {
+    Ok(File::open(path)?.sync_all()?)
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_path: &Path) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Free space of the volume holding `path`, in bytes.
+#[cfg(unix)]
+fn available_space(path: &Path) -> Result<u64, Error> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| Error::Other(format!("invalid path: {}", e)))?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+#[cfg(not(unix))]
+fn available_space(_path: &Path) -> Result<u64, Error> {
+    Ok(0)
+}
+
+/// Prefix of the temporary series written by the compaction, see `SeriesTable::compact`.
+pub const COMPACT_PREFIX: &str = "compact";
+
+/// Suffix of the series replaced by the compaction until it's removed.
+pub const REPLACED_SUFFIX: &str = ".replaced";
+
+/// Returns `true` for the temporary series of the compaction, `compact-<nanos>-<seq>`,
+/// and for the series it replaced, `compact-<nanos>-<seq>.replaced`.
+fn is_compaction_temp(name: &str) -> bool {
+    let is_number = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    name.strip_suffix(REPLACED_SUFFIX)
+        .unwrap_or(name)
+        .strip_prefix(COMPACT_PREFIX)
+        .and_then(|rest| rest.strip_prefix('-'))
+        .and_then(|rest| rest.split_once('-'))
+        .map(|(nanos, seq)| is_number(nanos) && is_number(seq))
+        .unwrap_or(false)
+}
+
+/// The series are spread across the data roots: a new series is placed on the root
+/// with the most free space, the existing series are looked up on all roots.
+fn series_not_found(name: &str) -> Error {
+    Error::Io(io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("series '{}' not found", name),
+    ))
+}
+
 pub struct FileSystem {
-    base_path: PathBuf,
+    roots: Vec<PathBuf>,
+    read_only: bool,
 }
 
 impl FileSystem {
+    /// The file system of a read-only process, the missing series are not created.
+    pub fn with_read_only(self) -> FileSystem {
+        FileSystem { read_only: true, ..self }
+    }
+
+    /// Returns the `series` directory of the root holding the series, if any.
+    fn find_series(&self, name: &str) -> Option<PathBuf> {
+        self.roots
+            .iter()
+            .map(|root| root.join("series"))
+            .find(|series| series.join(name).is_dir())
+    }
+
+    /// Returns the `series` directory of the root with the most free space, the first
+    /// root is preferred on ties.
+    fn place_series(&self) -> Result<PathBuf, Error> {
+        let mut best: Option<(u64, &PathBuf)> = None;
+        for root in self.roots.iter() {
+            let space = available_space(root)?;
+            if best.map(|(best_space, _)| space > best_space).unwrap_or(true) {
+                best = Some((space, root));
+            }
+        }
+        Ok(best.map(|(_, root)| root.join("series")).unwrap())
+    }
+
+    fn series_or_err(&self, name: &str) -> Result<PathBuf, Error> {
+        self.find_series(name).ok_or_else(|| series_not_found(name))
+    }
+
     pub fn series<S: AsRef<str>>(&self, name: S) -> Result<Arc<SeriesDir>, Error> {
-        let base_path = self.base_path.join("series").join(name.as_ref());
-        fs::create_dir_all(&base_path)?;
+        let base_path = match self.find_series(name.as_ref()) {
+            Some(series) => series.join(name.as_ref()),
+            None if self.read_only => return Err(series_not_found(name.as_ref())),
+            None => {
+                let series = self.place_series()?;
+                let base_path = series.join(name.as_ref());
+                fs::create_dir_all(&base_path)?;
+                sync_dir(&series)?;
+                base_path
+            }
+        };
 
-        Ok(Arc::new(SeriesDir { base_path }))
+        Ok(Arc::new(SeriesDir {
+            base_path: RwLock::new(base_path),
+        }))
     }
 
+    /// Renames the series, the series stays on the same root.
     pub fn rename_series<S: AsRef<str>>(&self, src: S, dst: S) -> Result<(), Error> {
-        let src_path = self.base_path.join("series").join(src.as_ref());
-        let dst_path = self.base_path.join("series").join(dst.as_ref());
+        let series = self.series_or_err(src.as_ref())?;
+
+        fs::rename(series.join(src.as_ref()), series.join(dst.as_ref()))?;
 
-        Ok(fs::rename(src_path, dst_path)?)
+        sync_dir(&series)
     }
 
-    pub fn get_series(&self) -> Result<Vec<String>, Error> {
+    /// Renames the series of the opened `dir`, `dir` points to the new location afterwards.
+    pub fn rename_series_dir<S: AsRef<str>>(&self, dir: &SeriesDir, dst: S) -> Result<(), Error> {
+        let base_path = dir.path();
+        let series = base_path.parent().unwrap_or(&base_path).to_owned();
+
+        dir.rename(series.join(dst.as_ref()))?;
+
+        sync_dir(&series)
+    }
+
+    /// Removes the series directory with all the files.
+    pub fn remove_series<S: AsRef<str>>(&self, name: S) -> Result<(), Error> {
+        let series = self.series_or_err(name.as_ref())?;
+
+        fs::remove_dir_all(series.join(name.as_ref()))?;
+
+        sync_dir(&series)
+    }
+
+    /// Returns the names of the series directories on all roots.
+    fn list_series(&self) -> Result<Vec<(String, PathBuf)>, Error> {
         let mut series = Vec::new();
-        for entry in fs::read_dir(self.base_path.join("series"))? {
-            let series_path = entry?.path().clone();
-            if series_path.join("series.dat").is_file() {
+        for root in self.roots.iter() {
+            for entry in fs::read_dir(root.join("series"))? {
+                let series_path = entry?.path().clone();
                 if let Some(filename) = series_path
                     .file_name()
                     .and_then(|f| f.to_owned().into_string().ok())
                 {
-                    series.push(filename);
+                    series.push((filename, series_path));
                 }
             }
         }
+        Ok(series)
+    }
+
+    /// Returns the series names, the temporary series of the compaction are skipped.
+    pub fn get_series(&self) -> Result<Vec<String>, Error> {
+        let mut series = self
+            .list_series()?
+            .into_iter()
+            .filter(|(name, path)| path.join("series.dat").is_file() && !is_compaction_temp(name))
+            .map(|(name, _)| name)
+            .collect::<Vec<_>>();
         series.sort();
         Ok(series)
     }
+
+    /// Completes the compactions interrupted by a crash: the replaced series is restored
+    /// if the compacted one didn't take its name yet, the other temporary series are removed.
+    pub fn recover_compactions(&self) -> Result<(), Error> {
+        let mut temps = self
+            .list_series()?
+            .into_iter()
+            .filter(|(name, _)| is_compaction_temp(name))
+            .map(|(name, _)| name)
+            .collect::<Vec<_>>();
+        // the replaced series are handled before the temporary series holding their names
+        temps.sort_by_key(|name| !name.ends_with(REPLACED_SUFFIX));
+
+        for name in temps {
+            if let Some(temp) = name.strip_suffix(REPLACED_SUFFIX) {
+                let replaced = match self.find_series(temp) {
+                    Some(series) => match fs::read_to_string(file_path(&series.join(temp), FileKind::Compacted)) {
+                        Ok(replaced) => Some(replaced),
+                        Err(error) if error.kind() == io::ErrorKind::NotFound => None,
+                        Err(error) => return Err(error.into()),
+                    },
+                    None => None,
+                };
+                if let Some(replaced) = replaced.filter(|replaced| self.find_series(replaced).is_none()) {
+                    log::warn!("restoring series '{}' replaced by an interrupted compaction", replaced);
+                    self.rename_series(name.as_str(), replaced.as_str())?;
+                    continue;
+                }
+            }
+            log::warn!("removing '{}' left by an interrupted compaction", name);
+            self.remove_series(&name)?;
+        }
+        Ok(())
+    }
 }
 
 pub fn open<P: AsRef<Path>>(base_path: P) -> Result<FileSystem, Error> {
-    fs::create_dir_all(base_path.as_ref().join("series"))?;
-    Ok(FileSystem {
-        base_path: base_path.as_ref().to_owned(),
-    })
+    open_roots(&[base_path])
+}
+
+/// Opens the file system over the multiple data roots, like the directories on different disks.
+/// Fails if a series exists in more than one root, as it's not known which one is current.
+pub fn open_roots<P: AsRef<Path>>(roots: &[P]) -> Result<FileSystem, Error> {
+    if roots.is_empty() {
+        return Err(Error::Other("at least one data root is required".to_owned()));
+    }
+    for root in roots {
+        fs::create_dir_all(root.as_ref().join("series"))?;
+    }
+    let fs = FileSystem {
+        roots: roots.iter().map(|root| root.as_ref().to_owned()).collect(),
+        read_only: false,
+    };
+
+    let mut series = fs.list_series()?;
+    series.sort();
+    if let Some(pair) = series.windows(2).find(|pair| pair[0].0 == pair[1].0) {
+        return Err(Error::Other(format!(
+            "series '{}' exists in multiple data roots: {:?} and {:?}",
+            pair[0].0, pair[0].1, pair[1].1
+        )));
+    }
+
+    Ok(fs)
 }
 
 #[cfg(test)]
@@ -103,6 +406,22 @@ pub mod test {
     use super::*;
     use std::time::{SystemTime, UNIX_EPOCH};
     use std::ops::Deref;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    static TEMP_SEQ: AtomicU64 = AtomicU64::new(0);
+
+    /// Returns the unique path of the temporary directory, the counter keeps the
+    /// paths of the tests started within the same clock tick apart.
+    pub fn temp_path() -> PathBuf {
+        PathBuf::from(format!(
+            "temp-dir-{:?}-{}",
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_nanos(),
+            TEMP_SEQ.fetch_add(1, Ordering::SeqCst)
+        ))
+    }
 
     pub struct TempFS {
         pub fs: FileSystem,
@@ -122,14 +441,83 @@ pub mod test {
         }
     }
 
-    pub fn open() -> Result<TempFS, Error> {
-        let path = PathBuf::from(format!(
-            "temp-dir-{:?}",
-            SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap()
-                .as_nanos()
+    #[test]
+    fn test_sync_dir() -> Result<(), Error> {
+        let fs = open()?;
+        let dir = fs.series("series1")?;
+
+        dir.open(FileKind::Data, OpenMode::Write)?;
+        dir.sync_dir()?;
+
+        fs.rename_series("series1", "series2")?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rename_series_dir() -> Result<(), Error> {
+        let fs = open()?;
+
+        let dir = fs.series("series3")?;
+        let mut file = dir.open(FileKind::Data, OpenMode::Write)?;
+        fs.rename_series_dir(&dir, "series4")?;
+
+        file.write_all(b"data")?;
+        assert_eq!(Some("data".to_owned()), dir.read_to_string(FileKind::Data)?);
+        assert_eq!(vec!["series4".to_owned()], fs.get_series()?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_only() -> Result<(), Error> {
+        let fs = open()?;
+        fs.series("series1")?;
+
+        let read_only = super::open(&fs.path)?.with_read_only();
+        assert!(read_only.series("series1")?.path().ends_with("series1"));
+        assert!(matches!(
+            read_only.series("series2"),
+            Err(Error::Io(error)) if error.kind() == io::ErrorKind::NotFound
         ));
+        assert!(!fs.path.join("series").join("series2").exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_multiple_roots() -> Result<(), Error> {
+        let first = open()?;
+        let second = open()?;
+
+        first.series("series1")?.open(FileKind::Data, OpenMode::Write)?;
+        second.series("series2")?.open(FileKind::Data, OpenMode::Write)?;
+
+        let fs = open_roots(&[&first.path, &second.path])?;
+
+        assert_eq!(vec!["series1".to_owned(), "series2".to_owned()], fs.get_series()?);
+        assert!(fs.series("series2")?.path().starts_with(&second.path));
+
+        fs.rename_series("series2", "series3")?;
+        assert!(fs.series("series3")?.path().starts_with(&second.path));
+
+        let dir = fs.series("series4")?;
+        dir.open(FileKind::Data, OpenMode::Write)?;
+        assert!(dir.path().starts_with(&first.path) || dir.path().starts_with(&second.path));
+
+        fs.remove_series("series1")?;
+        assert_eq!(vec!["series3".to_owned(), "series4".to_owned()], fs.get_series()?);
+        assert!(fs.remove_series("series1").is_err());
+
+        // the series found in both roots is not picked from either
+        first.series("series3")?.open(FileKind::Data, OpenMode::Write)?;
+        assert!(matches!(open_roots(&[&first.path, &second.path]), Err(Error::Other(_))));
+
+        Ok(())
+    }
+
+    pub fn open() -> Result<TempFS, Error> {
+        let path = temp_path();
 
         Ok(TempFS {
             fs: super::open(&path)?,
@@ -5,28 +5,31 @@ use std::sync::{Arc, RwLock};
 
 use super::error::Error;
 
-const MAX_INDEX_SIZE: u32 = 2 * 1024 * 1024 * 1024;
+pub const MAX_INDEX_SIZE: u32 = 2 * 1024 * 1024 * 1024;
 
-const INDEX_BLOCK_SIZE: u32 = ENTRY_SIZE * 1024;
+pub const INDEX_BLOCK_SIZE: u32 = ENTRY_SIZE * 1024;
 
-const ENTRY_SIZE: u32 = 8 + 4;
+pub const ENTRY_SIZE: u32 = 8 + 4;
 
 struct Interior {
     mmap: MmapMut,
     file: File,
     len: usize,
+    read_only: bool,
+    /// The index fails with `IndexFull` past this size, `MAX_INDEX_SIZE` unless reduced in tests.
+    max_size: u32,
 }
 
 impl Interior {
-    fn open(file: File, upper_offset: u32) -> Result<Interior, Error> {
+    fn open(file: File, upper_offset: u32, max_size: u32) -> Result<Interior, Error> {
         if upper_offset % ENTRY_SIZE != 0 {
             return Err(Error::InvalidOffset);
         }
-        if upper_offset > MAX_INDEX_SIZE {
-            return Err(Error::IndexFileTooBig);
+        if upper_offset > max_size {
+            return Err(Error::IndexFull);
         }
 
-        let len = MAX_INDEX_SIZE.min((upper_offset / INDEX_BLOCK_SIZE + 1) * INDEX_BLOCK_SIZE);
+        let len = max_size.min((upper_offset / INDEX_BLOCK_SIZE + 1) * INDEX_BLOCK_SIZE);
 
         file.set_len(len as u64)?;
 
@@ -34,12 +37,38 @@ impl Interior {
             mmap: unsafe { MmapOptions::new().map_mut(&file)? },
             file: file,
             len: len as usize,
+            read_only: false,
+            max_size,
         })
     }
-    fn remap_if_needed(&mut self, offset: u32) -> Result<(), Error> {
-        if offset as u64 + ENTRY_SIZE as u64 > MAX_INDEX_SIZE as u64 {
-            return Err(Error::IndexFileTooBig);
+    /// Maps the index file written by another process, without resizing it.
+    fn open_read_only(file: File) -> Result<Interior, Error> {
+        let mut index = Interior {
+            mmap: MmapMut::map_anon(1)?,
+            file,
+            len: 0,
+            read_only: true,
+            max_size: MAX_INDEX_SIZE,
+        };
+        index.remap_read_only()?;
+        Ok(index)
+    }
+    fn remap_read_only(&mut self) -> Result<(), Error> {
+        let len = (self.file.metadata()?.len() as usize).min(self.max_size as usize);
+        if len > self.len {
+            self.mmap = unsafe { MmapOptions::new().len(len).map_copy(&self.file)? };
+            self.len = len;
+        }
+        Ok(())
+    }
+    fn refresh(&mut self, upper_offset: u32) -> Result<(), Error> {
+        if self.read_only && upper_offset as usize > self.len {
+            self.remap_read_only()?;
         }
+        Ok(())
+    }
+    fn remap_if_needed(&mut self, offset: u32) -> Result<(), Error> {
+        self.check_room(offset)?;
         if offset as usize + ENTRY_SIZE as usize <= self.len {
             return Ok(());
         }
@@ -54,6 +83,10 @@ impl Interior {
         Ok(())
     }
     fn set(&mut self, offset: u32, ts: i64, block_offset: u32) -> Result<u32, Error> {
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+
         self.remap_if_needed(offset)?;
 
         let offset = offset as usize;
@@ -77,6 +110,19 @@ impl Interior {
             (&self.mmap[start..start + 8]).try_into()?,
         ))
     }
+    fn entry_at(&self, offset: u32) -> Result<(i64, u32), Error> {
+        if !offset.is_multiple_of(ENTRY_SIZE) {
+            return Err(Error::OffsetIsNotAligned);
+        }
+        if offset as usize + ENTRY_SIZE as usize > self.len {
+            return Err(Error::OffsetOutsideTheRange);
+        }
+        let start = offset as usize;
+        Ok((
+            i64::from_be_bytes((&self.mmap[start..start + 8]).try_into()?),
+            u32::from_be_bytes((&self.mmap[start + 8..start + 12]).try_into()?),
+        ))
+    }
     fn nth_offset(&self, nth: usize, upper_offset: usize) -> Result<Option<u32>, Error> {
         let start = ENTRY_SIZE as usize * nth + 8;
         if start + 4 > upper_offset {
@@ -102,6 +148,36 @@ impl Interior {
 }
 
 impl Interior {
+    fn first_ts(&self, upper_offset: u32) -> Result<Option<i64>, Error> {
+        if upper_offset < ENTRY_SIZE {
+            return Ok(None);
+        }
+        Ok(Some(self.nth_ts(0)?))
+    }
+    fn last_ts(&self, upper_offset: u32) -> Result<Option<i64>, Error> {
+        if upper_offset < ENTRY_SIZE {
+            return Ok(None);
+        }
+        Ok(Some(self.entry_at(upper_offset - ENTRY_SIZE)?.0))
+    }
+    /// Returns the index offset of the first entry with the timestamp greater
+    /// than `ts`, or `upper_offset` if there is no such entry.
+    fn upper_bound(&self, ts: i64, upper_offset: u32) -> Result<u32, Error> {
+        let mut lo = 0usize;
+        let mut hi = (upper_offset / ENTRY_SIZE) as usize;
+
+        while lo < hi {
+            let m = lo + (hi - lo) / 2;
+
+            if self.nth_ts(m)? <= ts {
+                lo = m + 1;
+            } else {
+                hi = m;
+            }
+        }
+
+        Ok(lo as u32 * ENTRY_SIZE)
+    }
     fn ceiling_offset(&self, ts: i64, upper_offset: u32) -> Result<Option<u32>, Error> {
         if upper_offset as usize > self.len {
             return Err(Error::OffsetOutsideTheRange);
@@ -136,6 +212,22 @@ impl Interior {
     }
 }
 
+impl Interior {
+    fn check_room(&self, offset: u32) -> Result<(), Error> {
+        if offset as u64 + ENTRY_SIZE as u64 > self.max_size as u64 {
+            return Err(Error::IndexFull);
+        }
+        Ok(())
+    }
+    /// The appends are logged with the warning once the index is filled up to 90%.
+    fn is_nearly_full(&self, upper_offset: u32) -> bool {
+        upper_offset >= self.max_size / 10 * 9
+    }
+    fn usage(&self, upper_offset: u32) -> f64 {
+        upper_offset as f64 / self.max_size as f64
+    }
+}
+
 #[cfg(test)]
 mod test_index {
     use super::super::file_system::{self, FileKind, OpenMode};
@@ -145,7 +237,7 @@ mod test_index {
         let fs = file_system::test::open()?;
         let dir = fs.series("series1")?;
         {
-            let mut index = Interior::open(dir.open(FileKind::Index, OpenMode::Write)?, 0)?;
+            let mut index = Interior::open(dir.open(FileKind::Index, OpenMode::Write)?, 0, MAX_INDEX_SIZE)?;
             assert_eq!(1 * ENTRY_SIZE, index.set(0 * ENTRY_SIZE, -10, 0)?);
             assert_eq!(2 * ENTRY_SIZE, index.set(1 * ENTRY_SIZE,-2, 1)?);
             assert_eq!(3 * ENTRY_SIZE, index.set(2 * ENTRY_SIZE,-1, 4)?);
@@ -160,7 +252,48 @@ mod test_index {
             assert_eq!(Some(0), index.ceiling_offset(-1000, upper)?);
 
             assert_eq!(None, index.ceiling_offset(7, upper)?);
+
+            assert_eq!(Some(-10), index.first_ts(upper)?);
+            assert_eq!(Some(6), index.last_ts(upper)?);
+            assert_eq!(Some(-2), index.last_ts(2 * ENTRY_SIZE)?);
+
+            assert_eq!(0, index.upper_bound(-11, upper)?);
+            assert_eq!(ENTRY_SIZE, index.upper_bound(-10, upper)?);
+            assert_eq!(3 * ENTRY_SIZE, index.upper_bound(0, upper)?);
+            assert_eq!(4 * ENTRY_SIZE, index.upper_bound(4, upper)?);
+            assert_eq!(upper, index.upper_bound(6, upper)?);
+            assert_eq!(None, index.first_ts(0)?);
+            assert_eq!(None, index.last_ts(0)?);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_index_full() -> Result<(), Error> {
+        let fs = file_system::test::open()?;
+        let dir = fs.series("series1")?;
+        let max_size = 4 * INDEX_BLOCK_SIZE;
+        let mut index = Interior::open(dir.open(FileKind::Index, OpenMode::Write)?, 0, max_size)?;
+
+        let mut upper = 0;
+        while !index.is_nearly_full(upper) {
+            upper = index.set(upper, upper as i64, upper)?;
+        }
+        assert_eq!((max_size / 10 * 9).div_ceil(ENTRY_SIZE) * ENTRY_SIZE, upper);
+        assert!(index.usage(upper) >= 0.9);
+
+        while upper + ENTRY_SIZE <= max_size {
+            upper = index.set(upper, upper as i64, upper)?;
         }
+        assert!(matches!(index.set(upper, upper as i64, upper), Err(Error::IndexFull)));
+        assert_eq!(Some(upper as i64 - ENTRY_SIZE as i64), index.last_ts(upper)?);
+
+        drop(index);
+        assert!(matches!(
+            Interior::open(dir.open(FileKind::Index, OpenMode::Write)?, upper + ENTRY_SIZE, max_size),
+            Err(Error::IndexFull)
+        ));
+
         Ok(())
     }
 }
@@ -170,11 +303,22 @@ pub struct Index {
 }
 
 impl Index {
-    pub fn open(file: File, offset: u32) -> Result<Index, Error> {
+    /// Opens the index filled up to `offset`, the appends fail with `IndexFull` past `max_size`.
+    pub fn open(file: File, offset: u32, max_size: u32) -> Result<Index, Error> {
+        Ok(Index {
+            inter: Arc::new(RwLock::new(Interior::open(file, offset, max_size)?)),
+        })
+    }
+    pub fn open_read_only(file: File) -> Result<Index, Error> {
         Ok(Index {
-            inter: Arc::new(RwLock::new(Interior::open(file, offset)?)),
+            inter: Arc::new(RwLock::new(Interior::open_read_only(file)?)),
         })
     }
+    /// Remaps the index opened read-only if it doesn't cover `upper` anymore.
+    pub fn refresh(&self, upper: u32) -> Result<(), Error> {
+        let mut inter = self.inter.write().unwrap();
+        inter.refresh(upper)
+    }
     pub fn set(&self, offset: u32, ts: i64, block_offset: u32) -> Result<u32, Error> {
         let mut inter = self.inter.write().unwrap();
         inter.set(offset, ts, block_offset)
@@ -183,6 +327,48 @@ impl Index {
         let mut inter = self.inter.write().unwrap();
         inter.sync()
     }
+    /// Fails with `IndexFull` if there is no room for the entry at `offset`.
+    pub fn check_room(&self, offset: u32) -> Result<(), Error> {
+        self.inter.read().unwrap().check_room(offset)
+    }
+    /// Returns `true` if the index filled up to `upper` is close to the max size.
+    pub fn is_nearly_full(&self, upper: u32) -> bool {
+        self.inter.read().unwrap().is_nearly_full(upper)
+    }
+    /// Returns the share of the max index size used by the index filled up to `upper`.
+    pub fn usage(&self, upper: u32) -> f64 {
+        self.inter.read().unwrap().usage(upper)
+    }
+    /// Returns the timestamp of the first index entry, i.e. the highest timestamp of the first block.
+    pub fn first_ts(&self, upper: u32) -> Result<Option<i64>, Error> {
+        let inter = self.inter.read().unwrap();
+        inter.first_ts(upper)
+    }
+    pub fn last_ts(&self, upper: u32) -> Result<Option<i64>, Error> {
+        let inter = self.inter.read().unwrap();
+        inter.last_ts(upper)
+    }
+    pub fn upper_bound(&self, ts: i64, upper: u32) -> Result<u32, Error> {
+        let inter = self.inter.read().unwrap();
+        inter.upper_bound(ts, upper)
+    }
+    /// Returns the block offset of the entry at the index offset.
+    pub fn offset_at(&self, offset: u32) -> Result<u32, Error> {
+        let inter = self.inter.read().unwrap();
+        Ok(inter.entry_at(offset)?.1)
+    }
+    /// Returns the timestamp of the entry at the index offset.
+    pub fn ts_at(&self, offset: u32) -> Result<i64, Error> {
+        let inter = self.inter.read().unwrap();
+        Ok(inter.entry_at(offset)?.0)
+    }
+    /// Returns the `(ts, block offset)` entries before the index offset `upper`.
+    pub fn entries(&self, upper: u32) -> Result<Vec<(i64, u32)>, Error> {
+        let inter = self.inter.read().unwrap();
+        (0..upper / ENTRY_SIZE)
+            .map(|n| inter.entry_at(n * ENTRY_SIZE))
+            .collect()
+    }
     pub fn ceiling_offset(&self, ts: i64, upper: u32) -> Result<Option<u32>, Error> {
         let inter = self.inter.read().unwrap();
         inter.ceiling_offset(ts, upper)
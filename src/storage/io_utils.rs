@@ -17,10 +17,6 @@ pub trait WriteBytes: Write {
         self.write_all(&v.to_be_bytes())?;
         Ok(())
     }
-    fn write_u64(&mut self, v: &u64) -> io::Result<()> {
-        self.write_all(&v.to_be_bytes())?;
-        Ok(())
-    }
     fn write_f64(&mut self, v: &f64) -> io::Result<()> {
         self.write_all(&v.to_be_bytes())?;
         Ok(())
@@ -40,11 +36,6 @@ pub trait ReadBytes: Read {
         self.read_exact(&mut buf)?;
         Ok(i64::from_be_bytes(buf))
     }
-    fn read_u64(&mut self) -> io::Result<u64> {
-        let mut buf = [0u8; 8];
-        self.read_exact(&mut buf)?;
-        Ok(u64::from_be_bytes(buf))
-    }
     fn read_f64(&mut self) -> io::Result<f64> {
         let mut buf = [0u8; 8];
         self.read_exact(&mut buf)?;
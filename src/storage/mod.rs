@@ -5,12 +5,23 @@ mod index;
 mod io_utils;
 mod series;
 mod commit_log;
+mod config;
+mod sync_mode;
+mod clock;
+mod virtual_series;
 pub mod file_system;
 pub mod series_table;
 pub mod error;
 pub mod env;
+pub mod scrubber;
 
+pub use clock::{Clock, SystemClock, TestClock};
+pub use commit_log::{Checksum, Commit};
 pub use compression::Compression;
-pub use entry::Entry;
-pub use series::{SeriesReader, SeriesIterator, SeriesWriter};
-pub use series_table::SeriesTable;
\ No newline at end of file
+pub use data::{DataReader, BLOCK_HEADER_SIZE, MAX_ENTRIES_PER_BLOCK};
+pub use config::{DuplicatePolicy, SeriesConfig, TimestampUnit};
+pub use entry::{merge_sorted, Entry, MergeSorted};
+pub use series::{AppendReport, SampleIterator, ScanPlan, SeriesReader, SeriesIterator, SeriesSize, SeriesVerifier, SeriesWriter};
+pub use series_table::{CompactReport, SeriesTable};
+pub use sync_mode::{IndexSyncPolicy, SyncMode};
+pub use virtual_series::{BinaryOp, VirtualDefinition, VirtualIterator, VirtualSeries};
\ No newline at end of file
@@ -0,0 +1,115 @@
+use super::error::Error;
+use super::SeriesTable;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Weak};
+use std::thread;
+use std::time::Duration;
+
+/// Pause after each verified block, so the scrubber doesn't compete with the queries.
+const BLOCK_PAUSE: Duration = Duration::from_millis(1);
+
+#[derive(Debug, Default)]
+pub struct ScrubStats {
+    /// Number of the completed passes over all series.
+    pub passes: AtomicU64,
+    /// Number of the series found corrupted, over all passes.
+    pub corrupted: AtomicU64,
+}
+
+/// Verifies all series block by block, pausing for `pause` after each block.
+/// Returns the series that failed the verification.
+pub fn scrub(series_table: &SeriesTable, pause: Duration) -> Vec<(String, Error)> {
+    let mut corrupted = Vec::new();
+    for (name, reader) in series_table.iter_readers() {
+        let result = reader.verifier().and_then(|mut verifier| {
+            while verifier.verify_block()? {
+                thread::sleep(pause);
+            }
+            Ok(())
+        });
+
+        if let Err(error) = result {
+            log::warn!("series '{}' is corrupted: {:?}", name, error);
+            corrupted.push((name, error));
+        }
+    }
+    corrupted
+}
+
+/// Starts the background thread verifying all series every `interval`. Stops once the
+/// table is dropped.
+pub fn start(series_table: &Arc<SeriesTable>, interval: Duration) -> Arc<ScrubStats> {
+    let stats = Arc::new(ScrubStats::default());
+
+    let thread_stats = stats.clone();
+    let series_table: Weak<SeriesTable> = Arc::downgrade(series_table);
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+
+        // the table is held for the pass only
+        let table = match series_table.upgrade() {
+            Some(table) => table,
+            None => return,
+        };
+        let corrupted = scrub(&table, BLOCK_PAUSE);
+
+        thread_stats.corrupted.fetch_add(corrupted.len() as u64, Ordering::Relaxed);
+        thread_stats.passes.fetch_add(1, Ordering::Relaxed);
+    });
+
+    stats
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::file_system::{FileKind, OpenMode};
+    use super::super::{env, series_table, Entry};
+    use super::*;
+    use std::io::{Seek, SeekFrom, Write};
+    use std::time::Instant;
+
+    #[test]
+    fn test_scrub() -> Result<(), Error> {
+        let env = env::test::create()?;
+        let series_table = Arc::new(series_table::create(env.reopen()?)?);
+
+        for name in ["t1", "t2"] {
+            series_table.create(name)?;
+            for ts in 0..10 {
+                series_table.writer(name).unwrap().append(&vec![Entry { ts, value: 1.0 }])?;
+            }
+        }
+
+        assert!(scrub(&series_table, Duration::ZERO).is_empty());
+
+        // damage the header of the second block
+        let block_offset = env.series("t2")?.index().offset_at(super::super::index::ENTRY_SIZE)?;
+        let mut file = env.series("t2")?.dir().open(FileKind::Data, OpenMode::Write)?;
+        file.seek(SeekFrom::Start(block_offset as u64))?;
+        file.write_all(&[0xff, 0xff])?;
+
+        let corrupted = scrub(&series_table, Duration::ZERO);
+        assert_eq!(1, corrupted.len());
+        assert_eq!("t2", corrupted[0].0);
+        assert!(matches!(corrupted[0].1, Error::Crc16Mismatch));
+
+        let stats = start(&series_table, Duration::from_millis(10));
+        let started = Instant::now();
+        while stats.passes.load(Ordering::Relaxed) == 0 && started.elapsed() < Duration::from_secs(5) {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(stats.corrupted.load(Ordering::Relaxed) >= 1);
+
+        // the scrubber stops once the table is dropped
+        let dropped = Arc::downgrade(&series_table);
+        drop(series_table);
+        while dropped.upgrade().is_some() {
+            thread::sleep(Duration::from_millis(10));
+        }
+        let passes = stats.passes.load(Ordering::Relaxed);
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(passes, stats.passes.load(Ordering::Relaxed));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,90 @@
+use super::super::error::Error;
+use super::super::file_system::{FileKind, SeriesDir};
+use super::super::io_utils::{ReadBytes, WriteBytes};
+use std::io::{self, Read, Write};
+
+/// Batch files starting with the magic contain the batch being committed and the
+/// last committed batch. Older batch files contain just the committed batch id.
+const BATCH_MAGIC: [u8; 4] = *b"MSBT";
+
+/// The batch written before its commit, it's committed if the commit log
+/// reached `data_offset`.
+pub struct PendingBatch<'a> {
+    pub data_offset: u32,
+    pub batch_id: &'a str,
+}
+
+fn write_id<W: Write>(write: &mut W, batch_id: Option<&str>) -> Result<(), Error> {
+    match batch_id {
+        Some(batch_id) => {
+            write.write_u8(&1)?;
+            write.write_u32(&(batch_id.len() as u32))?;
+            write.write_all(batch_id.as_bytes())?;
+        }
+        None => write.write_u8(&0)?,
+    }
+    Ok(())
+}
+
+fn read_id<R: Read>(read: &mut R) -> Result<Option<String>, Error> {
+    if read.read_u8()? == 0 {
+        return Ok(None);
+    }
+    let mut bytes = vec![0u8; read.read_u32()? as usize];
+    read.read_exact(&mut bytes)?;
+    String::from_utf8(bytes)
+        .map(Some)
+        .map_err(|_| Error::Io(io::Error::new(io::ErrorKind::InvalidData, "batch id is not utf-8")))
+}
+
+/// Writes the batch file, the `pending` batch is written before its commit
+/// together with the `committed` one it replaces.
+pub fn write(dir: &SeriesDir, pending: Option<PendingBatch>, committed: Option<&str>) -> Result<(), Error> {
+    dir.write_atomically_with(FileKind::Batch, |file| {
+        file.write_all(&BATCH_MAGIC)?;
+        match pending {
+            Some(pending) => {
+                file.write_u8(&1)?;
+                file.write_u32(&pending.data_offset)?;
+                write_id(file, Some(pending.batch_id))?;
+            }
+            None => file.write_u8(&0)?,
+        }
+        write_id(file, committed)
+    })
+}
+
+/// Reads the id of the last committed batch given the committed `data_offset`. The
+/// batch file is rewritten without the pending batch unless the series is `read_only`.
+pub fn recover(dir: &SeriesDir, data_offset: u32, read_only: bool) -> Result<Option<String>, Error> {
+    let bytes = match dir.read(FileKind::Batch)? {
+        Some(bytes) => bytes,
+        None => return Ok(None),
+    };
+    if !bytes.starts_with(&BATCH_MAGIC) {
+        return Ok(Some(String::from_utf8_lossy(&bytes).into_owned()));
+    }
+
+    let mut read = &bytes[BATCH_MAGIC.len()..];
+    let pending = match read.read_u8()? {
+        0 => None,
+        _ => Some((read.read_u32()?, read_id(&mut read)?)),
+    };
+    let committed = read_id(&mut read)?;
+
+    match pending {
+        // a failed commit is rolled back, so the batch written at or below the
+        // committed offset was committed
+        Some((offset, batch_id)) => {
+            let committed = match offset <= data_offset {
+                true => batch_id,
+                false => committed,
+            };
+            if !read_only {
+                write(dir, None, committed.as_deref())?;
+            }
+            Ok(committed)
+        }
+        None => Ok(committed),
+    }
+}
@@ -1,17 +1,28 @@
+mod batch_file;
+mod rate_limiter;
 mod series_reader;
+mod series_verifier;
 mod series_writer;
 
-pub use series_reader::{SeriesIterator, SeriesReader};
-pub use series_writer::SeriesWriter;
+pub use series_reader::{SampleIterator, ScanPlan, SeriesIterator, SeriesReader, SeriesSize};
+pub use series_verifier::SeriesVerifier;
+pub use series_writer::{AppendReport, SeriesWriter};
 
 #[cfg(test)]
 mod test {
     use super::super::entry::Entry;
     use super::super::env;
     use super::super::error::Error;
+    use super::super::config::SeriesConfig;
     use super::*;
+    use super::super::file_system::FileKind;
+    use std::fs;
+    use std::io::{Seek, SeekFrom, Write};
     use std::sync::Arc;
+    use std::time::Duration;
     use super::super::super::failpoints::Failpoints;
+    use super::super::{index, DuplicatePolicy, IndexSyncPolicy, SyncMode};
+    use super::super::commit_log::Commit;
 
     fn entry(ts: i64, value: f64) -> Entry {
         Entry { ts, value }
@@ -73,6 +84,32 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn test_sample() -> Result<(), Error> {
+        let env = env::test::create()?;
+        let series_env = env.series("series1")?;
+
+        let entries = (1..=10).map(|i| entry(i, i as f64)).collect::<Vec<Entry>>();
+        {
+            let writer = SeriesWriter::create(series_env.clone())?;
+            writer.append(&entries[0..4])?;
+            writer.append(&entries[4..10])?;
+        }
+
+        let reader = SeriesReader::create(series_env)?;
+        assert_eq!(
+            vec![entry(1, 1.0), entry(4, 4.0), entry(7, 7.0), entry(10, 10.0)],
+            reader.sample(0, 3)?.collect::<Result<Vec<Entry>, Error>>()?
+        );
+        assert_eq!(
+            vec![entry(3, 3.0), entry(6, 6.0), entry(9, 9.0)],
+            reader.sample(3, 3)?.collect::<Result<Vec<Entry>, Error>>()?
+        );
+        assert_eq!(entries, reader.sample(0, 1)?.collect::<Result<Vec<Entry>, Error>>()?);
+
+        Ok(())
+    }
+
     #[test]
     fn test_recover_after_data_write_failure() -> Result<(), Error> {
         let fp = Arc::new(Failpoints::create());
@@ -131,4 +168,1113 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn test_negative_timestamps() -> Result<(), Error> {
+        let env = env::test::create()?;
+        let series_env = env.series("series1")?;
+
+        let entries = vec![entry(-3000, 1.0), entry(-2000, 2.0), entry(-1000, 3.0)];
+
+        SeriesWriter::create(series_env.clone())?.append(&entries)?;
+
+        assert_eq!(-1000, series_env.commit_log().current().highest_ts);
+
+        let reader = SeriesReader::create(series_env.clone())?;
+        assert_eq!(
+            entries[1..].to_vec(),
+            reader.iterator(-2000)?.collect::<Result<Vec<Entry>, Error>>()?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_batch_id_persisted() -> Result<(), Error> {
+        let env = env::test::create()?;
+        let series_env = env.series("series1")?;
+
+        {
+            let writer = SeriesWriter::create(series_env.clone())?;
+            let mut appender = writer.appender()?;
+            assert!(appender.batch_id("b1".to_owned()));
+            appender.append(&vec![entry(1, 1.0)])?;
+            appender.done()?;
+        }
+
+        {
+            let writer = SeriesWriter::create(series_env.clone())?;
+            assert!(!writer.appender()?.batch_id("b1".to_owned()));
+            assert!(writer.appender()?.batch_id("b2".to_owned()));
+
+            // the cleared series accepts the batch again
+            writer.clear()?;
+            assert!(writer.appender()?.batch_id("b1".to_owned()));
+        }
+
+        let writer = SeriesWriter::create(series_env.clone())?;
+        assert!(writer.appender()?.batch_id("b1".to_owned()));
+        assert!(!series_env.dir().exists(FileKind::Batch));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_batch_id_recovery() -> Result<(), Error> {
+        let fp = Arc::new(Failpoints::create());
+        let env = env::test::create_with_failpoints(fp.clone())?;
+        let series_env = env.series("series1")?;
+
+        let append = |batch_id: &str, ts: i64| -> Result<Commit, Error> {
+            let writer = SeriesWriter::create(series_env.clone())?;
+            let mut appender = writer.appender()?;
+            assert!(appender.batch_id(batch_id.to_owned()));
+            appender.append(&vec![entry(ts, 1.0)])?;
+            appender.done()
+        };
+
+        append("b1", 1)?;
+
+        // crash after the batch file is written, before the commit
+        fp.on("series_writer::commit::batch");
+        append("b2", 2).unwrap_err();
+        fp.off("series_writer::commit::batch");
+
+        {
+            let writer = SeriesWriter::create(series_env.clone())?;
+            assert!(!writer.appender()?.batch_id("b1".to_owned()));
+            assert!(writer.appender()?.batch_id("b2".to_owned()));
+        }
+
+        // the commit fails and the batch file is restored
+        fp.on("commit::write");
+        append("b2", 2).unwrap_err();
+        fp.off("commit::write");
+
+        append("b2", 2)?;
+
+        // a plain append after the batch does not change the last batch
+        SeriesWriter::create(series_env.clone())?.append(&vec![entry(3, 1.0)])?;
+
+        {
+            let writer = SeriesWriter::create(series_env.clone())?;
+            assert!(!writer.appender()?.batch_id("b2".to_owned()));
+            assert!(writer.appender()?.batch_id("b1".to_owned()));
+        }
+
+        let reader = SeriesReader::create(series_env.clone())?;
+        assert_eq!(
+            vec![entry(1, 1.0), entry(2, 1.0), entry(3, 1.0)],
+            reader.iterator(0)?.collect::<Result<Vec<Entry>, Error>>()?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_legacy_batch_id() -> Result<(), Error> {
+        let env = env::test::create()?;
+        let series_env = env.series("series1")?;
+
+        series_env.dir().write_atomically(FileKind::Batch, b"b1")?;
+
+        let writer = SeriesWriter::create(series_env.clone())?;
+        assert!(!writer.appender()?.batch_id("b1".to_owned()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_appender_drop() -> Result<(), Error> {
+        let env = env::test::create()?;
+        let series_env = env.series("series1")?;
+
+        let writer = SeriesWriter::create(series_env.clone())?;
+        let reader = SeriesReader::create(series_env.clone())?;
+
+        {
+            let mut appender = writer.appender()?;
+            appender.append(&vec![entry(1, 1.0)])?;
+        }
+
+        assert!(reader.iterator(0)?.next().is_none());
+
+        {
+            let mut appender = writer.appender()?;
+            appender.commit_on_drop();
+            appender.append(&vec![entry(2, 2.0)])?;
+        }
+
+        assert_eq!(
+            vec![entry(2, 2.0)],
+            reader.iterator(0)?.collect::<Result<Vec<Entry>, Error>>()?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_iterator_fast_path() -> Result<(), Error> {
+        let fp = Arc::new(Failpoints::create());
+        let env = env::test::create_with_failpoints(fp.clone())?;
+        let series_env = env.series("series1")?;
+
+        let writer = SeriesWriter::create(series_env.clone())?;
+        writer.append(&vec![entry(10, 1.0), entry(20, 2.0)])?;
+        writer.append(&vec![entry(30, 3.0)])?;
+
+        let reader = SeriesReader::create(series_env.clone())?;
+
+        fp.on("series_reader::index::ceiling_offset");
+
+        assert_eq!(
+            vec![entry(10, 1.0), entry(20, 2.0), entry(30, 3.0)],
+            reader.iterator(i64::MIN)?.collect::<Result<Vec<Entry>, Error>>()?
+        );
+        assert_eq!(
+            vec![entry(20, 2.0), entry(30, 3.0)],
+            reader.iterator(20)?.collect::<Result<Vec<Entry>, Error>>()?
+        );
+        assert!(reader.iterator(31)?.next().is_none());
+        assert!(reader.iterator(i64::MAX)?.next().is_none());
+
+        assert!(reader.iterator(21).is_err());
+
+        fp.off("series_reader::index::ceiling_offset");
+
+        assert_eq!(
+            vec![entry(30, 3.0)],
+            reader.iterator(21)?.collect::<Result<Vec<Entry>, Error>>()?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_iterator_after_highest_ts_reads_no_blocks() -> Result<(), Error> {
+        let env = env::test::create()?;
+        let series_env = env.series("series1")?;
+
+        SeriesWriter::create(series_env.clone())?.append(&vec![entry(10, 1.0), entry(20, 2.0)])?;
+
+        let data_offset = series_env.commit_log().current().data_offset;
+        {
+            use super::super::file_system::{FileKind, OpenMode};
+            use std::io::Write;
+
+            let mut file = series_env.dir().open(FileKind::Data, OpenMode::Write)?;
+            file.write_all(&vec![0xff; data_offset as usize])?;
+        }
+
+        let reader = SeriesReader::create(series_env.clone())?;
+
+        assert!(reader.iterator(21)?.next().is_none());
+        assert!(reader.iterator(0)?.next().unwrap().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sparse_index() -> Result<(), Error> {
+        let env = env::test::create()?;
+
+        let dir = env.fs().series("series1")?;
+        SeriesConfig {
+            index_density: 4,
+            ..SeriesConfig::default()
+        }
+        .write(&dir)?;
+
+        let series_env = env.series("series1")?;
+
+        let entries = (0..10).map(|i| entry(i * 10, i as f64)).collect::<Vec<Entry>>();
+        {
+            let writer = SeriesWriter::create(series_env.clone())?;
+            for entry in entries.iter() {
+                writer.append(&vec![entry.clone()])?;
+            }
+        }
+
+        assert_eq!(3 * 12, series_env.commit_log().current().index_offset);
+
+        let reader = SeriesReader::create(series_env.clone())?;
+        for from in -5..100 {
+            assert_eq!(
+                entries.iter().filter(|e| e.ts >= from).cloned().collect::<Vec<Entry>>(),
+                reader.iterator(from)?.collect::<Result<Vec<Entry>, Error>>()?,
+                "from {}",
+                from
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_count_in_range() -> Result<(), Error> {
+        let entries = [
+            entry(1, 11.0),
+            entry(2, 12.0),
+            entry(3, 13.0),
+            entry(5, 15.0),
+            entry(8, 18.0),
+            entry(10, 110.0),
+            entry(20, 120.0),
+            entry(21, 121.0),
+            entry(40, 140.0),
+            entry(100, 1100.0),
+            entry(110, 1110.0),
+            entry(120, 1120.0),
+            entry(140, 1140.0),
+        ];
+
+        for index_density in [1, 3] {
+            let env = env::test::create()?;
+            SeriesConfig {
+                index_density,
+                ..SeriesConfig::default()
+            }
+            .write(&*env.fs().series("series1")?)?;
+
+            let series_env = env.series("series1")?;
+            let writer = SeriesWriter::create(series_env.clone())?;
+            let reader = SeriesReader::create(series_env)?;
+            assert_eq!(0, reader.count_in_range(i64::MIN, i64::MAX)?);
+
+            for batch in [&entries[0..2], &entries[2..5], &entries[5..6], &entries[6..8], &entries[8..13]] {
+                writer.append(batch)?;
+            }
+
+            for from in (-2..145).step_by(3) {
+                for to in (from - 1..145).step_by(2) {
+                    let expected = entries.iter().filter(|e| from <= e.ts && e.ts <= to).count() as u64;
+                    assert_eq!(expected, reader.count_in_range(from, to)?, "[{}, {}]", from, to);
+                }
+            }
+            assert_eq!(entries.len() as u64, reader.count_in_range(i64::MIN, i64::MAX)?);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_truncate_after() -> Result<(), Error> {
+        let env = env::test::create()?;
+        let series_env = env.series("series1")?;
+
+        let writer = SeriesWriter::create(series_env.clone())?;
+        let reader = SeriesReader::create(series_env.clone())?;
+
+        writer.append(&vec![entry(1, 1.0), entry(2, 2.0)])?;
+        writer.append(&vec![entry(3, 3.0), entry(4, 4.0), entry(5, 5.0)])?;
+        writer.append(&vec![entry(6, 6.0), entry(7, 7.0)])?;
+
+        writer.truncate_after(10)?;
+        assert_eq!(7, reader.iterator(0)?.count());
+
+        writer.truncate_after(5)?;
+        assert_eq!(5, reader.iterator(0)?.count());
+        assert_eq!(5, series_env.commit_log().current().highest_ts);
+
+        // the block holding 4 and 5 is discarded together with 3
+        writer.truncate_after(3)?;
+        assert_eq!(
+            vec![entry(1, 1.0), entry(2, 2.0)],
+            reader.iterator(0)?.collect::<Result<Vec<Entry>, Error>>()?
+        );
+        assert_eq!(2, series_env.commit_log().current().highest_ts);
+
+        writer.append(&vec![entry(3, 3.5), entry(8, 8.0)])?;
+        assert_eq!(
+            vec![entry(1, 1.0), entry(2, 2.0), entry(3, 3.5), entry(8, 8.0)],
+            reader.iterator(0)?.collect::<Result<Vec<Entry>, Error>>()?
+        );
+        assert_eq!(
+            vec![entry(8, 8.0)],
+            reader.iterator(4)?.collect::<Result<Vec<Entry>, Error>>()?
+        );
+
+        writer.truncate_after(2)?;
+        assert_eq!(
+            vec![entry(1, 1.0), entry(2, 2.0)],
+            reader.iterator(0)?.collect::<Result<Vec<Entry>, Error>>()?
+        );
+
+        writer.truncate_after(0)?;
+        assert!(reader.iterator(i64::MIN)?.next().is_none());
+        assert_eq!(i64::MIN, series_env.commit_log().current().highest_ts);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_truncate_after_in_group() -> Result<(), Error> {
+        let env = env::test::create()?;
+
+        let dir = env.fs().series("series1")?;
+        SeriesConfig {
+            index_density: 4,
+            ..SeriesConfig::default()
+        }
+        .write(&dir)?;
+
+        let series_env = env.series("series1")?;
+        {
+            let writer = SeriesWriter::create(series_env.clone())?;
+            for ts in 0..6 {
+                writer.append(&vec![entry(ts * 2, 1.0), entry(ts * 2 + 1, 2.0)])?;
+            }
+
+            // the second block of the second group is kept
+            writer.truncate_after(10)?;
+            let reader = SeriesReader::create(series_env.clone())?;
+            reader.verify()?;
+            assert_eq!((0..10).collect::<Vec<i64>>(), reader.iterator(0)?.map(|entry| entry.unwrap().ts).collect::<Vec<i64>>());
+            assert!(reader.iterator(10)?.next().is_none());
+
+            writer.append(&vec![entry(10, 3.0), entry(11, 4.0)])?;
+        }
+
+        let env = env.reopen()?;
+        let series_env = env.series("series1")?;
+        let reader = SeriesReader::create(series_env.clone())?;
+        reader.verify()?;
+        assert_eq!(vec![entry(10, 3.0), entry(11, 4.0)], reader.iterator(10)?.collect::<Result<Vec<Entry>, Error>>()?);
+        assert_eq!(2 * index::ENTRY_SIZE, reader.commit().index_offset);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_append_detailed() -> Result<(), Error> {
+        let env = env::test::create()?;
+        let series_env = env.series("series1")?;
+        let writer = SeriesWriter::create(series_env.clone())?;
+        let reader = SeriesReader::create(series_env)?;
+
+        let report = writer.append_detailed(&vec![entry(5, 1.0), entry(3, 2.0)])?;
+        assert_eq!(AppendReport { written: 2, rejected: vec![], duplicates: vec![] }, report);
+
+        let report = writer.append_detailed(&vec![entry(4, 3.0), entry(7, 4.0), entry(5, 5.0), entry(1, 6.0)])?;
+        assert_eq!(
+            AppendReport {
+                written: 2,
+                rejected: vec![entry(4, 3.0), entry(1, 6.0)],
+                duplicates: vec![],
+            },
+            report
+        );
+
+        assert_eq!(
+            vec![entry(3, 2.0), entry(5, 1.0), entry(5, 5.0), entry(7, 4.0)],
+            reader.iterator(0)?.collect::<Result<Vec<Entry>, Error>>()?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_append_detailed_duplicates() -> Result<(), Error> {
+        let env = env::test::create()?;
+        SeriesConfig {
+            duplicates: DuplicatePolicy::KeepLast,
+            ..SeriesConfig::default()
+        }
+        .write(&*env.fs().series("series1")?)?;
+        let series_env = env.series("series1")?;
+        let writer = SeriesWriter::create(series_env.clone())?;
+        let reader = SeriesReader::create(series_env)?;
+
+        writer.append(&vec![entry(1, 1.0), entry(2, 2.0)])?;
+
+        let report = writer.append_detailed(&vec![entry(2, 2.5), entry(4, 4.0), entry(1, 1.5), entry(4, 4.5)])?;
+        assert_eq!(
+            AppendReport {
+                written: 1,
+                rejected: vec![entry(1, 1.5)],
+                duplicates: vec![entry(2, 2.5), entry(4, 4.0)],
+            },
+            report
+        );
+
+        assert_eq!(
+            vec![entry(1, 1.0), entry(2, 2.0), entry(4, 4.5)],
+            reader.iterator(0)?.collect::<Result<Vec<Entry>, Error>>()?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify() -> Result<(), Error> {
+        let env = env::test::create()?;
+
+        let dir = env.fs().series("series1")?;
+        SeriesConfig {
+            index_density: 2,
+            ..SeriesConfig::default()
+        }
+        .write(&dir)?;
+
+        let series_env = env.series("series1")?;
+        let writer = SeriesWriter::create(series_env.clone())?;
+        let reader = SeriesReader::create(series_env.clone())?;
+
+        reader.verify()?;
+
+        for ts in 0..5 {
+            writer.append(&vec![entry(ts * 2, 1.0), entry(ts * 2 + 1, 2.0)])?;
+        }
+        reader.verify()?;
+
+        writer.truncate_after(4)?;
+        reader.verify()?;
+
+        // the appends write the index entries with the commit, so the last entry
+        // holding the uncommitted timestamp is corrupted
+        let last_offset = reader.commit().index_offset - index::ENTRY_SIZE;
+        let last_block_offset = series_env.index().offset_at(last_offset)?;
+        series_env.index().set(last_offset, 100, last_block_offset)?;
+        assert!(matches!(reader.verify(), Err(Error::IndexIsNotConsistent)));
+        series_env.index().set(last_offset, reader.commit().highest_ts, last_block_offset)?;
+        reader.verify()?;
+
+        series_env.index().set(0, 100, 0)?;
+        assert!(matches!(reader.verify(), Err(Error::IndexIsNotConsistent)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_time_bounds() -> Result<(), Error> {
+        let env = env::test::create()?;
+        let series_env = env.series("series1")?;
+        let writer = SeriesWriter::create(series_env.clone())?;
+        let reader = SeriesReader::create(series_env)?;
+
+        assert_eq!(None, reader.time_bounds()?);
+
+        writer.append(&vec![entry(-5, 1.0), entry(3, 2.0)])?;
+        assert_eq!(Some((-5, 3)), reader.time_bounds()?);
+
+        writer.append(&vec![entry(10, 3.0), entry(7, 4.0)])?;
+        assert_eq!(Some((-5, 10)), reader.time_bounds()?);
+
+        writer.truncate_after(i64::MIN)?;
+        assert_eq!(None, reader.time_bounds()?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_latest() -> Result<(), Error> {
+        let env = env::test::create()?;
+        let series_env = env.series("series1")?;
+        let writer = SeriesWriter::create(series_env.clone())?;
+        let reader = SeriesReader::create(series_env)?;
+
+        assert_eq!(None, reader.latest()?);
+
+        writer.append(&vec![entry(-5, 1.0), entry(3, 2.0)])?;
+        assert_eq!(Some(entry(3, 2.0)), reader.latest()?);
+
+        writer.append(&vec![entry(10, 3.0), entry(7, 4.0)])?;
+        assert_eq!(Some(entry(10, 3.0)), reader.latest()?);
+
+        writer.truncate_after(i64::MIN)?;
+        assert_eq!(None, reader.latest()?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_plan() -> Result<(), Error> {
+        let env = env::test::create()?;
+        let series_env = env.series("series1")?;
+        let writer = SeriesWriter::create(series_env.clone())?;
+        let reader = SeriesReader::create(series_env)?;
+
+        assert_eq!(
+            ScanPlan { start_offset: 0, end_offset: 0, blocks: 0, skipped: false },
+            reader.plan(0, i64::MAX)?
+        );
+
+        // a block per append, the offsets of the blocks
+        let mut offsets = vec![0];
+        for i in 1..=5 {
+            writer.append(&vec![entry(i * 1000, i as f64)])?;
+            offsets.push(reader.commit().data_offset);
+        }
+
+        assert_eq!(
+            ScanPlan { start_offset: 0, end_offset: offsets[5], blocks: 5, skipped: false },
+            reader.plan(i64::MIN, i64::MAX)?
+        );
+        assert_eq!(
+            ScanPlan { start_offset: offsets[2], end_offset: offsets[4], blocks: 2, skipped: true },
+            reader.plan(3000, 3500)?
+        );
+        assert_eq!(
+            ScanPlan { start_offset: offsets[5], end_offset: offsets[5], blocks: 0, skipped: true },
+            reader.plan(6000, i64::MAX)?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_index_entries() -> Result<(), Error> {
+        let env = env::test::create()?;
+        let series_env = env.series("series1")?;
+        let writer = SeriesWriter::create(series_env.clone())?;
+        let reader = SeriesReader::create(series_env)?;
+
+        assert!(reader.index_entries()?.is_empty());
+
+        let mut expected = Vec::new();
+        for i in 0..3 {
+            let offset = reader.commit().data_offset;
+            writer.append(&vec![entry(i * 10, 1.0), entry(i * 10 + 5, 2.0)])?;
+            expected.push((i * 10 + 5, offset));
+        }
+
+        assert_eq!(expected, reader.index_entries()?);
+
+        Ok(())
+    }
+
+    fn append_with_duplicates(duplicates: DuplicatePolicy) -> Result<(Vec<Entry>, bool), Error> {
+        let env = env::test::create()?;
+
+        let dir = env.fs().series("series1")?;
+        SeriesConfig {
+            duplicates,
+            ..SeriesConfig::default()
+        }
+        .write(&dir)?;
+
+        let series_env = env.series("series1")?;
+        let writer = SeriesWriter::create(series_env.clone())?;
+        let reader = SeriesReader::create(series_env)?;
+
+        writer.append(&vec![entry(1, 1.0), entry(2, 2.0)])?;
+        let rejected = matches!(
+            writer.append(&vec![entry(2, 2.5), entry(3, 3.0)]),
+            Err(Error::DuplicateTimestamp)
+        );
+        writer.append(&vec![entry(5, 5.0), entry(4, 4.0), entry(5, 5.5)]).ok();
+
+        Ok((reader.iterator(0)?.collect::<Result<Vec<Entry>, Error>>()?, rejected))
+    }
+
+    #[test]
+    fn test_duplicate_policy() -> Result<(), Error> {
+        assert_eq!(
+            (
+                vec![entry(1, 1.0), entry(2, 2.0), entry(2, 2.5), entry(3, 3.0), entry(4, 4.0), entry(5, 5.0), entry(5, 5.5)],
+                false
+            ),
+            append_with_duplicates(DuplicatePolicy::Allow)?
+        );
+        assert_eq!(
+            (
+                vec![entry(1, 1.0), entry(2, 2.0), entry(3, 3.0), entry(4, 4.0), entry(5, 5.5)],
+                false
+            ),
+            append_with_duplicates(DuplicatePolicy::KeepLast)?
+        );
+        assert_eq!(
+            (vec![entry(1, 1.0), entry(2, 2.0)], true),
+            append_with_duplicates(DuplicatePolicy::Reject)?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_ahead() -> Result<(), Error> {
+        let env = env::test::create()?;
+        let series_env = env.series("series1")?;
+        let writer = SeriesWriter::create(series_env.clone())?;
+        let reader = SeriesReader::create(series_env)?;
+
+        for ts in 0..100 {
+            writer.append(&vec![entry(ts * 2, 1.0), entry(ts * 2 + 1, 2.0)])?;
+        }
+
+        for from in [0, 51, 199, 200] {
+            assert_eq!(
+                reader.iterator(from)?.collect::<Result<Vec<Entry>, Error>>()?,
+                reader.iterator(from)?.read_ahead(4).collect::<Result<Vec<Entry>, Error>>()?
+            );
+        }
+
+        assert_eq!(3, reader.iterator(0)?.read_ahead(1).take(3).count());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sync_mode_every() -> Result<(), Error> {
+        let env = env::test::create()?;
+        let writer = SeriesWriter::create_with_sync_mode(env.series("series1")?, SyncMode::Every(3))?;
+
+        writer.append(&vec![entry(1, 1.0)])?;
+        writer.append(&vec![entry(2, 2.0)])?;
+        assert!(writer.has_unsynced());
+
+        writer.append(&vec![entry(3, 3.0)])?;
+        assert!(!writer.has_unsynced());
+
+        writer.append(&vec![entry(4, 4.0)])?;
+        assert!(writer.has_unsynced());
+
+        writer.sync()?;
+        assert!(!writer.has_unsynced());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_index_recovered_after_crash() -> Result<(), Error> {
+        let fp = Arc::new(Failpoints::create());
+        let env = env::test::create_with_failpoints(fp.clone())?;
+
+        let dir = env.fs().series("series1")?;
+        SeriesConfig {
+            index_density: 2,
+            ..SeriesConfig::default()
+        }
+        .write(&dir)?;
+
+        let entries = (0..10).map(|i| entry(i, i as f64)).collect::<Vec<Entry>>();
+        {
+            let writer = SeriesWriter::create_with_index_sync(
+                env.series("series1")?,
+                SyncMode::Always,
+                IndexSyncPolicy::Every(4),
+            )?;
+            for entry in entries.iter() {
+                writer.append(&vec![entry.clone()])?;
+            }
+            fp.on("series_writer::drop");
+        }
+        fp.off("series_writer::drop");
+
+        // the index entries after the synced offset are lost on power failure
+        let synced = dir.read_to_string(FileKind::IndexSynced)?.unwrap().parse::<u64>().unwrap();
+        assert_eq!(4 * index::ENTRY_SIZE as u64, synced);
+        {
+            let mut file = fs::OpenOptions::new().write(true).open(dir.path().join("series.idx"))?;
+            file.seek(SeekFrom::Start(synced))?;
+            file.write_all(&[0u8; index::ENTRY_SIZE as usize])?;
+        }
+
+        let env = env.reopen()?;
+        let reader = SeriesReader::create(env.series("series1")?)?;
+
+        reader.verify()?;
+        assert_eq!(entries, reader.iterator(i64::MIN)?.collect::<Result<Vec<Entry>, Error>>()?);
+        assert_eq!(vec![entry(9, 9.0)], reader.iterator(9)?.collect::<Result<Vec<Entry>, Error>>()?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_data_file_shorter_than_commit() -> Result<(), Error> {
+        let temp = env::test::create()?;
+        let dir = temp.fs().series("series1")?;
+
+        let mut offsets = Vec::new();
+        {
+            let writer = SeriesWriter::create(temp.series("series1")?)?;
+            let reader = SeriesReader::create(temp.series("series1")?)?;
+            for i in 0..3 {
+                writer.append(&vec![entry(i * 10, i as f64), entry(i * 10 + 5, i as f64)])?;
+                offsets.push(reader.commit().data_offset);
+            }
+        }
+
+        // the last block is cut in the middle
+        fs::OpenOptions::new()
+            .write(true)
+            .open(dir.path().join("series.dat"))?
+            .set_len(offsets[2] as u64 - 1)?;
+
+        let env = temp.reopen()?;
+        let reader = SeriesReader::create(env.series("series1")?)?;
+
+        reader.verify()?;
+        assert_eq!(offsets[1], reader.commit().data_offset);
+        assert_eq!(15, reader.commit().highest_ts);
+        assert_eq!(
+            vec![entry(0, 0.0), entry(5, 0.0), entry(10, 1.0), entry(15, 1.0)],
+            reader.iterator(i64::MIN)?.collect::<Result<Vec<Entry>, Error>>()?
+        );
+
+        // the data file is lost entirely
+        drop(reader);
+        drop(env);
+        fs::OpenOptions::new()
+            .write(true)
+            .open(dir.path().join("series.dat"))?
+            .set_len(0)?;
+
+        let env = temp.reopen()?;
+        let writer = SeriesWriter::create(env.series("series1")?)?;
+        let reader = SeriesReader::create(env.series("series1")?)?;
+
+        assert_eq!(0, reader.commit().data_offset);
+        assert_eq!(0, reader.iterator(i64::MIN)?.count());
+        assert_eq!(None, reader.time_bounds()?);
+
+        writer.append(&vec![entry(5, 5.0)])?;
+        assert_eq!(vec![entry(5, 5.0)], reader.iterator(i64::MIN)?.collect::<Result<Vec<Entry>, Error>>()?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_preallocated_blocks_lost() -> Result<(), Error> {
+        let temp = env::test::create()?;
+        let dir = temp.fs().series("series1")?;
+        SeriesConfig {
+            preallocate_mb: Some(1),
+            ..SeriesConfig::default()
+        }
+        .write(&dir)?;
+
+        let mut offsets = Vec::new();
+        {
+            let writer = SeriesWriter::create(temp.series("series1")?)?;
+            let reader = SeriesReader::create(temp.series("series1")?)?;
+            for i in 0..3 {
+                writer.append(&vec![entry(i * 10, i as f64), entry(i * 10 + 5, i as f64)])?;
+                offsets.push(reader.commit().data_offset);
+            }
+            assert_eq!(offsets[2] as u64, reader.size()?.data_bytes);
+        }
+
+        // the file keeps its preallocated length, but the last block is zeroed
+        {
+            let mut file = fs::OpenOptions::new().write(true).open(dir.path().join("series.dat"))?;
+            file.seek(SeekFrom::Start(offsets[1] as u64))?;
+            file.write_all(&vec![0u8; (offsets[2] - offsets[1]) as usize])?;
+        }
+
+        let env = temp.reopen()?;
+        let reader = SeriesReader::create(env.series("series1")?)?;
+
+        reader.verify()?;
+        assert_eq!(offsets[1], reader.commit().data_offset);
+        assert_eq!(15, reader.commit().highest_ts);
+        assert_eq!(offsets[1] as u64, reader.size()?.data_bytes);
+        assert_eq!(
+            vec![entry(0, 0.0), entry(5, 0.0), entry(10, 1.0), entry(15, 1.0)],
+            reader.iterator(i64::MIN)?.collect::<Result<Vec<Entry>, Error>>()?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_preallocate() -> Result<(), Error> {
+        let temp = env::test::create()?;
+        let dir = temp.fs().series("series1")?;
+        SeriesConfig {
+            preallocate_mb: Some(1),
+            ..SeriesConfig::default()
+        }
+        .write(&dir)?;
+        let data_len = || fs::metadata(dir.path().join("series.dat")).map(|metadata| metadata.len());
+
+        let entries = (0..10_000).map(|i| entry(i, i as f64)).collect::<Vec<Entry>>();
+        {
+            let writer = SeriesWriter::create(temp.series("series1")?)?;
+            let reader = SeriesReader::create(temp.series("series1")?)?;
+            writer.append(&entries[..1000].to_vec())?;
+
+            // the file is extended once by the first block
+            let allocated = reader.commit().data_offset as u64 + 1024 * 1024;
+            assert_eq!(allocated, data_len()?);
+
+            for batch in entries[1000..].chunks(1000) {
+                writer.append(&batch.to_vec())?;
+            }
+            assert_eq!(allocated, data_len()?);
+
+            assert_eq!(entries, reader.iterator(i64::MIN)?.collect::<Result<Vec<Entry>, Error>>()?);
+            assert_eq!(entries[9_500..], reader.iterator(9_500)?.collect::<Result<Vec<Entry>, Error>>()?[..]);
+            assert_eq!(10_000, reader.size()?.entries_estimate);
+            reader.verify()?;
+        }
+
+        // the preallocated zeros after the committed blocks are kept on restart
+        let env = temp.reopen()?;
+        let writer = SeriesWriter::create(env.series("series1")?)?;
+        let reader = SeriesReader::create(env.series("series1")?)?;
+        writer.append(&vec![entry(10_000, 1.0)])?;
+
+        assert_eq!(10_001, reader.iterator(i64::MIN)?.count());
+        assert_eq!(vec![entry(10_000, 1.0)], reader.iterator(10_000)?.collect::<Result<Vec<Entry>, Error>>()?);
+        reader.verify()?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sync_mode_interval() -> Result<(), Error> {
+        let env = env::test::create()?;
+        let series_env = env.series("series1")?;
+        let writer = SeriesWriter::create_with_sync_mode(series_env.clone(), SyncMode::Interval(Duration::from_millis(20)))?;
+        let reader = SeriesReader::create(series_env)?;
+
+        writer.append(&vec![entry(1, 1.0), entry(2, 2.0)])?;
+        assert!(writer.has_unsynced());
+        assert_eq!(2, reader.iterator(0)?.count());
+
+        // the writer is fsynced by the flusher of the series table
+        writer.flush()?;
+        assert!(!writer.has_unsynced());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_only_refresh() -> Result<(), Error> {
+        let env = env::test::create()?;
+        let writer = SeriesWriter::create(env.series("series1")?)?;
+        writer.append(&vec![entry(1, 1.0), entry(2, 2.0)])?;
+
+        let read_only_env = env.open_read_only()?;
+        let reader = SeriesReader::create(read_only_env.series("series1")?)?;
+        assert_eq!(2, reader.iterator(0)?.count());
+
+        let read_only_writer = SeriesWriter::create(read_only_env.series("series1")?)?;
+        assert!(matches!(read_only_writer.append(&vec![entry(3, 3.0)]), Err(Error::ReadOnly)));
+
+        for ts in 3..1000 {
+            writer.append(&vec![entry(ts, ts as f64)])?;
+        }
+        assert_eq!(2, reader.iterator(0)?.count());
+
+        reader.refresh()?;
+        assert_eq!(
+            (1..1000).map(|ts| entry(ts, ts as f64)).collect::<Vec<Entry>>(),
+            reader.iterator(0)?.collect::<Result<Vec<Entry>, Error>>()?
+        );
+        assert_eq!(
+            vec![entry(999, 999.0)],
+            reader.iterator(999)?.collect::<Result<Vec<Entry>, Error>>()?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_index_full() -> Result<(), Error> {
+        let env = env::test::create_with_max_index_size(4 * index::INDEX_BLOCK_SIZE)?;
+        let writer = SeriesWriter::create(env.series("series1")?)?;
+        let reader = SeriesReader::create(env.series("series1")?)?;
+
+        // each append creates a block, so the index is filled with 4096 entries
+        let mut appender = writer.appender()?;
+        for ts in 0..3700 {
+            appender.append(&vec![entry(ts, 1.0)])?;
+        }
+        appender.done()?;
+        assert!(reader.size()?.index_usage > 0.9);
+
+        let mut appender = writer.appender()?;
+        for ts in 3700..4096 {
+            appender.append(&vec![entry(ts, 1.0)])?;
+        }
+        appender.done()?;
+        assert_eq!(1.0, reader.size()?.index_usage);
+
+        assert!(matches!(writer.append(&vec![entry(4096, 1.0)]), Err(Error::IndexFull)));
+        assert_eq!(4096, reader.iterator(i64::MIN)?.count());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_append_sorted() -> Result<(), Error> {
+        let env = env::test::create()?;
+        let writer = SeriesWriter::create(env.series("series1")?)?;
+        let reader = SeriesReader::create(env.series("series1")?)?;
+
+        assert_eq!(3, writer.append_sorted(&vec![entry(1, 1.0), entry(2, 2.0), entry(2, 3.0)])?);
+        assert_eq!(2, writer.append_sorted(&vec![entry(1, 4.0), entry(2, 5.0), entry(4, 6.0)])?);
+        assert!(matches!(
+            writer.append_sorted(&vec![entry(5, 7.0), entry(3, 8.0)]),
+            Err(Error::UnorderedEntries)
+        ));
+
+        assert_eq!(
+            vec![entry(1, 1.0), entry(2, 2.0), entry(2, 3.0), entry(2, 5.0), entry(4, 6.0)],
+            reader.iterator(i64::MIN)?.collect::<Result<Vec<Entry>, Error>>()?
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_write_csv_async() -> Result<(), Error> {
+        let env = env::test::create()?;
+        let writer = SeriesWriter::create(env.series("series1")?)?;
+        let entries = (0..5000).map(|ts| entry(ts, ts as f64 / 3.0)).collect::<Vec<Entry>>();
+        writer.append(&entries)?;
+
+        let reader = SeriesReader::create(env.series("series1")?)?;
+
+        let mut buf: Vec<u8> = Vec::new();
+        reader.write_csv_async(i64::MIN, i64::MAX, 4, &mut buf).await?;
+        assert_eq!(crate::csv::to_csv(&entries, 4), String::from_utf8(buf).unwrap());
+
+        let mut buf: Vec<u8> = Vec::new();
+        reader.write_csv_async(1000, 2999, 2, &mut buf).await?;
+        assert_eq!(crate::csv::to_csv(&entries[1000..3000], 2), String::from_utf8(buf).unwrap());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_archive() -> Result<(), Error> {
+        let env = env::test::create()?;
+        let writer = SeriesWriter::create(env.series("series1")?)?;
+        let entries = (0..100_000).map(|ts| entry(ts, ts as f64)).collect::<Vec<Entry>>();
+        writer.append(&entries)?;
+
+        writer.archive()?;
+        assert!(writer.archived());
+        assert!(matches!(writer.append(&vec![entry(100_000, 1.0)]), Err(Error::Archived)));
+
+        let reader = SeriesReader::create(env.series("series1")?)?;
+        assert_eq!(entries, reader.iterator(i64::MIN)?.collect::<Result<Vec<Entry>, Error>>()?);
+        assert_eq!(entries[70_000..], reader.iterator(70_000)?.collect::<Result<Vec<Entry>, Error>>()?[..]);
+        assert_eq!(100_000, reader.size()?.entries_estimate);
+        reader.verify()?;
+
+        let reopened = env.reopen()?;
+        assert!(SeriesWriter::create(reopened.series("series1")?)?.archived());
+        let read_only_env = env.open_read_only()?;
+        let reader = SeriesReader::create(read_only_env.series("series1")?)?;
+        assert_eq!(entries, reader.iterator(i64::MIN)?.collect::<Result<Vec<Entry>, Error>>()?);
+
+        writer.clear()?;
+        assert!(!writer.archived());
+        writer.append(&vec![entry(1, 1.0)])?;
+        assert_eq!(
+            vec![entry(1, 1.0)],
+            SeriesReader::create(env.series("series1")?)?
+                .iterator(i64::MIN)?
+                .collect::<Result<Vec<Entry>, Error>>()?
+        );
+
+        Ok(())
+    }
+
+    mod prop {
+        use super::super::super::entry::Entry;
+        use super::super::super::env;
+        use super::super::super::{Compression, SeriesConfig};
+        use super::super::*;
+        use proptest::prelude::*;
+
+        fn compression() -> impl Strategy<Value = Compression> {
+            prop_oneof![
+                Just(Compression::None),
+                Just(Compression::Deflate),
+                Just(Compression::Delta),
+            ]
+        }
+
+        fn batch() -> impl Strategy<Value = (Vec<Entry>, Compression)> {
+            (
+                prop::collection::vec(
+                    (-1000i64..1000, any::<f64>().prop_filter("nan", |v| !v.is_nan()))
+                        .prop_map(|(ts, value)| Entry { ts, value }),
+                    0..300,
+                ),
+                compression(),
+            )
+        }
+
+        /// The entries expected to be read back: the entries of each batch lower
+        /// than the highest timestamp appended so far are skipped.
+        fn expected(batches: &[(Vec<Entry>, Compression)]) -> Vec<Entry> {
+            let mut highest_ts = i64::MIN;
+            let mut expected = Vec::new();
+            for (entries, _) in batches {
+                let mut accepted = entries
+                    .iter()
+                    .filter(|entry| entry.ts >= highest_ts)
+                    .cloned()
+                    .collect::<Vec<Entry>>();
+                accepted.sort_by_key(|entry| entry.ts);
+                if let Some(last) = accepted.last() {
+                    highest_ts = last.ts;
+                }
+                expected.extend(accepted);
+            }
+            expected
+        }
+
+        fn read(reader: &SeriesReader, from: i64) -> Vec<Entry> {
+            reader
+                .iterator(from)
+                .unwrap()
+                .collect::<Result<Vec<Entry>, _>>()
+                .unwrap()
+        }
+
+        proptest! {
+            #![proptest_config(ProptestConfig::with_cases(32))]
+
+            #[test]
+            fn test_round_trip(
+                batches in prop::collection::vec(batch(), 1..8),
+                index_density in 1u32..5,
+                from in -1100i64..1100,
+            ) {
+                let env = env::test::create().unwrap();
+
+                let dir = env.fs().series("series1").unwrap();
+                SeriesConfig {
+                    index_density,
+                    ..SeriesConfig::default()
+                }
+                .write(&dir)
+                .unwrap();
+
+                let series_env = env.series("series1").unwrap();
+                let writer = SeriesWriter::create(series_env.clone()).unwrap();
+
+                for (entries, compression) in batches.iter() {
+                    let mut appender = writer.appender().unwrap();
+                    appender.compression(*compression);
+                    appender.append(entries).unwrap();
+                    appender.done().unwrap();
+                }
+
+                let expected = expected(&batches);
+                let expected_from = expected
+                    .iter()
+                    .filter(|entry| entry.ts >= from)
+                    .cloned()
+                    .collect::<Vec<Entry>>();
+
+                let reader = SeriesReader::create(series_env.clone()).unwrap();
+                prop_assert_eq!(&expected, &read(&reader, i64::MIN));
+                prop_assert_eq!(&expected_from, &read(&reader, from));
+                prop_assert!(reader.verify().is_ok());
+
+                let reopened = env.reopen().unwrap();
+                let reader = SeriesReader::create(reopened.series("series1").unwrap()).unwrap();
+                prop_assert_eq!(&expected, &read(&reader, i64::MIN));
+                prop_assert_eq!(&expected_from, &read(&reader, from));
+            }
+        }
+    }
 }
@@ -0,0 +1,68 @@
+use super::super::clock::Clock;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+/// Token bucket limiting the number of entries appended per second. The bucket
+/// holds up to `rate` tokens, so a burst of one second worth of entries is accepted
+/// at once. A batch is accepted while there are tokens left, even if it is larger
+/// than the remaining tokens: the bucket goes into debt, and the next batches are
+/// rejected until it is refilled.
+pub struct RateLimiter {
+    rate: f64,
+    tokens: f64,
+    refilled_at: SystemTime,
+    clock: Arc<dyn Clock>,
+}
+
+impl RateLimiter {
+    pub fn new(rate: u32, clock: Arc<dyn Clock>) -> RateLimiter {
+        RateLimiter {
+            rate: rate as f64,
+            tokens: rate as f64,
+            refilled_at: clock.now(),
+            clock,
+        }
+    }
+
+    pub fn try_acquire(&mut self, entries: usize) -> bool {
+        let now = self.clock.now();
+        // the clock could go backwards, the bucket is not refilled then
+        let elapsed = now.duration_since(self.refilled_at).unwrap_or_default().as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate);
+        self.refilled_at = now;
+
+        if self.tokens <= 0.0 {
+            return false;
+        }
+        self.tokens -= entries as f64;
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::super::clock::TestClock;
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_rate_limiter() {
+        let clock = Arc::new(TestClock::new(SystemTime::now()));
+        let mut limiter = RateLimiter::new(10, clock.clone());
+
+        assert!(limiter.try_acquire(6));
+        assert!(limiter.try_acquire(6));
+        assert!(!limiter.try_acquire(1));
+
+        // 2 tokens in debt, refilled by 1 token per 100ms
+        clock.advance(Duration::from_millis(200));
+        assert!(!limiter.try_acquire(1));
+        clock.advance(Duration::from_millis(100));
+        assert!(limiter.try_acquire(1));
+
+        // the bucket is never refilled above the rate
+        clock.advance(Duration::from_secs(60));
+        assert!(limiter.try_acquire(10));
+        assert!(!limiter.try_acquire(1));
+    }
+}
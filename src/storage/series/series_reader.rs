@@ -1,10 +1,45 @@
-use super::super::data::DataReader;
+use super::super::super::failpoints::failpoint;
+use super::super::commit_log::Commit;
+use super::super::compression::Compression;
+use super::super::config::SeriesConfig;
+use super::super::data::{self, DataReader, ReadAhead};
 use super::super::entry::Entry;
 use super::super::env::SeriesEnv;
 use super::super::error::Error;
-use super::super::file_system::{FileKind, OpenMode};
+use super::super::index;
+use super::SeriesVerifier;
+use crate::buffering::BufferingBuilder;
+use crate::csv;
+use serde_derive::Serialize;
 use std::collections::VecDeque;
 use std::sync::Arc;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SeriesSize {
+    /// Committed bytes of the data file, the preallocated bytes are not counted.
+    pub data_bytes: u64,
+    pub index_bytes: u64,
+    pub log_bytes: u64,
+    /// Number of the committed entries, counted by the block headers.
+    pub entries_estimate: u64,
+    /// Share of the max index size used by the committed index entries, the
+    /// appends fail with `IndexFull` once it reaches 1.
+    pub index_usage: f64,
+}
+
+/// Blocks read by the query of `from..=to`, see `SeriesReader::plan`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ScanPlan {
+    /// Offset of the first block read, found by the index.
+    pub start_offset: u32,
+    /// Offset after the last block that could be read.
+    pub end_offset: u32,
+    /// Number of the blocks between `start_offset` and `end_offset`.
+    pub blocks: u64,
+    /// `true` if the index skipped the blocks before `start_offset`.
+    pub skipped: bool,
+}
 
 pub struct SeriesReader {
     env: Arc<SeriesEnv>,
@@ -15,30 +50,285 @@ impl SeriesReader {
         Ok(SeriesReader { env: env.clone() })
     }
 
-    pub fn iterator(&self, from_ts: i64) -> Result<SeriesIterator, Error> {
+    pub fn config(&self) -> Arc<SeriesConfig> {
+        self.env.config()
+    }
+
+    pub fn commit(&self) -> Arc<Commit> {
+        self.env.commit_log().current()
+    }
+
+    /// Returns the size of the series files and the number of entries.
+    pub fn size(&self) -> Result<SeriesSize, Error> {
+        let commit = self.env.commit_log().current();
+        let sizes = self.env.dir().file_sizes()?;
+
+        Ok(SeriesSize {
+            data_bytes: sizes.data.min(commit.data_offset as u64),
+            index_bytes: sizes.index,
+            log_bytes: sizes.log,
+            entries_estimate: data::count_entries(data::open_data(&self.env.dir())?, commit.data_offset)?,
+            index_usage: self.env.index().usage(commit.index_offset),
+        })
+    }
+
+    /// Returns the compression of each committed block, only the block headers are read.
+    pub fn block_compressions(&self) -> Result<Vec<Compression>, Error> {
+        let commit = self.env.commit_log().current();
+        data::block_compressions(data::open_data(&self.env.dir())?, commit.data_offset)
+    }
+
+    /// Returns the committed index entries, `(highest ts, block offset)` of each group of blocks.
+    pub fn index_entries(&self) -> Result<Vec<(i64, u32)>, Error> {
+        let commit = self.env.commit_log().current();
+        self.env.index().entries(commit.index_offset)
+    }
+
+    /// Returns the timestamps of the first and the last entry of the series,
+    /// `None` if the series is empty.
+    pub fn time_bounds(&self) -> Result<Option<(i64, i64)>, Error> {
+        let commit = self.env.commit_log().current();
+
+        let last = match self.env.index().last_ts(commit.index_offset)? {
+            Some(last) => last,
+            None => return Ok(None),
+        };
+
+        // the index keeps the highest timestamp of the block, so the first
+        // timestamp is read from the first block
+        match self.iterator(i64::MIN)?.next() {
+            Some(first) => Ok(Some((first?.ts, last))),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the entry with the highest timestamp, the last appended one if there are
+    /// several, `None` if the series is empty. Only the last block is read.
+    pub fn latest(&self) -> Result<Option<Entry>, Error> {
         let commit = self.env.commit_log().current();
 
-        let start_offset = self
-            .env
-            .index()
-            .ceiling_offset(from_ts, commit.index_offset)?
-            .unwrap_or(0);
+        let mut latest = None;
+        for entry in self.iterator(commit.highest_ts)? {
+            latest = Some(entry?);
+        }
+        Ok(latest)
+    }
+
+    /// Counts the entries with `from <= ts <= to`. The index is used to find the groups of
+    /// blocks overlapping the range, only the blocks of the first and the last group are
+    /// decoded, the entries of the blocks in between are counted by the block headers.
+    pub fn count_in_range(&self, from: i64, to: i64) -> Result<u64, Error> {
+        let commit = self.env.commit_log().current();
+        let index = self.env.index();
+        let upper = commit.index_offset;
+
+        if from > to {
+            return Ok(0);
+        }
+
+        // the first group with the highest ts >= from, and the first one with the highest ts > to,
+        // the entries of the following groups are not lower than the highest ts of the previous one
+        let first = match from.checked_sub(1) {
+            Some(ts) => index.upper_bound(ts, upper)?,
+            None => 0,
+        };
+        let last = index.upper_bound(to, upper)?;
+
+        if first >= upper {
+            return Ok(0);
+        }
+
+        let group_end = |offset: u32| match offset + index::ENTRY_SIZE < upper {
+            true => index.offset_at(offset + index::ENTRY_SIZE),
+            false => Ok(commit.data_offset),
+        };
+
+        let count_decoded = |start: u32, end: u32| -> Result<u64, Error> {
+            let mut reader = DataReader::create(data::open_data(&self.env.dir())?, start)?;
+            let mut offset = start;
+            let mut count = 0u64;
+            while offset < end {
+                let (entries, next) = reader.read_block()?;
+                count += entries.iter().filter(|entry| from <= entry.ts && entry.ts <= to).count() as u64;
+                offset = next;
+            }
+            Ok(count)
+        };
+
+        let mut count = count_decoded(index.offset_at(first)?, group_end(first)?)?;
+        if last == first {
+            return Ok(count);
+        }
+
+        let middle_end = match last < upper {
+            true => index.offset_at(last)?,
+            false => commit.data_offset,
+        };
+        count += data::count_entries_between(data::open_data(&self.env.dir())?, group_end(first)?, middle_end)?;
+
+        if last < upper {
+            count += count_decoded(middle_end, group_end(last)?)?;
+        }
+
+        Ok(count)
+    }
+
+    /// Returns the blocks the query of `from..=to` reads, without reading them. The blocks
+    /// are estimated by the index, so the blocks of the last index entry are all counted,
+    /// even if the query stops at the first of them.
+    pub fn plan(&self, from: i64, to: i64) -> Result<ScanPlan, Error> {
+        let commit = self.env.commit_log().current();
+        let index = self.env.index();
+        let upper = commit.index_offset;
+
+        let start_offset = self.start_offset(&commit, from)?;
+
+        // the first entry of the index with the highest ts > to, the query stops within its blocks
+        let last = index.upper_bound(to, upper)?;
+        let end_offset = match last + index::ENTRY_SIZE < upper {
+            true => index.offset_at(last + index::ENTRY_SIZE)?,
+            false => commit.data_offset,
+        }
+        .max(start_offset);
+
+        Ok(ScanPlan {
+            start_offset,
+            end_offset,
+            blocks: data::count_blocks_between(data::open_data(&self.env.dir())?, start_offset, end_offset)?,
+            skipped: start_offset > 0,
+        })
+    }
+
+    /// Returns the verifier of the committed blocks, the blocks are verified one at a time.
+    pub fn verifier(&self) -> Result<SeriesVerifier, Error> {
+        SeriesVerifier::create(self.env.clone())
+    }
+
+    /// Verifies all committed blocks and the index.
+    pub fn verify(&self) -> Result<(), Error> {
+        let mut verifier = self.verifier()?;
+        while verifier.verify_block()? {}
+        Ok(())
+    }
+
+    /// Picks up the entries committed by another process since the series was
+    /// opened (or refreshed). Does nothing unless the series is opened read-only.
+    pub fn refresh(&self) -> Result<(), Error> {
+        self.env.refresh()
+    }
+
+    /// Writes the entries with `from <= ts <= to` to `writer` as csv (see `csv::to_csv`). The
+    /// series is read on the blocking thread, the batches are written as soon as they are read.
+    pub async fn write_csv_async<W>(&self, from: i64, to: i64, precision: usize, writer: &mut W) -> Result<(), Error>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        self.write_csv_sampled_async(from, to, 1, precision, writer).await
+    }
+
+    /// Same as `write_csv_async`, only every `every_n`-th entry is written (see `sample`).
+    pub async fn write_csv_sampled_async<W>(
+        &self,
+        from: i64,
+        to: i64,
+        every_n: usize,
+        precision: usize,
+        writer: &mut W,
+    ) -> Result<(), Error>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        let (tx, mut rx) = tokio::sync::mpsc::channel::<Result<Vec<Entry>, Error>>(1);
+
+        let reader = SeriesReader { env: self.env.clone() };
+        tokio::task::spawn_blocking(move || {
+            let iterator = match reader.sample(from, every_n) {
+                Ok(iterator) => iterator,
+                Err(error) => return tx.blocking_send(Err(error)),
+            };
+            for batch in iterator
+                .take_while(|entry| entry.as_ref().map(|entry| entry.ts <= to).unwrap_or(true))
+                .buffering::<Result<Vec<Entry>, Error>>(1024)
+            {
+                let failed = batch.is_err();
+                // the receiver is dropped if the writer fails
+                tx.blocking_send(batch)?;
+                if failed {
+                    break;
+                }
+            }
+            Ok(())
+        });
+
+        while let Some(entries) = rx.recv().await {
+            writer.write_all(csv::to_csv(&entries?, precision).as_bytes()).await?;
+        }
+        writer.flush().await?;
+
+        Ok(())
+    }
+
+    pub fn iterator(&self, from_ts: i64) -> Result<SeriesIterator, Error> {
+        let commit = self.env.commit_log().current();
+        let start_offset = self.start_offset(&commit, from_ts)?;
 
         Ok(SeriesIterator {
-            data_reader: DataReader::create(
-                self.env.dir().open(FileKind::Data, OpenMode::Read)?,
-                start_offset,
-            )?,
+            data_reader: Blocks::Direct(DataReader::create(data::open_data(&self.env.dir())?, start_offset)?),
             offset: start_offset,
             size: commit.data_offset,
             from_ts,
             buffer: VecDeque::new(),
         })
     }
+
+    /// Returns every `every_n`-th entry with `ts >= from_ts`, starting from the first one.
+    /// The blocks are still decoded, only the returned entries are skipped, so it is as
+    /// cheap as the plain scan, but the result is small enough for a preview.
+    pub fn sample(&self, from_ts: i64, every_n: usize) -> Result<SampleIterator, Error> {
+        Ok(SampleIterator {
+            iterator: self.iterator(from_ts)?,
+            every_n: every_n.max(1),
+            skip: 0,
+        })
+    }
+
+    /// Returns the offset of the first block with the entries not lower than `from_ts`.
+    fn start_offset(&self, commit: &Commit, from_ts: i64) -> Result<u32, Error> {
+        Ok(if from_ts > commit.highest_ts {
+            commit.data_offset
+        } else if self.env.index().first_ts(commit.index_offset)? >= Some(from_ts) {
+            0
+        } else {
+            failpoint!(
+                self.env.fp(),
+                "series_reader::index::ceiling_offset",
+                Err(Error::Other("fp".to_owned()))
+            );
+
+            self.env
+                .index()
+                .ceiling_offset(from_ts, commit.index_offset)?
+                .unwrap_or(0)
+        })
+    }
+}
+
+enum Blocks {
+    Direct(DataReader),
+    ReadAhead(ReadAhead),
+}
+
+impl Blocks {
+    fn read_block(&mut self) -> Result<(Vec<Entry>, u32), Error> {
+        match self {
+            Blocks::Direct(reader) => reader.read_block(),
+            Blocks::ReadAhead(reader) => reader.read_block(),
+        }
+    }
 }
 
 pub struct SeriesIterator {
-    data_reader: DataReader,
+    data_reader: Blocks,
     offset: u32,
     size: u32,
     from_ts: i64,
@@ -46,6 +336,16 @@ pub struct SeriesIterator {
 }
 
 impl SeriesIterator {
+    /// Reads up to `blocks` blocks ahead on the helper thread. Off by default, speeds
+    /// up the sequential scans if the reads are slow (spinning disk, network fs).
+    pub fn read_ahead(self, blocks: usize) -> SeriesIterator {
+        let data_reader = match self.data_reader {
+            Blocks::Direct(reader) if blocks > 0 => Blocks::ReadAhead(ReadAhead::spawn(reader, self.size, blocks)),
+            data_reader => data_reader,
+        };
+        SeriesIterator { data_reader, ..self }
+    }
+
     fn fetch_block(&mut self) -> Result<(), Error> {
         if self.offset < self.size {
             let (entries, offset) = self.data_reader.read_block()?;
@@ -69,7 +369,9 @@ impl Iterator for SeriesIterator {
     type Item = Result<Entry, Error>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.buffer.is_empty() {
+        // the blocks before from_ts are skipped entirely, which happens when
+        // the index entry covers several blocks
+        while self.buffer.is_empty() && self.offset < self.size {
             if let Err(error) = self.fetch_block() {
                 return Some(Err(error));
             }
@@ -81,3 +383,29 @@ impl Iterator for SeriesIterator {
         }
     }
 }
+
+pub struct SampleIterator {
+    iterator: SeriesIterator,
+    every_n: usize,
+    /// Entries to skip before the next returned one.
+    skip: usize,
+}
+
+impl Iterator for SampleIterator {
+    type Item = Result<Entry, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let entry = self.iterator.next()?;
+            // the errors are returned right away, so they are not skipped
+            if entry.is_err() {
+                return Some(entry);
+            }
+            if self.skip == 0 {
+                self.skip = self.every_n - 1;
+                return Some(entry);
+            }
+            self.skip -= 1;
+        }
+    }
+}
@@ -0,0 +1,98 @@
+use super::super::commit_log::Commit;
+use super::super::data::{self, DataReader};
+use super::super::env::SeriesEnv;
+use super::super::error::Error;
+use super::super::index;
+use std::sync::Arc;
+
+/// Verifies the committed blocks of the series one by one: the blocks should be
+/// readable, the entries ordered by timestamp, and the index entries should point
+/// to the blocks and hold the highest timestamp of the blocks they cover.
+pub struct SeriesVerifier {
+    env: Arc<SeriesEnv>,
+    commit: Arc<Commit>,
+    data_reader: DataReader,
+    offset: u32,
+    /// Offset of the index entry covering the current block, and the highest timestamp seen.
+    group: Option<(u32, i64)>,
+    last_ts: i64,
+}
+
+impl SeriesVerifier {
+    pub fn create(env: Arc<SeriesEnv>) -> Result<SeriesVerifier, Error> {
+        Ok(SeriesVerifier {
+            commit: env.commit_log().current(),
+            data_reader: DataReader::create(data::open_data(&env.dir())?, 0)?,
+            env,
+            offset: 0,
+            group: None,
+            last_ts: i64::MIN,
+        })
+    }
+
+    /// Checks the highest timestamp of the index entry. The last entry could be
+    /// extended by the appends committed since the verification started (with the
+    /// index density greater than 1), in that case it can be greater, up to the
+    /// highest timestamp of the current commit.
+    fn close_group(&self, last: bool) -> Result<(), Error> {
+        let (index_offset, highest_ts) = match self.group {
+            Some(group) => group,
+            None => return Ok(()),
+        };
+
+        let _publishing = self.env.publishing();
+        let index_ts = self.env.index().ts_at(index_offset)?;
+        let current = self.env.commit_log().current();
+
+        let extended = last && current != self.commit && index_ts > highest_ts && index_ts <= current.highest_ts;
+        if index_ts == highest_ts || extended {
+            Ok(())
+        } else {
+            Err(Error::IndexIsNotConsistent)
+        }
+    }
+
+    /// Verifies the next block, returns `false` if all blocks are verified.
+    pub fn verify_block(&mut self) -> Result<bool, Error> {
+        if self.offset >= self.commit.data_offset {
+            self.close_group(true)?;
+
+            let index_end = self.group.map(|(index_offset, _)| index_offset + index::ENTRY_SIZE);
+            if index_end.unwrap_or(0) != self.commit.index_offset {
+                return Err(Error::IndexIsNotConsistent);
+            }
+            return Ok(false);
+        }
+
+        let next_index_offset = self
+            .group
+            .map(|(index_offset, _)| index_offset + index::ENTRY_SIZE)
+            .unwrap_or(0);
+
+        if next_index_offset < self.commit.index_offset
+            && self.env.index().offset_at(next_index_offset)? == self.offset
+        {
+            self.close_group(false)?;
+            self.group = Some((next_index_offset, i64::MIN));
+        } else if self.group.is_none() {
+            return Err(Error::IndexIsNotConsistent);
+        }
+
+        let (entries, next_offset) = self.data_reader.read_block()?;
+
+        for entry in &entries {
+            if entry.ts < self.last_ts {
+                return Err(Error::UnorderedEntries);
+            }
+            self.last_ts = entry.ts;
+        }
+
+        if let Some((_, highest_ts)) = self.group.as_mut() {
+            *highest_ts = self.last_ts;
+        }
+
+        self.offset = next_offset;
+
+        Ok(true)
+    }
+}
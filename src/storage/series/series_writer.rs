@@ -1,18 +1,46 @@
 use super::super::super::failpoints::failpoint;
-use super::super::commit_log::Commit;
-use super::super::data::{self, DataWriter};
+use super::super::commit_log::{self, Commit};
+use super::super::data::{self, DataReader, DataWriter};
 use super::super::entry::Entry;
 use super::super::env::SeriesEnv;
 use super::super::error::Error;
 use super::super::file_system::{FileKind, OpenMode};
-use super::super::Compression;
+use super::super::index;
+use super::super::{Compression, DuplicatePolicy, IndexSyncPolicy, SeriesConfig, SyncMode};
+use super::batch_file::{self, PendingBatch};
+use super::rate_limiter::RateLimiter;
 use crate::buffering::BufferingBuilder;
 use std::ops::DerefMut;
-use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
 
 pub struct Interior {
     data_writer: DataWriter,
     env: Arc<SeriesEnv>,
+    last_batch_id: Option<String>,
+    group_blocks: u32,
+    sync_mode: SyncMode,
+    unsynced: u32,
+    index_sync: IndexSyncPolicy,
+    /// Number of the data fsyncs since the index is fsynced.
+    index_unsynced: u32,
+    /// Offset up to which the index is fsynced.
+    index_synced: u32,
+    archived: bool,
+    /// Whether the nearly full index is already logged.
+    index_warned: bool,
+    /// Set once the series is replaced by the compacted one.
+    replaced: bool,
+    rate_limiter: Option<RateLimiter>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AppendReport {
+    pub written: usize,
+    /// The entries skipped because they are lower than the highest timestamp of the series.
+    pub rejected: Vec<Entry>,
+    /// The entries dropped by `DuplicatePolicy::KeepLast`, either replaced by a later entry
+    /// of the same timestamp or already written.
+    pub duplicates: Vec<Entry>,
 }
 
 pub struct Appender<I>
@@ -23,6 +51,21 @@ where
     data_offset: u32,
     index_offset: u32,
     highest_ts: i64,
+    /// Index offset of the last commit, the entries before it are committed.
+    committed_index_offset: u32,
+    /// Index entries `(index offset, ts, block offset)` of the appended blocks. They are
+    /// written with the commit, so the committed entries never hold the timestamps of
+    /// the uncommitted blocks, e.g. if the append fails.
+    index_entries: Vec<(u32, i64, u32)>,
+    /// Blocks in the last index group, including the appended ones.
+    group_blocks: u32,
+    batch_id: Option<String>,
+    pending: bool,
+    commit_on_drop: bool,
+    /// Whether the appends are limited by the series `SeriesConfig::rate_limit`, the
+    /// internal rewrites of the committed entries (e.g. the compaction) are not.
+    rate_limited: bool,
+    compression: Compression,
 }
 
 impl<I> Appender<I>
@@ -30,37 +73,208 @@ where
     I: DerefMut<Target = Interior>,
 {
     fn create(inter: I) -> Result<Appender<I>, Error> {
+        inter.check_writable()?;
+        if inter.archived {
+            return Err(Error::Archived);
+        }
+
         let commit = inter.env.commit_log().current();
+        let compression = inter.env.config().compression;
+
+        let group_blocks = inter.group_blocks;
 
         Ok(Appender {
             inter: inter,
             data_offset: commit.data_offset,
             index_offset: commit.index_offset,
             highest_ts: commit.highest_ts,
+            committed_index_offset: commit.index_offset,
+            index_entries: Vec::new(),
+            group_blocks,
+            batch_id: None,
+            pending: false,
+            commit_on_drop: false,
+            rate_limited: true,
+            compression,
         })
     }
 
-    pub fn done(mut self) -> Result<(), Error> {
-        self.inter.data_writer.sync()?;
-        self.inter.env.index().sync()?;
+    /// Commits the appended entries when the appender goes out of scope
+    /// without calling `done()`. Errors are logged.
+    pub fn commit_on_drop(&mut self) {
+        self.commit_on_drop = true;
+    }
+
+    /// Sets the compression of the blocks appended after the call, the series
+    /// `SeriesConfig::compression` by default.
+    pub fn compression(&mut self, compression: Compression) {
+        self.compression = compression;
+    }
+
+    /// Marks the appended entries as the batch `batch_id`. Returns `false` if
+    /// the batch with the same id was the last one committed, in that case
+    /// the entries should not be appended.
+    pub fn batch_id(&mut self, batch_id: String) -> bool {
+        if self.inter.last_batch_id.as_ref() == Some(&batch_id) {
+            return false;
+        }
+        self.batch_id = Some(batch_id);
+        true
+    }
 
-        self.inter.env.commit_log().commit(Commit {
+    /// Commits the appended entries, returns the new commit.
+    pub fn done(mut self) -> Result<Commit, Error> {
+        self.commit()
+    }
+
+    fn commit(&mut self) -> Result<Commit, Error> {
+        let commit = Commit {
             data_offset: self.data_offset,
             index_offset: self.index_offset,
             highest_ts: self.highest_ts,
-        })
+        };
+
+        let env = self.inter.env.clone();
+
+        // the batch is written before the commit, it's committed once the commit log reaches its offset
+        if let Some(batch_id) = &self.batch_id {
+            let pending = PendingBatch { data_offset: commit.data_offset, batch_id };
+            batch_file::write(&env.dir(), Some(pending), self.inter.last_batch_id.as_deref())?;
+        }
+
+        failpoint!(
+            env.fp(),
+            "series_writer::commit::batch",
+            Err(Error::Io(std::io::Error::new(
+                std::io::ErrorKind::WriteZero,
+                "fp"
+            )))
+        );
+
+        let _publishing = env.publishing();
+
+        let mut restore = None;
+        if let Err(error) = self.publish(&env, &commit, &mut restore) {
+            // the rollback is best effort, the original error is returned
+            if let Some((offset, ts, block_offset)) = restore {
+                if let Err(error) = env.index().set(offset, ts, block_offset) {
+                    log::warn!("can not restore the last committed index entry: {:?}", error);
+                }
+            }
+            if self.batch_id.is_some() {
+                if let Err(error) = batch_file::write(&env.dir(), None, self.inter.last_batch_id.as_deref()) {
+                    log::warn!("can not rewrite the batch file: {:?}", error);
+                }
+            }
+            return Err(error);
+        }
+
+        self.committed_index_offset = self.index_offset;
+        self.index_entries.clear();
+        self.inter.group_blocks = self.group_blocks;
+
+        if let Some(batch_id) = self.batch_id.take() {
+            self.inter.last_batch_id = Some(batch_id);
+        }
+
+        self.pending = false;
+
+        Ok(commit)
+    }
+
+    /// Writes the index entries and the commit. The last committed entry is extended if the
+    /// appended blocks joined its group, `restore` is set to its previous value before it's written.
+    fn publish(
+        &mut self,
+        env: &SeriesEnv,
+        commit: &Commit,
+        restore: &mut Option<(u32, i64, u32)>,
+    ) -> Result<(), Error> {
+        let last_committed = match self.committed_index_offset {
+            0 => None,
+            offset => Some(offset - index::ENTRY_SIZE),
+        };
+        for &(offset, ts, block_offset) in &self.index_entries {
+            if Some(offset) == last_committed && restore.is_none() {
+                *restore = Some((offset, env.index().ts_at(offset)?, block_offset));
+            }
+            env.index().set(offset, ts, block_offset)?;
+
+            failpoint!(
+                env.fp(),
+                "series_writer::commit::index",
+                Err(Error::Io(std::io::Error::new(
+                    std::io::ErrorKind::WriteZero,
+                    "fp"
+                )))
+            );
+        }
+        self.inter.sync_or_defer()?;
+        env.commit_log().commit(commit.clone())
     }
 
-    fn process_entries<'a, E>(&mut self, entries: E) -> Vec<&'a Entry>
+    /// Returns the entries to append and the duplicates dropped by the series `DuplicatePolicy`.
+    fn process_entries<'a, E>(&mut self, entries: E, sorted: bool) -> Result<(Vec<&'a Entry>, Vec<&'a Entry>), Error>
     where
         E: IntoIterator<Item = &'a Entry> + 'a,
     {
-        let mut entries: Vec<&Entry> = entries
-            .into_iter()
-            .filter(|entry| entry.ts >= self.highest_ts)
-            .collect();
-        entries.sort_by_key(|entry| entry.ts);
-        entries
+        let mut entries: Vec<&Entry> = match sorted {
+            true => {
+                let mut last_ts = i64::MIN;
+                let mut sorted = Vec::new();
+                for entry in entries {
+                    if entry.ts < last_ts {
+                        return Err(Error::UnorderedEntries);
+                    }
+                    last_ts = entry.ts;
+                    if entry.ts >= self.highest_ts {
+                        sorted.push(entry);
+                    }
+                }
+                sorted
+            }
+            false => {
+                let mut entries: Vec<&Entry> = entries
+                    .into_iter()
+                    .filter(|entry| entry.ts >= self.highest_ts)
+                    .collect();
+                entries.sort_by_key(|entry| entry.ts);
+                entries
+            }
+        };
+
+        // highest_ts of the empty series is not a timestamp of any entry
+        let written_ts = Some(self.highest_ts).filter(|_| self.data_offset > 0);
+        let mut duplicates = Vec::new();
+
+        match self.inter.env.config().duplicates {
+            DuplicatePolicy::Allow => {}
+            DuplicatePolicy::KeepLast => {
+                // the sort is stable, so the last appended entry of each run of equal
+                // timestamps replaces the ones before it
+                let mut deduped: Vec<&Entry> = Vec::with_capacity(entries.len());
+                for entry in entries {
+                    if Some(entry.ts) == written_ts {
+                        duplicates.push(entry);
+                        continue;
+                    }
+                    match deduped.last_mut() {
+                        Some(last) if last.ts == entry.ts => duplicates.push(std::mem::replace(last, entry)),
+                        _ => deduped.push(entry),
+                    }
+                }
+                entries = deduped;
+            }
+            DuplicatePolicy::Reject => {
+                if entries.first().map(|entry| entry.ts) == written_ts
+                    || entries.windows(2).any(|pair| pair[0].ts == pair[1].ts)
+                {
+                    return Err(Error::DuplicateTimestamp);
+                }
+            }
+        }
+
+        Ok((entries, duplicates))
     }
 
     fn append_block<'a>(
@@ -73,8 +287,7 @@ where
             _ => return Ok(()),
         };
 
-        #[rustfmt::skip]
-        let index_offset = self.inter.env.index().set(self.index_offset, highest_ts, self.data_offset)?;
+        let (index_offset, index_entry) = self.index_block(highest_ts)?;
 
         failpoint!(
             self.inter.env.fp(),
@@ -85,6 +298,14 @@ where
             )))
         );
 
+        failpoint!(
+            self.inter.env.fp(),
+            "series_writer::data_file_too_big",
+            Err(Error::DataFileTooBig)
+        );
+
+        failpoint!(self.inter.env.fp(), "series_writer::append_block::panic", panic);
+
         #[rustfmt::skip]
         let data_offset = self.inter.data_writer.write_block(self.data_offset, block, compression)?;
 
@@ -97,89 +318,883 @@ where
             )))
         );
 
+        self.push_index_entry(index_entry);
         self.data_offset = data_offset;
         self.index_offset = index_offset;
         self.highest_ts = highest_ts;
+        self.pending = true;
+
+        Ok(())
+    }
+
+    /// Appends the already encoded block (the header and the payload as written to the
+    /// data file) verbatim, without decoding and encoding the entries again. The block
+    /// entries should be sorted and not lower than the highest timestamp of the series,
+    /// otherwise it fails with `UnorderedEntries`. As the block is not modified, the
+    /// duplicate timestamps are rejected unless the series allows duplicates.
+    pub fn append_raw_block(&mut self, header: &[u8], payload: &[u8]) -> Result<usize, Error> {
+        let entries = data::decode_raw_block(header, payload)?;
+        let (first_ts, highest_ts) = match (entries.first(), entries.last()) {
+            (Some(first), Some(last)) => (first.ts, last.ts),
+            _ => return Ok(0),
+        };
+
+        if first_ts < self.highest_ts || entries.windows(2).any(|pair| pair[0].ts > pair[1].ts) {
+            return Err(Error::UnorderedEntries);
+        }
+
+        if self.inter.env.config().duplicates != DuplicatePolicy::Allow
+            && ((self.data_offset > 0 && first_ts == self.highest_ts)
+                || entries.windows(2).any(|pair| pair[0].ts == pair[1].ts))
+        {
+            return Err(Error::DuplicateTimestamp);
+        }
+
+        self.acquire_rate(entries.len())?;
+
+        let (index_offset, index_entry) = self.index_block(highest_ts)?;
+
+        #[rustfmt::skip]
+        let data_offset = self.inter.data_writer.write_raw_block(self.data_offset, header, payload)?;
+
+        self.push_index_entry(index_entry);
+        self.data_offset = data_offset;
+        self.index_offset = index_offset;
+        self.highest_ts = highest_ts;
+        self.pending = true;
+
+        Ok(entries.len())
+    }
+
+    /// Returns the index entry pointing to the block appended at the current data offset
+    /// with the highest timestamp `highest_ts`, and the offset of the next index entry.
+    /// The entry is kept by `push_index_entry` once the block is written.
+    fn index_block(&mut self, highest_ts: i64) -> Result<(u32, (u32, i64, u32)), Error> {
+        // With the index density K, an index entry covers the group of up to K blocks:
+        // it points to the first block of the group and holds the highest ts of the group.
+        let density = self.inter.env.config().index_density.max(1);
+        let (index_offset, entry) = if self.index_offset > 0 && !self.group_blocks.is_multiple_of(density) {
+            let last_offset = self.index_offset - index::ENTRY_SIZE;
+            let group_offset = match self.index_entries.last() {
+                Some(&(offset, _, block_offset)) if offset == last_offset => block_offset,
+                _ => self.inter.env.index().offset_at(last_offset)?,
+            };
+            (self.index_offset, (last_offset, highest_ts, group_offset))
+        } else {
+            self.inter.env.index().check_room(self.index_offset)?;
+            (
+                self.index_offset + index::ENTRY_SIZE,
+                (self.index_offset, highest_ts, self.data_offset),
+            )
+        };
+
+        if !self.inter.index_warned && self.inter.env.index().is_nearly_full(index_offset) {
+            log::warn!(
+                "index of {:?} is {:.0}% full, the appends fail once it is full",
+                self.inter.env.dir().path(),
+                self.inter.env.index().usage(index_offset) * 100.0
+            );
+            self.inter.index_warned = true;
+        }
+
+        Ok((index_offset, entry))
+    }
 
+    fn acquire_rate(&mut self, entries: usize) -> Result<(), Error> {
+        let rate_limited = self.rate_limited;
+        if let Some(rate_limiter) = self.inter.rate_limiter.as_mut().filter(|_| rate_limited) {
+            if !rate_limiter.try_acquire(entries) {
+                return Err(Error::RateLimited);
+            }
+        }
         Ok(())
     }
 
-    pub fn append<'a, E>(&mut self, entries: E) -> Result<(), Error>
+    fn push_index_entry(&mut self, entry: (u32, i64, u32)) {
+        match self.index_entries.last_mut() {
+            Some(last) if last.0 == entry.0 => *last = entry,
+            _ => self.index_entries.push(entry),
+        }
+        self.group_blocks = match entry.0 + index::ENTRY_SIZE == self.index_offset {
+            true => self.group_blocks + 1,
+            false => 1,
+        };
+    }
+
+    /// Same as `append`, but also returns the skipped (stale) entries.
+    pub fn append_detailed<'a, E>(&mut self, entries: E) -> Result<AppendReport, Error>
+    where
+        E: IntoIterator<Item = &'a Entry> + 'a,
+    {
+        let (accepted, rejected): (Vec<&Entry>, Vec<&Entry>) =
+            entries.into_iter().partition(|entry| entry.ts >= self.highest_ts);
+
+        let (written, duplicates) = self.append_entries(accepted, false)?;
+        Ok(AppendReport {
+            written,
+            rejected: rejected.into_iter().cloned().collect(),
+            duplicates: duplicates.into_iter().cloned().collect(),
+        })
+    }
+
+    /// Appends the entries, returns the number of entries written. The entries
+    /// that are lower than the highest timestamp of the series are skipped.
+    pub fn append<'a, E>(&mut self, entries: E) -> Result<usize, Error>
+    where
+        E: IntoIterator<Item = &'a Entry> + 'a,
+    {
+        self.append_entries(entries, false).map(|(written, _)| written)
+    }
+
+    /// Same as `append`, but the entries should be already sorted by timestamp, so
+    /// the sort is skipped. Fails with `UnorderedEntries` on the first entry lower
+    /// than the previous one, nothing is appended in that case.
+    pub fn append_sorted<'a, E>(&mut self, entries: E) -> Result<usize, Error>
     where
         E: IntoIterator<Item = &'a Entry> + 'a,
     {
-        for block in self
-            .process_entries(entries)
+        self.append_entries(entries, true).map(|(written, _)| written)
+    }
+
+    /// Appends the entries, returns the number of entries written and the dropped duplicates.
+    fn append_entries<'a, E>(&mut self, entries: E, sorted: bool) -> Result<(usize, Vec<&'a Entry>), Error>
+    where
+        E: IntoIterator<Item = &'a Entry> + 'a,
+    {
+        let (entries, duplicates) = self.process_entries(entries, sorted)?;
+
+        if !entries.is_empty() {
+            self.acquire_rate(entries.len())?;
+        }
+
+        let mut written = 0usize;
+        for block in entries
             .into_iter()
             .buffering::<Vec<&'a Entry>>(data::MAX_ENTRIES_PER_BLOCK)
         {
-            self.append_block(block, Compression::Delta)?;
+            let len = block.len();
+            self.append_block(block, self.compression)?;
+            written += len;
         }
 
-        Ok(())
+        Ok((written, duplicates))
+    }
+}
+
+impl<I> Drop for Appender<I>
+where
+    I: DerefMut<Target = Interior>,
+{
+    fn drop(&mut self) {
+        if !self.pending {
+            return;
+        }
+
+        // the appended entries could be inconsistent if the append panicked
+        if self.commit_on_drop && !std::thread::panicking() {
+            if let Err(error) = self.commit() {
+                log::warn!("can not commit on drop: {:?}", error);
+            }
+        } else {
+            log::warn!("appender is dropped without done(), appended entries are discarded");
+        }
     }
 }
 
 impl Interior {
     fn create(env: Arc<SeriesEnv>) -> Result<Interior, Error> {
+        let mode = match env.read_only() {
+            true => OpenMode::Read,
+            false => OpenMode::Write,
+        };
+        let preallocate = env.config().preallocate_mb.unwrap_or(0) as u64 * 1024 * 1024;
+        let data_writer = DataWriter::create(env.dir().open(FileKind::Data, mode)?)?.with_preallocate(preallocate);
+
+        // data and index files could be just created
+        env.dir().sync_dir()?;
+
         Ok(Interior {
-            data_writer: DataWriter::create(env.dir().open(FileKind::Data, OpenMode::Write)?)?,
-            env: env,
+            data_writer,
+            last_batch_id: batch_file::recover(&env.dir(), env.commit_log().current().data_offset, env.read_only())?,
+            group_blocks: 0,
+            sync_mode: SyncMode::Always,
+            unsynced: 0,
+            index_sync: IndexSyncPolicy::WithData,
+            index_unsynced: 0,
+            index_synced: env.commit_log().current().index_offset,
+            archived: env.dir().exists(FileKind::ArchivedData),
+            index_warned: false,
+            replaced: false,
+            rate_limiter: env.config().rate_limit.map(|rate| RateLimiter::new(rate, env.clock())),
+            env,
         })
     }
+    fn check_writable(&self) -> Result<(), Error> {
+        if self.env.read_only() {
+            return Err(Error::ReadOnly);
+        }
+        if self.replaced {
+            return Err(Error::SeriesReplaced);
+        }
+        Ok(())
+    }
+    /// Persists the offset up to which the index is fsynced, the index entries after it
+    /// are rebuilt from the data when the series is opened. Not needed if the index is
+    /// fsynced together with the data.
+    fn mark_index_synced(&mut self, offset: u32) -> Result<(), Error> {
+        self.index_synced = offset;
+        match self.index_sync {
+            IndexSyncPolicy::WithData => Ok(()),
+            IndexSyncPolicy::Every(_) => self
+                .env
+                .dir()
+                .write_atomically(FileKind::IndexSynced, offset.to_string().as_bytes()),
+        }
+    }
+    fn sync_index(&mut self) -> Result<(), Error> {
+        self.env.index().sync()?;
+        self.index_unsynced = 0;
+        self.mark_index_synced(self.env.commit_log().current().index_offset)
+    }
+    /// Fsyncs the data, the index is fsynced according to the index sync policy.
+    fn sync_data(&mut self) -> Result<(), Error> {
+        if self.unsynced > 0 {
+            self.data_writer.sync()?;
+            self.unsynced = 0;
+            self.index_unsynced += 1;
+
+            match self.index_sync {
+                IndexSyncPolicy::WithData => self.sync_index()?,
+                IndexSyncPolicy::Every(n) if self.index_unsynced >= n => self.sync_index()?,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+    fn sync(&mut self) -> Result<(), Error> {
+        self.sync_data()?;
+        if self.index_unsynced > 0 {
+            self.sync_index()?;
+        }
+        Ok(())
+    }
+    fn sync_on_drop(&mut self) -> Result<(), Error> {
+        // simulates the crash, the deferred fsyncs are lost
+        failpoint!(self.env.fp(), "series_writer::drop", Ok(()));
+
+        self.sync()
+    }
+    /// Fsyncs the appended blocks before the commit, unless the sync
+    /// mode allows to defer it.
+    fn sync_or_defer(&mut self) -> Result<(), Error> {
+        self.unsynced += 1;
+        match self.sync_mode {
+            SyncMode::Always => self.sync_data(),
+            SyncMode::Every(n) if self.unsynced >= n => self.sync_data(),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Drop for Interior {
+    fn drop(&mut self) {
+        if let Err(error) = self.sync_on_drop() {
+            log::warn!("can not sync on drop: {:?}", error);
+        }
+    }
+}
+
+/// Locks the writer. If the lock is poisoned by a panic during an append, the
+/// lock is recovered: the appends start from the last commit, so the uncommitted
+/// blocks are just overwritten.
+fn lock(writer: &Mutex<Interior>) -> MutexGuard<'_, Interior> {
+    writer.lock().unwrap_or_else(|error: PoisonError<_>| {
+        log::warn!("writer lock is poisoned, recovering");
+        writer.clear_poison();
+        error.into_inner()
+    })
 }
 
 #[derive(Clone)]
 pub struct SeriesWriter {
     writer: Arc<Mutex<Interior>>,
+    /// The env of the series, read without waiting for the running append.
+    env: Arc<SeriesEnv>,
 }
 
 impl SeriesWriter {
     pub fn create(env: Arc<SeriesEnv>) -> Result<SeriesWriter, Error> {
+        SeriesWriter::create_with_sync_mode(env, SyncMode::Always)
+    }
+
+    pub fn create_with_sync_mode(env: Arc<SeriesEnv>, sync_mode: SyncMode) -> Result<SeriesWriter, Error> {
+        SeriesWriter::create_with_index_sync(env, sync_mode, IndexSyncPolicy::WithData)
+    }
+
+    pub fn create_with_index_sync(
+        env: Arc<SeriesEnv>,
+        sync_mode: SyncMode,
+        index_sync: IndexSyncPolicy,
+    ) -> Result<SeriesWriter, Error> {
+        let mut inter = Interior::create(env.clone())?;
+        inter.sync_mode = sync_mode;
+        inter.index_sync = index_sync;
+
+        if !inter.env.read_only() {
+            match index_sync {
+                IndexSyncPolicy::WithData => inter.env.dir().remove(FileKind::IndexSynced)?,
+                IndexSyncPolicy::Every(_) => inter.mark_index_synced(inter.index_synced)?,
+            }
+        }
+
         Ok(SeriesWriter {
-            writer: Arc::new(Mutex::new(Interior::create(env)?)),
+            writer: Arc::new(Mutex::new(inter)),
+            env,
         })
     }
 
+    pub fn config(&self) -> Arc<SeriesConfig> {
+        self.env.config()
+    }
+
+    /// Fsyncs the commits deferred by the sync mode.
+    pub fn sync(&self) -> Result<(), Error> {
+        lock(&self.writer).sync()
+    }
+
+    /// Fsyncs the unsynced commits, the index is fsynced as the index sync policy defines.
+    /// Called by the flusher of the series table with `SyncMode::Interval`.
+    pub fn flush(&self) -> Result<(), Error> {
+        lock(&self.writer).sync_data()
+    }
+
+    /// Returns `true` if the writer is used through the other handles, e.g. by an append
+    /// still running on a blocking thread.
+    pub fn is_shared(&self) -> bool {
+        Arc::strong_count(&self.writer) > 1
+    }
+
+    /// Updates the config of the series, the appends started after the update use it.
+    /// Returns the updated config. Only the settings applied to the appends should be
+    /// changed, the timestamp unit and the index density describe the existing data.
+    pub fn update_config<F>(&self, update: F) -> Result<SeriesConfig, Error>
+    where
+        F: FnOnce(&mut SeriesConfig),
+    {
+        let mut inter = lock(&self.writer);
+        inter.check_writable()?;
+
+        let mut config = inter.env.config().as_ref().clone();
+        let rate_limit = config.rate_limit;
+        update(&mut config);
+        inter.env.set_config(config.clone())?;
+
+        if config.rate_limit != rate_limit {
+            inter.rate_limiter = config.rate_limit.map(|rate| RateLimiter::new(rate, inter.env.clock()));
+        }
+
+        Ok(config)
+    }
+
+    /// Returns `true` if there are commits that are not fsynced yet.
+    pub fn has_unsynced(&self) -> bool {
+        lock(&self.writer).unsynced > 0
+    }
+
     pub fn appender(&self) -> Result<Appender<MutexGuard<'_, Interior>>, Error> {
-        Appender::create(self.writer.lock().unwrap())
+        Appender::create(lock(&self.writer))
+    }
+
+    pub fn append<'a, I>(&self, batch: I) -> Result<usize, Error>
+    where
+        I: IntoIterator<Item = &'a Entry> + 'a,
+    {
+        let mut appender = self.appender()?;
+        let written = appender.append(batch)?;
+        appender.done()?;
+        Ok(written)
     }
 
-    pub fn append<'a, I>(&self, batch: I) -> Result<(), Error>
+    /// Same as `append`, but also returns the new commit, e.g. to ship the
+    /// appended blocks (up to the commit `data_offset`) to a replica.
+    pub fn append_committed<'a, I>(&self, batch: I) -> Result<(usize, Commit), Error>
     where
         I: IntoIterator<Item = &'a Entry> + 'a,
     {
         let mut appender = self.appender()?;
-        appender.append(batch)?;
-        appender.done()
+        let written = appender.append(batch)?;
+        let commit = appender.done()?;
+        Ok((written, commit))
+    }
+
+    /// Appends the already encoded block, see `Appender::append_raw_block`.
+    pub fn append_raw_block(&self, header: &[u8], payload: &[u8]) -> Result<usize, Error> {
+        let mut appender = self.appender()?;
+        let written = appender.append_raw_block(header, payload)?;
+        appender.done()?;
+        Ok(written)
+    }
+
+    /// Appends the batch sorted by timestamp, see `Appender::append_sorted`.
+    pub fn append_sorted<'a, I>(&self, batch: I) -> Result<usize, Error>
+    where
+        I: IntoIterator<Item = &'a Entry> + 'a,
+    {
+        let mut appender = self.appender()?;
+        let written = appender.append_sorted(batch)?;
+        appender.done()?;
+        Ok(written)
+    }
+
+    /// Appends the batch, returns the number of entries written and the
+    /// entries skipped because they are lower than the highest timestamp.
+    pub fn append_detailed<'a, I>(&self, batch: I) -> Result<AppendReport, Error>
+    where
+        I: IntoIterator<Item = &'a Entry> + 'a,
+    {
+        let mut appender = self.appender()?;
+        let report = appender.append_detailed(batch)?;
+        appender.done()?;
+        Ok(report)
+    }
+
+    pub async fn append_async(&self, batch: Vec<Entry>) -> Result<usize, Error> {
+        let writer = self.writer.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut appender = Appender::create(lock(&writer))?;
+            let written = appender.append(&batch)?;
+            appender.done()?;
+            Ok(written)
+        })
+        .await
+        .unwrap()
     }
 
-    pub async fn append_async(&self, batch: Vec<Entry>) -> Result<(), Error> {
+    /// Appends the batch unless the batch with the same id was the last one
+    /// appended. Returns `None` if the batch was skipped.
+    pub async fn append_idempotent_async(&self, batch_id: String, batch: Vec<Entry>) -> Result<Option<usize>, Error> {
         let writer = self.writer.clone();
         tokio::task::spawn_blocking(move || {
-            let mut appender = Appender::create(writer.lock().unwrap())?;
-            appender.append(&batch)?;
-            appender.done()
+            let mut appender = Appender::create(lock(&writer))?;
+            if !appender.batch_id(batch_id) {
+                return Ok(None);
+            }
+            let written = appender.append(&batch)?;
+            appender.done()?;
+            Ok(Some(written))
         })
         .await
         .unwrap()
     }
 
+    /// Discards the blocks holding the entries with the timestamp greater than `ts`
+    /// in a single commit, moved back to the last block with the highest timestamp
+    /// lower or equal to `ts`. The entries lower or equal to `ts` sharing the block
+    /// with the discarded ones are discarded as well.
+    pub fn truncate_after(&self, ts: i64) -> Result<(), Error> {
+        let mut inter = lock(&self.writer);
+
+        inter.check_writable()?;
+        if inter.archived {
+            return Err(Error::Archived);
+        }
+
+        let commit = inter.env.commit_log().current();
+        if ts >= commit.highest_ts {
+            return Ok(());
+        }
+
+        let index = inter.env.index();
+
+        let index_offset = index.upper_bound(ts, commit.index_offset)?;
+        if index_offset >= commit.index_offset {
+            return Ok(());
+        }
+
+        // the blocks of the group at index_offset are kept up to the first one with
+        // the entries greater than ts
+        let group_offset = index.offset_at(index_offset)?;
+        let mut data_offset = group_offset;
+        let mut highest_ts = match index_offset {
+            0 => i64::MIN,
+            _ => index.ts_at(index_offset - index::ENTRY_SIZE)?,
+        };
+        let mut kept = 0;
+        let mut reader = DataReader::create(data::open_data(&inter.env.dir())?, group_offset)?;
+        while data_offset < commit.data_offset {
+            let (entries, next) = reader.read_block()?;
+            match entries.last() {
+                Some(entry) if entry.ts <= ts => highest_ts = entry.ts,
+                _ => break,
+            }
+            data_offset = next;
+            kept += 1;
+        }
+
+        let index_offset = match kept {
+            0 => index_offset,
+            _ => index_offset + index::ENTRY_SIZE,
+        };
+
+        let env = inter.env.clone();
+        let _publishing = env.publishing();
+        env.commit_log().commit(Commit {
+            data_offset,
+            index_offset,
+            highest_ts,
+        })?;
+        // the entry of the partially kept group is reset on open if it's lost
+        if kept > 0 {
+            index.set(index_offset - index::ENTRY_SIZE, highest_ts, group_offset)?;
+        }
+
+        inter.group_blocks = kept;
+        if inter.index_synced > index_offset {
+            inter.mark_index_synced(index_offset)?;
+        }
+        Ok(())
+    }
+
+    /// Removes all entries of the series, the series config is kept. The appends
+    /// are blocked until the data file is truncated. The archived series can be
+    /// appended again afterwards.
+    pub fn clear(&self) -> Result<(), Error> {
+        let mut inter = lock(&self.writer);
+
+        inter.check_writable()?;
+
+        // the batches appended before are forgotten, so the same batch can be appended again
+        inter.env.dir().remove(FileKind::Batch)?;
+        inter.env.dir().sync_dir()?;
+        inter.last_batch_id = None;
+
+        inter.env.commit_log().commit(commit_log::FIRST)?;
+        inter.group_blocks = 0;
+        inter.unsynced = 0;
+        inter.mark_index_synced(0)?;
+
+        if inter.archived {
+            inter.env.dir().remove(FileKind::ArchivedData)?;
+            inter.env.dir().sync_dir()?;
+            inter.archived = false;
+        }
+
+        inter.data_writer.truncate()
+    }
+
+    /// Replaces the data file with the gzip-compressed one, which is decompressed
+    /// by the readers. The series can't be appended afterwards (until it is cleared).
+    pub fn archive(&self) -> Result<(), Error> {
+        let mut inter = lock(&self.writer);
+
+        inter.check_writable()?;
+        if inter.archived {
+            return Ok(());
+        }
+
+        inter.sync()?;
+        data::archive(&inter.env.dir(), inter.env.commit_log().current().data_offset)?;
+
+        // the empty data file is kept, so the series can be opened read-only
+        inter.data_writer = DataWriter::create(inter.env.dir().open(FileKind::Data, OpenMode::Write)?)?;
+        inter.env.dir().sync_dir()?;
+        inter.archived = true;
+
+        Ok(())
+    }
+
+    pub fn archived(&self) -> bool {
+        lock(&self.writer).archived
+    }
+
+    /// Appends the committed entries to the empty series `dst` in full blocks, then calls
+    /// `replace` to replace this series with `dst`. The appends are blocked meanwhile and
+    /// are rejected with `SeriesReplaced` afterwards, unless `replace` fails.
+    pub fn compact_into<F>(&self, dst: &SeriesWriter, replace: F) -> Result<(), Error>
+    where
+        F: FnOnce() -> Result<(), Error>,
+    {
+        let mut inter = lock(&self.writer);
+        inter.check_writable()?;
+
+        let commit = inter.env.commit_log().current();
+        let mut reader = DataReader::create(data::open_data(&inter.env.dir())?, 0)?;
+
+        let mut appender = dst.appender()?;
+        appender.rate_limited = false;
+        if let Some(batch_id) = inter.last_batch_id.clone() {
+            appender.batch_id(batch_id);
+        }
+
+        let mut block = Vec::with_capacity(data::MAX_ENTRIES_PER_BLOCK);
+        let mut offset = 0;
+        while offset < commit.data_offset {
+            let (entries, next) = reader.read_block()?;
+            for entry in entries {
+                block.push(entry);
+                if block.len() == data::MAX_ENTRIES_PER_BLOCK {
+                    appender.append_sorted(&block)?;
+                    block.clear();
+                }
+            }
+            offset = next;
+        }
+        appender.append_sorted(&block)?;
+        appender.done()?;
+
+        replace()?;
+        inter.replaced = true;
+
+        Ok(())
+    }
+
     pub async fn append_with_batch_size_async(
         &self,
         size: usize,
         entries: Vec<Entry>,
-    ) -> Result<(), Error> {
+    ) -> Result<usize, Error> {
         let writer = self.writer.clone();
         tokio::task::spawn_blocking(move || {
-            let mut appender = Appender::create(writer.lock().unwrap())?;
+            let mut appender = Appender::create(lock(&writer))?;
 
+            let mut written = 0usize;
             for batch in entries.into_iter().buffering::<Vec<Entry>>(size) {
-                appender.append(&batch)?;
+                written += appender.append(&batch)?;
             }
-            appender.done()
+            appender.done()?;
+            Ok(written)
         })
         .await
         .unwrap()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::super::super::env;
+    use super::super::super::{SeriesConfig, TestClock};
+    use super::super::SeriesReader;
+    use super::*;
+    use crate::failpoints::Failpoints;
+    use std::io::Read;
+    use std::time::{Duration, SystemTime};
+
+    fn entry(ts: i64, value: f64) -> Entry {
+        Entry { ts, value }
+    }
+
+    fn read_all(reader: &SeriesReader) -> Result<Vec<Entry>, Error> {
+        reader.iterator(i64::MIN)?.collect()
+    }
+
+    /// Fails the append at the failpoint, then reopens the series as it would be after
+    /// restart: only the committed entries are visible and the series can be appended.
+    /// With the index density greater than 1 the failed block joins the group of the
+    /// committed blocks, the committed index entry should not be changed.
+    fn recover_after_failure(failpoint: &str) -> Result<(), Error> {
+        for index_density in [1, 4] {
+            let fp = Arc::new(Failpoints::create());
+            let env = env::test::create_with_failpoints(fp.clone())?;
+            SeriesConfig {
+                index_density,
+                ..SeriesConfig::default()
+            }
+            .write(&*env.fs().series("series1")?)?;
+            {
+                let series_env = env.series("series1")?;
+                let writer = SeriesWriter::create(series_env.clone())?;
+                writer.append(&vec![entry(1, 1.0)])?;
+                writer.append(&vec![entry(2, 2.0)])?;
+
+                fp.on(failpoint);
+                writer.append(&vec![entry(1000, 3.0)]).unwrap_err();
+                fp.off(failpoint);
+
+                let reader = SeriesReader::create(series_env)?;
+                reader.verify()?;
+                assert_eq!(Some((1, 2)), reader.time_bounds()?);
+            }
+
+            let env = env.reopen()?;
+            let series_env = env.series("series1")?;
+            let reader = SeriesReader::create(series_env.clone())?;
+
+            reader.verify()?;
+            assert_eq!(Some((1, 2)), reader.time_bounds()?);
+            assert_eq!(vec![entry(1, 1.0), entry(2, 2.0)], read_all(&reader)?);
+
+            let writer = SeriesWriter::create(series_env)?;
+            writer.append(&vec![entry(3, 3.1), entry(4, 4.0)])?;
+
+            reader.verify()?;
+            assert_eq!(Some((1, 4)), reader.time_bounds()?);
+            assert_eq!(
+                vec![entry(1, 1.0), entry(2, 2.0), entry(3, 3.1), entry(4, 4.0)],
+                read_all(&reader)?
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_recover_last_index_entry() -> Result<(), Error> {
+        let env = env::test::create()?;
+        SeriesConfig {
+            index_density: 4,
+            ..SeriesConfig::default()
+        }
+        .write(&*env.fs().series("series1")?)?;
+        {
+            let series_env = env.series("series1")?;
+            let writer = SeriesWriter::create(series_env.clone())?;
+            writer.append(&vec![entry(1, 1.0)])?;
+            writer.append(&vec![entry(2, 2.0)])?;
+
+            // as if the process crashed after the index entry of the next append is written
+            series_env.index().set(0, 1000, 0)?;
+            assert!(matches!(SeriesReader::create(series_env)?.verify(), Err(Error::IndexIsNotConsistent)));
+        }
+
+        let env = env.reopen()?;
+        let reader = SeriesReader::create(env.series("series1")?)?;
+        reader.verify()?;
+        assert_eq!(Some((1, 2)), reader.time_bounds()?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_append_committed() -> Result<(), Error> {
+        let env = env::test::create()?;
+        let series_env = env.series("series1")?;
+        let writer = SeriesWriter::create(series_env.clone())?;
+
+        let (written, first) = writer.append_committed(&vec![entry(1, 1.0), entry(2, 2.0)])?;
+        assert_eq!(2, written);
+        assert_eq!(2, first.highest_ts);
+        assert_eq!(first, *series_env.commit_log().current());
+
+        let (written, second) = writer.append_committed(&vec![entry(3, 3.0)])?;
+        assert_eq!(1, written);
+        assert!(second.data_offset > first.data_offset);
+        assert!(second.index_offset > first.index_offset);
+        assert_eq!(second, *series_env.commit_log().current());
+
+        let (written, stale) = writer.append_committed(&vec![entry(0, 0.0)])?;
+        assert_eq!(0, written);
+        assert_eq!(second, stale);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_append_raw_block() -> Result<(), Error> {
+        let env = env::test::create()?;
+        let src_env = env.series("series1")?;
+        let src = SeriesWriter::create(src_env.clone())?;
+        let entries = (1..100).map(|ts| entry(ts, ts as f64)).collect::<Vec<Entry>>();
+        src.append(&entries)?;
+
+        let mut block = Vec::new();
+        data::open_data(&src_env.dir())?
+            .take(src_env.commit_log().current().data_offset as u64)
+            .read_to_end(&mut block)?;
+        let (header, payload) = block.split_at(data::BLOCK_HEADER_SIZE as usize);
+
+        let dst_env = env.series("series2")?;
+        let dst = SeriesWriter::create(dst_env.clone())?;
+        dst.append(&vec![entry(0, 0.0)])?;
+        assert_eq!(99, dst.append_raw_block(header, payload)?);
+
+        let mut corrupted = header.to_vec();
+        corrupted[0] ^= 1;
+        assert!(matches!(dst.append_raw_block(&corrupted, payload), Err(Error::Crc16Mismatch)));
+        assert!(matches!(dst.append_raw_block(header, &payload[1..]), Err(Error::CorruptBlock)));
+        assert!(matches!(dst.append_raw_block(header, payload), Err(Error::UnorderedEntries)));
+
+        dst.append(&vec![entry(100, 100.0)])?;
+
+        let reader = SeriesReader::create(dst_env)?;
+        reader.verify()?;
+        let mut expected = vec![entry(0, 0.0)];
+        expected.extend(entries);
+        expected.push(entry(100, 100.0));
+        assert_eq!(expected, read_all(&reader)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rate_limit_refilled_by_clock() -> Result<(), Error> {
+        let clock = Arc::new(TestClock::new(SystemTime::now()));
+        let env = env::test::create_with_clock(clock.clone())?;
+        SeriesConfig {
+            rate_limit: Some(10),
+            ..SeriesConfig::default()
+        }
+        .write(&*env.fs().series("series1")?)?;
+        let writer = SeriesWriter::create(env.series("series1")?)?;
+
+        writer.append(&(0..10).map(|ts| entry(ts, 1.0)).collect::<Vec<Entry>>())?;
+        assert!(matches!(writer.append(&vec![entry(10, 1.0)]), Err(Error::RateLimited)));
+
+        // the clock stays still, so the bucket is not refilled
+        assert!(matches!(writer.append(&vec![entry(10, 1.0)]), Err(Error::RateLimited)));
+
+        clock.advance(Duration::from_millis(500));
+        assert_eq!(5, writer.append(&(10..15).map(|ts| entry(ts, 1.0)).collect::<Vec<Entry>>())?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_append_after_panic() -> Result<(), Error> {
+        let fp = Arc::new(Failpoints::create());
+        let env = env::test::create_with_failpoints(fp.clone())?;
+        let series_env = env.series("series1")?;
+        let writer = SeriesWriter::create(series_env.clone())?;
+        writer.append(&vec![entry(1, 1.0), entry(2, 2.0)])?;
+
+        fp.on("series_writer::append_block::panic");
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let mut appender = writer.appender().unwrap();
+            appender.commit_on_drop();
+            appender.append(&vec![entry(3, 3.0)])
+        }));
+        assert!(result.is_err());
+        fp.off("series_writer::append_block::panic");
+
+        writer.append(&vec![entry(3, 3.1), entry(4, 4.0)])?;
+        writer.sync()?;
+
+        let reader = SeriesReader::create(series_env)?;
+        reader.verify()?;
+        assert_eq!(
+            vec![entry(1, 1.0), entry(2, 2.0), entry(3, 3.1), entry(4, 4.0)],
+            read_all(&reader)?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_recover_after_index_set_failure() -> Result<(), Error> {
+        recover_after_failure("series_writer::index::set")
+    }
+
+    #[test]
+    fn test_recover_after_write_block_failure() -> Result<(), Error> {
+        recover_after_failure("series_writer::data_writer::write_block")
+    }
+
+    #[test]
+    fn test_recover_after_commit_write_failure() -> Result<(), Error> {
+        recover_after_failure("commit::write")
+    }
+
+    #[test]
+    fn test_recover_after_commit_index_failure() -> Result<(), Error> {
+        recover_after_failure("series_writer::commit::index")
+    }
+}
@@ -1,10 +1,30 @@
+use super::clock::Clock;
+use super::compression::Compression;
+use super::config::SeriesConfig;
 use super::env::Env;
 use super::error::Error;
+use super::file_system::{self, FileKind};
+use super::sync_mode::SyncMode;
+use super::virtual_series::{VirtualDefinition, VirtualSeries};
 use super::{SeriesReader, SeriesWriter};
 use super::super::failpoints::failpoint;
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
-use std::time;
+use serde_derive::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+use std::time::{self, Duration};
+
+/// Series name is used as a directory name, so only `[A-Za-z0-9_.-]` are allowed.
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 255
+        && name != "."
+        && name != ".."
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-' || b == b'.')
+}
 
 struct TableEntry {
     writer: Arc<SeriesWriter>,
@@ -12,32 +32,292 @@ struct TableEntry {
 }
 
 impl TableEntry {
+    /// Opens the reader and the writer of the series, both share the same series env.
     pub fn open_or_create<S: AsRef<str>>(env: &Env, name: S) -> Result<TableEntry, Error> {
+        let series_env = env.series(name.as_ref())?;
         Ok(TableEntry {
-            writer: Arc::new(SeriesWriter::create(env.series(name.as_ref())?)?),
-            reader: Arc::new(SeriesReader::create(env.series(name.as_ref())?)?),
+            writer: Arc::new(SeriesWriter::create_with_index_sync(
+                series_env.clone(),
+                env.sync_mode(),
+                env.index_sync(),
+            )?),
+            reader: Arc::new(SeriesReader::create(series_env)?),
         })
     }
+    /// Returns `true` if the reader and the writer are not used outside of the table.
+    fn is_idle(&self) -> bool {
+        Arc::strong_count(&self.reader) == 1 && Arc::strong_count(&self.writer) == 1 && !self.writer.is_shared()
+    }
+}
+
+struct Slot {
+    /// `None` until the series is accessed, or after it is closed as idle.
+    entry: Option<Arc<TableEntry>>,
+    /// Tick of the last access, the least recently used series are closed first.
+    used: u64,
+    /// The temporary series of a restore or a compaction, not counted against `max_series`
+    /// until renamed.
+    temp: bool,
+}
+
+/// Series of the table. With `max_open` the series are opened on the first access
+/// and the least recently used idle series are closed once more than `max_open`
+/// are open.
+struct Entries {
+    slots: HashMap<String, Slot>,
+    tick: u64,
+    max_open: Option<usize>,
+}
+
+impl Entries {
+    fn contains(&self, name: &str) -> bool {
+        self.slots.contains_key(name)
+    }
+    /// Returns the number of series, the temporary series are not counted.
+    fn count(&self) -> usize {
+        self.slots.values().filter(|slot| !slot.temp).count()
+    }
+    fn is_temp(&self, name: &str) -> bool {
+        self.slots.get(name).map(|slot| slot.temp).unwrap_or(false)
+    }
+    fn names(&self) -> Vec<String> {
+        let mut names = self.slots.keys().cloned().collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+    /// Returns the entry of the series, opens it if needed. `None` if the series doesn't exist.
+    fn get(&mut self, env: &Env, name: &str) -> Result<Option<Arc<TableEntry>>, Error> {
+        self.tick += 1;
+        let slot = match self.slots.get_mut(name) {
+            Some(slot) => slot,
+            None => return Ok(None),
+        };
+        slot.used = self.tick;
+        if let Some(entry) = &slot.entry {
+            return Ok(Some(entry.clone()));
+        }
+
+        let entry = Arc::new(TableEntry::open_or_create(env, name)?);
+        slot.entry = Some(entry.clone());
+        self.close_idle(env);
+        Ok(Some(entry))
+    }
+    fn insert(&mut self, env: &Env, name: &str, entry: Arc<TableEntry>, temp: bool) {
+        self.tick += 1;
+        let slot = Slot {
+            entry: Some(entry),
+            used: self.tick,
+            temp,
+        };
+        self.slots.insert(name.to_owned(), slot);
+        self.close_idle(env);
+    }
+    fn remove(&mut self, name: &str) {
+        self.slots.remove(name);
+    }
+    /// Moves the series to `dst`, replaces the series `dst` if it exists. The
+    /// temporary series is counted once it takes the name.
+    fn rename(&mut self, src: &str, dst: &str) {
+        if let Some(slot) = self.slots.remove(src) {
+            self.slots.insert(dst.to_owned(), Slot { temp: false, ..slot });
+        }
+    }
+    fn open_writers(&self) -> Vec<(String, Arc<SeriesWriter>)> {
+        self.slots
+            .iter()
+            .filter_map(|(name, slot)| slot.entry.as_ref().map(|entry| (name.clone(), entry.writer.clone())))
+            .collect()
+    }
+    #[cfg(test)]
+    fn open_names(&self) -> Vec<String> {
+        let mut names = self
+            .slots
+            .iter()
+            .filter(|(_, slot)| slot.entry.is_some())
+            .map(|(name, _)| name.clone())
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+    /// Closes the least recently used series until at most `max_open` are open, the
+    /// deferred fsyncs of the writer are done first. The series used outside of the
+    /// table are kept open, so a series never has two writers.
+    fn close_idle(&mut self, env: &Env) {
+        let max_open = match self.max_open {
+            Some(max_open) => max_open,
+            None => return,
+        };
+        let mut open = self.slots.values().filter(|slot| slot.entry.is_some()).count();
+        if open <= max_open {
+            return;
+        }
+
+        let mut idle = self
+            .slots
+            .iter_mut()
+            .filter(|(_, slot)| match &slot.entry {
+                Some(entry) => Arc::strong_count(entry) == 1 && entry.is_idle(),
+                None => false,
+            })
+            .collect::<Vec<_>>();
+        idle.sort_by_key(|(_, slot)| slot.used);
+
+        for (name, slot) in idle {
+            if open <= max_open {
+                return;
+            }
+            if let Some(Err(error)) = slot.entry.as_ref().map(|entry| entry.writer.sync()) {
+                log::warn!("can not sync series '{}', keeping it open: {:?}", name, error);
+                continue;
+            }
+            log::debug!("Closing idle series '{}'", name);
+            slot.entry = None;
+            env.close_series(name);
+            open -= 1;
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CompactReport {
+    /// Size of the data file before and after the compaction.
+    pub bytes_before: u64,
+    pub bytes_after: u64,
+}
+
+/// Marks the series as being compacted until dropped.
+struct Compacting<'a> {
+    compacting: &'a Mutex<HashSet<String>>,
+    name: String,
+}
+
+impl Drop for Compacting<'_> {
+    fn drop(&mut self) {
+        self.compacting.lock().unwrap().remove(&self.name);
+    }
 }
 
 pub struct SeriesTable {
     env: Env,
-    entries: Arc<Mutex<HashMap<String, Arc<TableEntry>>>>,
+    /// Shared with the flusher thread, see `spawn_flusher`.
+    entries: Arc<Mutex<Entries>>,
+    compacting: Mutex<HashSet<String>>,
+    /// Virtual series by name, kept in memory only. Locked after `entries`, so the
+    /// series and the virtual series never take the same name.
+    virtual_series: Mutex<HashMap<String, VirtualDefinition>>,
+    /// Makes the temporary series names unique within the same clock tick.
+    temp_seq: AtomicU64,
+    /// Max number of series, the temporary series of the compactions and restores are not counted.
+    max_series: Option<usize>,
 }
 
 impl SeriesTable {
+    pub fn clock(&self) -> Arc<dyn Clock> {
+        self.env.clock()
+    }
+    /// Limits the number of series, `create` fails with `Error::TooManySeries` once
+    /// `max_series` exist. The series that exist already are kept.
+    pub fn with_max_series(self, max_series: usize) -> SeriesTable {
+        SeriesTable {
+            max_series: Some(max_series),
+            ..self
+        }
+    }
+    /// Opens the series if needed, the series that can not be opened are logged and skipped.
+    fn entry(&self, entries: &mut Entries, name: &str) -> Option<Arc<TableEntry>> {
+        entries.get(&self.env, name).unwrap_or_else(|error| {
+            log::warn!("can not open series '{}': {:?}", name, error);
+            None
+        })
+    }
     pub fn reader<S: AsRef<str>>(&self, name: S) -> Option<Arc<SeriesReader>> {
-        let entries = self.entries.lock().unwrap();
-        entries.get(name.as_ref()).map(|entry| entry.reader.clone())
+        let mut entries = self.entries.lock().unwrap();
+        self.entry(&mut entries, name.as_ref()).map(|entry| entry.reader.clone())
     }
     pub fn writer<S: AsRef<str>>(&self, name: S) -> Option<Arc<SeriesWriter>> {
-        let entries = self.entries.lock().unwrap();
-        entries.get(name.as_ref()).map(|entry| entry.writer.clone())
+        let mut entries = self.entries.lock().unwrap();
+        self.entry(&mut entries, name.as_ref()).map(|entry| entry.writer.clone())
+    }
+    /// Returns the names of all series, sorted. The series are not opened.
+    pub fn names(&self) -> Vec<String> {
+        self.entries.lock().unwrap().names()
+    }
+    /// Returns the readers of all series, sorted by name. The readers are
+    /// snapshotted, so the series created or renamed afterwards are not visible.
+    /// The series are opened if needed.
+    pub fn iter_readers(&self) -> Vec<(String, Arc<SeriesReader>)> {
+        let mut entries = self.entries.lock().unwrap();
+        entries
+            .names()
+            .into_iter()
+            .filter_map(|name| self.entry(&mut entries, &name).map(|entry| (name, entry.reader.clone())))
+            .collect()
     }
-    pub fn create<S: AsRef<str>>(&self, name: S) -> Result<(), Error> {
+    /// Returns the writers of all series, sorted by name. The series are opened if needed.
+    pub fn iter_writers(&self) -> Vec<(String, Arc<SeriesWriter>)> {
         let mut entries = self.entries.lock().unwrap();
-        if entries.contains_key(name.as_ref()) {
-            return Ok(());
+        entries
+            .names()
+            .into_iter()
+            .filter_map(|name| self.entry(&mut entries, &name).map(|entry| (name, entry.writer.clone())))
+            .collect()
+    }
+    /// Creates the series unless it exists, returns `true` if the series is created.
+    /// The config of the existing series is kept as is.
+    pub fn create<S: AsRef<str>>(&self, name: S) -> Result<bool, Error> {
+        self.create_entry(name, SeriesConfig::default(), false).map(|(created, _)| created)
+    }
+    /// Same as `create`, but fails with `Error::ConfigMismatch` if the series exists
+    /// with a different config.
+    pub fn create_with_config<S: AsRef<str>>(&self, name: S, config: SeriesConfig) -> Result<bool, Error> {
+        let (created, entry) = self.create_entry(name, config.clone(), false)?;
+        if !created && *entry.reader.config() != config {
+            return Err(Error::ConfigMismatch);
+        }
+        Ok(created)
+    }
+    /// Same as `create`, the appended blocks are compressed with `compression` by default.
+    pub fn create_with_compression<S: AsRef<str>>(&self, name: S, compression: Compression) -> Result<bool, Error> {
+        self.create_with_config(
+            name,
+            SeriesConfig {
+                compression,
+                ..SeriesConfig::default()
+            },
+        )
+    }
+    /// Creates the series unless it exists and returns its writer, the series
+    /// is looked up once.
+    pub fn create_and_writer<S: AsRef<str>>(&self, name: S) -> Result<Arc<SeriesWriter>, Error> {
+        self.create_entry(name, SeriesConfig::default(), false)
+            .map(|(_, entry)| entry.writer.clone())
+    }
+    fn create_entry<S: AsRef<str>>(
+        &self,
+        name: S,
+        config: SeriesConfig,
+        temp: bool,
+    ) -> Result<(bool, Arc<TableEntry>), Error> {
+        if !is_valid_name(name.as_ref()) {
+            return Err(Error::InvalidSeriesName);
+        }
+
+        if self.env.read_only() {
+            return Err(Error::ReadOnly);
+        }
+
+        // the lock is held until the series is opened, so the concurrent creates
+        // of the same series wait for the first one and get its writer
+        let mut entries = self.entries.lock().unwrap();
+        if let Some(entry) = entries.get(&self.env, name.as_ref())? {
+            return Ok((false, entry));
+        }
+        if self.virtual_series.lock().unwrap().contains_key(name.as_ref()) {
+            return Err(Error::VirtualSeriesExists);
+        }
+
+        if !temp {
+            self.check_max_series(&entries)?;
         }
 
         failpoint!(
@@ -46,54 +326,250 @@ impl SeriesTable {
             Err(Error::Io(std::io::Error::new(std::io::ErrorKind::WriteZero, "fp")))
         );
 
-        let entry = TableEntry::open_or_create(&self.env, &name)?;
-        entries.insert(name.as_ref().to_owned(), Arc::new(entry));
+        config.write(&*self.env.fs().series(name.as_ref())?)?;
 
-        Ok(())
+        let entry = Arc::new(TableEntry::open_or_create(&self.env, &name)?);
+        entries.insert(&self.env, name.as_ref(), entry.clone(), temp);
+
+        Ok((true, entry))
+    }
+    fn check_max_series(&self, entries: &Entries) -> Result<(), Error> {
+        match self.max_series {
+            Some(max_series) if entries.count() >= max_series => Err(Error::TooManySeries),
+            _ => Ok(()),
+        }
+    }
+    /// Defines the virtual series computed from the other series on read, see `VirtualSeries`.
+    /// The definitions are not persisted. Returns `false` if a series or a virtual series
+    /// with the name exists, the series with the name of a virtual series are not created.
+    pub fn define_virtual<S: AsRef<str>>(&self, name: S, definition: VirtualDefinition) -> Result<bool, Error> {
+        if !is_valid_name(name.as_ref()) {
+            return Err(Error::InvalidSeriesName);
+        }
+        // the entries are locked before the virtual series, as in `create_entry`
+        let entries = self.entries.lock().unwrap();
+        if entries.contains(name.as_ref()) {
+            return Ok(false);
+        }
+        let mut virtual_series = self.virtual_series.lock().unwrap();
+        if virtual_series.contains_key(name.as_ref()) {
+            return Ok(false);
+        }
+        virtual_series.insert(name.as_ref().to_owned(), definition);
+        Ok(true)
+    }
+    /// Returns the virtual series, `None` if it is not defined or one of its series doesn't exist.
+    pub fn virtual_series<S: AsRef<str>>(&self, name: S) -> Result<Option<Arc<VirtualSeries>>, Error> {
+        let definition = match self.virtual_series.lock().unwrap().get(name.as_ref()) {
+            Some(definition) => definition.clone(),
+            None => return Ok(None),
+        };
+        match (self.reader(&definition.left), self.reader(&definition.right)) {
+            (Some(left), Some(right)) => Ok(Some(Arc::new(VirtualSeries::create(left, right, definition.op)?))),
+            _ => Ok(None),
+        }
     }
+    /// Creates the temporary series of the restore, it is not counted against `max_series`
+    /// until renamed, see `rename`.
     pub fn create_temp(&self) -> Result<String, Error> {
-        let name = format!(
-            "restore-{}",
-            time::SystemTime::now()
-                .duration_since(time::UNIX_EPOCH)
-                .unwrap()
-                .as_nanos()
-        );
-        self.create(&name)?;
+        let name = self.temp_name("restore");
+        self.create_entry(&name, SeriesConfig::default(), true)?;
         Ok(name)
     }
-    pub fn rename<S: AsRef<str>>(&self, src: S, dst: S) -> Result<bool, Error> {
-        let mut entries = self.entries.lock().unwrap();
-        if !entries.contains_key(src.as_ref()) || entries.contains_key(dst.as_ref()) {
-            return Ok(false);
+    fn temp_name(&self, prefix: &str) -> String {
+        let nanos = self
+            .env
+            .clock()
+            .now()
+            .duration_since(time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        format!("{}-{}-{}", prefix, nanos, self.temp_seq.fetch_add(1, Ordering::SeqCst))
+    }
+    fn start_compaction(&self, name: &str) -> Result<Compacting<'_>, Error> {
+        let mut compacting = self.compacting.lock().unwrap();
+        if !compacting.insert(name.to_owned()) {
+            return Err(Error::CompactionInProgress);
         }
+        Ok(Compacting {
+            compacting: &self.compacting,
+            name: name.to_owned(),
+        })
+    }
+    /// Rewrites the series into the new one with the full blocks, e.g. after many small
+    /// appends, and replaces the series with it. The iterators created before keep reading
+    /// the old files, the appends are blocked until the compaction is done. Returns `None`
+    /// if the series doesn't exist.
+    pub fn compact<S: AsRef<str>>(&self, name: S) -> Result<Option<CompactReport>, Error> {
+        let name = name.as_ref();
+        let (reader, writer) = match (self.reader(name), self.writer(name)) {
+            (Some(reader), Some(writer)) => (reader, writer),
+            _ => return Ok(None),
+        };
+
+        let _compacting = self.start_compaction(name)?;
+
+        let bytes_before = reader.size()?.data_bytes;
 
-        self.env.fs().rename_series(src.as_ref(), dst.as_ref())?;
+        // the series is restored from `replaced` on startup if the compaction is interrupted
+        // before the temporary series takes its name, see `FileSystem::recover_compactions`
+        let temp = self.temp_name(file_system::COMPACT_PREFIX);
+        let replaced = format!("{}{}", temp, file_system::REPLACED_SUFFIX);
+        self.create_entry(&temp, reader.config().as_ref().clone(), true)?;
 
+        let result = self
+            .env
+            .series(&temp)
+            .and_then(|env| env.dir().write_atomically(FileKind::Compacted, name.as_bytes()))
+            .and_then(|_| {
+                writer.compact_into(&self.writer(&temp).unwrap(), || {
+                    let mut entries = self.entries.lock().unwrap();
+
+                    self.env.rename_series(name, replaced.as_str())?;
+
+                    failpoint!(self.env.fp, "series_table::compact::replaced::panic", panic);
+
+                    if let Err(error) = self.env.rename_series(temp.as_str(), name) {
+                        self.env.rename_series(replaced.as_str(), name)?;
+                        return Err(error);
+                    }
+
+                    entries.rename(&temp, name);
+                    Ok(())
+                })
+            });
+
+        if let Err(error) = result {
+            self.entries.lock().unwrap().remove(&temp);
+            if let Err(error) = self.env.remove_series(&temp) {
+                log::warn!("can not remove series '{}': {:?}", &temp, error);
+            }
+            return Err(error);
+        }
+
+        failpoint!(self.env.fp, "series_table::compact::renamed::panic", panic);
+
+        self.env.remove_series(&replaced)?;
+        if let Err(error) = self.env.series(name)?.dir().remove(FileKind::Compacted) {
+            log::warn!("can not remove the compaction marker of '{}': {:?}", name, error);
+        }
+
+        Ok(Some(CompactReport {
+            bytes_before,
+            bytes_after: self.reader(name).unwrap().size()?.data_bytes,
+        }))
+    }
+    /// Removes all entries of the series, the series itself and its config are kept.
+    /// Returns `false` if the series doesn't exist.
+    pub fn truncate<S: AsRef<str>>(&self, name: S) -> Result<bool, Error> {
+        match self.writer(name) {
+            Some(writer) => writer.clear().map(|_| true),
+            None => Ok(false),
+        }
+    }
+    /// Updates the config of the series, see `SeriesWriter::update_config`. Returns `None`
+    /// if the series doesn't exist.
+    pub fn update_config<S, F>(&self, name: S, update: F) -> Result<Option<SeriesConfig>, Error>
+    where
+        S: AsRef<str>,
+        F: FnOnce(&mut SeriesConfig),
+    {
+        match self.writer(name) {
+            Some(writer) => writer.update_config(update).map(Some),
+            None => Ok(None),
+        }
+    }
+    /// Archives the series, see `SeriesWriter::archive`. Returns `false` if the series doesn't exist.
+    pub fn archive<S: AsRef<str>>(&self, name: S) -> Result<bool, Error> {
+        match self.writer(name) {
+            Some(writer) => writer.archive().map(|_| true),
+            None => Ok(false),
+        }
+    }
+    /// Renames the series, returns `false` if `src` doesn't exist or `dst` does. The temporary
+    /// series renamed to `dst` adds a series, so it fails with `Error::TooManySeries` once
+    /// `max_series` exist.
+    pub fn rename<S: AsRef<str>>(&self, src: S, dst: S) -> Result<bool, Error> {
+        let mut entries = self.entries.lock().unwrap();
+        if !entries.contains(src.as_ref())
+            || entries.contains(dst.as_ref())
+            || self.virtual_series.lock().unwrap().contains_key(dst.as_ref())
         {
-            entries.remove(src.as_ref());
+            return Ok(false);
+        }
+        if entries.is_temp(src.as_ref()) {
+            self.check_max_series(&entries)?;
         }
 
-        let entry = TableEntry::open_or_create(&self.env, dst.as_ref())?;
-        entries.insert(dst.as_ref().to_owned(), Arc::new(entry));
+        self.env.rename_series(src.as_ref(), dst.as_ref())?;
+
+        // the readers and writers are kept, so the iterators and appends
+        // started before the rename are not interrupted
+        entries.rename(src.as_ref(), dst.as_ref());
 
         Ok(true)
     }
 }
 
 pub fn create(env: Env) -> Result<SeriesTable, Error> {
-    let mut entries = HashMap::new();
+    if !env.read_only() {
+        env.fs().recover_compactions()?;
+    }
+    let mut slots = HashMap::new();
     for name in env.fs().get_series()? {
-        entries.insert(
-            name.to_owned(),
-            Arc::new(TableEntry::open_or_create(&env, &name)?),
-        );
+        let entry = Arc::new(TableEntry::open_or_create(&env, &name)?);
+        slots.insert(name, Slot { entry: Some(entry), used: 0, temp: false });
     }
+    Ok(with_entries(env, slots, None))
+}
+
+/// Same as `create`, but the series are opened on the first access instead of at
+/// startup, and at most `max_open` idle series are kept open.
+pub fn create_lazy(env: Env, max_open: usize) -> Result<SeriesTable, Error> {
+    if !env.read_only() {
+        env.fs().recover_compactions()?;
+    }
+    let slots = env
+        .fs()
+        .get_series()?
+        .into_iter()
+        .map(|name| (name, Slot { entry: None, used: 0, temp: false }))
+        .collect();
+    Ok(with_entries(env, slots, Some(max_open)))
+}
+
+/// Fsyncs the commits of the open writers deferred by `SyncMode::Interval` every `interval`,
+/// a single thread serves all series of the table. Stops once the table is dropped.
+fn spawn_flusher(entries: Weak<Mutex<Entries>>, interval: Duration) {
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+
+        let writers = match entries.upgrade() {
+            Some(entries) => entries.lock().unwrap().open_writers(),
+            None => return,
+        };
+
+        for (name, writer) in writers {
+            if let Err(error) = writer.flush() {
+                log::warn!("can not sync series '{}' in background: {:?}", name, error);
+            }
+        }
+    });
+}
 
-    Ok(SeriesTable {
+fn with_entries(env: Env, slots: HashMap<String, Slot>, max_open: Option<usize>) -> SeriesTable {
+    let entries = Arc::new(Mutex::new(Entries { slots, tick: 0, max_open }));
+    if let SyncMode::Interval(interval) = env.sync_mode() {
+        spawn_flusher(Arc::downgrade(&entries), interval);
+    }
+    SeriesTable {
         env,
-        entries: Arc::new(Mutex::new(entries)),
-    })
+        entries,
+        compacting: Mutex::new(HashSet::new()),
+        virtual_series: Mutex::new(HashMap::new()),
+        temp_seq: AtomicU64::new(0),
+        max_series: None,
+    }
 }
 
 #[cfg(test)]
@@ -104,7 +580,6 @@ pub mod test {
     use std::fs;
     use std::ops::Deref;
     use std::path::PathBuf;
-    use std::time::{SystemTime, UNIX_EPOCH};
 
     pub struct TempSeriesTable {
         pub series_table: Arc<SeriesTable>,
@@ -124,18 +599,28 @@ pub mod test {
         }
     }
 
+    impl TempSeriesTable {
+        /// Opens the table over the same directory, as it would be after restart.
+        pub fn reopen(&self) -> Result<SeriesTable, Error> {
+            super::create(env::create(file_system::open(self.path.clone())?, self.env.fp.clone()))
+        }
+
+        /// Names of the series directories, including the temporary ones.
+        pub fn dirs(&self) -> Result<Vec<String>, Error> {
+            let mut dirs = fs::read_dir(self.path.join("series"))?
+                .map(|entry| Ok(entry?.file_name().to_string_lossy().into_owned()))
+                .collect::<Result<Vec<String>, Error>>()?;
+            dirs.sort();
+            Ok(dirs)
+        }
+    }
+
     pub fn create() -> Result<TempSeriesTable, Error> {
         create_with_failpoints(Arc::new(Failpoints::create()))
     }
 
     pub fn create_with_failpoints(fp: Arc<Failpoints>) -> Result<TempSeriesTable, Error> {
-        let path = PathBuf::from(format!(
-            "temp-dir-{:?}",
-            SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap()
-                .as_nanos()
-        ));
+        let path = file_system::test::temp_path();
 
         Ok(TempSeriesTable {
             series_table: Arc::new(super::create(env::create(
@@ -145,4 +630,452 @@ pub mod test {
             path: path.clone(),
         })
     }
+
+    #[test]
+    fn test_is_valid_name() {
+        assert!(is_valid_name("co2.room-1_a"));
+        assert!(!is_valid_name(""));
+        assert!(!is_valid_name(".."));
+        assert!(!is_valid_name("a/b"));
+        assert!(!is_valid_name("a b"));
+    }
+
+    #[test]
+    fn test_create_and_writer() -> Result<(), Error> {
+        use super::super::Entry;
+
+        let series_table = create()?;
+
+        let writer = series_table.create_and_writer("t")?;
+        writer.append(&vec![Entry { ts: 1, value: 1.0 }])?;
+
+        let writer = series_table.create_and_writer("t")?;
+        writer.append(&vec![Entry { ts: 2, value: 2.0 }])?;
+
+        assert_eq!(2, series_table.reader("t").unwrap().iterator(0)?.count());
+        assert!(matches!(series_table.create_and_writer("a b"), Err(Error::InvalidSeriesName)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_truncate() -> Result<(), Error> {
+        use super::super::{Entry, TimestampUnit};
+
+        let table = create()?;
+        table.create_with_config(
+            "t",
+            SeriesConfig {
+                timestamp_unit: TimestampUnit::Seconds,
+                ..SeriesConfig::default()
+            },
+        )?;
+
+        // the series is not created again with another config
+        assert!(matches!(table.create_with_config("t", SeriesConfig::default()), Err(Error::ConfigMismatch)));
+        assert!(!table.create("t")?);
+
+        let writer = table.writer("t").unwrap();
+        writer.append(&vec![Entry { ts: 1, value: 1.0 }, Entry { ts: 2, value: 2.0 }])?;
+
+        assert!(table.truncate("t")?);
+        assert!(!table.truncate("unknown")?);
+
+        let reader = table.reader("t").unwrap();
+        assert_eq!(0, reader.iterator(i64::MIN)?.count());
+        assert_eq!(TimestampUnit::Seconds, reader.config().timestamp_unit);
+        assert_eq!(i64::MIN, reader.commit().highest_ts);
+
+        writer.append(&vec![Entry { ts: 0, value: 3.0 }])?;
+        assert_eq!(
+            vec![Entry { ts: 0, value: 3.0 }],
+            reader.iterator(i64::MIN)?.collect::<Result<Vec<Entry>, Error>>()?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compact() -> Result<(), Error> {
+        use super::super::Entry;
+
+        let table = create()?;
+        table.create("t")?;
+
+        let entries = (0..3000).map(|ts| Entry { ts, value: ts as f64 }).collect::<Vec<Entry>>();
+        let writer = table.writer("t").unwrap();
+        for chunk in entries.chunks(10) {
+            writer.append(chunk)?;
+        }
+
+        let reader = table.reader("t").unwrap();
+        let mut iterator = reader.iterator(i64::MIN)?;
+        let mut read = iterator.by_ref().take(1500).collect::<Result<Vec<Entry>, Error>>()?;
+
+        let report = table.compact("t")?.unwrap();
+        assert!(report.bytes_after < report.bytes_before);
+        assert!(table.compact("unknown")?.is_none());
+
+        // the iterator created before reads the replaced files
+        read.extend(iterator.collect::<Result<Vec<Entry>, Error>>()?);
+        assert_eq!(entries, read);
+
+        assert!(matches!(writer.append(&vec![Entry { ts: 3000, value: 1.0 }]), Err(Error::SeriesReplaced)));
+
+        let reader = table.reader("t").unwrap();
+        assert_eq!(entries, reader.iterator(i64::MIN)?.collect::<Result<Vec<Entry>, Error>>()?);
+        assert_eq!(report.bytes_after, reader.size()?.data_bytes);
+        assert_eq!(vec!["t"], table.iter_readers().into_iter().map(|(name, _)| name).collect::<Vec<String>>());
+
+        table.writer("t").unwrap().append(&vec![Entry { ts: 3000, value: 1.0 }])?;
+        assert_eq!(3001, reader.iterator(i64::MIN)?.count());
+
+        let _compacting = table.start_compaction("t")?;
+        assert!(matches!(table.compact("t"), Err(Error::CompactionInProgress)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_flusher() -> Result<(), Error> {
+        use super::super::{Entry, SyncMode};
+        use std::time::Instant;
+
+        let path = file_system::test::temp_path();
+        let table = TempSeriesTable {
+            series_table: Arc::new(super::create(
+                env::create(file_system::open(path.clone())?, Arc::new(Failpoints::create()))
+                    .with_sync_mode(SyncMode::Interval(Duration::from_millis(20))),
+            )?),
+            path,
+        };
+
+        let writers = ["t1", "t2"]
+            .iter()
+            .map(|name| table.create_and_writer(name))
+            .collect::<Result<Vec<_>, Error>>()?;
+        for writer in writers.iter() {
+            writer.append(&vec![Entry { ts: 1, value: 1.0 }])?;
+            assert!(writer.has_unsynced());
+        }
+
+        // a single thread fsyncs the writers of all series
+        let started = Instant::now();
+        while writers.iter().any(|writer| writer.has_unsynced()) && started.elapsed() < Duration::from_secs(5) {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(writers.iter().all(|writer| !writer.has_unsynced()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compact_recovery() -> Result<(), Error> {
+        use super::super::Entry;
+
+        for failpoint in &["series_table::compact::replaced::panic", "series_table::compact::renamed::panic"] {
+            let fp = Arc::new(Failpoints::create());
+            let table = create_with_failpoints(fp.clone())?;
+            table.create("t")?;
+
+            let entries = (0..10).map(|ts| Entry { ts, value: ts as f64 }).collect::<Vec<Entry>>();
+            for entry in entries.iter() {
+                table.writer("t").unwrap().append(&vec![entry.clone()])?;
+            }
+
+            // the compaction is interrupted by a crash after one or both renames
+            fp.on(failpoint);
+            let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| table.compact("t")));
+            assert!(result.is_err());
+            fp.off(failpoint);
+            assert_eq!(2, table.dirs()?.len());
+            assert!(table.env.fs().get_series()?.iter().all(|name| name == "t"));
+
+            let reopened = table.reopen()?;
+            assert_eq!(vec!["t".to_owned()], table.dirs()?);
+            assert_eq!(vec!["t".to_owned()], reopened.env.fs().get_series()?);
+
+            let reader = reopened.reader("t").unwrap();
+            reader.verify()?;
+            assert_eq!(entries, reader.iterator(i64::MIN)?.collect::<Result<Vec<Entry>, Error>>()?);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compact_rate_limited() -> Result<(), Error> {
+        use super::super::Entry;
+
+        let table = create()?;
+        table.create("t")?;
+
+        let entries = (0..70_000).map(|ts| Entry { ts, value: ts as f64 }).collect::<Vec<Entry>>();
+        table.writer("t").unwrap().append(&entries)?;
+        table.update_config("t", |config| config.rate_limit = Some(100))?;
+
+        // the compaction copies more entries than the limit allows per second
+        table.compact("t")?.unwrap();
+
+        let reader = table.reader("t").unwrap();
+        assert_eq!(Some(100), reader.config().rate_limit);
+        assert_eq!(entries, reader.iterator(i64::MIN)?.collect::<Result<Vec<Entry>, Error>>()?);
+
+        // the appends are still limited, the first one takes the tokens in advance
+        let writer = table.writer("t").unwrap();
+        writer.append(&(70_000..70_101).map(|ts| Entry { ts, value: 1.0 }).collect::<Vec<Entry>>())?;
+        assert!(matches!(writer.append(&vec![Entry { ts: 70_101, value: 1.0 }]), Err(Error::RateLimited)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_iter() -> Result<(), Error> {
+        use super::super::Entry;
+
+        let table = create()?;
+        table.create("t2")?;
+        table.create("t1")?;
+
+        for (name, writer) in table.iter_writers() {
+            writer.append(&vec![Entry { ts: name.len() as i64, value: 1.0 }, Entry { ts: 10, value: 2.0 }])?;
+        }
+
+        let readers = table.iter_readers();
+        assert_eq!(
+            vec!["t1", "t2"],
+            readers.iter().map(|(name, _)| name.as_str()).collect::<Vec<&str>>()
+        );
+        for (_, reader) in readers {
+            assert_eq!(2, reader.iterator(0)?.count());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rename_while_reading() -> Result<(), Error> {
+        use super::super::Entry;
+
+        let table = create()?;
+        table.create("t1")?;
+
+        let entries = (0..1000)
+            .map(|i| Entry { ts: i, value: i as f64 })
+            .collect::<Vec<Entry>>();
+        for chunk in entries.chunks(100) {
+            table.writer("t1").unwrap().append(chunk)?;
+        }
+
+        let reader = table.reader("t1").unwrap();
+        let mut iterator = reader.iterator(0)?;
+        let mut read = iterator.by_ref().take(150).collect::<Result<Vec<Entry>, Error>>()?;
+
+        assert!(table.rename("t1", "t2")?);
+        assert!(table.reader("t1").is_none());
+
+        read.extend(iterator.collect::<Result<Vec<Entry>, Error>>()?);
+        assert_eq!(entries, read);
+
+        assert_eq!(entries, reader.iterator(0)?.collect::<Result<Vec<Entry>, Error>>()?);
+
+        table.writer("t2").unwrap().append(&vec![Entry { ts: 1000, value: 1.0 }])?;
+        assert_eq!(
+            1001,
+            table.reader("t2").unwrap().iterator(0)?.count()
+        );
+
+        table.create("t1")?;
+        assert_eq!(0, table.reader("t1").unwrap().iterator(0)?.count());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_create_lazy() -> Result<(), Error> {
+        use super::super::Entry;
+
+        let path = file_system::test::temp_path();
+        let fp = Arc::new(Failpoints::create());
+
+        let eager = super::create(env::create(file_system::open(path.clone())?, fp.clone()))?;
+        for name in ["a", "b", "c"] {
+            eager.create(name)?;
+            eager.writer(name).unwrap().append(&vec![Entry { ts: 1, value: 1.0 }])?;
+        }
+        drop(eager);
+
+        let table = TempSeriesTable {
+            series_table: Arc::new(super::create_lazy(env::create(file_system::open(path.clone())?, fp), 2)?),
+            path,
+        };
+        let open = || table.entries.lock().unwrap().open_names();
+
+        assert_eq!(vec!["a", "b", "c"], table.names());
+        assert!(open().is_empty());
+
+        let a = table.reader("a").unwrap();
+        assert_eq!(vec!["a"], open());
+        assert_eq!(1, a.iterator(0)?.count());
+
+        table.writer("b").unwrap().append(&vec![Entry { ts: 2, value: 2.0 }])?;
+        assert_eq!(vec!["a", "b"], open());
+
+        // "a" is still used, so the idle "b" is closed
+        assert_eq!(1, table.reader("c").unwrap().iterator(0)?.count());
+        assert_eq!(vec!["a", "c"], open());
+
+        drop(a);
+        assert_eq!(2, table.reader("b").unwrap().iterator(0)?.count());
+        assert_eq!(vec!["b", "c"], open());
+
+        assert!(table.reader("d").is_none());
+        assert!(table.create("d")?);
+        assert_eq!(vec!["a", "b", "c", "d"], table.names());
+        assert_eq!(2, open().len());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_create_concurrent() -> Result<(), Error> {
+        use super::super::Entry;
+        use std::sync::Barrier;
+        use std::thread;
+
+        let series_table = create()?;
+        let barrier = Arc::new(Barrier::new(16));
+
+        let handles = (0..16)
+            .map(|_| {
+                let series_table = series_table.series_table.clone();
+                let barrier = barrier.clone();
+                thread::spawn(move || -> Result<(bool, Arc<SeriesWriter>), Error> {
+                    barrier.wait();
+                    let created = series_table.create("t")?;
+                    Ok((created, series_table.create_and_writer("t")?))
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let results = handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        assert_eq!(1, results.iter().filter(|(created, _)| *created).count());
+        assert!(results.iter().all(|(_, writer)| Arc::ptr_eq(writer, &results[0].1)));
+
+        assert_eq!(vec!["t".to_owned()], series_table.env.fs().get_series()?);
+
+        results[0].1.append(&vec![Entry { ts: 1, value: 1.0 }])?;
+        assert_eq!(1, series_table.reader("t").unwrap().iterator(i64::MIN)?.count());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_create_temp_concurrent() -> Result<(), Error> {
+        use std::thread;
+
+        let series_table = create()?;
+
+        let handles = (0..8)
+            .map(|_| {
+                let series_table = series_table.series_table.clone();
+                thread::spawn(move || (0..25).map(|_| series_table.create_temp()).collect::<Result<Vec<_>, _>>())
+            })
+            .collect::<Vec<_>>();
+
+        let mut names = HashSet::new();
+        for handle in handles {
+            for name in handle.join().unwrap()? {
+                assert!(names.insert(name));
+            }
+        }
+
+        assert_eq!(200, names.len());
+        assert_eq!(200, series_table.names().len());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_series() -> Result<(), Error> {
+        use super::super::Entry;
+
+        let path = file_system::test::temp_path();
+        let env = env::create(file_system::open(path.clone())?, Arc::new(Failpoints::create()));
+        let table = TempSeriesTable {
+            series_table: Arc::new(super::create(env)?.with_max_series(2)),
+            path,
+        };
+
+        assert!(table.create("a")?);
+        assert!(table.create("b")?);
+        assert!(matches!(table.create("c"), Err(Error::TooManySeries)));
+        assert!(matches!(table.create_and_writer("c"), Err(Error::TooManySeries)));
+        assert_eq!(vec!["a", "b"], table.names());
+
+        // the existing series are still created and compacted
+        assert!(!table.create("a")?);
+        table.writer("a").unwrap().append(&vec![Entry { ts: 1, value: 1.0 }])?;
+        assert!(table.compact("a")?.is_some());
+        assert_eq!(vec!["a", "b"], table.names());
+
+        // the restore of a new series is limited as well
+        let temp = table.create_temp()?;
+        assert!(matches!(table.rename(temp.as_str(), "c"), Err(Error::TooManySeries)));
+        assert!(!table.rename(temp.as_str(), "a")?);
+
+        // the temporary series are not counted, so the pending restore doesn't block the creates
+        let path = file_system::test::temp_path();
+        let env = env::create(file_system::open(path.clone())?, Arc::new(Failpoints::create()));
+        let table = TempSeriesTable {
+            series_table: Arc::new(super::create(env)?.with_max_series(2)),
+            path,
+        };
+        assert!(table.create("a")?);
+        let temp = table.create_temp()?;
+        assert!(table.create("b")?);
+        assert!(matches!(table.rename(temp.as_str(), "c"), Err(Error::TooManySeries)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_close_idle() -> Result<(), Error> {
+        use super::super::{Entry, SyncMode};
+
+        let path = file_system::test::temp_path();
+        let env = env::create(file_system::open(path.clone())?, Arc::new(Failpoints::create()))
+            .with_sync_mode(SyncMode::Every(100));
+        let table = TempSeriesTable {
+            series_table: Arc::new(super::create_lazy(env, 2)?),
+            path,
+        };
+        let open = || table.entries.lock().unwrap().open_names();
+
+        for (i, name) in ["a", "b", "c"].iter().enumerate() {
+            table.create(name)?;
+            table.writer(name).unwrap().append(&vec![Entry { ts: i as i64, value: 1.0 }])?;
+        }
+        assert_eq!(vec!["b", "c"], open());
+
+        // "a" is opened again, "b" is the least recently used now
+        table.writer("a").unwrap().append(&vec![Entry { ts: 10, value: 2.0 }])?;
+        assert_eq!(vec!["a", "c"], open());
+
+        assert_eq!(
+            vec![Entry { ts: 0, value: 1.0 }, Entry { ts: 10, value: 2.0 }],
+            table.reader("a").unwrap().iterator(0)?.collect::<Result<Vec<Entry>, Error>>()?
+        );
+        assert_eq!(
+            vec![Entry { ts: 1, value: 1.0 }],
+            table.reader("b").unwrap().iterator(0)?.collect::<Result<Vec<Entry>, Error>>()?
+        );
+        assert_eq!(vec!["a", "b"], open());
+
+        Ok(())
+    }
 }
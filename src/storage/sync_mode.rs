@@ -0,0 +1,88 @@
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Defines when the data and index files are fsynced by the series writer.
+/// The commits that are not fsynced yet survive the process crash, but
+/// could be lost on power failure.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SyncMode {
+    /// Files are fsynced before each commit.
+    #[default]
+    Always,
+    /// Files are fsynced before every N-th commit.
+    Every(u32),
+    /// Files are fsynced by the flusher thread of the series table every interval, if there
+    /// are unsynced commits. The writers used without the table are fsynced when dropped.
+    Interval(Duration),
+}
+
+impl FromStr for SyncMode {
+    type Err = ();
+
+    /// Parses `always`, `every:{commits}` or `interval:{millis}`.
+    fn from_str(s: &str) -> Result<SyncMode, Self::Err> {
+        match s.split_once(':') {
+            None if s == "always" => Ok(SyncMode::Always),
+            Some(("every", n)) => match n.parse::<u32>() {
+                Ok(n) if n > 0 => Ok(SyncMode::Every(n)),
+                _ => Err(()),
+            },
+            Some(("interval", millis)) => match millis.parse::<u64>() {
+                Ok(millis) if millis > 0 => Ok(SyncMode::Interval(Duration::from_millis(millis))),
+                _ => Err(()),
+            },
+            _ => Err(()),
+        }
+    }
+}
+
+/// Defines when the index is fsynced, relative to the data fsyncs of `SyncMode`.
+/// The index entries lost on power failure are rebuilt from the data file when the
+/// series is opened, so the index could be fsynced less often than the data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IndexSyncPolicy {
+    /// The index is fsynced together with the data.
+    #[default]
+    WithData,
+    /// The index is fsynced on every N-th data fsync.
+    Every(u32),
+}
+
+impl FromStr for IndexSyncPolicy {
+    type Err = ();
+
+    /// Parses `with_data` or `every:{syncs}`.
+    fn from_str(s: &str) -> Result<IndexSyncPolicy, Self::Err> {
+        match s.split_once(':') {
+            None if s == "with_data" => Ok(IndexSyncPolicy::WithData),
+            Some(("every", n)) => match n.parse::<u32>() {
+                Ok(n) if n > 0 => Ok(IndexSyncPolicy::Every(n)),
+                _ => Err(()),
+            },
+            _ => Err(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_from_str() {
+        assert_eq!(Ok(SyncMode::Always), "always".parse());
+        assert_eq!(Ok(SyncMode::Every(10)), "every:10".parse());
+        assert_eq!(Ok(SyncMode::Interval(Duration::from_millis(500))), "interval:500".parse());
+        assert_eq!(Err(()), "every:0".parse::<SyncMode>());
+        assert_eq!(Err(()), "interval:".parse::<SyncMode>());
+        assert_eq!(Err(()), "never".parse::<SyncMode>());
+    }
+
+    #[test]
+    fn test_index_sync_from_str() {
+        assert_eq!(Ok(IndexSyncPolicy::WithData), "with_data".parse());
+        assert_eq!(Ok(IndexSyncPolicy::Every(10)), "every:10".parse());
+        assert_eq!(Err(()), "every:0".parse::<IndexSyncPolicy>());
+        assert_eq!(Err(()), "always".parse::<IndexSyncPolicy>());
+    }
+}
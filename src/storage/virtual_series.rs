@@ -0,0 +1,182 @@
+use super::entry::Entry;
+use super::error::Error;
+use super::series::{SeriesIterator, SeriesReader};
+use serde_derive::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::iter::Peekable;
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+impl BinaryOp {
+    pub fn apply(&self, left: f64, right: f64) -> f64 {
+        match self {
+            BinaryOp::Add => left + right,
+            BinaryOp::Sub => left - right,
+            BinaryOp::Mul => left * right,
+            BinaryOp::Div => left / right,
+        }
+    }
+}
+
+/// Definition of the virtual series `left op right`, e.g. `net = in - out`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VirtualDefinition {
+    pub left: String,
+    pub right: String,
+    pub op: BinaryOp,
+}
+
+/// Series computed on read from two series. Only the entries with the same
+/// timestamp in both series are combined, the unmatched entries are skipped.
+pub struct VirtualSeries {
+    left: Arc<SeriesReader>,
+    right: Arc<SeriesReader>,
+    op: BinaryOp,
+}
+
+impl VirtualSeries {
+    pub fn create(left: Arc<SeriesReader>, right: Arc<SeriesReader>, op: BinaryOp) -> Result<VirtualSeries, Error> {
+        if left.config().timestamp_unit != right.config().timestamp_unit {
+            return Err(Error::Other("the series have different timestamp units".to_owned()));
+        }
+        Ok(VirtualSeries { left, right, op })
+    }
+
+    pub fn left(&self) -> &Arc<SeriesReader> {
+        &self.left
+    }
+
+    pub fn iterator(&self, from_ts: i64) -> Result<VirtualIterator, Error> {
+        Ok(VirtualIterator {
+            left: self.left.iterator(from_ts)?.peekable(),
+            right: self.right.iterator(from_ts)?.peekable(),
+            op: self.op,
+        })
+    }
+}
+
+pub struct VirtualIterator {
+    left: Peekable<SeriesIterator>,
+    right: Peekable<SeriesIterator>,
+    op: BinaryOp,
+}
+
+impl Iterator for VirtualIterator {
+    type Item = Result<Entry, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let order = match (self.left.peek(), self.right.peek()) {
+                (Some(Err(_)), _) => return self.left.next(),
+                (_, Some(Err(_))) => return self.right.next(),
+                (Some(Ok(left)), Some(Ok(right))) => left.ts.cmp(&right.ts),
+                _ => return None,
+            };
+            match order {
+                Ordering::Less => {
+                    self.left.next();
+                }
+                Ordering::Greater => {
+                    self.right.next();
+                }
+                Ordering::Equal => {
+                    if let (Some(Ok(left)), Some(Ok(right))) = (self.left.next(), self.right.next()) {
+                        return Some(Ok(Entry {
+                            ts: left.ts,
+                            value: self.op.apply(left.value, right.value),
+                        }));
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::query::{Aggregation, QueryBuilder, Statement, StatementExpr};
+    use crate::storage::series_table;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn test_virtual_series() -> Result<(), Error> {
+        let series_table = series_table::test::create()?;
+        series_table.create("in")?;
+        series_table.create("out")?;
+
+        series_table.writer("in").unwrap().append(&vec![
+            Entry { ts: 1000, value: 10.0 },
+            Entry { ts: 2000, value: 20.0 },
+            Entry { ts: 3000, value: 30.0 },
+            Entry { ts: 4000, value: 40.0 },
+        ])?;
+        series_table.writer("out").unwrap().append(&vec![
+            Entry { ts: 1000, value: 4.0 },
+            Entry { ts: 2500, value: 100.0 },
+            Entry { ts: 3000, value: 10.0 },
+            Entry { ts: 4000, value: 34.0 },
+        ])?;
+
+        let definition = VirtualDefinition {
+            left: "in".to_owned(),
+            right: "out".to_owned(),
+            op: BinaryOp::Sub,
+        };
+        assert!(series_table.define_virtual("net", definition.clone())?);
+        assert!(!series_table.define_virtual("net", definition.clone())?);
+        assert!(!series_table.define_virtual("in", definition)?);
+
+        // the series can not take the name of the virtual series
+        assert!(matches!(series_table.create("net"), Err(Error::VirtualSeriesExists)));
+        assert!(matches!(series_table.create_and_writer("net"), Err(Error::VirtualSeriesExists)));
+        let temp = series_table.create_temp()?;
+        assert!(!series_table.rename(temp.as_str(), "net")?);
+
+        let net = series_table.virtual_series("net")?.unwrap();
+        assert_eq!(
+            vec![
+                Entry { ts: 1000, value: 6.0 },
+                Entry { ts: 3000, value: 20.0 },
+                Entry { ts: 4000, value: 6.0 },
+            ],
+            net.iterator(0)?.collect::<Result<Vec<Entry>, Error>>()?
+        );
+        assert_eq!(2, net.iterator(3000)?.count());
+
+        let statement = Statement::try_from(StatementExpr {
+            from: "0".to_owned(),
+            group_by: "hour".to_owned(),
+            aggregators: "mean".to_owned(),
+            limit: "10".to_owned(),
+            fill: None,
+            align: None,
+            to: None,
+        })
+        .unwrap();
+
+        let rows = net.query(statement).rows()?;
+        assert_eq!(1, rows.len());
+        assert_eq!(Aggregation::Mean(32.0 / 3.0), rows[0].values[0]);
+
+        assert!(series_table.virtual_series("unknown")?.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_binary_op() {
+        assert_eq!(5.0, BinaryOp::Add.apply(2.0, 3.0));
+        assert_eq!(-1.0, BinaryOp::Sub.apply(2.0, 3.0));
+        assert_eq!(6.0, BinaryOp::Mul.apply(2.0, 3.0));
+        assert_eq!(0.5, BinaryOp::Div.apply(1.0, 2.0));
+    }
+}
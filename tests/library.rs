@@ -0,0 +1,48 @@
+//! The storage and the queries are used as a library, without the REST API,
+//! so this test builds with `--no-default-features` as well.
+
+use milliseriesdb::query::{QueryBuilder, Statement, StatementExpr};
+use milliseriesdb::storage::error::Error;
+use milliseriesdb::storage::{env, file_system, series_table, Entry};
+use std::convert::TryFrom;
+use std::fs;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[test]
+fn test_embedded() -> Result<(), Error> {
+    let path = format!(
+        "temp-dir-{:?}",
+        SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos()
+    );
+
+    let result = (|| {
+        let series_table = series_table::create(env::create(file_system::open(&path)?))?;
+        series_table.create("t")?;
+        series_table.writer("t").unwrap().append(&vec![
+            Entry { ts: 0, value: 1.0 },
+            Entry { ts: 1000, value: 3.0 },
+            Entry { ts: 60000, value: 5.0 },
+        ])?;
+
+        let statement = Statement::try_from(StatementExpr {
+            from: "0".to_owned(),
+            group_by: "minute".to_owned(),
+            aggregators: "mean".to_owned(),
+            limit: "10".to_owned(),
+            fill: None,
+            align: None,
+            to: None,
+        })
+        .unwrap();
+
+        series_table.reader("t").unwrap().query(statement).rows()
+    })();
+
+    fs::remove_dir_all(&path).unwrap();
+
+    let rows = result?;
+    assert_eq!(vec![0, 60000], rows.iter().map(|row| row.ts).collect::<Vec<i64>>());
+    assert_eq!(vec![2.0, 5.0], rows.iter().map(|row| row.values[0].value()).collect::<Vec<f64>>());
+
+    Ok(())
+}